@@ -0,0 +1,430 @@
+use bevy::prelude::*;
+
+use crate::{
+    loading::{FontAssets, KEY_BINDINGS_FILE},
+    persistence, queue_state_change,
+    settings::{KeyBindings, PlayerControls},
+    AppState,
+};
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ControlsMenu>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::Controls).with_system(setup_controls_menu.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Controls)
+                    .with_system(
+                        handle_controls_input
+                            .system()
+                            .label("handle_controls_input"),
+                    )
+                    .with_system(
+                        update_controls_menu_text
+                            .system()
+                            .after("handle_controls_input"),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Controls)
+                    .with_system(save_key_bindings.system())
+                    .with_system(cleanup_controls_menu.system()),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ControlLayout {
+    Solo,
+    PlayerOne,
+    PlayerTwo,
+}
+
+impl ControlLayout {
+    fn label(&self) -> &'static str {
+        match self {
+            ControlLayout::Solo => "Solo",
+            ControlLayout::PlayerOne => "Player 1",
+            ControlLayout::PlayerTwo => "Player 2",
+        }
+    }
+
+    // Layouts that are played at the same time must not share keys.
+    fn plays_with(&self, other: ControlLayout) -> bool {
+        match self {
+            ControlLayout::Solo => other == ControlLayout::Solo,
+            ControlLayout::PlayerOne | ControlLayout::PlayerTwo => other != ControlLayout::Solo,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ControlAction {
+    Left,
+    Right,
+    Up,
+    Down,
+    Lift,
+    Swap,
+}
+
+impl ControlAction {
+    fn label(&self) -> &'static str {
+        match self {
+            ControlAction::Left => "Left",
+            ControlAction::Right => "Right",
+            ControlAction::Up => "Up",
+            ControlAction::Down => "Down",
+            ControlAction::Lift => "Lift",
+            ControlAction::Swap => "Swap",
+        }
+    }
+}
+
+const CONTROL_LAYOUTS: [ControlLayout; 3] = [
+    ControlLayout::Solo,
+    ControlLayout::PlayerOne,
+    ControlLayout::PlayerTwo,
+];
+
+const CONTROL_ACTIONS: [ControlAction; 6] = [
+    ControlAction::Left,
+    ControlAction::Right,
+    ControlAction::Up,
+    ControlAction::Down,
+    ControlAction::Lift,
+    ControlAction::Swap,
+];
+
+type ControlRow = (ControlLayout, ControlAction);
+
+fn control_rows() -> Vec<ControlRow> {
+    CONTROL_LAYOUTS
+        .iter()
+        .flat_map(|&layout| CONTROL_ACTIONS.iter().map(move |&action| (layout, action)))
+        .collect()
+}
+
+fn layout_controls(key_bindings: &mut KeyBindings, layout: ControlLayout) -> &mut PlayerControls {
+    match layout {
+        ControlLayout::Solo => &mut key_bindings.solo,
+        ControlLayout::PlayerOne => &mut key_bindings.player_one,
+        ControlLayout::PlayerTwo => &mut key_bindings.player_two,
+    }
+}
+
+fn action_keys(controls: &mut PlayerControls, action: ControlAction) -> &mut Vec<KeyCode> {
+    match action {
+        ControlAction::Left => &mut controls.left,
+        ControlAction::Right => &mut controls.right,
+        ControlAction::Up => &mut controls.up,
+        ControlAction::Down => &mut controls.down,
+        ControlAction::Lift => &mut controls.lift,
+        ControlAction::Swap => &mut controls.swap,
+    }
+}
+
+fn row_keys(key_bindings: &mut KeyBindings, (layout, action): ControlRow) -> &mut Vec<KeyCode> {
+    action_keys(layout_controls(key_bindings, layout), action)
+}
+
+fn bound_keys(key_bindings: &KeyBindings, (layout, action): ControlRow) -> &[KeyCode] {
+    let controls = match layout {
+        ControlLayout::Solo => &key_bindings.solo,
+        ControlLayout::PlayerOne => &key_bindings.player_one,
+        ControlLayout::PlayerTwo => &key_bindings.player_two,
+    };
+    match action {
+        ControlAction::Left => &controls.left,
+        ControlAction::Right => &controls.right,
+        ControlAction::Up => &controls.up,
+        ControlAction::Down => &controls.down,
+        ControlAction::Lift => &controls.lift,
+        ControlAction::Swap => &controls.swap,
+    }
+}
+
+// Binds `key` to the row alone. Another action of a layout played alongside that
+// used the key takes over the row's old keys instead, and is returned.
+fn rebind(key_bindings: &mut KeyBindings, row: ControlRow, key: KeyCode) -> Option<ControlRow> {
+    let conflict = control_rows().into_iter().find(|&other| {
+        other != row && row.0.plays_with(other.0) && bound_keys(key_bindings, other).contains(&key)
+    });
+    let old_keys = std::mem::replace(row_keys(key_bindings, row), vec![key]);
+    if let Some(conflict) = conflict {
+        let keys = row_keys(key_bindings, conflict);
+        keys.retain(|bound| *bound != key);
+        keys.extend(old_keys.into_iter().filter(|old| *old != key));
+    }
+    conflict
+}
+
+#[derive(Default)]
+struct ControlsMenu {
+    focus: usize,
+    // the next key press is bound to the focused row
+    waiting: bool,
+    message: String,
+}
+
+struct ControlsMenuUi;
+
+struct ControlRowText(usize);
+
+struct ControlsMessageText;
+
+fn control_row_line(key_bindings: &KeyBindings, menu: &ControlsMenu, index: usize) -> String {
+    let row = control_rows()[index];
+    let keys = if menu.waiting && menu.focus == index {
+        "...".to_string()
+    } else {
+        bound_keys(key_bindings, row)
+            .iter()
+            .map(|key| format!("{:?}", key))
+            .collect::<Vec<_>>()
+            .join(" / ")
+    };
+    let line = format!("{} {}: {}", row.0.label(), row.1.label(), keys);
+    if menu.focus == index {
+        format!("< {} >", line)
+    } else {
+        line
+    }
+}
+
+fn setup_controls_menu(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    key_bindings: Res<KeyBindings>,
+    mut menu: ResMut<ControlsMenu>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    *menu = ControlsMenu::default();
+    let text_section = |value: String, font_size: f32| TextSection {
+        value,
+        style: TextStyle {
+            font: font_assets.font.clone(),
+            font_size,
+            color: Color::rgb(1.0, 1.0, 1.0),
+        },
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .insert(ControlsMenuUi)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section("Controls".to_string(), 50.0)],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            for index in 0..control_rows().len() {
+                parent
+                    .spawn_bundle(TextBundle {
+                        text: Text {
+                            sections: vec![text_section(
+                                control_row_line(&key_bindings, &menu, index),
+                                22.0,
+                            )],
+                            alignment: Default::default(),
+                        },
+                        ..Default::default()
+                    })
+                    .insert(ControlRowText(index));
+            }
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text {
+                        sections: vec![text_section(String::new(), 22.0)],
+                        alignment: Default::default(),
+                    },
+                    ..Default::default()
+                })
+                .insert(ControlsMessageText);
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(
+                        "Space: Rebind / Escape: Back".to_string(),
+                        25.0,
+                    )],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+        });
+}
+
+// While waiting, any key but Escape is bound to the focused row; Escape cancels.
+fn handle_controls_input(
+    input: Res<Input<KeyCode>>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut menu: ResMut<ControlsMenu>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let rows = control_rows();
+    if menu.waiting {
+        let key = match input.get_just_pressed().next() {
+            Some(key) => *key,
+            None => return,
+        };
+        menu.waiting = false;
+        if key == KeyCode::Escape {
+            menu.message = String::new();
+            return;
+        }
+        let row = rows[menu.focus];
+        menu.message = match rebind(&mut key_bindings, row, key) {
+            Some((layout, action)) => format!(
+                "{:?} was used by {} {}, swapped",
+                key,
+                layout.label(),
+                action.label()
+            ),
+            None => String::new(),
+        };
+    } else if input.just_pressed(KeyCode::Up) {
+        menu.focus = (menu.focus + rows.len() - 1) % rows.len();
+    } else if input.just_pressed(KeyCode::Down) {
+        menu.focus = (menu.focus + 1) % rows.len();
+    } else if input.just_pressed(KeyCode::Space) {
+        menu.waiting = true;
+        menu.message = "Press a key (Escape: cancel)".to_string();
+    } else if input.just_pressed(KeyCode::Escape) {
+        queue_state_change(state.set(AppState::Menu));
+    }
+}
+
+fn update_controls_menu_text(
+    key_bindings: Res<KeyBindings>,
+    menu: Res<ControlsMenu>,
+    mut row_text: Query<(&mut Text, &ControlRowText), Without<ControlsMessageText>>,
+    mut message_text: Query<&mut Text, With<ControlsMessageText>>,
+) {
+    if !key_bindings.is_changed() && !menu.is_changed() {
+        return;
+    }
+    for (mut text, row_text) in row_text.iter_mut() {
+        text.sections[0].value = control_row_line(&key_bindings, &menu, row_text.0);
+    }
+    for mut text in message_text.iter_mut() {
+        text.sections[0].value = menu.message.clone();
+    }
+}
+
+fn save_key_bindings(key_bindings: Res<KeyBindings>) {
+    persistence::save(KEY_BINDINGS_FILE, &*key_bindings);
+}
+
+fn cleanup_controls_menu(
+    mut commands: Commands,
+    controls_menu_ui: Query<Entity, With<ControlsMenuUi>>,
+) {
+    for entity in controls_menu_ui.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[test]
+fn test_rebind() {
+    let mut key_bindings = KeyBindings::default();
+    assert_eq!(
+        rebind(
+            &mut key_bindings,
+            (ControlLayout::Solo, ControlAction::Swap),
+            KeyCode::Z
+        ),
+        None
+    );
+    assert_eq!(key_bindings.solo.swap, vec![KeyCode::Z]);
+
+    // taking another action's key swaps the two
+    assert_eq!(
+        rebind(
+            &mut key_bindings,
+            (ControlLayout::Solo, ControlAction::Swap),
+            KeyCode::Left
+        ),
+        Some((ControlLayout::Solo, ControlAction::Left))
+    );
+    assert_eq!(key_bindings.solo.swap, vec![KeyCode::Left]);
+    assert_eq!(key_bindings.solo.left, vec![KeyCode::Z]);
+
+    // the two players share a keyboard, solo is never played alongside them
+    assert_eq!(
+        rebind(
+            &mut key_bindings,
+            (ControlLayout::PlayerOne, ControlAction::Swap),
+            KeyCode::Return
+        ),
+        Some((ControlLayout::PlayerTwo, ControlAction::Swap))
+    );
+    assert_eq!(key_bindings.player_two.swap, vec![KeyCode::F]);
+    assert_eq!(
+        rebind(
+            &mut key_bindings,
+            (ControlLayout::PlayerOne, ControlAction::Left),
+            KeyCode::Z
+        ),
+        None
+    );
+}
+
+#[test]
+fn test_handle_controls_input() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(handle_controls_input.system());
+    world.insert_resource(State::new(AppState::Controls));
+    world.insert_resource(KeyBindings::default());
+    world.insert_resource(ControlsMenu::default());
+
+    // move up from the first row to the last, player two's swap
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Up);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Up);
+    input.update();
+    input.press(KeyCode::Space);
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<ControlsMenu>().unwrap().waiting);
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Space);
+    input.update();
+    input.press(KeyCode::L);
+    update_stage.run(&mut world);
+    assert!(!world.get_resource::<ControlsMenu>().unwrap().waiting);
+    assert_eq!(
+        world.get_resource::<KeyBindings>().unwrap().player_two.swap,
+        vec![KeyCode::L]
+    );
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::L);
+    input.update();
+    input.press(KeyCode::Escape);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::Menu
+    );
+}
@@ -0,0 +1,547 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    ingame::{BoardConfig, ColorTable, GameSeed, GameSpeed},
+    loading::FontAssets,
+    queue_state_change,
+    settings::{on_off, ColorCount, Difficulty, FallStyle, GameMode, GameSettings, StartLayout},
+    AppState,
+};
+
+pub struct CustomGamePlugin;
+
+impl Plugin for CustomGamePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<CustomGameConfig>()
+            .init_resource::<CustomMenuFocus>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::CustomGame).with_system(setup_custom_menu.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::CustomGame)
+                    .with_system(navigate_custom_menu.system().label("navigate_custom_menu"))
+                    .with_system(
+                        adjust_custom_setting
+                            .system()
+                            .label("adjust_custom_setting")
+                            .after("navigate_custom_menu"),
+                    )
+                    .with_system(
+                        update_custom_menu_text
+                            .system()
+                            .after("adjust_custom_setting"),
+                    )
+                    .with_system(start_custom_game.system())
+                    .with_system(leave_custom_menu.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::CustomGame).with_system(cleanup_custom_menu.system()),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CustomSetting {
+    Width,
+    Height,
+    ColumnGuides,
+    GameOverLine,
+    Colors,
+    Speed,
+    FallStyle,
+    TopOutGrace,
+    InitialRows,
+    StartLayout,
+    Seed,
+}
+
+const CUSTOM_SETTINGS: [CustomSetting; 11] = [
+    CustomSetting::Width,
+    CustomSetting::Height,
+    CustomSetting::ColumnGuides,
+    CustomSetting::GameOverLine,
+    CustomSetting::Colors,
+    CustomSetting::Speed,
+    CustomSetting::FallStyle,
+    CustomSetting::TopOutGrace,
+    CustomSetting::InitialRows,
+    CustomSetting::StartLayout,
+    CustomSetting::Seed,
+];
+
+// Kept between visits so the player can tweak the last custom game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CustomGameConfig {
+    pub width: usize,
+    pub height: usize,
+    pub column_guides: bool,
+    pub game_over_line: bool,
+    pub color_count: usize,
+    pub speed: f32,
+    pub fall_style: FallStyle,
+    // None waits one lift delay, see `GameSettings::top_out_grace`
+    pub top_out_grace: Option<f32>,
+    pub initial_rows: usize,
+    // a `Flat` start gets its row count from `initial_rows` when the game starts
+    pub start_layout: StartLayout,
+    // None draws a fresh seed every game
+    pub seed: Option<u64>,
+}
+
+impl Default for CustomGameConfig {
+    fn default() -> Self {
+        let board_config = BoardConfig::default();
+        let settings = GameSettings::default();
+        Self {
+            width: board_config.width,
+            height: board_config.height,
+            column_guides: settings.column_guides,
+            game_over_line: settings.game_over_line,
+            color_count: ColorCount::default().0,
+            speed: GameSpeed::default().origin,
+            fall_style: settings.fall_style,
+            top_out_grace: settings.top_out_grace,
+            initial_rows: board_config.initial_rows,
+            start_layout: StartLayout::Pattern,
+            seed: None,
+        }
+    }
+}
+
+impl CustomGameConfig {
+    fn adjust(&mut self, setting: CustomSetting, step: i32) {
+        let shift = |value: usize, min: usize, max: usize| {
+            (value as i32 + step).clamp(min as i32, max as i32) as usize
+        };
+        match setting {
+            CustomSetting::Width => self.width = shift(self.width, 4, 10),
+            CustomSetting::Height => self.height = shift(self.height, 10, 16),
+            // right turns it on, left off
+            CustomSetting::ColumnGuides => self.column_guides = step > 0,
+            CustomSetting::GameOverLine => self.game_over_line = step > 0,
+            CustomSetting::Colors => self.color_count = shift(self.color_count, 4, 8),
+            CustomSetting::Speed => self.speed = (self.speed + 5.0 * step as f32).clamp(5.0, 30.0),
+            CustomSetting::FallStyle => {
+                self.fall_style = if step > 0 {
+                    FallStyle::Instant
+                } else {
+                    FallStyle::Animated
+                }
+            }
+            // half seconds up to 5, with Auto below 0
+            CustomSetting::TopOutGrace => {
+                self.top_out_grace = match self.top_out_grace.unwrap_or(-0.5) + 0.5 * step as f32 {
+                    grace if grace < 0.0 => None,
+                    grace => Some(grace.min(5.0)),
+                }
+            }
+            CustomSetting::InitialRows => {
+                self.initial_rows = shift(self.initial_rows, 0, self.max_initial_rows())
+            }
+            CustomSetting::StartLayout => {
+                let index = match self.start_layout {
+                    StartLayout::Pattern => 0,
+                    StartLayout::Empty => 1,
+                    StartLayout::Flat(_) => 2,
+                };
+                self.start_layout = [
+                    StartLayout::Pattern,
+                    StartLayout::Empty,
+                    StartLayout::Flat(0),
+                ][shift(index, 0, 2)];
+            }
+            CustomSetting::Seed => {
+                self.seed = match self.seed.unwrap_or(0) as i64 + step as i64 {
+                    seed if seed <= 0 => None,
+                    seed => Some(seed as u64),
+                }
+            }
+        }
+        // a shorter board or a switch back to the pattern may lower the limit
+        self.initial_rows = self.initial_rows.min(self.max_initial_rows());
+    }
+
+    // The pattern has 7 rows; a flat start can stack up to just below the danger row.
+    fn max_initial_rows(&self) -> usize {
+        match self.start_layout {
+            StartLayout::Flat(_) => BoardConfig {
+                height: self.height,
+                ..Default::default()
+            }
+            .max_start_rows(),
+            _ => 7,
+        }
+    }
+
+    fn label(&self, setting: CustomSetting) -> String {
+        match setting {
+            CustomSetting::Width => format!("Width: {}", self.width),
+            CustomSetting::Height => format!("Height: {}", self.height),
+            CustomSetting::ColumnGuides => format!("Guides: {}", on_off(self.column_guides)),
+            CustomSetting::GameOverLine => {
+                format!("Game over line: {}", on_off(self.game_over_line))
+            }
+            CustomSetting::Colors => format!("Colors: {}", self.color_count),
+            CustomSetting::Speed => format!("Speed: {}", self.speed),
+            CustomSetting::FallStyle => format!("Fall: {:?}", self.fall_style),
+            CustomSetting::TopOutGrace => match self.top_out_grace {
+                Some(grace) => format!("Top out grace: {:.1}s", grace),
+                None => "Top out grace: Auto".to_string(),
+            },
+            CustomSetting::InitialRows => format!("Initial rows: {}", self.initial_rows),
+            CustomSetting::StartLayout => match self.start_layout {
+                StartLayout::Pattern => "Start: Pattern".to_string(),
+                StartLayout::Empty => "Start: Empty".to_string(),
+                StartLayout::Flat(_) => "Start: Flat".to_string(),
+            },
+            CustomSetting::Seed => match self.seed {
+                Some(seed) => format!("Seed: {}", seed),
+                None => "Seed: Random".to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct CustomMenuFocus(usize);
+
+struct CustomMenuUi;
+
+struct CustomSettingText(CustomSetting);
+
+fn custom_menu_line(
+    config: &CustomGameConfig,
+    focus: &CustomMenuFocus,
+    setting: CustomSetting,
+) -> String {
+    if CUSTOM_SETTINGS[focus.0] == setting {
+        format!("< {} >", config.label(setting))
+    } else {
+        config.label(setting)
+    }
+}
+
+fn setup_custom_menu(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    config: Res<CustomGameConfig>,
+    mut focus: ResMut<CustomMenuFocus>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    focus.0 = 0;
+    let text_section = |value: String, font_size: f32| TextSection {
+        value,
+        style: TextStyle {
+            font: font_assets.font.clone(),
+            font_size,
+            color: Color::rgb(1.0, 1.0, 1.0),
+        },
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .insert(CustomMenuUi)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section("Custom Game".to_string(), 50.0)],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            for setting in CUSTOM_SETTINGS {
+                parent
+                    .spawn_bundle(TextBundle {
+                        text: Text {
+                            sections: vec![text_section(
+                                custom_menu_line(&config, &focus, setting),
+                                30.0,
+                            )],
+                            alignment: Default::default(),
+                        },
+                        ..Default::default()
+                    })
+                    .insert(CustomSettingText(setting));
+            }
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(
+                        "Space: Start / Escape: Back".to_string(),
+                        25.0,
+                    )],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+        });
+}
+
+fn navigate_custom_menu(input: Res<Input<KeyCode>>, mut focus: ResMut<CustomMenuFocus>) {
+    if input.just_pressed(KeyCode::Up) {
+        focus.0 = (focus.0 + CUSTOM_SETTINGS.len() - 1) % CUSTOM_SETTINGS.len();
+    } else if input.just_pressed(KeyCode::Down) {
+        focus.0 = (focus.0 + 1) % CUSTOM_SETTINGS.len();
+    }
+}
+
+fn adjust_custom_setting(
+    input: Res<Input<KeyCode>>,
+    focus: Res<CustomMenuFocus>,
+    mut config: ResMut<CustomGameConfig>,
+) {
+    if input.just_pressed(KeyCode::Left) {
+        config.adjust(CUSTOM_SETTINGS[focus.0], -1);
+    } else if input.just_pressed(KeyCode::Right) {
+        config.adjust(CUSTOM_SETTINGS[focus.0], 1);
+    }
+}
+
+fn update_custom_menu_text(
+    config: Res<CustomGameConfig>,
+    focus: Res<CustomMenuFocus>,
+    mut setting_text: Query<(&mut Text, &CustomSettingText)>,
+) {
+    if !config.is_changed() && !focus.is_changed() {
+        return;
+    }
+    for (mut text, setting_text) in setting_text.iter_mut() {
+        text.sections[0].value = custom_menu_line(&config, &focus, setting_text.0);
+    }
+}
+
+// The resources a custom game overrides.
+#[derive(SystemParam)]
+pub struct CustomGameTargets<'a> {
+    board_config: ResMut<'a, BoardConfig>,
+    color_count: ResMut<'a, ColorCount>,
+    color_table: ResMut<'a, ColorTable>,
+    game_speed: ResMut<'a, GameSpeed>,
+    difficulty: ResMut<'a, Difficulty>,
+    game_mode: ResMut<'a, GameMode>,
+    settings: ResMut<'a, GameSettings>,
+}
+
+impl CustomGameTargets<'_> {
+    fn apply(&mut self, config: &CustomGameConfig) {
+        self.board_config.width = config.width;
+        self.board_config.height = config.height;
+        self.board_config.initial_rows = config.initial_rows;
+        self.settings.column_guides = config.column_guides;
+        self.settings.game_over_line = config.game_over_line;
+        self.settings.fall_style = config.fall_style;
+        self.settings.top_out_grace = config.top_out_grace;
+        self.settings.start_layout = match config.start_layout {
+            StartLayout::Flat(_) => StartLayout::Flat(config.initial_rows as u8),
+            start_layout => start_layout,
+        };
+        self.color_count.0 = config.color_count;
+        // colors past the six drawn ones get generated tints
+        *self.color_table = ColorTable::extended(config.color_count);
+        self.game_speed.origin = config.speed;
+        // the speed set here is played as is
+        *self.difficulty = Difficulty::Normal;
+        *self.game_mode = GameMode::Endless;
+    }
+}
+
+fn start_custom_game(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    config: Res<CustomGameConfig>,
+    mut targets: CustomGameTargets,
+    mut state: ResMut<State<AppState>>,
+) {
+    if !input.just_pressed(KeyCode::Space) || !queue_state_change(state.set(AppState::InGame)) {
+        return;
+    }
+    targets.apply(&config);
+    match config.seed {
+        Some(seed) => commands.insert_resource(GameSeed(seed)),
+        None => commands.remove_resource::<GameSeed>(),
+    }
+}
+
+fn leave_custom_menu(input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if input.just_pressed(KeyCode::Escape) {
+        queue_state_change(state.set(AppState::Menu));
+    }
+}
+
+fn cleanup_custom_menu(mut commands: Commands, custom_menu_ui: Query<Entity, With<CustomMenuUi>>) {
+    for entity in custom_menu_ui.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[test]
+fn test_adjust_custom_setting() {
+    let mut config = CustomGameConfig::default();
+    config.adjust(CustomSetting::Width, 1);
+    assert_eq!(config.width, 7);
+    config.adjust(CustomSetting::Colors, 5);
+    assert_eq!(config.color_count, 8);
+    config.adjust(CustomSetting::ColumnGuides, -1);
+    assert_eq!(config.label(CustomSetting::ColumnGuides), "Guides: Off");
+    config.adjust(CustomSetting::ColumnGuides, 1);
+    assert!(config.column_guides);
+    config.adjust(CustomSetting::GameOverLine, -1);
+    assert_eq!(
+        config.label(CustomSetting::GameOverLine),
+        "Game over line: Off"
+    );
+    config.adjust(CustomSetting::FallStyle, 1);
+    assert_eq!(config.label(CustomSetting::FallStyle), "Fall: Instant");
+    config.adjust(CustomSetting::TopOutGrace, 1);
+    assert_eq!(config.top_out_grace, Some(0.0));
+    config.adjust(CustomSetting::TopOutGrace, 20);
+    assert_eq!(
+        config.label(CustomSetting::TopOutGrace),
+        "Top out grace: 5.0s"
+    );
+    config.adjust(CustomSetting::TopOutGrace, -20);
+    assert_eq!(
+        config.label(CustomSetting::TopOutGrace),
+        "Top out grace: Auto"
+    );
+    config.adjust(CustomSetting::Seed, 1);
+    assert_eq!(config.seed, Some(1));
+    config.adjust(CustomSetting::Seed, -1);
+    assert_eq!(config.seed, None);
+    assert_eq!(config.label(CustomSetting::Seed), "Seed: Random");
+    config.adjust(CustomSetting::StartLayout, 1);
+    assert_eq!(config.label(CustomSetting::StartLayout), "Start: Empty");
+    config.adjust(CustomSetting::StartLayout, 5);
+    assert_eq!(config.start_layout, StartLayout::Flat(0));
+    config.adjust(CustomSetting::InitialRows, 10);
+    assert_eq!(config.initial_rows, 10);
+    config.adjust(CustomSetting::Height, -5);
+    assert_eq!(config.initial_rows, 7);
+    config.adjust(CustomSetting::StartLayout, -5);
+    assert_eq!(config.start_layout, StartLayout::Pattern);
+    config.adjust(CustomSetting::InitialRows, 5);
+    assert_eq!(config.initial_rows, 7);
+}
+
+#[test]
+fn test_start_custom_game() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(navigate_custom_menu.system().label("navigate_custom_menu"));
+    update_stage.add_system(
+        adjust_custom_setting
+            .system()
+            .label("adjust_custom_setting")
+            .after("navigate_custom_menu"),
+    );
+    update_stage.add_system(start_custom_game.system().after("adjust_custom_setting"));
+    world.insert_resource(State::new(AppState::CustomGame));
+    world.insert_resource(CustomGameConfig::default());
+    world.insert_resource(CustomMenuFocus::default());
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(ColorCount::default());
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(GameSpeed::default());
+    world.insert_resource(Difficulty::Hard);
+    world.insert_resource(GameMode::Puzzle);
+    world.insert_resource(GameSettings::default());
+
+    // focus starts on the width
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Right);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<CustomGameConfig>().unwrap().width, 7);
+    assert_eq!(world.get_resource::<BoardConfig>().unwrap().width, 6);
+
+    // move to the seed and pick one, then the flat start above it
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Right);
+    input.update();
+    input.press(KeyCode::Up);
+    update_stage.run(&mut world);
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Up);
+    input.update();
+    input.press(KeyCode::Right);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<CustomGameConfig>().unwrap().seed,
+        Some(1)
+    );
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Right);
+    input.update();
+    input.press(KeyCode::Up);
+    update_stage.run(&mut world);
+    for _ in 0..2 {
+        let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+        input.release(KeyCode::Up);
+        input.release(KeyCode::Right);
+        input.update();
+        input.press(KeyCode::Right);
+        update_stage.run(&mut world);
+    }
+    assert_eq!(
+        world
+            .get_resource::<CustomGameConfig>()
+            .unwrap()
+            .start_layout,
+        StartLayout::Flat(0)
+    );
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Right);
+    input.update();
+    input.press(KeyCode::Space);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<BoardConfig>().unwrap().width, 7);
+    assert_eq!(world.get_resource::<GameSeed>().unwrap().0, 1);
+    assert_eq!(
+        world.get_resource::<GameSettings>().unwrap().start_layout,
+        StartLayout::Flat(7)
+    );
+    assert_eq!(
+        *world.get_resource::<Difficulty>().unwrap(),
+        Difficulty::Normal
+    );
+    assert_eq!(
+        *world.get_resource::<GameMode>().unwrap(),
+        GameMode::Endless
+    );
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+}
+
+#[test]
+fn test_leave_custom_menu() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(leave_custom_menu.system());
+    world.insert_resource(State::new(AppState::CustomGame));
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Escape);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::Menu
+    );
+}
@@ -0,0 +1,240 @@
+use bevy::{
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    ecs::schedule::ShouldRun,
+    prelude::*,
+};
+
+use crate::{loading::FontAssets, settings::GameSettings};
+
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(DevMode(cfg!(debug_assertions)))
+            .init_resource::<StepMode>()
+            .init_resource::<CursorDebug>()
+            .add_plugin(FrameTimeDiagnosticsPlugin)
+            .add_system(toggle_step_mode.system().label("toggle_step_mode"))
+            .add_system(request_step.system().after("toggle_step_mode"))
+            .add_system(toggle_cursor_debug.system())
+            .add_system(toggle_fps_overlay.system().label("toggle_fps_overlay"))
+            .add_system(sync_diagnostics_text.system().after("toggle_fps_overlay"));
+    }
+}
+
+pub struct DevMode(pub bool);
+
+// While `enabled`, gameplay only advances one fixed step per step request.
+#[derive(Debug, Default)]
+pub struct StepMode {
+    pub enabled: bool,
+    pub step_requested: bool,
+}
+
+// Shows the cursor's grid cell and the colors under it, for laying out puzzles.
+#[derive(Debug, Default)]
+pub struct CursorDebug(pub bool);
+
+fn toggle_step_mode(
+    keyboard_input: Res<Input<KeyCode>>,
+    dev_mode: Res<DevMode>,
+    mut step_mode: ResMut<StepMode>,
+) {
+    if dev_mode.0 && keyboard_input.just_pressed(KeyCode::F9) {
+        step_mode.enabled = !step_mode.enabled;
+        step_mode.step_requested = false;
+    }
+}
+
+fn request_step(
+    keyboard_input: Res<Input<KeyCode>>,
+    dev_mode: Res<DevMode>,
+    mut step_mode: ResMut<StepMode>,
+) {
+    if dev_mode.0 && step_mode.enabled && keyboard_input.just_pressed(KeyCode::F10) {
+        step_mode.step_requested = true;
+    }
+}
+
+fn toggle_cursor_debug(
+    keyboard_input: Res<Input<KeyCode>>,
+    dev_mode: Res<DevMode>,
+    mut cursor_debug: ResMut<CursorDebug>,
+) {
+    if dev_mode.0 && keyboard_input.just_pressed(KeyCode::F4) {
+        cursor_debug.0 = !cursor_debug.0;
+    }
+}
+
+// Not tied to a state and not part of the HUD, so it stays up in menus too.
+struct DiagnosticsText;
+
+// Works in release builds as well, unlike the dev mode keys.
+fn toggle_fps_overlay(keyboard_input: Res<Input<KeyCode>>, mut settings: ResMut<GameSettings>) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        settings.fps_overlay = !settings.fps_overlay;
+    }
+}
+
+// `fps` is the smoothed value from the diagnostics store, missing for the first frames.
+fn fps_label(fps: Option<f64>) -> String {
+    match fps {
+        Some(fps) => format!("FPS: {:.0}", fps),
+        None => "FPS: --".to_string(),
+    }
+}
+
+// Waits for the font to load before spawning the text.
+fn sync_diagnostics_text(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    font_assets: Option<Res<FontAssets>>,
+    diagnostics: Res<Diagnostics>,
+    mut diagnostics_text: Query<(Entity, &mut Text), With<DiagnosticsText>>,
+) {
+    if !settings.fps_overlay {
+        for (entity, _) in diagnostics_text.iter_mut() {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
+    let label = fps_label(
+        diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FPS)
+            .and_then(|fps| fps.average()),
+    );
+    let mut spawned = false;
+    for (_, mut text) in diagnostics_text.iter_mut() {
+        text.sections[0].value = label.clone();
+        spawned = true;
+    }
+    let font_assets = match font_assets {
+        Some(font_assets) if !spawned => font_assets,
+        _ => return,
+    };
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.0),
+                    left: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: label,
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 20.0,
+                        color: Color::rgb(1.0, 1.0, 0.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(DiagnosticsText);
+}
+
+// piped after the InGame state criteria, so it only narrows when gameplay runs.
+pub fn step_mode_criteria(
+    In(input): In<ShouldRun>,
+    dev_mode: Res<DevMode>,
+    mut step_mode: ResMut<StepMode>,
+) -> ShouldRun {
+    if !dev_mode.0 || !step_mode.enabled {
+        return input;
+    }
+    match input {
+        ShouldRun::Yes | ShouldRun::YesAndCheckAgain if step_mode.step_requested => {
+            step_mode.step_requested = false;
+            input
+        }
+        ShouldRun::Yes => ShouldRun::No,
+        ShouldRun::YesAndCheckAgain => ShouldRun::NoAndCheckAgain,
+        _ => input,
+    }
+}
+
+#[test]
+fn test_step_mode_criteria() {
+    #[derive(Default)]
+    struct StepCounter(u32);
+    fn count_step(mut counter: ResMut<StepCounter>) {
+        counter.0 += 1;
+    }
+
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_run_criteria((|| ShouldRun::Yes).system().label("always"));
+    update_stage.add_system_set(
+        SystemSet::new()
+            .with_run_criteria(RunCriteria::pipe("always", step_mode_criteria.system()))
+            .with_system(count_step.system()),
+    );
+    world.insert_resource(DevMode(true));
+    world.insert_resource(StepMode::default());
+    world.insert_resource(StepCounter::default());
+
+    // step mode disabled -> run every update
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<StepCounter>().unwrap().0, 1);
+
+    world.get_resource_mut::<StepMode>().unwrap().enabled = true;
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<StepCounter>().unwrap().0, 1);
+
+    world.get_resource_mut::<StepMode>().unwrap().step_requested = true;
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<StepCounter>().unwrap().0, 2);
+    assert!(!world.get_resource::<StepMode>().unwrap().step_requested);
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<StepCounter>().unwrap().0, 2);
+
+    // without dev mode, step mode is ignored
+    world.get_resource_mut::<DevMode>().unwrap().0 = false;
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<StepCounter>().unwrap().0, 3);
+}
+
+#[test]
+fn test_fps_label() {
+    assert_eq!(fps_label(Some(59.6)), "FPS: 60");
+    assert_eq!(fps_label(None), "FPS: --");
+}
+
+#[test]
+fn test_sync_diagnostics_text() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(toggle_fps_overlay.system().label("toggle_fps_overlay"));
+    update_stage.add_system(sync_diagnostics_text.system().after("toggle_fps_overlay"));
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(Diagnostics::default());
+    world.insert_resource(FontAssets {
+        font: Handle::default(),
+    });
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::F3);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<GameSettings>().unwrap().fps_overlay);
+    assert_eq!(world.query::<&DiagnosticsText>().iter(&world).count(), 1);
+
+    // only one text while it stays on
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<&DiagnosticsText>().iter(&world).count(), 1);
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::F3);
+    input.press(KeyCode::F3);
+    update_stage.run(&mut world);
+    assert!(!world.get_resource::<GameSettings>().unwrap().fps_overlay);
+    assert_eq!(world.query::<&DiagnosticsText>().iter(&world).count(), 0);
+}
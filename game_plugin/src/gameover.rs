@@ -0,0 +1,197 @@
+use crate::{
+    hud::format_mmss,
+    ingame::{
+        ClearStats, MaxChain, PlayTime, Player, PuzzleOutcome, Score, Winner, SCORE_ATTACK_SECONDS,
+    },
+    loading::{FontAssets, HIGH_SCORES_FILE},
+    persistence, queue_state_change,
+    settings::GameMode,
+    stats::{is_new_high_score, HighScores},
+    AppState,
+};
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::GameOver)
+                .with_system(setup_gameover.system().label("setup_gameover"))
+                .with_system(record_high_score.system().after("setup_gameover")),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::GameOver).with_system(select_gameover_action.system()),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::GameOver).with_system(cleanup_gameover.system()),
+        );
+    }
+}
+
+struct GameOverUi;
+
+// How the game that just ended went.
+#[derive(SystemParam)]
+pub struct GameResult<'a> {
+    game_mode: Res<'a, GameMode>,
+    score: Res<'a, Score>,
+    play_time: Res<'a, PlayTime>,
+    max_chain: Res<'a, MaxChain>,
+    clear_stats: Res<'a, ClearStats>,
+    puzzle_outcome: Option<Res<'a, PuzzleOutcome>>,
+    winner: Option<Res<'a, Winner>>,
+}
+
+impl GameResult<'_> {
+    fn title(&self) -> &'static str {
+        let game_mode = *self.game_mode;
+        match (self.puzzle_outcome.as_deref(), self.winner.as_deref()) {
+            (Some(PuzzleOutcome::Cleared), _) if game_mode == GameMode::Sandbox => "Cleared!",
+            (Some(PuzzleOutcome::Cleared), _) => "Puzzle Cleared!",
+            (Some(PuzzleOutcome::OutOfMoves), _) => "Out of Moves",
+            (None, Some(Winner(Player::One))) if game_mode == GameMode::VsCom => "You Win!",
+            (None, Some(Winner(Player::One))) => "Player 1 Wins!",
+            (None, Some(Winner(Player::Two))) => "Player 2 Wins!",
+            (None, Some(Winner(Player::Com))) => "COM Wins!",
+            (None, None)
+                if game_mode == GameMode::ScoreAttack
+                    && self.play_time.0 >= SCORE_ATTACK_SECONDS =>
+            {
+                "Time Up!"
+            }
+            (None, None) => "Game Over",
+        }
+    }
+}
+
+fn setup_gameover(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    result: GameResult,
+    high_scores: Res<HighScores>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let title = result.title();
+    let GameResult {
+        game_mode,
+        score,
+        play_time,
+        max_chain,
+        clear_stats,
+        ..
+    } = result;
+    let text_section = |value: String, font_size: f32| TextSection {
+        value,
+        style: TextStyle {
+            font: font_assets.font.clone(),
+            font_size,
+            color: Color::rgb(1.0, 1.0, 1.0),
+        },
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.5).into()),
+            ..Default::default()
+        })
+        .insert(GameOverUi)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(title.to_string(), 60.0)],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(format!("Score: {}", score.0), 40.0)],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(
+                        format!("Time: {}", format_mmss(play_time.0)),
+                        40.0,
+                    )],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(format!("Max Chain: {}", max_chain.0), 40.0)],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(
+                        format!("Blocks Cleared: {}", clear_stats.total),
+                        40.0,
+                    )],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section(clear_stats.breakdown(), 30.0)],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+            // compared before `record_high_score` stores this run
+            if is_new_high_score(score.0, high_scores.best(*game_mode)) {
+                parent.spawn_bundle(TextBundle {
+                    text: Text {
+                        sections: vec![text_section("New High Score!".to_string(), 40.0)],
+                        alignment: Default::default(),
+                    },
+                    ..Default::default()
+                });
+            }
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: vec![text_section("Return: Menu / R: Retry".to_string(), 30.0)],
+                    alignment: Default::default(),
+                },
+                ..Default::default()
+            });
+        });
+}
+
+fn select_gameover_action(input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if input.just_pressed(KeyCode::Return) {
+        queue_state_change(state.set(AppState::Menu));
+    } else if input.just_pressed(KeyCode::R) {
+        queue_state_change(state.set(AppState::InGame));
+    }
+}
+
+fn record_high_score(
+    score: Res<Score>,
+    game_mode: Res<GameMode>,
+    mut high_scores: ResMut<HighScores>,
+) {
+    if is_new_high_score(score.0, high_scores.best(*game_mode)) {
+        high_scores.scores.insert(*game_mode, score.0);
+        persistence::save(HIGH_SCORES_FILE, &*high_scores);
+    }
+}
+
+fn cleanup_gameover(mut commands: Commands, gameover_ui: Query<Entity, With<GameOverUi>>) {
+    for entity in gameover_ui.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+
+use crate::{
+    ingame::{PlayTime, Score, SCORE_ATTACK_SECONDS},
+    loading::FontAssets,
+    settings::GameMode,
+    stats::{efficiency, PlayerStats},
+    AppState,
+};
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(HudVisible(true))
+            .add_system(toggle_hud.system().label("toggle_hud"))
+            // after every owner has had its say for the frame
+            .add_system_to_stage(CoreStage::PostUpdate, apply_hud_visibility.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame)
+                    .with_system(setup_efficiency_text.system())
+                    .with_system(setup_time_text.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(update_efficiency_text.system())
+                    .with_system(update_time_text.system()),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(cleanup_hud.system()));
+    }
+}
+
+// Put on score, chain, timer and other overlays that F6 hides for clean screenshots.
+// Owners that show and hide an element themselves set `shown` instead of its `Visible`.
+pub struct HudElement {
+    pub shown: bool,
+}
+
+impl Default for HudElement {
+    fn default() -> Self {
+        Self { shown: true }
+    }
+}
+
+pub struct HudVisible(pub bool);
+
+fn toggle_hud(keyboard_input: Res<Input<KeyCode>>, mut hud_visible: ResMut<HudVisible>) {
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        hud_visible.0 = !hud_visible.0;
+    }
+}
+
+// Also covers HUD elements spawned while the HUD is hidden.
+fn apply_hud_visibility(
+    hud_visible: Res<HudVisible>,
+    mut hud_element: Query<(&HudElement, &mut Visible)>,
+) {
+    for (hud_element, mut visible) in hud_element.iter_mut() {
+        let is_visible = hud_element.shown && hud_visible.0;
+        if visible.is_visible != is_visible {
+            visible.is_visible = is_visible;
+        }
+    }
+}
+
+struct EfficiencyText;
+
+fn setup_efficiency_text(mut commands: Commands, font_assets: Res<FontAssets>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(20.0),
+                    right: Val::Px(20.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: String::new(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(EfficiencyText)
+        .insert(HudElement::default());
+}
+
+fn update_efficiency_text(
+    score: Res<Score>,
+    play_time: Res<PlayTime>,
+    player_stats: Res<PlayerStats>,
+    mut efficiency_text: Query<&mut Text, With<EfficiencyText>>,
+) {
+    for mut text in efficiency_text.iter_mut() {
+        text.sections[0].value = format!(
+            "Score/s {:.1} (best {:.1})",
+            efficiency(score.0, play_time.0),
+            player_stats.best_efficiency
+        );
+    }
+}
+
+struct TimeText;
+
+// Whole minutes and seconds; runs past an hour keep counting minutes.
+pub fn format_mmss(secs: f32) -> String {
+    let secs = secs.max(0.0) as u32;
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+fn setup_time_text(mut commands: Commands, font_assets: Res<FontAssets>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(60.0),
+                    right: Val::Px(20.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: format_mmss(0.0),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(TimeText)
+        .insert(HudElement::default());
+}
+
+// `PlayTime` only ticks during gameplay, so the clock holds while paused and at game over.
+// Score attack counts down the time left instead.
+fn update_time_text(
+    play_time: Res<PlayTime>,
+    game_mode: Res<GameMode>,
+    mut time_text: Query<&mut Text, With<TimeText>>,
+) {
+    let secs = match *game_mode {
+        GameMode::ScoreAttack => (SCORE_ATTACK_SECONDS - play_time.0).ceil(),
+        _ => play_time.0,
+    };
+    for mut text in time_text.iter_mut() {
+        text.sections[0].value = format_mmss(secs);
+    }
+}
+
+// The overlays on the boards go with their board.
+fn cleanup_hud(
+    mut commands: Commands,
+    hud_text: Query<Entity, Or<(With<EfficiencyText>, With<TimeText>)>>,
+) {
+    for entity in hud_text.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[test]
+fn test_toggle_hud() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(toggle_hud.system().label("toggle_hud"));
+    update_stage.add_system(apply_hud_visibility.system().after("toggle_hud"));
+    world.insert_resource(HudVisible(true));
+    let hud_element = world
+        .spawn()
+        .insert(HudElement::default())
+        .insert(Visible::default())
+        .id();
+    // hidden by its owner, like a chain text between chains
+    let hidden_element = world
+        .spawn()
+        .insert(HudElement { shown: false })
+        .insert(Visible::default())
+        .id();
+    let other = world.spawn().insert(Visible::default()).id();
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::F6);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    assert!(!world.get::<Visible>(hud_element).unwrap().is_visible);
+    assert!(!world.get::<Visible>(hidden_element).unwrap().is_visible);
+    assert!(world.get::<Visible>(other).unwrap().is_visible);
+
+    // the owner showing it while the HUD is off doesn't bring it back
+    world.get_mut::<HudElement>(hidden_element).unwrap().shown = true;
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert!(!world.get::<Visible>(hidden_element).unwrap().is_visible);
+    world.get_mut::<HudElement>(hidden_element).unwrap().shown = false;
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::F6);
+    input.update();
+    input.press(KeyCode::F6);
+    update_stage.run(&mut world);
+    assert!(world.get::<Visible>(hud_element).unwrap().is_visible);
+    assert!(!world.get::<Visible>(hidden_element).unwrap().is_visible);
+}
+
+#[test]
+fn test_format_mmss() {
+    assert_eq!(format_mmss(0.0), "00:00");
+    assert_eq!(format_mmss(59.9), "00:59");
+    assert_eq!(format_mmss(61.0), "01:01");
+    assert_eq!(format_mmss(3600.0), "60:00");
+    assert_eq!(format_mmss(-1.0), "00:00");
+}
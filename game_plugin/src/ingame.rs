@@ -1,16 +1,38 @@
-use std::{collections::VecDeque, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    time::Duration,
+};
 
 use bevy::{
+    ecs::{
+        schedule::{RunCriteria, ShouldRun},
+        system::SystemParam,
+    },
     prelude::*,
     sprite::collide_aabb::{collide, Collision},
+    transform::TransformSystem,
+    window::WindowResized,
 };
 use bevy_easings::*;
+use bevy_kira_audio::Audio;
 
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    debug::{step_mode_criteria, CursorDebug, DevMode},
+    hud::HudElement,
     loading::{
-        BlockMaterials, BoardBottomCoverMaterials, BoardMaterials, BottomMaterials, CursorMaterials,
+        BlockMaterials, BoardBottomCoverMaterials, BoardMaterials, BottomMaterials,
+        CursorMaterials, FontAssets, PuzzleAssets, SoundAssets,
+    },
+    puzzle::{parse_puzzle_layout, Puzzle},
+    queue_state_change,
+    settings::{
+        just_pressed, pressed, AccessibilitySettings, AssistMode, AssistSettings, ColorCount,
+        Difficulty, FallStyle, GameMode, GameSettings, InputTuning, KeyBindings, PlayerControls,
+        ReduceFlashing, SpeedRamp, SquareClear, StartLayout, Theme,
     },
     AppState,
 };
@@ -20,24 +42,139 @@ pub struct IngamePlugin;
 impl Plugin for IngamePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_plugin(bevy_easings::EasingsPlugin)
+            .add_event::<BoardSettled>()
+            .add_event::<SwapRejected>()
+            .add_event::<GarbageEvent>()
+            .add_event::<ClearEvent>()
+            .add_event::<BlockCleared>()
+            .init_resource::<BoardConfig>()
+            .init_resource::<TuningConfig>()
+            .init_resource::<ChainWindow>()
+            .init_resource::<FallSpeed>()
+            .init_resource::<BoardSnapshot>()
+            .init_resource::<ColorTable>()
+            .init_resource::<NextRow>()
+            .init_resource::<IncomingGarbage>()
+            .init_resource::<TimeScale>()
+            .init_resource::<StartCountdown>()
+            .init_resource::<GameStarted>()
+            .init_resource::<GameSpeed>()
+            .init_resource::<FixedStep>()
+            .init_resource::<StepInput>()
+            .init_resource::<StepClock>()
+            .insert_resource(MatchPreview(false))
+            .add_system(fit_board_to_window.system())
+            .add_system(apply_theme.system())
+            .add_system_to_stage(CoreStage::PreUpdate, restore_step_translation.system())
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_step_translation
+                    .system()
+                    .before(TransformSystem::TransformPropagate),
+            )
+            .stage(CoreStage::Update, |stage: &mut SystemStage| {
+                stage
+                    .add_system_run_criteria(
+                        State::on_update(AppState::InGame).label("ingame_update"),
+                    )
+                    .add_system_run_criteria(
+                        RunCriteria::pipe("ingame_update", countdown_criteria.system())
+                            .label("started"),
+                    )
+                    .add_system_run_criteria(
+                        RunCriteria::pipe("started", fixed_step_criteria.system())
+                            .label("fixed_step"),
+                    )
+                    .add_system_run_criteria(
+                        RunCriteria::pipe("fixed_step", step_mode_criteria.system())
+                            .label("gameplay"),
+                    )
+                    .add_system_run_criteria(
+                        RunCriteria::pipe("gameplay", endless_mode_criteria.system())
+                            .label("endless_gameplay"),
+                    )
+                    .add_system_run_criteria(
+                        RunCriteria::pipe("gameplay", versus_mode_criteria.system())
+                            .label("versus_gameplay"),
+                    )
+                    .add_system_run_criteria(
+                        RunCriteria::pipe("gameplay", score_attack_criteria.system())
+                            .label("score_attack_gameplay"),
+                    )
+            })
             .add_system_set(
                 SystemSet::on_enter(AppState::InGame)
+                    .with_system(setup_board_rng.exclusive_system().at_start())
                     .with_system(setup_camera.system())
-                    .with_system(setup_board.system())
+                    .with_system(setup_run_resources.system())
+                    .with_system(setup_countdown.system())
+                    .with_system(setup_gamespeed.system().label("setup_gamespeed"))
+                    .with_system(setup_board.system().after("setup_gamespeed"))
+                    .with_system(resume_saved_game.exclusive_system().at_end())
+                    .with_system(setup_puzzle_board.system().after("setup_gamespeed")),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("ingame_update")
                     .with_system(setup_board_bottom_cover.system())
-                    .with_system(setup_chaincounter.system()),
+                    .with_system(setup_board_frame.system())
+                    .with_system(setup_next_row_preview.system())
+                    .with_system(update_next_row_preview.system())
+                    .with_system(update_incoming_garbage_markers.system())
+                    .with_system(setup_chain_text.system())
+                    .with_system(spawn_block_symbols.system())
+                    .with_system(pulse_cursor.system())
+                    .with_system(dump_board.system())
+                    .with_system(sync_cursor_debug_text.system())
+                    .with_system(tick_countdown.system())
+                    .with_system(update_chain_text.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::InGame).with_system(cleanup_ingame.system()),
             )
+            // Once a frame, before the steps of that frame.
             .add_system_set(
-                SystemSet::on_update(AppState::InGame)
+                SystemSet::new()
+                    .with_run_criteria("started")
+                    .with_system(
+                        collect_step_input
+                            .exclusive_system()
+                            .at_start()
+                            .label("collect_step_input"),
+                    )
+                    .with_system(custom_ease_system::<DespawnScale>.system())
+                    .with_system(custom_ease_system::<LandingSquash>.system()),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("gameplay")
+                    .with_system(
+                        step_keyboard_input
+                            .exclusive_system()
+                            .at_start()
+                            .label("step_keyboard_input")
+                            .after("collect_step_input"),
+                    )
+                    .with_system(sync_grid_pos.exclusive_system().at_start())
+                    .with_system(record_step_translation.exclusive_system().at_end()),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("gameplay")
                     .label("move_set")
                     .before("fall_set")
                     .with_system(move_tag_block.system())
-                    .with_system(custom_ease_system::<Moving>.system())
+                    .with_system(ai_swap_blocks.system())
+                    .with_system(preview_cascade.system())
+                    .with_system(fade_preview_highlight.system())
+                    .with_system(slide_swapping_block.system())
                     .with_system(move_block.system().label("move_block"))
+                    .with_system(highlight_swapping_block.system())
                     .with_system(moving_to_fixed.system().after("move_block")),
             )
             .add_system_set(
-                SystemSet::on_update(AppState::InGame)
+                SystemSet::new()
+                    .with_run_criteria("gameplay")
                     .label("fall_set")
                     .after("move_set")
                     .with_system(check_fall_block.system().label("check_fall"))
@@ -70,40 +207,123 @@ impl Plugin for IngamePlugin {
                             .system()
                             .label("fixedprepare_to_fixed")
                             .after("stop_fall_block"),
-                    ),
+                    )
+                    .with_system(squash_landed_block.system().after("fixedprepare_to_fixed")),
             )
             .add_system_set(
-                SystemSet::on_update(AppState::InGame)
+                SystemSet::new()
+                    .with_run_criteria("endless_gameplay")
                     .label("spawning_set")
-                    .with_system(spawning_to_fixed.system())
+                    .with_system(fade_in_spawning_block.system().before("spawning_to_fixed"))
+                    .with_system(spawning_to_fixed.system().label("spawning_to_fixed"))
                     .with_system(bottom_down.system().label("bottom_down"))
                     .with_system(generate_spawning_block.system().before("bottom_down")),
             )
             .add_system_set(
-                SystemSet::on_update(AppState::InGame)
+                SystemSet::new()
+                    .with_run_criteria("gameplay")
                     .after("fall_set")
                     .with_system(move_cursor.system())
+                    .with_system(place_sandbox_block.system())
                     .with_system(match_block.system().label("match_block"))
+                    .with_system(clear_all_blocks.system().after("match_block"))
+                    .with_system(update_board_snapshot.system())
                     .with_system(
                         prepare_despawn_block
                             .system()
                             .label("prepare_despawn_block")
                             .after("match_block"),
                     )
+                    .with_system(score_clear.system().after("prepare_despawn_block"))
+                    .with_system(track_clear_stats.system().after("prepare_despawn_block"))
+                    .with_system(play_clear_sound.system().after("prepare_despawn_block"))
+                    .with_system(start_despawning.system().after("prepare_despawn_block"))
                     .with_system(
                         despawn_block
                             .system()
                             .label("despawn_block")
                             .after("prepare_despawn_block"),
                     )
+                    .with_system(
+                        animate_despawning
+                            .system()
+                            .label("animate_despawning")
+                            .after("despawn_block"),
+                    )
+                    .with_system(
+                        sync_block_symbol_visible
+                            .system()
+                            .after("animate_despawning"),
+                    )
+                    .with_system(outline_despawning_block.system())
+                    .with_system(clear_adjacent_garbage.system().after("despawn_block"))
+                    .with_system(spawn_combo_popup.system().after("prepare_despawn_block"))
+                    .with_system(fade_combo_popup.system())
+                    .with_system(danger_flash.system())
+                    .with_system(check_game_over.system())
+                    .with_system(finish_game_over.system())
+                    .with_system(tick_play_time.system().label("tick_play_time")),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("versus_gameplay")
+                    .after("fall_set")
+                    .with_system(
+                        send_garbage
+                            .system()
+                            .label("send_garbage")
+                            .after("prepare_despawn_block"),
+                    )
+                    .with_system(
+                        queue_garbage
+                            .system()
+                            .label("queue_garbage")
+                            .after("send_garbage"),
+                    )
+                    .with_system(spawn_garbage.system().after("queue_garbage")),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("score_attack_gameplay")
+                    .with_system(end_score_attack.system().after("tick_play_time")),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("endless_gameplay")
+                    .after("fall_set")
+                    .with_system(speed_ramp.system().label("speed_ramp"))
+                    .with_system(
+                        manual_liftup
+                            .system()
+                            .label("manual_liftup")
+                            .after("speed_ramp"),
+                    )
+                    .with_system(auto_liftup.system().after("manual_liftup"))
+                    .with_system(detect_no_moves.system().after("detect_board_settled")),
+            )
+            // Regular systems rather than exclusive ones: a coerced exclusive system never
+            // picks up archetypes created after it was initialized, so it wouldn't see blocks.
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("gameplay")
+                    .after("fall_set")
+                    .with_system(
+                        detect_board_settled
+                            .system()
+                            .label("detect_board_settled")
+                            .after("despawn_block"),
+                    )
                     .with_system(
                         remove_chain
                             .system()
                             .label("remove_chain")
-                            .after("despawn_block"),
+                            .after("detect_board_settled"),
                     )
-                    .with_system(reset_chain_counter.system().after("despawn_block"))
-                    .with_system(auto_liftup.system()),
+                    .with_system(reset_chain_counter.system().after("detect_board_settled"))
+                    .with_system(check_puzzle_result.system().after("detect_board_settled"))
+                    .with_system(check_board_cleared.system().after("detect_board_settled"))
+                    .with_system(clear_swap_hint.system().after("detect_board_settled"))
+                    .with_system(show_swap_hint.system().after("detect_board_settled")),
             );
     }
 }
@@ -111,15 +331,209 @@ impl Plugin for IngamePlugin {
 const BOARD_WIDTH: usize = 6;
 const BOARD_HEIGHT: usize = 13;
 const BLOCK_SIZE: f32 = 50.0;
+const SPAWNING_ROWS: usize = 2;
+const PATTERN_HEIGHT: usize = 7;
+const SWAP_SECONDS: f32 = 0.04;
+// share of the despawn time spent flashing before the block shrinks away
+const DESPAWN_FLASH_PORTION: f32 = 0.7;
+const DESPAWN_FLASH_SECONDS: f32 = 0.08;
+const DANGER_FLASH_SECONDS: f32 = 0.25;
+const MANUAL_LIFT_SPEED: f32 = 100.0;
+// spawning blocks fade in through this many shared materials per color
+const SPAWN_FADE_STEPS: u8 = 8;
+// in front of the blocks, so spawning rows stay hidden below the board
+const BOTTOM_COVER_Z: f32 = 1.0;
+// Most of a block a fall may cover in one update. `stop_fall_block` only sees overlaps,
+// so a longer step could pass through the block below.
+const MAX_FALL_STEP: f32 = BLOCK_SIZE * 0.9;
+// deals tried before a board without a move is left as it is
+const NO_MOVES_SHUFFLE_ATTEMPTS: usize = 50;
+// brightness of the blocks in the next row preview
+const NEXT_ROW_PREVIEW_SHADE: f32 = 0.5;
+// 3, 2, 1 and Go! each show this long; play starts with Go!
+const COUNTDOWN_STEP_SECONDS: f32 = 0.6;
+const CURSOR_PULSE_SECONDS: f32 = 1.2;
+// how far the cursor grows and shrinks around its size
+const CURSOR_PULSE_AMOUNT: f32 = 0.05;
+const LANDING_SQUASH_SECONDS: f32 = 0.1;
+// how much wider and flatter a block gets at the moment it lands
+const LANDING_SQUASH_AMOUNT: f32 = 0.2;
+// how long a board keeps still after its chain ends
+const CHAIN_FREEZE_SECONDS: f32 = 1.0;
+const FIXED_STEP_SECONDS: f32 = 1.0 / 120.0;
+// Frame time past this is dropped, so a stall doesn't come back as a burst of steps.
+const MAX_STEP_BACKLOG_SECONDS: f32 = 0.25;
+
+// Board geometry. The menu may insert its own before entering InGame, e.g. for a
+// wide 8-column board; the defaults give the classic 6x13 board.
+#[derive(Debug, Clone, Copy)]
+pub struct BoardConfig {
+    pub width: usize,
+    pub height: usize,
+    pub block_size: f32,
+    // bottom rows of the start pattern to place, 0 starts with an empty board
+    pub initial_rows: usize,
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        Self {
+            width: BOARD_WIDTH,
+            height: BOARD_HEIGHT,
+            block_size: BLOCK_SIZE,
+            initial_rows: PATTERN_HEIGHT,
+        }
+    }
+}
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum BlockColor {
-    Red,
-    Green,
-    Blue,
-    Yellow,
-    Purple,
-    Indigo,
+impl BoardConfig {
+    fn size(&self) -> Vec2 {
+        Vec2::new(
+            self.width as f32 * self.block_size,
+            self.height as f32 * self.block_size,
+        )
+    }
+
+    // y of the bottom row's center before the stack starts lifting
+    fn first_row_y(&self) -> f32 {
+        (-(self.height as f32) + 1.0) * self.block_size / 2.0
+    }
+
+    // Bottom edge of the grid. The cover hides everything below it and a spawning block
+    // becomes `Fixed` once its own bottom edge rises past it.
+    fn bottom_edge_y(&self) -> f32 {
+        -self.size().y / 2.0
+    }
+
+    // Most rows a `Flat` start fills, keeping the stack below the danger row.
+    pub fn max_start_rows(&self) -> usize {
+        self.height.saturating_sub(3)
+    }
+
+    // resting y of the `Bottom`, one row below the board
+    fn bottom_y(&self) -> f32 {
+        self.first_row_y() - self.block_size
+    }
+
+    // The cursor spans two columns, so it sits on a column boundary.
+    fn cursor_start(&self) -> Vec2 {
+        Vec2::new(
+            ((self.width / 2) as f32 - self.width as f32 / 2.0) * self.block_size,
+            self.first_row_y() + (self.height / 2) as f32 * self.block_size,
+        )
+    }
+
+    fn cursor_min(&self) -> Vec2 {
+        Vec2::new(
+            (1.0 - self.width as f32 / 2.0) * self.block_size,
+            self.first_row_y(),
+        )
+    }
+
+    fn cursor_max(&self) -> Vec2 {
+        Vec2::new(
+            (self.width as f32 / 2.0 - 1.0) * self.block_size,
+            -self.first_row_y(),
+        )
+    }
+
+    // a settled block above the second row from the top ends the game
+    fn game_over_y(&self) -> f32 {
+        -self.first_row_y() - self.block_size
+    }
+
+    // A manual size, so a block covers one cell whatever the pixel size of its art.
+    fn block_sprite(&self) -> Sprite {
+        Sprite::new(Vec2::splat(self.block_size))
+    }
+
+    // top edge of a block resting at `game_over_y`, where the marker line is drawn
+    fn game_over_line_y(&self) -> f32 {
+        self.game_over_y() + self.block_size / 2.0
+    }
+
+    // one row short of `game_over_y`
+    fn danger_y(&self) -> f32 {
+        self.game_over_y() - self.block_size
+    }
+
+    // Half the area a board needs on screen around its center: a block of margin at
+    // the sides and top, and room below for the cover and the next row preview.
+    fn half_extent(&self) -> Vec2 {
+        let size = self.size();
+        Vec2::new(
+            size.x / 2.0 + self.block_size,
+            size.y / 2.0 + 3.0 * self.block_size,
+        )
+    }
+}
+
+// Feel of the game that doesn't change its rules. A longer swap is easier to follow.
+#[derive(Debug, Clone, Copy)]
+pub struct TuningConfig {
+    pub swap_seconds: f32,
+}
+
+impl Default for TuningConfig {
+    fn default() -> Self {
+        Self {
+            swap_seconds: SWAP_SECONDS,
+        }
+    }
+}
+
+// The colors with their own block art, in id order. `ColorCount` picks ids from the
+// front, so Indigo only shows up on hard.
+const BLOCK_COLORS: [BlockColor; 6] = [
+    BlockColor::RED,
+    BlockColor::GREEN,
+    BlockColor::BLUE,
+    BlockColor::YELLOW,
+    BlockColor::PURPLE,
+    BlockColor::INDIGO,
+];
+
+// An id into the `ColorTable`; blocks match when their ids do. The first six ids are the
+// colors with their own block art.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub struct BlockColor(pub u8);
+
+impl BlockColor {
+    pub const RED: Self = Self(0);
+    pub const GREEN: Self = Self(1);
+    pub const BLUE: Self = Self(2);
+    pub const YELLOW: Self = Self(3);
+    pub const PURPLE: Self = Self(4);
+    pub const INDIGO: Self = Self(5);
+
+    // the drawn color whose art this one is shown with
+    fn base(self) -> Self {
+        Self(self.0 % BLOCK_COLORS.len() as u8)
+    }
+}
+
+// Tint laid over each color id's block art. The default holds the six drawn colors as
+// they are; `ColorTable::extended` adds ids past them, each shown with the art of its
+// `BlockColor::base` under a generated tint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorTable(pub Vec<Color>);
+
+impl Default for ColorTable {
+    fn default() -> Self {
+        Self(vec![Color::WHITE; BLOCK_COLORS.len()])
+    }
+}
+
+impl ColorTable {
+    pub fn extended(len: usize) -> Self {
+        let mut tints = Self::default().0;
+        for id in tints.len()..len.min(u8::MAX as usize + 1) {
+            // golden-angle steps keep the hues of neighbouring ids apart
+            let hue = (id - BLOCK_COLORS.len()) as f32 * 137.5 % 360.0;
+            tints.push(Color::hsl(hue, 0.8, 0.7));
+        }
+        Self(tints)
+    }
 }
 
 #[derive(Debug)]
@@ -133,16 +547,107 @@ struct Move(f32);
 #[derive(Default, Debug)]
 struct Moving(f32);
 
-impl Lerp for Moving {
-    type Scalar = f32;
-    fn lerp(&self, other: &Self, scalar: &Self::Scalar) -> Self {
-        // std::f32::lerp is unstable
-        Self(self.0 + (other.0 - self.0) * scalar)
-    }
+// Carries a `Moving` block from `from` to `to` in fixed steps, and is removed when the
+// swap is done.
+#[derive(Debug)]
+struct SwapSlide {
+    from: f32,
+    to: f32,
+    timer: Timer,
 }
 
 #[derive(Debug)]
 struct Fixed;
+
+// Logical cell of a settled block, counted from the bottom-left of the board.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+struct GridPos {
+    col: u8,
+    row: u8,
+}
+
+// Block transforms are local to their parent `Board`, so the conversion does not
+// depend on where the board itself is placed.
+impl GridPos {
+    fn from_logical_cell((col, row): (i32, i32), board_config: &BoardConfig) -> Option<Self> {
+        if col < 0 || col >= board_config.width as i32 || row < 0 || row > u8::MAX as i32 {
+            return None;
+        }
+        Some(Self {
+            col: col as u8,
+            row: row as u8,
+        })
+    }
+
+    fn to_translation(self, z: f32, board_config: &BoardConfig) -> Vec3 {
+        let size = board_config.size();
+        Vec3::new(
+            (self.col as f32 + 0.5) * board_config.block_size - size.x / 2.0,
+            (self.row as f32 + 0.5) * board_config.block_size - size.y / 2.0,
+            z,
+        )
+    }
+}
+
+// Buckets for the block-to-block scans. Blocks are one cell in size, so two that touch
+// or overlap never land more than one bucket apart on either axis.
+fn spatial_bucket(translation: Vec3, block_size: f32) -> (i32, i32) {
+    (
+        (translation.x / block_size).round() as i32,
+        (translation.y / block_size).round() as i32,
+    )
+}
+
+// How far the stack has risen since the last row came in from the bottom.
+fn bottom_lift_offset(bottom_transform: &Transform, board_config: &BoardConfig) -> f32 {
+    (bottom_transform.translation.y - board_config.bottom_y()).rem_euclid(board_config.block_size)
+}
+
+// Board-scoped systems group entities by the board they are a child of. Entities
+// spawned without a board form one group of their own.
+type BoardKey = Option<Entity>;
+
+fn board_key(parent: Option<&Parent>) -> BoardKey {
+    parent.map(|parent| parent.0)
+}
+
+fn lift_offsets<'a>(
+    bottom: impl Iterator<Item = (&'a Transform, Option<&'a Parent>)>,
+    board_config: &BoardConfig,
+) -> HashMap<BoardKey, f32> {
+    bottom
+        .map(|(transform, parent)| {
+            (
+                board_key(parent),
+                bottom_lift_offset(transform, board_config),
+            )
+        })
+        .collect()
+}
+
+// The bottoms `lift_offsets` reads, for systems that turn positions into cells.
+#[derive(SystemParam)]
+pub struct BoardLift<'a> {
+    bottom: Query<'a, (&'static Transform, Option<&'static Parent>), With<Bottom>>,
+    board_config: Res<'a, BoardConfig>,
+}
+
+impl BoardLift<'_> {
+    fn offsets(&self) -> HashMap<BoardKey, f32> {
+        lift_offsets(self.bottom.iter(), &self.board_config)
+    }
+}
+
+// Rounds a board-local transform to its (column, row) with the lift offset taken
+// out, so blocks keep their cell while the stack rises. Spawning rows are negative.
+fn logical_cell(transform: &Transform, lift_offset: f32, board_config: &BoardConfig) -> (i32, i32) {
+    let size = board_config.size();
+    let col = (transform.translation.x + size.x / 2.0) / board_config.block_size - 0.5;
+    let row =
+        (transform.translation.y - lift_offset + size.y / 2.0) / board_config.block_size - 0.5;
+    (col.round() as i32, row.round() as i32)
+}
+
 struct Matched;
 struct FallPrepare;
 struct Floating(Timer);
@@ -150,1355 +655,8878 @@ struct Fall;
 struct FixedPrepare;
 struct Despawining(Timer);
 
-struct Chain(Timer);
+#[derive(Default, Debug)]
+struct DespawnScale(f32);
+
+// Steady frame behind a clearing block, drawn instead of the flash with `ReduceFlashing`;
+// a child of the block.
+struct DespawnOutline;
+
+// Eases from 1.0 on landing down to 0.0; only drawn, never read by gameplay.
+#[derive(Default, Debug)]
+struct LandingSquash(f32);
+
+impl Lerp for LandingSquash {
+    type Scalar = f32;
+    fn lerp(&self, other: &Self, scalar: &Self::Scalar) -> Self {
+        Self(self.0 + (other.0 - self.0) * scalar)
+    }
+}
+
+impl Lerp for DespawnScale {
+    type Scalar = f32;
+    fn lerp(&self, other: &Self, scalar: &Self::Scalar) -> Self {
+        Self(self.0 + (other.0 - self.0) * scalar)
+    }
+}
+
+struct Chain;
+
+// One cell of a garbage piece sent by the opponent. Every cell lists the whole piece,
+// itself included, so the piece falls as one and turns into blocks a row at a time.
+#[derive(Debug, Clone)]
+pub struct Garbage {
+    pub cells: Vec<Entity>,
+    pub color: BlockColor,
+}
+
+// Drops a `width` x `height` garbage piece onto `target_board`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GarbageEvent {
+    pub target_board: Entity,
+    pub width: usize,
+    pub height: usize,
+}
+
+// A clear of this many blocks sends a piece one narrower; every chain link sends a row.
+const GARBAGE_COMBO_THRESHOLD: usize = 4;
+
+// Garbage waits this long after it's sent before it drops, so the defender can clear
+// blocks to cancel it.
+const GARBAGE_DELAY_SECONDS: f32 = 3.0;
+
+// A garbage piece sent to a board that drops once `PlayTime` reaches `due`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GarbageSpec {
+    pub width: usize,
+    pub height: usize,
+    pub due: f32,
+}
+
+// Garbage sent to each board that hasn't dropped yet, oldest first.
+#[derive(Debug, Default)]
+pub struct IncomingGarbage(pub HashMap<Entity, VecDeque<GarbageSpec>>);
+
+// Sent by `prepare_despawn_block` for every board that clears blocks on an update;
+// scoring, sound, popups, garbage and the despawn animation all follow from it.
+// `centroid` is the middle of the cleared blocks, local to the board.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClearEvent {
+    pub board: Option<Entity>,
+    pub entities: Vec<Entity>,
+    pub combo: usize,
+    pub chain: u32,
+    pub centroid: Vec3,
+}
+
+// Sent by `despawn_block` for every colored block of a clear as it goes.
+struct BlockCleared {
+    board: BoardKey,
+    grid_pos: GridPos,
+    color: BlockColor,
+}
+
+const COMBO_POPUP_THRESHOLD: usize = 4;
+const COMBO_POPUP_SECONDS: f32 = 0.8;
+
+// Fades out and despawns itself once the timer runs out.
+struct ComboPopup(Timer);
+
+// Sent once a board goes from having blocks in motion to fully settled.
+struct BoardSettled(BoardKey);
 
-struct Bottom;
+// Sent when a swap is refused because a target block is still mid-swap.
+#[derive(Debug)]
+pub struct SwapRejected;
+
+pub struct Bottom;
 
 #[derive(Debug)]
 struct Cursor;
 
+// Put on the cursor of a COM board instead of `PlayerControls`.
+pub struct AiController {
+    pub timer: Timer,
+}
+
+impl Default for AiController {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(1.0, true),
+        }
+    }
+}
+
+// Marks whose board it is in a two-player or VS COM game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+    Com,
+}
+
+// The player left standing when the other board topped out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Winner(pub Player);
+
 #[derive(Debug)]
-struct Board;
+pub struct Board;
+
+// Where a board sits while the layout is at full size.
+struct BoardOrigin(Vec3);
 
 struct BoardBottomCover;
 
+const FRAME_THICKNESS: f32 = 3.0;
+const COLUMN_GUIDE_THICKNESS: f32 = 1.0;
+const GAME_OVER_LINE_THICKNESS: f32 = 2.0;
+
+// The border around a board's grid.
+struct BoardFrame;
+
+// A line between two of the board's columns.
+struct ColumnGuide;
+
+// Marks how high a stack may settle before the board tops out.
+struct GameOverLine;
+
+// One cell of the strip under the board that shows the next row to rise.
+struct NextRowPreview(usize);
+
+// A bar above the board for one piece of `IncomingGarbage`.
+struct IncomingGarbageMarker;
+
 struct CountTimer(Timer);
 
-struct ChainCounter(u32);
+// Runs while the board's settled stack is above `game_over_y`; the game ends when it
+// finishes, so the player gets `GameSettings::top_out_grace` to clear the stack back
+// down. Dropping below the line starts it over.
+struct TopOutTimer(Timer);
 
-fn setup_camera(mut commands: Commands) {
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+const FINISH_SECONDS: f32 = 0.5;
+pub const SCORE_ATTACK_SECONDS: f32 = 120.0;
+const FINISH_TIME_SCALE: f32 = 0.2;
+
+// Length of a gameplay step. Gameplay systems advance by it rather than by the frame
+// time, so falls, lifts and replays play out the same at any frame rate.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedStep(pub f32);
+
+impl Default for FixedStep {
+    fn default() -> Self {
+        FixedStep(FIXED_STEP_SECONDS)
+    }
 }
 
-// TODO: divide function
-fn setup_board(
-    mut commands: Commands,
-    board_materials: Res<BoardMaterials>,
-    block_materials: Res<BlockMaterials>,
-    bottom_materials: Res<BottomMaterials>,
-    cursor_materials: Res<CursorMaterials>,
-) {
-    let board_transform = Transform {
-        translation: Vec3::ZERO,
-        ..Default::default()
-    };
-    let board_sprite = Sprite::new(Vec2::new(
-        BOARD_WIDTH as f32 * BLOCK_SIZE,
-        BOARD_HEIGHT as f32 * BLOCK_SIZE,
-    ));
-    let board_entity = commands
-        .spawn_bundle(SpriteBundle {
-            material: board_materials.board_material.clone(),
-            sprite: board_sprite.clone(),
-            transform: board_transform,
-            ..Default::default()
-        })
-        .insert(Board)
-        .id();
-    let patterns = [[
-        [None, Some(3), None, None, None, None],
-        [None, Some(0), None, Some(1), Some(0), None],
-        [Some(0), Some(2), None, Some(2), Some(1), None],
-        [Some(1), Some(2), None, Some(3), Some(2), None],
-        [Some(3), Some(1), Some(3), Some(0), Some(3), Some(4)],
-        [Some(2), Some(0), Some(4), Some(1), Some(0), Some(1)],
-        [Some(4), Some(3), Some(2), Some(0), Some(4), Some(2)],
-    ]];
-    let mut rng = rand::thread_rng();
-    let mut block_colors = vec![
-        (BlockColor::Red, block_materials.red_material.clone()),
-        (BlockColor::Green, block_materials.green_material.clone()),
-        (BlockColor::Blue, block_materials.blue_material.clone()),
-        (BlockColor::Yellow, block_materials.yellow_material.clone()),
-        (BlockColor::Purple, block_materials.purple_material.clone()),
-        // (BlockColor::Indigo, block_materials.indigo_material.clone()),
-    ];
+impl FixedStep {
+    fn delta(&self) -> Duration {
+        Duration::from_secs_f32(self.0)
+    }
+}
 
-    let relative_x = board_transform.translation.x - board_sprite.size.x / 2.0 + BLOCK_SIZE / 2.0;
-    let relative_y = board_transform.translation.y - board_sprite.size.y / 2.0 + BLOCK_SIZE / 2.0;
-    let bottom_y = board_transform.translation.y - board_sprite.size.y / 2.0 - BLOCK_SIZE / 2.0;
-
-    if let Some(pattern) = patterns.iter().choose(&mut rng) {
-        for (row_idx, row) in pattern.iter().rev().enumerate() {
-            for (column_idx, one_block) in row.iter().enumerate() {
-                match one_block {
-                    None => {}
-                    Some(num) => {
-                        let block = commands
-                            .spawn_bundle(SpriteBundle {
-                                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                                material: block_colors[*num].1.clone(),
-                                transform: Transform {
-                                    translation: Vec3::new(
-                                        relative_x + BLOCK_SIZE * column_idx as f32,
-                                        relative_y + BLOCK_SIZE * row_idx as f32,
-                                        0.0,
-                                    ),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            })
-                            .insert(Block)
-                            .insert(block_colors[*num].0)
-                            .insert(Fixed)
-                            .id();
-                        commands.entity(board_entity).push_children(&[block]);
-                    }
-                };
-            }
-        }
-    };
+#[derive(Default)]
+struct StepClock {
+    accumulator: f32,
+    // working through the steps of the current frame
+    stepping: bool,
+}
 
-    block_colors.shuffle(&mut rng);
-    for row_idx in 0..2 {
-        let mut previous_block_queue = VecDeque::with_capacity(2);
-        for column_idx in 0..6 {
-            let number = rng.gen_range(0..block_colors.len());
-            let block = commands
-                .spawn_bundle(SpriteBundle {
-                    sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                    material: block_colors[number].1.clone(),
-                    transform: Transform {
-                        translation: Vec3::new(
-                            relative_x + BLOCK_SIZE * column_idx as f32,
-                            bottom_y - BLOCK_SIZE * row_idx as f32,
-                            0.0,
-                        ),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Block)
-                .insert(block_colors[number].0)
-                .insert(Spawning)
-                .id();
-            commands.entity(board_entity).push_children(&[block]);
-            let tmp_remove_block = Some(block_colors.remove(number));
-            previous_block_queue.push_back(tmp_remove_block);
-            if previous_block_queue.len() > 1 {
-                if let Some(Some(back_color_block)) = previous_block_queue.pop_front() {
-                    block_colors.push(back_color_block);
-                }
-            }
+impl StepClock {
+    // Keeps at most the backlog limit, so a long hitch doesn't run a burst of steps.
+    fn advance(&mut self, delta: f32) {
+        self.accumulator = (self.accumulator + delta).min(MAX_STEP_BACKLOG_SECONDS);
+    }
+
+    fn take_step(&mut self, step: f32) -> bool {
+        if self.accumulator >= step {
+            self.accumulator -= step;
+            true
+        } else {
+            false
         }
     }
-    let bottom = commands
-        .spawn_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * BOARD_WIDTH as f32, BLOCK_SIZE)),
-            material: bottom_materials.bottom_material.clone(),
-            transform: Transform {
-                translation: Vec3::new(0.0, bottom_y, 1.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Bottom)
-        .id();
-    commands.entity(board_entity).push_children(&[bottom]);
-    let cursor = commands
-        .spawn_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-            material: cursor_materials.cursor_material.clone(),
-            transform: Transform {
-                translation: Vec3::new(0.0, 0.0, 1.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Cursor)
-        .id();
-    commands.entity(board_entity).push_children(&[cursor]);
-    commands
-        .spawn()
-        .insert(CountTimer(Timer::from_seconds(1.0, false)));
+
+    // How far the frame is into the next step, from 0 to 1.
+    fn alpha(&self, step: f32) -> f32 {
+        (self.accumulator / step).clamp(0.0, 1.0)
+    }
 }
 
-fn setup_board_bottom_cover(
-    mut commands: Commands,
-    board_bottom_cover_materials: Res<BoardBottomCoverMaterials>,
-) {
-    commands
-        .spawn_bundle(SpriteBundle {
-            material: board_bottom_cover_materials
-                .board_bottom_cover_material
-                .clone(),
-            sprite: Sprite::new(Vec2::new(BOARD_WIDTH as f32 * BLOCK_SIZE, 2.0 * BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(0.0, -375.0, 1.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(BoardBottomCover);
+// Where a moving entity stood after the last two steps, so a frame can draw it between them.
+struct StepLerp {
+    previous: Vec3,
+    current: Vec3,
 }
 
-fn setup_chaincounter(mut commands: Commands) {
-    commands.spawn().insert(ChainCounter(1));
+// Keys pressed since the last step. A frame shorter than a step runs no step at all, so
+// its presses wait here for the next one.
+#[derive(Default)]
+struct StepInput(Vec<KeyCode>);
+
+// Multiplies the update time of falling, lifting and clearing blocks.
+pub struct TimeScale(pub f32);
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        TimeScale(1.0)
+    }
 }
 
-fn move_cursor(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut cursor: Query<&mut Transform, With<Cursor>>,
-) {
-    if let Ok(mut transform) = cursor.single_mut() {
-        if keyboard_input.just_pressed(KeyCode::Left) && transform.translation.x > -75.0 {
-            transform.translation.x -= BLOCK_SIZE;
-        }
-        if keyboard_input.just_pressed(KeyCode::Right) && transform.translation.x < 75.0 {
-            transform.translation.x += BLOCK_SIZE;
-        }
-        if keyboard_input.just_pressed(KeyCode::Up) && transform.translation.y < 300.0 {
-            transform.translation.y += BLOCK_SIZE;
-        }
-        if keyboard_input.just_pressed(KeyCode::Down) && transform.translation.y > -300.0 {
-            transform.translation.y -= BLOCK_SIZE;
-        }
+// The game time one fixed step moves things by, `TimeScale` applied.
+#[derive(SystemParam)]
+pub struct ScaledStep<'a> {
+    fixed_step: Res<'a, FixedStep>,
+    time_scale: Res<'a, TimeScale>,
+}
+
+impl ScaledStep<'_> {
+    fn seconds(&self) -> f32 {
+        self.fixed_step.0 * self.time_scale.0
     }
 }
 
-// TODO: if there is no fixed block -> check block and cancel tag.
-fn move_tag_block(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut commands: Commands,
-    cursor: Query<&Transform, With<Cursor>>,
-    mut block: Query<(Entity, &Transform, Option<&Fixed>), With<Block>>,
-) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        if let Ok(cursor_transform) = cursor.single() {
-            let x = cursor_transform.translation.x;
-            let left_x = x - BLOCK_SIZE / 2.0;
-            let right_x = x + BLOCK_SIZE / 2.0;
-            let mut right_block = (None, None);
-            let mut left_block = (None, None);
-            let mut left_collide = false;
-            let mut right_collide = false;
-
-            for (block_entity, block_transform, fixed) in block.iter_mut() {
-                if (block_transform.translation.y - cursor_transform.translation.y).abs()
-                    < BLOCK_SIZE / 2.0
-                {
-                    // left target
-                    if (block_transform.translation.x - left_x).abs() < BLOCK_SIZE / 2.0 {
-                        left_block = (Some(block_entity), fixed);
-                    }
-                    // right target
-                    if (block_transform.translation.x - right_x).abs() < BLOCK_SIZE / 2.0 {
-                        right_block = (Some(block_entity), fixed);
-                    }
-                }
-                // fall block collision
-                else if block_transform.translation.y - cursor_transform.translation.y
-                    < BLOCK_SIZE
-                    && block_transform.translation.y - cursor_transform.translation.y > 0.0
-                {
-                    // left collision exists
-                    if (block_transform.translation.x - left_x).abs() < BLOCK_SIZE / 2.0 {
-                        left_collide = true;
-                    }
-                    // right collision exsists
-                    else if (block_transform.translation.x - right_x).abs() < BLOCK_SIZE / 2.0 {
-                        right_collide = true;
-                    }
-                }
-            }
-            match (right_block, right_collide, left_block, left_collide) {
-                // both exist and fixed -> remove fixed and insert move
-                ((Some(right_entity), Some(_)), _, (Some(left_entity), Some(_)), _) => {
-                    commands
-                        .entity(right_entity)
-                        .remove::<Fixed>()
-                        .insert(Move(left_x));
-                    commands
-                        .entity(left_entity)
-                        .remove::<Fixed>()
-                        .insert(Move(right_x));
-                }
-                // one exists and fixed && no collide -> remove fixed and insert move
-                ((Some(right_entity), Some(_)), _, (None, None), false) => {
-                    commands
-                        .entity(right_entity)
-                        .remove::<Fixed>()
-                        .insert(Move(left_x));
-                }
-                ((None, None), false, (Some(left_entity), Some(_)), _) => {
-                    commands
-                        .entity(left_entity)
-                        .remove::<Fixed>()
-                        .insert(Move(right_x));
-                }
-                // no fixed
-                _ => {}
-            }
+// Present from the update a board tops out until the game over screen, while play
+// slows down to `FINISH_TIME_SCALE`.
+struct GameOverFinish(Timer);
+
+// Lift speed in pixels per second. Every run starts at `origin` scaled by the
+// `Difficulty`, after waiting `lift_delay` seconds, and then speeds up by `ramp`.
+// The resource is the chosen speed; each board lifts by its own copy, kept next to
+// its `CountTimer`, whose `origin` is the speed that board started at.
+#[derive(Debug, Clone, Copy)]
+pub struct GameSpeed {
+    pub origin: f32,
+    pub current: f32,
+    pub lift_delay: f32,
+    pub ramp: SpeedRamp,
+}
+
+impl Default for GameSpeed {
+    fn default() -> Self {
+        Self {
+            origin: 10.0,
+            current: 10.0,
+            lift_delay: 1.0,
+            ramp: Difficulty::default().speed_ramp(),
         }
     }
-    if keyboard_input.just_pressed(KeyCode::A) {
-        println!("-------------------");
-        for (block_entity, transform, fixed) in block.iter() {
-            println!(
-                "{}: {}: {:?}",
-                block_entity.id(),
-                transform.translation,
-                fixed
-            );
-        }
+}
+
+// Seconds the board's speed has been ramping for.
+#[derive(Default)]
+struct RampClock(f32);
+
+struct ChainCounter(u32);
+
+// Shows the board's chain while one is going; holds the count it last showed.
+struct ChainText(u32);
+
+// Glyph drawn on a block when `AccessibilitySettings::symbols` is on; a child of the block.
+struct BlockSymbol;
+
+// Lightens a block while it swaps; a child of the block, removed when it settles.
+struct SwapHighlight;
+
+// Pixels per second a falling block drops.
+#[derive(Debug, Clone, Copy)]
+pub struct FallSpeed(pub f32);
+
+impl Default for FallSpeed {
+    fn default() -> Self {
+        Self(600.0)
     }
 }
 
-// Transform easing isn't match, because y-axis must be defined.
-fn move_block(
-    mut commands: Commands,
-    mut block: Query<(Entity, &Transform, &Move), (With<Block>, With<Move>)>,
-) {
-    for (entity, transform, move_target) in block.iter_mut() {
-        commands
-            .entity(entity)
-            .insert(Moving(transform.translation.x))
-            .insert(Moving(transform.translation.x).ease_to(
-                Moving(move_target.0),
-                EaseMethod::Linear,
-                EasingType::Once {
-                    duration: std::time::Duration::from_secs_f32(0.04),
-                },
-            ))
-            .remove::<Move>();
+// Updates the board has to stay still before a chain ends. A landed block is only
+// checked by `match_block` on the following update, so 2 is the strict minimum.
+#[derive(Debug, Clone, Copy)]
+pub struct ChainWindow(pub u32);
+
+impl Default for ChainWindow {
+    fn default() -> Self {
+        Self(2)
     }
 }
 
-fn moving_to_fixed(
-    mut commands: Commands,
-    mut block: Query<
-        (
-            Entity,
-            &mut Transform,
-            &Moving,
-            Option<&EasingComponent<Moving>>,
-        ),
-        (With<Block>, With<Moving>),
-    >,
-) {
-    for (entity, mut transform, moving, easing_component) in block.iter_mut() {
-        match easing_component {
-            Some(_) => {
-                transform.translation.x = moving.0;
-            }
-            None => {
-                commands.entity(entity).remove::<Moving>().insert(Fixed);
-            }
+const ASSIST_CHAIN_WINDOW_FACTOR: u32 = 15;
+
+impl ChainWindow {
+    pub fn effective(&self, assist_mode: &AssistMode) -> u32 {
+        if assist_mode.0 {
+            self.0 * ASSIST_CHAIN_WINDOW_FACTOR
+        } else {
+            self.0
         }
     }
 }
 
-// TODO: which fast?
-// can not use collide
-// match and fall check should be double loop...
-// can not upwarding `Fall` state
-fn match_block(
-    mut commands: Commands,
-    mut block: Query<
-        (Entity, &Transform, &BlockColor),
-        (With<Block>, With<Fixed>, With<BlockColor>),
-    >,
-    mut other_block: Query<
-        (Entity, &Transform, &BlockColor),
-        (With<Block>, With<Fixed>, With<BlockColor>),
-    >,
-) {
-    let mut matched_entities: Vec<Entity> = Vec::new();
-    for (entity, transform, block_color) in block.iter_mut() {
-        let mut row_matched_entities = Vec::with_capacity(4);
-        let mut column_matched_entities = Vec::with_capacity(4);
-
-        for (other_entity, other_transform, other_block_color) in other_block.iter_mut() {
-            // left next to
-            if (transform.translation.x - other_transform.translation.x - BLOCK_SIZE).abs()
-                < BLOCK_SIZE / 2.0
-                && (transform.translation.y - other_transform.translation.y).abs()
-                    < BLOCK_SIZE / 2.0
-                && block_color == other_block_color
-            {
-                row_matched_entities.push(entity);
-                row_matched_entities.push(other_entity);
-            }
-            // right next to
-            if (transform.translation.x - other_transform.translation.x + BLOCK_SIZE).abs()
-                < BLOCK_SIZE / 2.0
-                && (transform.translation.y - other_transform.translation.y).abs()
-                    < BLOCK_SIZE / 2.0
-                && block_color == other_block_color
-            {
-                row_matched_entities.push(entity);
-                row_matched_entities.push(other_entity);
-            }
-            // top next to
-            if (transform.translation.y - other_transform.translation.y + BLOCK_SIZE).abs()
-                < BLOCK_SIZE / 2.0
-                && (transform.translation.x - other_transform.translation.x).abs()
-                    < BLOCK_SIZE / 2.0
-                && block_color == other_block_color
-            {
-                column_matched_entities.push(entity);
-                column_matched_entities.push(other_entity);
-            }
-            // down next to
-            if (transform.translation.y - other_transform.translation.y - BLOCK_SIZE).abs()
-                < BLOCK_SIZE / 2.0
-                && (transform.translation.x - other_transform.translation.x).abs()
-                    < BLOCK_SIZE / 2.0
-                && block_color == other_block_color
-            {
-                column_matched_entities.push(entity);
-                column_matched_entities.push(other_entity);
-            }
-        }
-        if row_matched_entities.len() == 4 {
-            matched_entities.append(&mut row_matched_entities);
-        }
-        if column_matched_entities.len() == 4 {
-            matched_entities.append(&mut column_matched_entities);
+#[derive(Debug, Default)]
+pub struct Score(pub u32);
+
+// Seconds of unpaused play in the current run.
+#[derive(Debug, Default)]
+pub struct PlayTime(pub f32);
+
+// Counts down from the start of a run; see `countdown_label`.
+struct StartCountdown(Timer);
+
+impl Default for StartCountdown {
+    fn default() -> Self {
+        Self(Timer::from_seconds(COUNTDOWN_STEP_SECONDS * 4.0, false))
+    }
+}
+
+// Gameplay systems wait for this, so nothing lifts, spawns or takes input before Go!.
+#[derive(Debug, Default)]
+struct GameStarted(bool);
+
+struct CountdownUi;
+
+struct CountdownText;
+
+// Longest chain of the current run.
+#[derive(Debug, Default)]
+pub struct MaxChain(pub u32);
+
+// Blocks cleared in the current run, with `per_color` indexed by color id.
+#[derive(Debug)]
+pub struct ClearStats {
+    pub per_color: Vec<u32>,
+    pub total: u32,
+}
+
+impl ClearStats {
+    // one count for every color in the table, including the generated ones
+    pub fn new(color_table: &ColorTable) -> Self {
+        Self {
+            per_color: vec![0; color_table.0.len()],
+            total: 0,
         }
     }
-    for en in matched_entities {
-        commands.entity(en).insert(Matched).remove::<Fixed>();
+
+    // e.g. "R 3  G 0  B 6  Y 0  P 0  I 0" for the results screen
+    pub fn breakdown(&self) -> String {
+        self.per_color
+            .iter()
+            .enumerate()
+            .map(|(id, count)| format!("{} {}", color_letter(BlockColor(id as u8)), count))
+            .collect::<Vec<_>>()
+            .join("  ")
     }
 }
 
-fn prepare_despawn_block(
-    mut commands: Commands,
-    match_block: Query<(Entity, Option<&Chain>), (With<Block>, With<Matched>)>,
-    mut chain_counter: Query<&mut ChainCounter>,
-) {
-    // TODO: despawning animation
-    if match_block
-        .iter()
-        .collect::<Vec<_>>()
-        .iter()
-        .any(|(_, chain)| chain.is_some())
-    {
-        if let Ok(mut cc) = chain_counter.single_mut() {
-            cc.0 += 1;
-            // println!("{}", cc.0);
-        }
+struct IngameCamera;
+
+// Highlights the cascade a swap will cause. On for puzzles, where working out the whole
+// cascade is the game; set as each game starts.
+pub struct MatchPreview(pub bool);
+
+struct PreviewHighlight(Timer);
+
+// Marks the swap `AssistSettings::show_hint` suggests. It is a child of the block in the
+// swap's left cell, or the right one when the left cell is empty, so it rises with the stack.
+struct SwapHint;
+
+// Pulsing overlay on a column stacked close to the top; holds the column.
+struct DangerFlash(u8);
+
+// rows from the bottom, each `BoardConfig::width` wide
+type BoardGrid = Vec<Vec<Option<BlockColor>>>;
+
+// Colors of each board's settled blocks, rebuilt every update for the COM and tests.
+// Cells of blocks in motion or clearing, and of garbage, read as empty.
+#[derive(Debug, Default)]
+pub struct BoardSnapshot(HashMap<BoardKey, BoardGrid>);
+
+impl BoardSnapshot {
+    pub fn board(&self, board: Option<Entity>) -> Option<&BoardGrid> {
+        self.0.get(&board)
     }
+}
 
-    let combo = match_block.iter().count();
-    for (entity, _chain) in match_block.iter() {
-        commands
-            .entity(entity)
-            .remove::<Matched>()
-            .insert(Despawining(Timer::from_seconds(combo as f32 * 0.3, false)));
+// Colors of each board's newest spawning row, which is still hidden under the cover.
+// Boards that spawn nothing have no entry.
+#[derive(Debug, Default)]
+struct NextRow(HashMap<BoardKey, Vec<BlockColor>>);
+
+// Optional fixed seed for the board RNG, e.g. for replays or puzzles.
+pub struct GameSeed(pub u64);
+
+// Swaps left in a puzzle; only present in puzzle mode.
+pub struct MovesLeft(pub u32);
+
+// How the last puzzle ended, for the game over screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleOutcome {
+    Cleared,
+    OutOfMoves,
+}
+
+// Every random board decision draws from here so that a `GameSeed` reproduces a game.
+pub struct BoardRng(pub StdRng);
+
+impl BoardRng {
+    fn new(game_seed: Option<&GameSeed>) -> Self {
+        match game_seed {
+            Some(game_seed) => Self(StdRng::seed_from_u64(game_seed.0)),
+            None => Self(StdRng::from_entropy()),
+        }
     }
 }
 
-// TODO: event?
-// match_block event -> prepare_despawn_block event -> remove_chain event
-fn remove_chain(
+fn setup_camera(mut commands: Commands) {
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(IngameCamera);
+}
+
+fn setup_run_resources(
     mut commands: Commands,
-    time: Res<Time>,
-    mut chain_block: Query<(Entity, Option<&mut Chain>), (With<Block>, With<Fixed>)>,
+    color_table: Res<ColorTable>,
+    game_mode: Res<GameMode>,
 ) {
-    for (entity, ch) in chain_block.iter_mut().filter(|(_en, ch)| ch.is_some()) {
-        if let Some(mut chain) = ch {
-            chain.0.tick(Duration::from_secs_f32(time.delta_seconds()));
-            if chain.0.finished() {
-                commands.entity(entity).remove::<Chain>();
-            }
-        }
+    commands.insert_resource(Score::default());
+    commands.insert_resource(PlayTime::default());
+    commands.insert_resource(MaxChain::default());
+    commands.insert_resource(ClearStats::new(&color_table));
+    commands.insert_resource(IncomingGarbage::default());
+    commands.insert_resource(TimeScale::default());
+    commands.insert_resource(StepInput::default());
+    commands.insert_resource(MatchPreview(*game_mode == GameMode::Puzzle));
+    commands.remove_resource::<GameOverFinish>();
+    commands.remove_resource::<PuzzleOutcome>();
+    commands.remove_resource::<Winner>();
+}
+
+// Puzzles have a fixed set of blocks and the sandbox only has the ones placed by hand,
+// so nothing lifts or spawns.
+fn endless_mode_criteria(In(input): In<ShouldRun>, game_mode: Res<GameMode>) -> ShouldRun {
+    match *game_mode {
+        GameMode::Endless | GameMode::ScoreAttack | GameMode::VsCom | GameMode::TwoPlayer => input,
+        GameMode::Puzzle | GameMode::Sandbox => ShouldRun::No,
     }
 }
 
-fn reset_chain_counter(
-    chain_block: Query<&Chain, (With<Block>, With<Chain>)>,
-    mut chain_counter: Query<&mut ChainCounter>,
-) {
-    if chain_block.iter().next().is_none() {
-        if let Ok(mut cc) = chain_counter.single_mut() {
-            cc.0 = 1;
-        }
+// Garbage is only sent between boards played against each other.
+fn versus_mode_criteria(In(input): In<ShouldRun>, game_mode: Res<GameMode>) -> ShouldRun {
+    match *game_mode {
+        GameMode::VsCom | GameMode::TwoPlayer => input,
+        _ => ShouldRun::No,
     }
 }
 
-fn despawn_block(
+fn score_attack_criteria(In(input): In<ShouldRun>, game_mode: Res<GameMode>) -> ShouldRun {
+    match *game_mode {
+        GameMode::ScoreAttack => input,
+        _ => ShouldRun::No,
+    }
+}
+
+// Set through `ResMut` rather than commands so the run criteria see a fresh countdown on
+// the first update of a retry too.
+fn setup_countdown(
     mut commands: Commands,
-    time: Res<Time>,
-    mut despawning_block: Query<
-        (Entity, &mut Despawining, &Transform),
-        (With<Block>, With<Despawining>),
-    >,
-    other_block: Query<(Entity, &Transform), (With<Block>, Without<Despawining>)>,
+    font_assets: Res<FontAssets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut countdown: ResMut<StartCountdown>,
+    mut game_started: ResMut<GameStarted>,
 ) {
-    for (despawning_entity, mut despawning, despawning_transform) in despawning_block.iter_mut() {
-        despawning
-            .0
-            .tick(Duration::from_secs_f32(time.delta_seconds()));
-        if despawning.0.just_finished() {
-            commands.entity(despawning_entity).despawn();
-            let mut chain_candidates = Vec::new();
-            for (other_entity, other_transform) in other_block.iter() {
-                if despawning_transform.translation.y < other_transform.translation.y
-                    && (despawning_transform.translation.x - other_transform.translation.x).abs()
-                        < BLOCK_SIZE / 2.0
-                {
-                    chain_candidates.push((other_entity, other_transform));
-                }
-            }
-            chain_candidates.sort_unstable_by(|(_, trans_a), (_, trans_b)| {
-                trans_a
-                    .translation
-                    .y
-                    .partial_cmp(&trans_b.translation.y)
-                    .unwrap()
-            });
-            let mut current_y = despawning_transform.translation.y;
-            for (en, tr) in chain_candidates.iter() {
-                if (tr.translation.y - BLOCK_SIZE - current_y).abs() < BLOCK_SIZE / 2.0 {
-                    commands
-                        .entity(*en)
-                        .insert(Chain(Timer::from_seconds(0.04, false)));
-                    current_y += BLOCK_SIZE;
-                } else {
-                    break;
-                }
-            }
-        }
+    *countdown = StartCountdown::default();
+    game_started.0 = false;
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .insert(CountdownUi)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text {
+                        sections: vec![TextSection {
+                            value: countdown_label(0.0).unwrap_or_default().to_string(),
+                            style: TextStyle {
+                                font: font_assets.font.clone(),
+                                font_size: 120.0,
+                                color: Color::rgb(1.0, 1.0, 1.0),
+                            },
+                        }],
+                        alignment: Default::default(),
+                    },
+                    ..Default::default()
+                })
+                .insert(CountdownText);
+        });
+}
+
+// None once Go! has been shown.
+fn countdown_label(elapsed: f32) -> Option<&'static str> {
+    match (elapsed / COUNTDOWN_STEP_SECONDS) as u32 {
+        0 => Some("3"),
+        1 => Some("2"),
+        2 => Some("1"),
+        3 => Some("Go!"),
+        _ => None,
     }
 }
 
-fn check_fall_block(
+fn tick_countdown(
     mut commands: Commands,
-    mut block: Query<(Entity, &Transform), (With<Block>, With<Fixed>)>,
-    mut other_block: Query<&Transform, With<Block>>,
+    time: Res<Time>,
+    mut countdown: ResMut<StartCountdown>,
+    mut game_started: ResMut<GameStarted>,
+    mut countdown_text: Query<&mut Text, With<CountdownText>>,
+    countdown_ui: Query<Entity, With<CountdownUi>>,
 ) {
-    // check is there block down next to?
-    for (entity, transform) in block.iter_mut() {
-        if transform.translation.y > -300.0 {
-            let mut is_exist = false;
-            for other_transform in other_block.iter_mut() {
-                if (transform.translation.y - other_transform.translation.y - BLOCK_SIZE).abs()
-                    < BLOCK_SIZE / 2.0
-                    && (transform.translation.x - other_transform.translation.x).abs() < BLOCK_SIZE
-                {
-                    is_exist = true;
-                    break;
+    if countdown.0.finished() {
+        return;
+    }
+    countdown
+        .0
+        .tick(Duration::from_secs_f32(time.delta_seconds()));
+    let elapsed = countdown.0.elapsed_secs();
+    if !game_started.0 && elapsed >= COUNTDOWN_STEP_SECONDS * 3.0 {
+        game_started.0 = true;
+    }
+    match countdown_label(elapsed) {
+        Some(label) => {
+            for mut text in countdown_text.iter_mut() {
+                if text.sections[0].value != label {
+                    text.sections[0].value = label.to_string();
                 }
             }
-            if !is_exist {
-                commands
-                    .entity(entity)
-                    .remove::<Fixed>()
-                    .insert(FallPrepare);
+        }
+        None => {
+            for entity in countdown_ui.iter() {
+                commands.entity(entity).despawn_recursive();
             }
         }
     }
 }
 
-fn fall_upward(
-    mut commands: Commands,
-    mut fallprepare_block: Query<(Entity, &Transform), (With<Block>, With<FallPrepare>)>,
-    mut fixed_block: Query<(Entity, &Transform), (With<Block>, With<Fixed>)>,
-) {
-    for (fallprepare_entity, fallprepare_transform) in fallprepare_block.iter_mut() {
-        let mut fall_block_candidates = vec![(fallprepare_entity, fallprepare_transform)];
+// Runs gameplay once for every whole step of frame time and keeps the rest for the next
+// frame. The state criteria in front have moved on by the later steps of a frame, so
+// only a state transition stops them early.
+fn fixed_step_criteria(
+    In(input): In<ShouldRun>,
+    time: Res<Time>,
+    fixed_step: Res<FixedStep>,
+    mut clock: ResMut<StepClock>,
+) -> ShouldRun {
+    if clock.stepping {
+        if input == ShouldRun::NoAndCheckAgain {
+            clock.stepping = false;
+            return input;
+        }
+    } else if matches!(input, ShouldRun::Yes | ShouldRun::YesAndCheckAgain) {
+        clock.advance(time.delta_seconds());
+    } else {
+        return input;
+    }
+    if clock.take_step(fixed_step.0) {
+        clock.stepping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.stepping = false;
+        ShouldRun::No
+    }
+}
 
-        for (fixed_entity, fixed_transform) in fixed_block.iter_mut() {
-            if fallprepare_transform.translation.y < fixed_transform.translation.y
-                && (fallprepare_transform.translation.x - fixed_transform.translation.x).abs()
-                    < BLOCK_SIZE / 2.0
-            {
-                fall_block_candidates.push((fixed_entity, fixed_transform));
+// Exclusive so it runs after every other system of the step, and works on the world so
+// it sees blocks spawned since the game started.
+fn record_step_translation(world: &mut World) {
+    let mut moving = world.query_filtered::<
+        (Entity, &Transform, Option<&mut StepLerp>),
+        Or<(With<Block>, With<Cursor>, With<Bottom>)>,
+    >();
+    let mut new = Vec::new();
+    for (entity, transform, lerp) in moving.iter_mut(world) {
+        match lerp {
+            Some(mut lerp) => {
+                lerp.previous = lerp.current;
+                lerp.current = transform.translation;
             }
+            None => new.push((entity, transform.translation)),
         }
-        fall_block_candidates.sort_unstable_by(|(_ena, trans_a), (_enb, trans_b)| {
-            trans_a
-                .translation
-                .y
-                .partial_cmp(&trans_b.translation.y)
-                .unwrap()
+    }
+    for (entity, translation) in new {
+        world.entity_mut(entity).insert(StepLerp {
+            previous: translation,
+            current: translation,
         });
-        let mut iter = fall_block_candidates.iter().peekable();
-        while let Some((en, tr)) = iter.next() {
-            commands
-                .entity(*en)
-                .remove::<FallPrepare>()
-                .remove::<Fixed>()
-                .insert(Floating(Timer::from_seconds(0.02, false)));
-            if let Some((_en, next_tr)) = iter.peek() {
-                if (next_tr.translation.y - tr.translation.y).abs() > BLOCK_SIZE * 1.5 {
-                    break;
-                }
-            }
-        }
     }
 }
 
-fn floating_to_fall(
-    mut commands: Commands,
-    time: Res<Time>,
-    mut floating_block: Query<(Entity, &mut Floating), (With<Floating>, With<Block>)>,
+// Puts back where the last step left each entity, so the steps never see a drawn position.
+fn restore_step_translation(mut query: Query<(&mut Transform, &StepLerp)>) {
+    for (mut transform, lerp) in query.iter_mut() {
+        transform.translation = lerp.current;
+    }
+}
+
+// Draws each entity between its last two steps by the time left over in the frame. An
+// entity moved outside the steps, like a board refit to the window, snaps to where it is.
+fn interpolate_step_translation(
+    clock: Res<StepClock>,
+    fixed_step: Res<FixedStep>,
+    mut query: Query<(&mut Transform, &mut StepLerp)>,
 ) {
-    for (entity, mut floating) in floating_block.iter_mut() {
-        floating
-            .0
-            .tick(Duration::from_secs_f32(time.delta_seconds()));
-        if floating.0.just_finished() {
-            commands.entity(entity).insert(Fall).remove::<Floating>();
+    let alpha = clock.alpha(fixed_step.0);
+    for (mut transform, mut lerp) in query.iter_mut() {
+        if transform.translation != lerp.current {
+            lerp.previous = transform.translation;
+            lerp.current = transform.translation;
+            continue;
         }
+        transform.translation = lerp.previous.lerp(lerp.current, alpha);
     }
 }
 
-// TODO: fix falling time
-fn fall_block(time: Res<Time>, mut block: Query<&mut Transform, (With<Block>, With<Fall>)>) {
-    for mut transform in block.iter_mut() {
-        transform.translation.y -= 600.0 * time.delta_seconds();
+fn collect_step_input(keyboard_input: Res<Input<KeyCode>>, mut step_input: ResMut<StepInput>) {
+    for key in keyboard_input.get_just_pressed() {
+        if !step_input.0.contains(key) {
+            step_input.0.push(*key);
+        }
     }
 }
 
-fn stop_fall_block(
-    mut commands: Commands,
-    mut fall_block: Query<(Entity, &mut Transform, &Sprite), (With<Block>, With<Fall>)>,
-    other_block: Query<(&Transform, &Sprite), (With<Block>, Without<Fall>)>,
+// Gives each step only the presses since the previous one, so a press moves the cursor
+// once however many steps its frame runs. A key already let go is pressed and released
+// again, which the step still sees as just pressed.
+fn step_keyboard_input(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut step_input: ResMut<StepInput>,
 ) {
-    for (fall_block_entity, mut fall_block_transform, fall_block_sprite) in fall_block.iter_mut() {
-        for (other_block_transform, other_block_sprite) in other_block.iter() {
-            if let Some(Collision::Top) = collide(
-                fall_block_transform.translation,
-                fall_block_sprite.size,
-                other_block_transform.translation,
-                other_block_sprite.size,
-            ) {
-                commands
-                    .entity(fall_block_entity)
-                    .insert(FixedPrepare)
-                    .remove::<Fall>();
-                // TODO: some animation
-                fall_block_transform.translation.y =
-                    other_block_transform.translation.y + BLOCK_SIZE;
-            }
+    keyboard_input.update();
+    for key in step_input.0.drain(..) {
+        if keyboard_input.pressed(key) {
+            keyboard_input.release(key);
+            keyboard_input.press(key);
+        } else {
+            keyboard_input.press(key);
+            keyboard_input.release(key);
         }
     }
 }
 
-fn fixedprepare_to_fixed(
-    mut commands: Commands,
-    mut fixedprepare_block: Query<(Entity, &mut Transform), (With<Block>, With<FixedPrepare>)>,
-    mut fall_block: Query<
-        (Entity, &mut Transform),
-        (With<Block>, With<Fall>, Without<FixedPrepare>),
-    >,
-) {
-    for (fixedprepare_entity, fixedprepare_transform) in fixedprepare_block.iter_mut() {
-        let fixedprepare_transform_vec = fixedprepare_transform.translation;
-        let mut fixed_block_candidates = vec![(fixedprepare_entity, fixedprepare_transform)];
+// Piped in front of step mode, so a frozen game doesn't step through the countdown.
+fn countdown_criteria(In(input): In<ShouldRun>, game_started: Res<GameStarted>) -> ShouldRun {
+    if game_started.0 {
+        return input;
+    }
+    match input {
+        ShouldRun::Yes => ShouldRun::No,
+        ShouldRun::YesAndCheckAgain => ShouldRun::NoAndCheckAgain,
+        _ => input,
+    }
+}
 
-        for (fall_block_entity, fall_transform) in fall_block.iter_mut() {
-            if fixedprepare_transform_vec.y < fall_transform.translation.y
-                && (fixedprepare_transform_vec.x - fall_transform.translation.x).abs()
-                    < BLOCK_SIZE / 2.0
-            {
-                fixed_block_candidates.push((fall_block_entity, fall_transform));
+fn setup_gamespeed(mut game_speed: ResMut<GameSpeed>, difficulty: Res<Difficulty>) {
+    game_speed.current = game_speed.origin * difficulty.speed_scale();
+    game_speed.lift_delay = difficulty.lift_delay();
+    game_speed.ramp = difficulty.speed_ramp();
+}
+
+// Only draws from the rng when the setting is on, so boards for a seed stay the same
+// either way. Matching and collision never read the rotation.
+fn block_rotation(settings: &GameSettings, rng: &mut StdRng) -> Quat {
+    if settings.random_block_rotation {
+        Quat::from_rotation_z(std::f32::consts::FRAC_PI_2 * rng.gen_range(0..4) as f32)
+    } else {
+        Quat::IDENTITY
+    }
+}
+
+// The seed the current run's `BoardRng` started from, drawn at random without a `GameSeed`.
+pub struct RunSeed(pub u64);
+
+// The COM's own draws, kept apart from `BoardRng` so that its choices never change the
+// rows either board gets.
+pub struct AiRng(pub StdRng);
+
+fn setup_board_rng(mut commands: Commands, game_seed: Option<Res<GameSeed>>) {
+    let seed = game_seed.map_or_else(random, |game_seed| game_seed.0);
+    commands.insert_resource(RunSeed(seed));
+    commands.insert_resource(BoardRng::new(Some(&GameSeed(seed))));
+    commands.insert_resource(AiRng(StdRng::seed_from_u64(seed.wrapping_add(1))));
+}
+
+// The layout every board of a new game starts from, rows from the bottom: the
+// spawning rows below the board, then the start stack. Nothing in it matches.
+fn start_colors(
+    settings: &GameSettings,
+    board_config: &BoardConfig,
+    mut block_colors: Vec<BlockColor>,
+    rng: &mut impl Rng,
+) -> BoardGrid {
+    let patterns: [[[Option<usize>; BOARD_WIDTH]; PATTERN_HEIGHT]; 1] = [[
+        [None, Some(3), None, None, None, None],
+        [None, Some(0), None, Some(1), Some(0), None],
+        [Some(0), Some(2), None, Some(2), Some(1), None],
+        [Some(1), Some(2), None, Some(3), Some(2), None],
+        [Some(3), Some(1), Some(3), Some(0), Some(3), Some(4)],
+        [Some(2), Some(0), Some(4), Some(1), Some(0), Some(1)],
+        [Some(4), Some(3), Some(2), Some(0), Some(4), Some(2)],
+    ]];
+    let stack_rows = match settings.start_layout {
+        StartLayout::Flat(rows) => (rows as usize).min(board_config.max_start_rows()),
+        _ => 0,
+    };
+    let mut colors =
+        vec![vec![None; board_config.width]; SPAWNING_ROWS + PATTERN_HEIGHT.max(stack_rows)];
+    match settings.start_layout {
+        StartLayout::Pattern => {
+            if let Some(pattern) = patterns.iter().choose(rng) {
+                let rows = pattern.iter().rev().take(board_config.initial_rows);
+                for (row_idx, row) in rows.enumerate() {
+                    for (cell, one_block) in colors[SPAWNING_ROWS + row_idx].iter_mut().zip(row) {
+                        // fewer colors than the pattern uses wrap around; the match cleanup
+                        // below breaks up any runs that makes
+                        *cell = one_block.map(|num| block_colors[num % block_colors.len()]);
+                    }
+                }
             }
         }
-        fixed_block_candidates.sort_unstable_by(|(_, trans_a), (_, trans_b)| {
-            trans_a
-                .translation
-                .y
-                .partial_cmp(&trans_b.translation.y)
-                .unwrap()
-        });
-        for (idx, (en, mut tr)) in fixed_block_candidates.into_iter().enumerate() {
-            if tr.translation.y - (fixedprepare_transform_vec.y + BLOCK_SIZE * idx as f32)
-                > BLOCK_SIZE * 0.5
-            {
-                break;
+        StartLayout::Empty => {}
+        // random colors; `remove_initial_matches` below breaks up any runs
+        StartLayout::Flat(_) => {
+            for row in colors[SPAWNING_ROWS..].iter_mut().take(stack_rows) {
+                for cell in row.iter_mut() {
+                    *cell = block_colors.choose(rng).copied();
+                }
             }
-            commands
-                .entity(en)
-                .remove::<FixedPrepare>()
-                .remove::<Fall>()
-                .insert(Fixed);
-            tr.translation.y = fixedprepare_transform_vec.y + BLOCK_SIZE * idx as f32;
         }
     }
-}
 
-fn auto_liftup(
-    time: Res<Time>,
-    mut state: ResMut<State<AppState>>,
-    mut count_timer: Query<&mut CountTimer>,
-    mut query_set: QuerySet<(
-        Query<
-            Entity,
-            (
-                Without<Fixed>,
-                Without<Spawning>,
-                Without<Moving>,
-                Without<Move>,
-                With<Block>,
-            ),
-        >,
-        Query<&Transform, (With<Fixed>, With<Block>)>,
-        Query<&mut Transform, Or<(With<Cursor>, With<Block>, With<Bottom>)>>,
-    )>,
-) {
-    if let Ok(mut count_timer) = count_timer.single_mut() {
-        count_timer
-            .0
-            .tick(Duration::from_secs_f32(time.delta_seconds()));
-        let max_bl = query_set
-            .q1()
-            .iter()
-            .max_by(|a_tr, b_tr| a_tr.translation.y.partial_cmp(&b_tr.translation.y).unwrap());
-        if let Some(max_tr) = max_bl {
-            if count_timer.0.finished() {
-                // lift up
-                if max_tr.translation.y > BLOCK_SIZE * 5.0 {
-                    state.set(AppState::GameOver).unwrap();
-                }
-                if max_tr.translation.y < BLOCK_SIZE * 5.0 && query_set.q0().iter().next().is_none()
-                {
-                    for mut transform in query_set.q2_mut().iter_mut() {
-                        transform.translation.y += time.delta_seconds() * 10.0;
-                    }
+    block_colors.shuffle(rng);
+    for row_idx in 0..SPAWNING_ROWS {
+        let mut previous_block_queue = VecDeque::with_capacity(2);
+        for block_color in colors[SPAWNING_ROWS - 1 - row_idx].iter_mut() {
+            let number = rng.gen_range(0..block_colors.len());
+            *block_color = Some(block_colors[number]);
+            let tmp_remove_block = Some(block_colors.remove(number));
+            previous_block_queue.push_back(tmp_remove_block);
+            if previous_block_queue.len() > 1 {
+                if let Some(Some(back_color_block)) = previous_block_queue.pop_front() {
+                    block_colors.push(back_color_block);
                 }
             }
         }
+        block_colors.extend(previous_block_queue.into_iter().flatten());
     }
+    remove_initial_matches(&mut colors, &block_colors, rng);
+    debug_assert!(!has_initial_match(&colors));
+    colors
 }
 
-fn spawning_to_fixed(
+// The resources behind a `BoardParts`, plus the key bindings for the new cursors.
+#[derive(SystemParam)]
+pub struct BoardResources<'a> {
+    board_materials: Res<'a, BoardMaterials>,
+    block_materials: Res<'a, BlockMaterials>,
+    bottom_materials: Res<'a, BottomMaterials>,
+    cursor_materials: Res<'a, CursorMaterials>,
+    board_config: Res<'a, BoardConfig>,
+    game_speed: Res<'a, GameSpeed>,
+    settings: Res<'a, GameSettings>,
+    key_bindings: Res<'a, KeyBindings>,
+}
+
+impl BoardResources<'_> {
+    fn parts(&self) -> BoardParts<'_> {
+        BoardParts {
+            board_materials: &self.board_materials,
+            block_materials: &self.block_materials,
+            bottom_materials: &self.bottom_materials,
+            cursor_materials: &self.cursor_materials,
+            board_config: &self.board_config,
+            game_speed: &self.game_speed,
+            settings: &self.settings,
+        }
+    }
+}
+
+// The colors in play: how many, and the tint of each.
+#[derive(SystemParam)]
+pub struct Palette<'a> {
+    color_count: Res<'a, ColorCount>,
+    color_table: Res<'a, ColorTable>,
+}
+
+impl Palette<'_> {
+    fn block_colors(&self) -> Vec<BlockColor> {
+        block_colors(&self.color_count, &self.color_table)
+    }
+}
+
+fn setup_board(
     mut commands: Commands,
-    spawning_block: Query<(Entity, &Transform), (With<Spawning>, With<Block>)>,
+    resources: BoardResources,
+    palette: Palette,
+    game_mode: Res<GameMode>,
+    pending_save: Option<Res<PendingGameSave>>,
+    mut board_rng: ResMut<BoardRng>,
+    mut next_row: ResMut<NextRow>,
 ) {
-    for (entity, transform) in spawning_block.iter() {
-        if transform.translation.y > -300.0 {
-            commands.entity(entity).remove::<Spawning>().insert(Fixed);
+    next_row.0.clear();
+    // a resumed game gets its board from `resume_saved_game`
+    if *game_mode == GameMode::Puzzle || pending_save.is_some() {
+        return;
+    }
+    let parts = resources.parts();
+    let (board_config, key_bindings) = (parts.board_config, &resources.key_bindings);
+    let rng = &mut board_rng.0;
+    if *game_mode == GameMode::Sandbox {
+        let (_, cursor) = spawn_board(&mut commands, &parts, rng, &[], Vec3::ZERO);
+        commands.entity(cursor).insert(key_bindings.solo.clone());
+        return;
+    }
+    let colors = start_colors(parts.settings, board_config, palette.block_colors(), rng);
+    let newest_row = colors[0].iter().flatten().copied().collect::<Vec<_>>();
+
+    let offset = board_config.size().x + 2.0 * board_config.block_size;
+    // both players get the same layout, side by side around the center
+    if *game_mode == GameMode::TwoPlayer {
+        for (player, controls, x) in [
+            (Player::One, &key_bindings.player_one, -offset / 2.0),
+            (Player::Two, &key_bindings.player_two, offset / 2.0),
+        ] {
+            let (board, cursor) =
+                spawn_board(&mut commands, &parts, rng, &colors, Vec3::new(x, 0.0, 0.0));
+            next_row.0.insert(Some(board), newest_row.clone());
+            commands.entity(board).insert(player);
+            commands.entity(cursor).insert(controls.clone());
         }
+        return;
+    }
+    let (board, cursor) = spawn_board(&mut commands, &parts, rng, &colors, Vec3::ZERO);
+    next_row.0.insert(Some(board), newest_row.clone());
+    commands.entity(cursor).insert(key_bindings.solo.clone());
+    // the COM starts from the same layout, two blocks to the right of the player
+    if *game_mode == GameMode::VsCom {
+        let (com_board, com_cursor) = spawn_board(
+            &mut commands,
+            &parts,
+            rng,
+            &colors,
+            Vec3::new(offset, 0.0, 0.0),
+        );
+        next_row.0.insert(Some(com_board), newest_row);
+        commands.entity(board).insert(Player::One);
+        commands.entity(com_board).insert(Player::Com);
+        commands.entity(com_cursor).insert(AiController::default());
     }
 }
 
-fn bottom_down(mut bottom: Query<&mut Transform, With<Bottom>>) {
-    for mut transform in bottom.iter_mut() {
-        if transform.translation.y >= BLOCK_SIZE * -6.0 {
-            transform.translation.y = BLOCK_SIZE * -7.0;
+// Lays out the puzzle as given: no spawning rows, and no match cleanup so that
+// a puzzle may start one swap away from a chain.
+fn setup_puzzle_board(
+    mut commands: Commands,
+    resources: BoardResources,
+    puzzle_assets: Res<PuzzleAssets>,
+    puzzles: Res<Assets<Puzzle>>,
+    game_mode: Res<GameMode>,
+    mut board_rng: ResMut<BoardRng>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if *game_mode != GameMode::Puzzle {
+        return;
+    }
+    let board_config = &resources.board_config;
+    let puzzle = match puzzles.get(&puzzle_assets.puzzle) {
+        Some(puzzle) => puzzle,
+        None => {
+            error!("puzzle is not loaded");
+            queue_state_change(state.set(AppState::Menu));
+            return;
+        }
+    };
+    let rows = match parse_puzzle_layout(&puzzle.layout, board_config.width) {
+        // the top row would already be over the game over line
+        Ok(rows) if rows.len() < board_config.height => rows,
+        Ok(rows) => {
+            error!(
+                "puzzle has {} rows, at most {} fit",
+                rows.len(),
+                board_config.height - 1
+            );
+            queue_state_change(state.set(AppState::Menu));
+            return;
+        }
+        Err(err) => {
+            error!("failed to parse puzzle: {}", err);
+            queue_state_change(state.set(AppState::Menu));
+            return;
+        }
+    };
+    let mut colors = vec![vec![None; board_config.width]; SPAWNING_ROWS];
+    colors.extend(rows);
+    commands.insert_resource(MovesLeft(puzzle.move_limit));
+    let (_, cursor) = spawn_board(
+        &mut commands,
+        &resources.parts(),
+        &mut board_rng.0,
+        &colors,
+        Vec3::ZERO,
+    );
+    commands
+        .entity(cursor)
+        .insert(resources.key_bindings.solo.clone());
+}
+
+// A paused endless game written to disk to be picked up later. Only the settled
+// layout is kept: every block snaps to its nearest cell, the board comes back
+// unlifted, and blocks being cleared and garbage are left out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameSave {
+    pub width: usize,
+    pub height: usize,
+    pub difficulty: Difficulty,
+    pub color_count: usize,
+    // rows from the bottom, starting with the `SPAWNING_ROWS` below the board
+    pub rows: Vec<Vec<Option<BlockColor>>>,
+    // the cell under the cursor's left half
+    pub cursor: (i32, i32),
+    pub score: u32,
+    pub chain: u32,
+    pub max_chain: u32,
+    pub play_time: f32,
+    pub speed_origin: f32,
+    pub ramp_clock: f32,
+    pub lift_delay_left: f32,
+}
+
+// Inserted by the menu to have the next run start from a save.
+pub struct PendingGameSave(pub GameSave);
+
+// Reads the one board in play; versus games have two and give None.
+pub fn serialize_game(world: &mut World) -> Option<GameSave> {
+    let board_config = *world.get_resource::<BoardConfig>()?;
+    let boards = world
+        .query_filtered::<Entity, With<Board>>()
+        .iter(world)
+        .collect::<Vec<_>>();
+    let board = match boards[..] {
+        [board] => Some(board),
+        _ => return None,
+    };
+    let lift_offset = world
+        .query_filtered::<(&Transform, Option<&Parent>), With<Bottom>>()
+        .iter(world)
+        .find(|(_, parent)| board_key(*parent) == board)
+        .map(|(transform, _)| bottom_lift_offset(transform, &board_config))
+        .unwrap_or(0.0);
+    let mut rows = vec![vec![None; board_config.width]; SPAWNING_ROWS + board_config.height];
+    for (transform, block_color, parent) in world
+        .query_filtered::<(&Transform, &BlockColor, Option<&Parent>), (
+            With<Block>,
+            Without<Matched>,
+            Without<Despawining>,
+        )>()
+        .iter(world)
+    {
+        if board_key(parent) != board {
+            continue;
+        }
+        let (col, row) = logical_cell(transform, lift_offset, &board_config);
+        let row = row + SPAWNING_ROWS as i32;
+        if (0..board_config.width as i32).contains(&col) && (0..rows.len() as i32).contains(&row) {
+            rows[row as usize][col as usize] = Some(*block_color);
         }
     }
+    let (cursor_transform, _) = world
+        .query_filtered::<(&Transform, Option<&Parent>), With<Cursor>>()
+        .iter(world)
+        .find(|(_, parent)| board_key(*parent) == board)?;
+    let cursor = logical_cell(
+        &Transform::from_xyz(
+            cursor_transform.translation.x - board_config.block_size / 2.0,
+            cursor_transform.translation.y,
+            0.0,
+        ),
+        lift_offset,
+        &board_config,
+    );
+    let (game_speed, ramp_clock, count_timer, _) = world
+        .query::<(&GameSpeed, &RampClock, &CountTimer, Option<&Parent>)>()
+        .iter(world)
+        .find(|(_, _, _, parent)| board_key(*parent) == board)?;
+    let (speed_origin, ramp_clock) = (game_speed.origin, ramp_clock.0);
+    let lift_delay_left = count_timer.0.duration().as_secs_f32() - count_timer.0.elapsed_secs();
+    let (chain, _) = world
+        .query::<(&ChainCounter, Option<&Parent>)>()
+        .iter(world)
+        .find(|(_, parent)| board_key(*parent) == board)?;
+    let chain = chain.0;
+    Some(GameSave {
+        width: board_config.width,
+        height: board_config.height,
+        difficulty: *world.get_resource::<Difficulty>()?,
+        color_count: world.get_resource::<ColorCount>()?.0,
+        rows,
+        cursor,
+        score: world.get_resource::<Score>()?.0,
+        chain,
+        max_chain: world.get_resource::<MaxChain>()?.0,
+        play_time: world.get_resource::<PlayTime>()?.0,
+        speed_origin,
+        ramp_clock,
+        lift_delay_left,
+    })
 }
 
-fn generate_spawning_block(
-    mut commands: Commands,
-    block_materials: Res<BlockMaterials>,
-    board: Query<(Entity, &Transform, &Sprite), With<Board>>,
-    bottom: Query<&Transform, With<Bottom>>,
-) {
-    for (board_entity, board_transform, sprite) in board.iter() {
-        for transform in bottom.iter() {
-            if transform.translation.y >= BLOCK_SIZE * -6.0 {
-                let relative_x =
-                    board_transform.translation.x - sprite.size.x / 2.0 + BLOCK_SIZE / 2.0;
-                let bottom_y =
-                    board_transform.translation.y - sprite.size.y / 2.0 - BLOCK_SIZE / 2.0;
-                let mut rng = rand::thread_rng();
-                let mut block_colors = vec![
-                    (BlockColor::Red, block_materials.red_material.clone()),
-                    (BlockColor::Green, block_materials.green_material.clone()),
-                    (BlockColor::Blue, block_materials.blue_material.clone()),
-                    (BlockColor::Yellow, block_materials.yellow_material.clone()),
-                    (BlockColor::Purple, block_materials.purple_material.clone()),
-                    // (BlockColor::Indigo, block_materials.indigo_material.clone()),
-                ];
-                block_colors.shuffle(&mut rng);
-                let mut previous_block_queue = VecDeque::with_capacity(2);
-                for column_idx in 0..6 {
-                    let number = rng.gen_range(0..block_colors.len());
-                    let block = commands
-                        .spawn_bundle(SpriteBundle {
-                            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                            material: block_colors[number].1.clone(),
-                            transform: Transform {
-                                translation: Vec3::new(
-                                    relative_x + BLOCK_SIZE * column_idx as f32,
-                                    bottom_y - BLOCK_SIZE as f32,
-                                    0.0,
-                                ),
-                                ..Default::default()
-                            },
+// Spawns the saved board for the solo player, with the `BoardConfig`, `Difficulty` and
+// `ColorCount` from the save already in place.
+pub fn apply_game_save(world: &mut World, save: &GameSave) {
+    let board_config = *world.get_resource::<BoardConfig>().unwrap();
+    let game_speed = *world.get_resource::<GameSpeed>().unwrap();
+    let mut board_rng = world.remove_resource::<BoardRng>().unwrap();
+    let mut queue = bevy::ecs::system::CommandQueue::default();
+    let (board, cursor) = {
+        let mut commands = Commands::new(&mut queue, world);
+        let parts = BoardParts {
+            board_materials: world.get_resource::<BoardMaterials>().unwrap(),
+            block_materials: world.get_resource::<BlockMaterials>().unwrap(),
+            bottom_materials: world.get_resource::<BottomMaterials>().unwrap(),
+            cursor_materials: world.get_resource::<CursorMaterials>().unwrap(),
+            board_config: &board_config,
+            game_speed: &game_speed,
+            settings: world.get_resource::<GameSettings>().unwrap(),
+        };
+        spawn_board(
+            &mut commands,
+            &parts,
+            &mut board_rng.0,
+            &save.rows,
+            Vec3::ZERO,
+        )
+    };
+    queue.apply(world);
+    world.insert_resource(board_rng);
+
+    let (col, row) = save.cursor;
+    let mut translation = GridPos {
+        col: col.max(0) as u8,
+        row: row.max(0) as u8,
+    }
+    .to_translation(1.0, &board_config);
+    translation.x += board_config.block_size / 2.0;
+    world.get_mut::<Transform>(cursor).unwrap().translation = translation;
+    let controls = world.get_resource::<KeyBindings>().unwrap().solo.clone();
+    world.entity_mut(cursor).insert(controls);
+
+    for (mut board_speed, mut ramp_clock, mut count_timer, parent) in world
+        .query::<(&mut GameSpeed, &mut RampClock, &mut CountTimer, &Parent)>()
+        .iter_mut(world)
+    {
+        if parent.0 != board {
+            continue;
+        }
+        board_speed.origin = save.speed_origin;
+        board_speed.current = board_speed
+            .ramp
+            .speed_at(save.speed_origin, save.ramp_clock);
+        ramp_clock.0 = save.ramp_clock;
+        let duration = count_timer.0.duration().as_secs_f32();
+        count_timer.0.set_elapsed(Duration::from_secs_f32(
+            (duration - save.lift_delay_left).clamp(0.0, duration),
+        ));
+    }
+    for (mut chain_counter, parent) in world
+        .query::<(&mut ChainCounter, &Parent)>()
+        .iter_mut(world)
+    {
+        if parent.0 == board {
+            chain_counter.0 = save.chain;
+        }
+    }
+    world.insert_resource(Score(save.score));
+    world.insert_resource(MaxChain(save.max_chain));
+    world.insert_resource(PlayTime(save.play_time));
+    let newest_row = save
+        .rows
+        .first()
+        .map(|row| row.iter().flatten().copied().collect())
+        .unwrap_or_default();
+    world
+        .get_resource_mut::<NextRow>()
+        .unwrap()
+        .0
+        .insert(Some(board), newest_row);
+}
+
+// Runs after the other setup, so its score and board replace the fresh ones.
+fn resume_saved_game(world: &mut World) {
+    if let Some(PendingGameSave(save)) = world.remove_resource::<PendingGameSave>() {
+        apply_game_save(world, &save);
+    }
+}
+
+// Everything a new board is built from, borrowed from the resources of the same name.
+struct BoardParts<'a> {
+    board_materials: &'a BoardMaterials,
+    block_materials: &'a BlockMaterials,
+    bottom_materials: &'a BottomMaterials,
+    cursor_materials: &'a CursorMaterials,
+    board_config: &'a BoardConfig,
+    game_speed: &'a GameSpeed,
+    settings: &'a GameSettings,
+}
+
+// `colors` holds rows from the bottom, starting with the `SPAWNING_ROWS` below the board.
+// Returns the board and its cursor; everything else on the board is one of its children.
+fn spawn_board(
+    commands: &mut Commands,
+    parts: &BoardParts,
+    rng: &mut StdRng,
+    colors: &[Vec<Option<BlockColor>>],
+    translation: Vec3,
+) -> (Entity, Entity) {
+    let BoardParts {
+        board_materials,
+        block_materials,
+        bottom_materials,
+        cursor_materials,
+        board_config,
+        game_speed,
+        settings,
+    } = *parts;
+    let block_size = board_config.block_size;
+    let board_transform = Transform {
+        translation,
+        ..Default::default()
+    };
+    let board_sprite = Sprite::new(board_config.size());
+    let board_entity = commands
+        .spawn_bundle(SpriteBundle {
+            material: board_materials.board_material.clone(),
+            sprite: board_sprite.clone(),
+            transform: board_transform,
+            ..Default::default()
+        })
+        .insert(Board)
+        .insert(BoardOrigin(translation))
+        .id();
+
+    // children are placed relative to the board
+    let relative_x = block_size / 2.0 - board_sprite.size.x / 2.0;
+    let bottom_y = board_config.bottom_y();
+
+    for (row_idx, row) in colors.iter().enumerate() {
+        for (column_idx, block_color) in row.iter().enumerate() {
+            if let Some(block_color) = block_color {
+                let mut block = commands.spawn_bundle(SpriteBundle {
+                    sprite: board_config.block_sprite(),
+                    material: block_material(block_materials, *block_color),
+                    ..Default::default()
+                });
+                block.insert(Block).insert(*block_color);
+                let rotation = block_rotation(settings, rng);
+                if row_idx < SPAWNING_ROWS {
+                    block
+                        .insert(Transform {
+                            translation: Vec3::new(
+                                relative_x + block_size * column_idx as f32,
+                                bottom_y - block_size * (SPAWNING_ROWS - 1 - row_idx) as f32,
+                                0.0,
+                            ),
+                            rotation,
                             ..Default::default()
                         })
-                        .insert(Block)
-                        .insert(block_colors[number].0)
-                        .insert(Spawning)
-                        .id();
-                    commands.entity(board_entity).push_children(&[block]);
-                    let tmp_remove_block = Some(block_colors.remove(number));
-                    previous_block_queue.push_back(tmp_remove_block);
-                    if previous_block_queue.len() > 1 {
-                        if let Some(Some(back_color_block)) = previous_block_queue.pop_front() {
-                            block_colors.push(back_color_block);
-                        }
-                    }
+                        .insert(Spawning);
+                } else {
+                    let grid_pos = GridPos {
+                        col: column_idx as u8,
+                        row: (row_idx - SPAWNING_ROWS) as u8,
+                    };
+                    block
+                        .insert(Transform {
+                            translation: grid_pos.to_translation(0.0, board_config),
+                            rotation,
+                            ..Default::default()
+                        })
+                        .insert(Fixed)
+                        .insert(grid_pos);
                 }
+                let block = block.id();
+                commands.entity(board_entity).push_children(&[block]);
             }
         }
     }
+    let bottom = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(board_sprite.size.x, block_size)),
+            material: bottom_materials.bottom_material.clone(),
+            transform: Transform {
+                translation: Vec3::new(0.0, bottom_y, 1.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Bottom)
+        .id();
+    commands.entity(board_entity).push_children(&[bottom]);
+    let cursor = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(block_size * 2.0, block_size)),
+            material: cursor_materials.cursor_material.clone(),
+            transform: Transform {
+                translation: board_config.cursor_start().extend(1.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Cursor)
+        .id();
+    let chain_counter = commands.spawn().insert(ChainCounter(1)).id();
+    let count_timer = commands
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(
+            game_speed.lift_delay,
+            false,
+        )))
+        .insert(GameSpeed {
+            origin: game_speed.current,
+            ..*game_speed
+        })
+        .insert(RampClock::default())
+        .insert(TopOutTimer(Timer::from_seconds(
+            settings.top_out_grace.unwrap_or(game_speed.lift_delay),
+            false,
+        )))
+        .id();
+    commands
+        .entity(board_entity)
+        .push_children(&[cursor, chain_counter, count_timer]);
+    (board_entity, cursor)
 }
 
-#[test]
-fn test_setup_board() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(setup_board.system());
-
-    world.insert_resource(BoardMaterials {
-        board_material: Handle::<ColorMaterial>::default(),
-    });
-    world.insert_resource(BlockMaterials {
-        red_material: Handle::<ColorMaterial>::default(),
-        green_material: Handle::<ColorMaterial>::default(),
-        blue_material: Handle::<ColorMaterial>::default(),
-        yellow_material: Handle::<ColorMaterial>::default(),
-        purple_material: Handle::<ColorMaterial>::default(),
-        indigo_material: Handle::<ColorMaterial>::default(),
-    });
-    world.insert_resource(BottomMaterials {
-        bottom_material: Handle::<ColorMaterial>::default(),
-    });
-    world.insert_resource(CursorMaterials {
-        cursor_material: Handle::<ColorMaterial>::default(),
-    });
+fn block_colors(color_count: &ColorCount, color_table: &ColorTable) -> Vec<BlockColor> {
+    (0..color_count.0.min(color_table.0.len()))
+        .map(|id| BlockColor(id as u8))
+        .collect()
+}
 
-    update_stage.run(&mut world);
-    assert_eq!(world.query::<&Board>().iter(&world).len(), 1);
-    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
-    assert!(world.query::<&Block>().iter(&world).len() > 5);
-    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 12);
-    assert_eq!(world.query::<&Bottom>().iter(&world).len(), 1);
+// Ids whose material `apply_theme` hasn't made yet show their base art untinted.
+pub fn block_material(
+    block_materials: &BlockMaterials,
+    block_color: BlockColor,
+) -> Handle<ColorMaterial> {
+    match block_color {
+        BlockColor::RED => block_materials.red_material.clone(),
+        BlockColor::GREEN => block_materials.green_material.clone(),
+        BlockColor::BLUE => block_materials.blue_material.clone(),
+        BlockColor::YELLOW => block_materials.yellow_material.clone(),
+        BlockColor::PURPLE => block_materials.purple_material.clone(),
+        BlockColor::INDIGO => block_materials.indigo_material.clone(),
+        _ => block_materials
+            .extra_materials
+            .get(block_color.0 as usize - BLOCK_COLORS.len())
+            .cloned()
+            .unwrap_or_else(|| block_material(block_materials, block_color.base())),
+    }
 }
 
-#[test]
-fn test_left_move_cursor() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_cursor.system());
-    world.spawn().insert(Board);
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
+// Multiplied over a color's block art, so it can deepen or dim the art but not lighten it.
+fn theme_tint(theme: Theme, block_color: BlockColor) -> Color {
+    match theme {
+        Theme::Classic => Color::WHITE,
+        // pulls each color toward its pure hue, further apart from the others
+        Theme::Vivid => match block_color.base() {
+            BlockColor::RED => Color::rgb(1.0, 0.55, 0.55),
+            BlockColor::GREEN => Color::rgb(0.55, 1.0, 0.55),
+            BlockColor::BLUE => Color::rgb(0.55, 0.7, 1.0),
+            BlockColor::YELLOW => Color::rgb(1.0, 1.0, 0.45),
+            BlockColor::PURPLE => Color::rgb(1.0, 0.55, 1.0),
+            _ => Color::rgb(0.6, 0.55, 1.0),
         },
-        ..Default::default()
-    });
+        Theme::Night => Color::rgb(0.65, 0.65, 0.8),
+    }
+}
 
-    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::ZERO
-    );
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Left);
-    world.insert_resource(input);
+// Every block of a color shares one material, so tinting the materials recolors the
+// blocks already on a board, new ones and the menu preview alike. Ids past the drawn
+// colors get their own copy of their base art here, tinted by the `ColorTable` on top.
+pub fn apply_theme(
+    theme: Res<Theme>,
+    color_table: Res<ColorTable>,
+    block_materials: Option<ResMut<BlockMaterials>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let mut block_materials = match block_materials {
+        Some(block_materials)
+            if theme.is_changed() || color_table.is_changed() || block_materials.is_added() =>
+        {
+            block_materials
+        }
+        _ => return,
+    };
+    while BLOCK_COLORS.len() + block_materials.extra_materials.len() < color_table.0.len() {
+        let id = BLOCK_COLORS.len() + block_materials.extra_materials.len();
+        let texture = materials
+            .get(block_material(
+                &block_materials,
+                BlockColor(id as u8).base(),
+            ))
+            .and_then(|base_material| base_material.texture.clone());
+        let extra_material = materials.add(ColorMaterial {
+            color: Color::WHITE,
+            texture,
+        });
+        block_materials.extra_materials.push(extra_material);
+    }
+    for (id, tint) in color_table.0.iter().enumerate() {
+        let block_color = BlockColor(id as u8);
+        if let Some(material) = materials.get_mut(block_material(&block_materials, block_color)) {
+            material.color = theme_tint(*theme, block_color) * Vec4::from(*tint);
+        }
+    }
+}
 
+// The only place colors map to symbols, so a color looks the same wherever its block
+// came from.
+fn block_symbol(block_color: BlockColor) -> &'static str {
+    match block_color {
+        BlockColor::RED => "♥",
+        BlockColor::GREEN => "▲",
+        BlockColor::BLUE => "■",
+        BlockColor::YELLOW => "★",
+        BlockColor::PURPLE => "◆",
+        BlockColor::INDIGO => "●",
+        _ => ["+", "×", "#", "="][(block_color.0 as usize - BLOCK_COLORS.len()) % 4],
+    }
+}
+
+// returns the cell that ends the first horizontal or vertical run of 3 same colors.
+fn find_initial_match<R: AsRef<[Option<BlockColor>]>>(colors: &[R]) -> Option<(usize, usize)> {
+    for (row_idx, row) in colors.iter().enumerate() {
+        let row = row.as_ref();
+        for (column_idx, block_color) in row.iter().enumerate() {
+            if block_color.is_none() {
+                continue;
+            }
+            if column_idx >= 2
+                && row[column_idx - 1] == *block_color
+                && row[column_idx - 2] == *block_color
+            {
+                return Some((row_idx, column_idx));
+            }
+            if row_idx >= 2
+                && colors[row_idx - 1].as_ref()[column_idx] == *block_color
+                && colors[row_idx - 2].as_ref()[column_idx] == *block_color
+            {
+                return Some((row_idx, column_idx));
+            }
+        }
+    }
+    None
+}
+
+fn has_initial_match<R: AsRef<[Option<BlockColor>]>>(colors: &[R]) -> bool {
+    find_initial_match(colors).is_some()
+}
+
+fn remove_initial_matches<R: AsRef<[Option<BlockColor>]> + AsMut<[Option<BlockColor>]>>(
+    colors: &mut [R],
+    block_colors: &[BlockColor],
+    rng: &mut impl Rng,
+) {
+    while let Some((row_idx, column_idx)) = find_initial_match(colors) {
+        let cell = &mut colors[row_idx].as_mut()[column_idx];
+        let current = *cell;
+        *cell = block_colors
+            .iter()
+            .filter(|block_color| Some(**block_color) != current)
+            .choose(rng)
+            .copied();
+    }
+}
+
+// Attaches a cover to every new board, the COM's included.
+fn setup_board_bottom_cover(
+    mut commands: Commands,
+    board_bottom_cover_materials: Res<BoardBottomCoverMaterials>,
+    board_config: Res<BoardConfig>,
+    board: Query<Entity, Added<Board>>,
+) {
+    let block_size = board_config.block_size;
+    for board_entity in board.iter() {
+        let board_bottom_cover = commands
+            .spawn_bundle(SpriteBundle {
+                material: board_bottom_cover_materials
+                    .board_bottom_cover_material
+                    .clone(),
+                sprite: Sprite::new(Vec2::new(board_config.size().x, 2.0 * block_size)),
+                transform: Transform {
+                    // the top edge sits on the bottom edge of the grid
+                    translation: Vec3::new(
+                        0.0,
+                        board_config.bottom_edge_y() - block_size,
+                        BOTTOM_COVER_Z,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BoardBottomCover)
+            .id();
+        commands
+            .entity(board_entity)
+            .push_children(&[board_bottom_cover]);
+    }
+}
+
+// x of each line between two columns, relative to the board.
+fn column_guide_xs(board_config: &BoardConfig) -> Vec<f32> {
+    (1..board_config.width)
+        .map(|col| col as f32 * board_config.block_size - board_config.size().x / 2.0)
+        .collect()
+}
+
+// The board image is transparent, so the grid gets a border and, unless turned off in
+// `GameSettings`, guides between the columns behind the blocks and the game over line
+// in front of them.
+fn setup_board_frame(
+    mut commands: Commands,
+    board_config: Res<BoardConfig>,
+    settings: Res<GameSettings>,
+    board: Query<Entity, Added<Board>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut frame_materials: Local<Option<[Handle<ColorMaterial>; 3]>>,
+) {
+    let [frame_material, guide_material, line_material] = frame_materials
+        .get_or_insert_with(|| {
+            [
+                materials.add(Color::rgba(1.0, 1.0, 1.0, 0.6).into()),
+                materials.add(Color::rgba(1.0, 1.0, 1.0, 0.1).into()),
+                materials.add(Color::rgba(1.0, 0.2, 0.2, 0.7).into()),
+            ]
+        })
+        .clone();
+    let size = board_config.size();
+    let outer = size + Vec2::splat(2.0 * FRAME_THICKNESS);
+    // top, bottom, left and right
+    let sides = [
+        (Vec2::new(outer.x, FRAME_THICKNESS), Vec2::new(0.0, 1.0)),
+        (Vec2::new(outer.x, FRAME_THICKNESS), Vec2::new(0.0, -1.0)),
+        (Vec2::new(FRAME_THICKNESS, outer.y), Vec2::new(-1.0, 0.0)),
+        (Vec2::new(FRAME_THICKNESS, outer.y), Vec2::new(1.0, 0.0)),
+    ];
+    for board_entity in board.iter() {
+        let mut children = Vec::new();
+        for (side_size, direction) in sides {
+            let offset = direction * (size + Vec2::splat(FRAME_THICKNESS)) / 2.0;
+            children.push(
+                commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite::new(side_size),
+                        material: frame_material.clone(),
+                        transform: Transform::from_xyz(offset.x, offset.y, 1.0),
+                        ..Default::default()
+                    })
+                    .insert(BoardFrame)
+                    .id(),
+            );
+        }
+        if settings.column_guides {
+            for x in column_guide_xs(&board_config) {
+                children.push(
+                    commands
+                        .spawn_bundle(SpriteBundle {
+                            sprite: Sprite::new(Vec2::new(COLUMN_GUIDE_THICKNESS, size.y)),
+                            material: guide_material.clone(),
+                            transform: Transform::from_xyz(x, 0.0, -0.5),
+                            ..Default::default()
+                        })
+                        .insert(ColumnGuide)
+                        .id(),
+                );
+            }
+        }
+        if settings.game_over_line {
+            children.push(
+                commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite::new(Vec2::new(size.x, GAME_OVER_LINE_THICKNESS)),
+                        material: line_material.clone(),
+                        transform: Transform::from_xyz(0.0, board_config.game_over_line_y(), 0.5),
+                        ..Default::default()
+                    })
+                    .insert(GameOverLine)
+                    .insert(HudElement::default())
+                    .id(),
+            );
+        }
+        commands.entity(board_entity).push_children(&children);
+    }
+}
+
+// A strip of small cells just under the bottom cover, one per column.
+fn setup_next_row_preview(
+    mut commands: Commands,
+    board_config: Res<BoardConfig>,
+    board: Query<Entity, Added<Board>>,
+) {
+    let block_size = board_config.block_size;
+    let relative_x = block_size / 2.0 - board_config.size().x / 2.0;
+    for board_entity in board.iter() {
+        for column_idx in 0..board_config.width {
+            let cell = commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(block_size, block_size) / 2.0),
+                    transform: Transform {
+                        translation: Vec3::new(
+                            relative_x + block_size * column_idx as f32,
+                            board_config.bottom_y() - 2.0 * block_size,
+                            1.0,
+                        ),
+                        ..Default::default()
+                    },
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: true,
+                    },
+                    ..Default::default()
+                })
+                .insert(NextRowPreview(column_idx))
+                .insert(HudElement { shown: false })
+                .id();
+            commands.entity(board_entity).push_children(&[cell]);
+        }
+    }
+}
+
+// A copy of a block material with `shade` laid over its themed color. When `apply_theme`
+// has since changed that color, the copy is recolored in place, so the sprites already
+// showing it follow the new `Theme` or `ColorTable` too.
+fn shaded_material<K: Hash + Eq>(
+    materials: &mut Assets<ColorMaterial>,
+    shaded_materials: &mut HashMap<K, (Color, Handle<ColorMaterial>)>,
+    key: K,
+    full_material: &Handle<ColorMaterial>,
+    shade: impl FnOnce(Color) -> Color,
+) -> Handle<ColorMaterial> {
+    let (color, texture) = match materials.get(full_material) {
+        Some(full_material) => (full_material.color, full_material.texture.clone()),
+        None => (Color::WHITE, None),
+    };
+    if let Some((shaded_from, shaded_material)) = shaded_materials.get_mut(&key) {
+        if *shaded_from != color {
+            *shaded_from = color;
+            if let Some(shaded_material) = materials.get_mut(&*shaded_material) {
+                shaded_material.color = shade(color);
+            }
+        }
+        return shaded_material.clone();
+    }
+    let shaded_material = materials.add(ColorMaterial {
+        color: shade(color),
+        texture,
+    });
+    shaded_materials.insert(key, (color, shaded_material.clone()));
+    shaded_material
+}
+
+// Shows `NextRow` in darker copies of the block materials, made on first use. Cells of
+// a board without a next row stay hidden.
+fn update_next_row_preview(
+    next_row: Res<NextRow>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    block_materials: Res<BlockMaterials>,
+    mut shaded_materials: Local<HashMap<BlockColor, (Color, Handle<ColorMaterial>)>>,
+    mut cell: Query<(
+        &NextRowPreview,
+        &Parent,
+        &mut Handle<ColorMaterial>,
+        &mut HudElement,
+    )>,
+) {
+    for (preview, parent, mut material, mut hud_element) in cell.iter_mut() {
+        let block_color = next_row
+            .0
+            .get(&Some(parent.0))
+            .and_then(|row| row.get(preview.0));
+        let block_color = match block_color {
+            Some(block_color) => *block_color,
+            None => {
+                if hud_element.shown {
+                    hud_element.shown = false;
+                }
+                continue;
+            }
+        };
+        let shaded_material = shaded_material(
+            &mut materials,
+            &mut shaded_materials,
+            block_color,
+            &block_material(&block_materials, block_color),
+            |color| {
+                Color::rgb(
+                    NEXT_ROW_PREVIEW_SHADE,
+                    NEXT_ROW_PREVIEW_SHADE,
+                    NEXT_ROW_PREVIEW_SHADE,
+                ) * Vec4::from(color)
+            },
+        );
+        if *material != shaded_material {
+            *material = shaded_material;
+        }
+        if !hud_element.shown {
+            hud_element.shown = true;
+        }
+    }
+}
+
+fn setup_chain_text(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    board_config: Res<BoardConfig>,
+    board: Query<Entity, Added<Board>>,
+) {
+    for board_entity in board.iter() {
+        let chain_text = commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(
+                    "",
+                    TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 40.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                    TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center,
+                    },
+                ),
+                transform: Transform::from_xyz(
+                    0.0,
+                    board_config.size().y / 2.0 - board_config.block_size,
+                    3.0,
+                ),
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: true,
+                },
+                ..Default::default()
+            })
+            .insert(ChainText(1))
+            .insert(HudElement { shown: false })
+            .id();
+        commands.entity(board_entity).push_children(&[chain_text]);
+    }
+}
+
+// Runs on every new `BlockColor`, which covers the initial board, spawned rows and
+// garbage turned into blocks.
+fn spawn_block_symbols(
+    mut commands: Commands,
+    accessibility: Res<AccessibilitySettings>,
+    font_assets: Res<FontAssets>,
+    board_config: Res<BoardConfig>,
+    block: Query<(Entity, &BlockColor, &Transform), Added<BlockColor>>,
+) {
+    if !accessibility.symbols {
+        return;
+    }
+    for (entity, block_color, transform) in block.iter() {
+        let symbol = commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(
+                    block_symbol(*block_color),
+                    TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: board_config.block_size * 0.5,
+                        color: Color::rgba(1.0, 1.0, 1.0, 0.9),
+                    },
+                    TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center,
+                    },
+                ),
+                // undoes the block's cosmetic rotation so the symbol stays upright
+                transform: Transform {
+                    translation: Vec3::new(0.0, 0.0, 0.5),
+                    rotation: transform.rotation.inverse(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BlockSymbol)
+            .id();
+        commands.entity(entity).push_children(&[symbol]);
+    }
+}
+
+// Symbols flash along with their block while it is cleared.
+fn sync_block_symbol_visible(
+    block: Query<(&Visible, &Children), (With<Block>, Changed<Visible>)>,
+    mut symbol: Query<&mut Visible, (With<BlockSymbol>, Without<Block>)>,
+) {
+    for (visible, children) in block.iter() {
+        for child in children.iter() {
+            if let Ok(mut symbol_visible) = symbol.get_mut(*child) {
+                symbol_visible.is_visible = visible.is_visible;
+            }
+        }
+    }
+}
+
+// Pops the text in whenever the chain grows, and hides it once the counter is back to 1.
+fn update_chain_text(
+    mut commands: Commands,
+    chain_counter: Query<(&ChainCounter, Option<&Parent>)>,
+    mut chain_text: Query<(
+        Entity,
+        &mut ChainText,
+        &mut Text,
+        &mut HudElement,
+        &Transform,
+        Option<&Parent>,
+    )>,
+) {
+    let chains = chain_counter
+        .iter()
+        .map(|(cc, parent)| (board_key(parent), cc.0))
+        .collect::<HashMap<_, _>>();
+    for (entity, mut chain_text, mut text, mut hud_element, transform, parent) in
+        chain_text.iter_mut()
+    {
+        let chain = chains.get(&board_key(parent)).copied().unwrap_or(1);
+        if chain == chain_text.0 {
+            continue;
+        }
+        chain_text.0 = chain;
+        hud_element.shown = chain > 1;
+        if chain > 1 {
+            text.sections[0].value = format!("x{}", chain);
+            let popped = Transform {
+                scale: Vec3::splat(1.5),
+                ..*transform
+            };
+            commands.entity(entity).insert(popped.ease_to(
+                Transform {
+                    scale: Vec3::ONE,
+                    ..*transform
+                },
+                EaseFunction::BackOut,
+                EasingType::Once {
+                    duration: Duration::from_secs_f32(0.15),
+                },
+            ));
+        }
+    }
+}
+
+fn cursor_pulse_scale(seconds: f32) -> f32 {
+    1.0 + CURSOR_PULSE_AMOUNT * (seconds * std::f32::consts::TAU / CURSOR_PULSE_SECONDS).sin()
+}
+
+// Only the drawn size pulses; swaps read the cursor's translation alone.
+fn pulse_cursor(
+    time: Res<Time>,
+    accessibility: Res<AccessibilitySettings>,
+    mut cursor: Query<&mut Transform, With<Cursor>>,
+) {
+    let scale = if accessibility.reduce_motion {
+        1.0
+    } else {
+        cursor_pulse_scale(time.seconds_since_startup() as f32)
+    };
+    for mut transform in cursor.iter_mut() {
+        transform.scale = Vec3::new(scale, scale, 1.0);
+    }
+}
+
+// A direction moves once when pressed and then repeats while held, as `InputTuning`
+// sets. `held` keeps how long each cursor has held each direction.
+fn move_cursor(
+    keyboard_input: Res<Input<KeyCode>>,
+    fixed_step: Res<FixedStep>,
+    tuning: Res<InputTuning>,
+    board_config: Res<BoardConfig>,
+    mut held: Local<HashMap<(Entity, usize), f32>>,
+    mut cursor: Query<(Entity, &mut Transform, &PlayerControls), With<Cursor>>,
+) {
+    let block_size = board_config.block_size;
+    let min = board_config.cursor_min();
+    let max = board_config.cursor_max();
+    for (entity, mut transform, controls) in cursor.iter_mut() {
+        for (direction, keys) in [
+            &controls.left,
+            &controls.right,
+            &controls.up,
+            &controls.down,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let moves = if just_pressed(&keyboard_input, keys) {
+                held.insert((entity, direction), 0.0);
+                1
+            } else if pressed(&keyboard_input, keys) {
+                let held = held.entry((entity, direction)).or_insert(0.0);
+                let held_before = *held;
+                *held += fixed_step.0;
+                tuning.repeats(held_before, *held)
+            } else {
+                held.remove(&(entity, direction));
+                0
+            };
+            if moves == 0 {
+                continue;
+            }
+            let translation = &mut transform.translation;
+            for _ in 0..moves {
+                // left, right, up, down as listed above
+                match direction {
+                    0 if translation.x > min.x + block_size / 2.0 => translation.x -= block_size,
+                    1 if translation.x < max.x - block_size / 2.0 => translation.x += block_size,
+                    2 if translation.y < max.y => translation.y += block_size,
+                    3 if translation.y > min.y => translation.y -= block_size,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// How many steps a swap blocked by a moving or falling block keeps retrying.
+const SWAP_BUFFER_STEPS: u32 = 8;
+
+// What a swap attempt changes off the board: the swap sound, the puzzle's move count
+// and the rejection that `SwapRejected` reports.
+#[derive(SystemParam)]
+pub struct SwapFeedback<'a> {
+    swap_rejected: EventWriter<'a, SwapRejected>,
+    audio: Res<'a, Audio>,
+    sound_assets: Res<'a, SoundAssets>,
+    moves_left: Option<ResMut<'a, MovesLeft>>,
+}
+
+impl SwapFeedback<'_> {
+    fn out_of_moves(&self) -> bool {
+        self.moves_left
+            .as_ref()
+            .is_some_and(|moves_left| moves_left.0 == 0)
+    }
+
+    fn swapped(&mut self) {
+        self.audio.play(self.sound_assets.swap_sound.clone());
+        if let Some(moves_left) = self.moves_left.as_mut() {
+            moves_left.0 -= 1;
+        }
+    }
+}
+
+fn move_tag_block(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    cursor: Query<(Entity, &Transform, &PlayerControls, Option<&Parent>), With<Cursor>>,
+    block: Query<SwapCandidate, With<Block>>,
+    lift: BoardLift,
+    mut feedback: SwapFeedback,
+    mut buffered: Local<HashMap<Entity, u32>>,
+) {
+    let lift_offsets = lift.offsets();
+    for (entity, cursor_transform, controls, parent) in cursor.iter() {
+        let pressed_now = just_pressed(&keyboard_input, &controls.swap);
+        let steps_left = match buffered.remove(&entity) {
+            _ if pressed_now => SWAP_BUFFER_STEPS,
+            Some(steps_left) => steps_left,
+            None => continue,
+        };
+        if feedback.out_of_moves() {
+            continue;
+        }
+        let board = board_key(parent);
+        match swap_at_cursor(
+            &mut commands,
+            cursor_transform,
+            board_blocks(block.iter(), board),
+            lift_offsets.get(&board).copied().unwrap_or(0.0),
+            &lift.board_config,
+        ) {
+            SwapAttempt::Swapped => feedback.swapped(),
+            SwapAttempt::Rejected => {
+                // only the press itself is reported; retries stay quiet
+                if pressed_now {
+                    feedback.swap_rejected.send(SwapRejected);
+                }
+                if steps_left > 1 {
+                    buffered.insert(entity, steps_left - 1);
+                }
+            }
+            SwapAttempt::Nothing => {}
+        }
+    }
+}
+
+// Number keys pick the colors in `BLOCK_COLORS` order.
+const SANDBOX_KEYS: [KeyCode; 6] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+];
+
+fn sandbox_color(keyboard_input: &Input<KeyCode>) -> Option<BlockColor> {
+    SANDBOX_KEYS
+        .iter()
+        .zip(BLOCK_COLORS)
+        .find(|(key, _)| keyboard_input.just_pressed(**key))
+        .map(|(_, block_color)| block_color)
+}
+
+// What a new block is drawn with: its color's material and, with the setting on, a
+// random turn from `block_rotation`.
+#[derive(SystemParam)]
+pub struct BlockLooks<'a> {
+    block_materials: Res<'a, BlockMaterials>,
+    settings: Res<'a, GameSettings>,
+    board_rng: ResMut<'a, BoardRng>,
+}
+
+// A number key puts a settled block of its color in the cursor's left cell. The same
+// key on a block of that color takes it away, and on another color replaces it; cells
+// with a block that isn't settled are left alone. Placed blocks fall and clear like any
+// other.
+fn place_sandbox_block(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    game_mode: Res<GameMode>,
+    cursor: Query<(&Transform, Option<&Parent>), With<Cursor>>,
+    block: Query<
+        (
+            Entity,
+            &Transform,
+            &BlockColor,
+            Option<&Parent>,
+            Option<&Fixed>,
+        ),
+        With<Block>,
+    >,
+    lift: BoardLift,
+    mut looks: BlockLooks,
+) {
+    if *game_mode != GameMode::Sandbox {
+        return;
+    }
+    let new_color = match sandbox_color(&keyboard_input) {
+        Some(new_color) => new_color,
+        None => return,
+    };
+    let lift_offsets = lift.offsets();
+    let board_config = &lift.board_config;
+    for (cursor_transform, parent) in cursor.iter() {
+        let board = board_key(parent);
+        let lift_offset = lift_offsets.get(&board).copied().unwrap_or(0.0);
+        let cell = logical_cell(
+            &Transform::from_xyz(
+                cursor_transform.translation.x - board_config.block_size / 2.0,
+                cursor_transform.translation.y,
+                0.0,
+            ),
+            lift_offset,
+            board_config,
+        );
+        let grid_pos = match GridPos::from_logical_cell(cell, board_config) {
+            Some(grid_pos) => grid_pos,
+            None => continue,
+        };
+        let current = block.iter().find(|(_, transform, _, block_parent, _)| {
+            board_key(*block_parent) == board
+                && logical_cell(transform, lift_offset, board_config) == cell
+        });
+        if let Some((entity, _, block_color, _, fixed)) = current {
+            if fixed.is_none() {
+                continue;
+            }
+            commands.entity(entity).despawn_recursive();
+            if *block_color == new_color {
+                continue;
+            }
+        }
+        let mut translation = grid_pos.to_translation(0.0, board_config);
+        translation.y += lift_offset;
+        let new_block = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: board_config.block_sprite(),
+                material: block_material(&looks.block_materials, new_color),
+                transform: Transform {
+                    translation,
+                    rotation: block_rotation(&looks.settings, &mut looks.board_rng.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Block)
+            .insert(new_color)
+            .insert(Fixed)
+            .insert(grid_pos)
+            .id();
+        if let Some(board) = board {
+            commands.entity(board).push_children(&[new_block]);
+        }
+    }
+}
+
+type SwapCandidate<'a> = (
+    Entity,
+    &'a Transform,
+    Option<&'a Parent>,
+    Option<&'a Fixed>,
+    Option<&'a Move>,
+    Option<&'a Moving>,
+    Option<&'a Garbage>,
+    (
+        Option<&'a Fall>,
+        Option<&'a Floating>,
+        Option<&'a FixedPrepare>,
+    ),
+);
+
+enum SwapAttempt {
+    Swapped,
+    // a block mid-swap or mid-fall has no Fixed, so it would otherwise look like an empty cell
+    Rejected,
+    Nothing,
+}
+
+fn board_blocks<'a>(
+    block: impl Iterator<Item = SwapCandidate<'a>>,
+    board: BoardKey,
+) -> impl Iterator<Item = SwapCandidate<'a>> {
+    block.filter(move |(_, _, parent, _, _, _, _, _)| board_key(*parent) == board)
+}
+
+// Swaps the two cells under the cursor. `block` must only hold the cursor's board.
+fn swap_at_cursor<'a>(
+    commands: &mut Commands,
+    cursor_transform: &Transform,
+    block: impl Iterator<Item = SwapCandidate<'a>>,
+    lift_offset: f32,
+    board_config: &BoardConfig,
+) -> SwapAttempt {
+    let x = cursor_transform.translation.x;
+    let left_x = x - board_config.block_size / 2.0;
+    let right_x = x + board_config.block_size / 2.0;
+    let mut right_block = (None, None);
+    let mut left_block = (None, None);
+    let mut left_collide = false;
+    let mut right_collide = false;
+    let mut target_moving = false;
+    let mut target_falling = false;
+    let (left_col, cursor_row) = logical_cell(
+        &Transform::from_xyz(left_x, cursor_transform.translation.y, 0.0),
+        lift_offset,
+        board_config,
+    );
+    let right_col = left_col + 1;
+    // a cursor past either wall would move a block off the board
+    if left_col < 0 || right_col >= board_config.width as i32 {
+        return SwapAttempt::Nothing;
+    }
+
+    for (block_entity, block_transform, _, fixed, move_target, moving, garbage, unsettled) in block
+    {
+        let (col, row) = logical_cell(block_transform, lift_offset, board_config);
+        // a block falling into or out of either cell, even partly, blocks the swap
+        let falling = matches!(
+            unsettled,
+            (Some(_), _, _) | (_, Some(_), _) | (_, _, Some(_))
+        );
+        if falling
+            && (col == left_col || col == right_col)
+            && (block_transform.translation.y - cursor_transform.translation.y).abs()
+                < board_config.block_size
+        {
+            target_falling = true;
+        }
+        // garbage fills its cell but never moves, like a block that isn't settled
+        let fixed = fixed.filter(|_| garbage.is_none());
+        if row == cursor_row {
+            let is_moving = move_target.is_some() || moving.is_some();
+            // left target
+            if col == left_col {
+                left_block = (Some(block_entity), fixed);
+                target_moving |= is_moving;
+            }
+            // right target
+            if col == right_col {
+                right_block = (Some(block_entity), fixed);
+                target_moving |= is_moving;
+            }
+        }
+        // fall block collision: in the cell above but not landed on it yet
+        else if row == cursor_row + 1
+            && block_transform.translation.y < cursor_transform.translation.y + BLOCK_SIZE
+        {
+            // left collision exists
+            if col == left_col {
+                left_collide = true;
+            }
+            // right collision exsists
+            else if col == right_col {
+                right_collide = true;
+            }
+        }
+    }
+    if target_moving || target_falling {
+        return SwapAttempt::Rejected;
+    }
+    let cell_state =
+        |(entity, fixed): (Option<Entity>, Option<&Fixed>), collide: bool| match (entity, fixed) {
+            (Some(entity), Some(_)) => Some(CellState::Fixed(entity)),
+            (Some(_), None) => Some(CellState::Occupied),
+            (None, _) if collide => Some(CellState::Landing),
+            (None, _) => None,
+        };
+    match swap_decision(
+        cell_state(left_block, left_collide),
+        cell_state(right_block, right_collide),
+    ) {
+        SwapDecision::BothSwap { left, right } => {
+            commands
+                .entity(right)
+                .remove::<Fixed>()
+                .insert(Move(left_x));
+            commands
+                .entity(left)
+                .remove::<Fixed>()
+                .insert(Move(right_x));
+            SwapAttempt::Swapped
+        }
+        SwapDecision::MoveRightToLeft(right) => {
+            commands
+                .entity(right)
+                .remove::<Fixed>()
+                .insert(Move(left_x));
+            SwapAttempt::Swapped
+        }
+        SwapDecision::MoveLeftToRight(left) => {
+            commands
+                .entity(left)
+                .remove::<Fixed>()
+                .insert(Move(right_x));
+            SwapAttempt::Swapped
+        }
+        SwapDecision::None => SwapAttempt::Nothing,
+    }
+}
+
+// One of the two cells under the cursor; an empty cell nothing is landing in is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CellState {
+    Fixed(Entity),
+    // garbage, or a block that can't be swapped right now
+    Occupied,
+    // empty, but a block from the cell above is already partly in it
+    Landing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SwapDecision {
+    BothSwap { left: Entity, right: Entity },
+    MoveRightToLeft(Entity),
+    MoveLeftToRight(Entity),
+    None,
+}
+
+// A settled block swaps with another one or slides into a free empty cell; anything
+// else stays put, the same way from either side.
+fn swap_decision(left: Option<CellState>, right: Option<CellState>) -> SwapDecision {
+    match (left, right) {
+        (Some(CellState::Fixed(left)), Some(CellState::Fixed(right))) => {
+            SwapDecision::BothSwap { left, right }
+        }
+        (None, Some(CellState::Fixed(right))) => SwapDecision::MoveRightToLeft(right),
+        (Some(CellState::Fixed(left)), None) => SwapDecision::MoveLeftToRight(left),
+        _ => SwapDecision::None,
+    }
+}
+
+// Left cells of the swaps that make the board clear something. Nothing is offered while
+// the board would clear or drop blocks on its own, since the outcome can't be told apart.
+fn matching_swaps<'a>(
+    grid: &'a BoardGrid,
+    square_clear: &'a SquareClear,
+) -> impl Iterator<Item = GridPos> + 'a {
+    let mut settled = grid.clone();
+    settle_grid(&mut settled);
+    let still = settled == *grid && simulate_cascade(grid.clone(), square_clear).is_empty();
+    grid.iter()
+        .enumerate()
+        .filter(move |_| still)
+        .flat_map(|(row_idx, row)| {
+            (0..row.len().saturating_sub(1))
+                // swapping two of a kind or two empty cells changes nothing
+                .filter(move |&column_idx| row[column_idx] != row[column_idx + 1])
+                .map(move |column_idx| GridPos {
+                    col: column_idx as u8,
+                    row: row_idx as u8,
+                })
+        })
+        .filter(move |&grid_pos| swap_clears(grid, grid_pos, square_clear))
+}
+
+// Whether one swap makes the still grid clear something.
+fn has_matching_swap(grid: &BoardGrid, square_clear: &SquareClear) -> bool {
+    matching_swaps(grid, square_clear).next().is_some()
+}
+
+// Whether swapping the cell at `grid_pos` with the one to its right makes the settled
+// grid clear something. Two blocks trading places can only line up runs through their
+// own cells; a block moved into an empty cell may fall, so that case is simulated.
+fn swap_clears(grid: &BoardGrid, grid_pos: GridPos, square_clear: &SquareClear) -> bool {
+    let (row_idx, column_idx) = (grid_pos.row as usize, grid_pos.col as usize);
+    let mut swapped = grid.clone();
+    swapped[row_idx].swap(column_idx, column_idx + 1);
+    if swapped[row_idx][column_idx].is_none() || swapped[row_idx][column_idx + 1].is_none() {
+        return !simulate_cascade(swapped, square_clear).is_empty();
+    }
+    let right = GridPos {
+        col: grid_pos.col + 1,
+        ..grid_pos
+    };
+    count_matches_at(&swapped, grid_pos) > 0
+        || count_matches_at(&swapped, right) > 0
+        || square_clear.0
+            && square_cells(&swapped)
+                .iter()
+                .any(|cell| *cell == grid_pos || *cell == right)
+}
+
+// Deals the grid's colors out again over the same cells until one swap clears something
+// and nothing clears by itself. None if no deal within the attempts gets there.
+fn shuffle_to_move(
+    grid: &BoardGrid,
+    rng: &mut StdRng,
+    square_clear: &SquareClear,
+) -> Option<BoardGrid> {
+    let mut colors = grid.iter().flatten().flatten().copied().collect::<Vec<_>>();
+    for _ in 0..NO_MOVES_SHUFFLE_ATTEMPTS {
+        colors.shuffle(rng);
+        let mut dealt = colors.iter().copied();
+        let shuffled = grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| cell.and_then(|_| dealt.next()))
+                    .collect()
+            })
+            .collect::<BoardGrid>();
+        if has_matching_swap(&shuffled, square_clear) {
+            return Some(shuffled);
+        }
+    }
+    None
+}
+
+// The settled boards, and the rules that decide what clears on them.
+#[derive(SystemParam)]
+pub struct MatchView<'a> {
+    board_snapshot: Res<'a, BoardSnapshot>,
+    square_clear: Res<'a, SquareClear>,
+}
+
+// The simple COM: every tick it makes a swap that clears something if the last
+// snapshot of its board has one, and otherwise swaps at a random settled block. Swaps
+// go through the same path as the player's.
+fn ai_swap_blocks(
+    mut commands: Commands,
+    fixed_step: Res<FixedStep>,
+    mut ai_rng: ResMut<AiRng>,
+    mut ai_cursor: Query<
+        (&mut Transform, &mut AiController, Option<&Parent>),
+        (With<Cursor>, Without<Bottom>),
+    >,
+    block: Query<SwapCandidate, (With<Block>, Without<Cursor>)>,
+    lift: BoardLift,
+    match_view: MatchView,
+) {
+    let lift_offsets = lift.offsets();
+    let board_config = &lift.board_config;
+    for (mut cursor_transform, mut ai_controller, parent) in ai_cursor.iter_mut() {
+        ai_controller.timer.tick(fixed_step.delta());
+        if !ai_controller.timer.just_finished() {
+            continue;
+        }
+        let board = board_key(parent);
+        let lift_offset = lift_offsets.get(&board).copied().unwrap_or(0.0);
+        let matching_swap = match_view
+            .board_snapshot
+            .board(board)
+            .and_then(|grid| matching_swaps(grid, &match_view.square_clear).choose(&mut ai_rng.0));
+        let cursor_target = match matching_swap {
+            Some(grid_pos) => {
+                let left = grid_pos.to_translation(0.0, board_config);
+                Some(Vec2::new(
+                    left.x + board_config.block_size / 2.0,
+                    left.y + lift_offset,
+                ))
+            }
+            None => board_blocks(block.iter(), board)
+                .filter(|(_, _, _, fixed, _, _, garbage, _)| fixed.is_some() && garbage.is_none())
+                .choose(&mut ai_rng.0)
+                .map(|(_, target_transform, _, _, _, _, _, _)| {
+                    // the cursor's left cell sits on the target, or its right one at the wall
+                    Vec2::new(
+                        (target_transform.translation.x + board_config.block_size / 2.0)
+                            .min(board_config.cursor_max().x),
+                        target_transform.translation.y,
+                    )
+                }),
+        };
+        if let Some(cursor_target) = cursor_target {
+            cursor_transform.translation.x = cursor_target.x;
+            cursor_transform.translation.y = cursor_target.y;
+            swap_at_cursor(
+                &mut commands,
+                &cursor_transform,
+                board_blocks(block.iter(), board),
+                lift_offset,
+                board_config,
+            );
+        }
+    }
+}
+
+// Transform easing isn't match, because y-axis must be defined.
+fn move_block(
+    mut commands: Commands,
+    tuning_config: Res<TuningConfig>,
+    mut block: Query<(Entity, &Transform, &Move), (With<Block>, With<Move>)>,
+) {
+    for (entity, transform, move_target) in block.iter_mut() {
+        commands
+            .entity(entity)
+            .insert(Moving(transform.translation.x))
+            .insert(SwapSlide {
+                from: transform.translation.x,
+                to: move_target.0,
+                timer: Timer::from_seconds(tuning_config.swap_seconds, false),
+            })
+            .remove::<Move>();
+    }
+}
+
+fn slide_swapping_block(
+    mut commands: Commands,
+    fixed_step: Res<FixedStep>,
+    mut block: Query<(Entity, &mut Moving, &mut SwapSlide), With<Block>>,
+) {
+    for (entity, mut moving, mut slide) in block.iter_mut() {
+        slide.timer.tick(fixed_step.delta());
+        moving.0 = slide.from + (slide.to - slide.from) * slide.timer.percent();
+        if slide.timer.finished() {
+            commands.entity(entity).remove::<SwapSlide>();
+        }
+    }
+}
+
+fn highlight_swapping_block(
+    mut commands: Commands,
+    board_config: Res<BoardConfig>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut highlight_material: Local<Option<Handle<ColorMaterial>>>,
+    block: Query<Entity, (With<Block>, Added<Moving>)>,
+) {
+    let material = highlight_material
+        .get_or_insert_with(|| materials.add(Color::rgba(1.0, 1.0, 1.0, 0.35).into()))
+        .clone();
+    for entity in block.iter() {
+        let highlight = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(board_config.block_size, board_config.block_size)),
+                material: material.clone(),
+                transform: Transform::from_xyz(0.0, 0.0, 0.4),
+                ..Default::default()
+            })
+            .insert(SwapHighlight)
+            .id();
+        commands.entity(entity).push_children(&[highlight]);
+    }
+}
+
+// A swap that settles while the board's chain is still going carries `Chain`, so a
+// match it makes extends the chain. The chain can't end under it: a moving block keeps
+// the board from settling, so `remove_chain` runs at least a `ChainWindow` after this,
+// and `match_block` sees the block on the very next update.
+fn moving_to_fixed(
+    mut commands: Commands,
+    mut block: Query<
+        (
+            Entity,
+            &mut Transform,
+            &Moving,
+            Option<&SwapSlide>,
+            Option<&Parent>,
+            Option<&Children>,
+        ),
+        (With<Block>, With<Moving>),
+    >,
+    chain_block: Query<Option<&Parent>, (With<Block>, With<Chain>)>,
+    swap_highlight: Query<(), With<SwapHighlight>>,
+) {
+    let chain_boards = chain_block.iter().map(board_key).collect::<HashSet<_>>();
+    for (entity, mut transform, moving, slide, parent, children) in block.iter_mut() {
+        match slide {
+            Some(_) => {
+                transform.translation.x = moving.0;
+            }
+            // the last slide step lands together with the component removal
+            None => {
+                transform.translation.x = moving.0;
+                commands.entity(entity).remove::<Moving>().insert(Fixed);
+                for child in children.iter().flat_map(|children| children.iter()) {
+                    if swap_highlight.get(*child).is_ok() {
+                        // recursive so the block's `Children` drops it too
+                        commands.entity(*child).despawn_recursive();
+                    }
+                }
+                if chain_boards.contains(&board_key(parent)) {
+                    commands.entity(entity).insert(Chain);
+                }
+            }
+        }
+    }
+}
+
+// Keeps `GridPos` in step with the transform of every settled block, including
+// blocks that just became `Fixed` and rows that moved up with the lift.
+fn sync_grid_pos(world: &mut World) {
+    let board_config = *world.get_resource::<BoardConfig>().unwrap();
+    let lift_offsets = lift_offsets(
+        world
+            .query_filtered::<(&Transform, Option<&Parent>), With<Bottom>>()
+            .iter(world),
+        &board_config,
+    );
+    let mut block = world.query_filtered::<
+        (Entity, &Transform, Option<&Parent>, Option<&GridPos>),
+        (With<Block>, With<Fixed>),
+    >();
+    let changed = block
+        .iter(world)
+        .filter_map(|(entity, transform, parent, grid_pos)| {
+            let lift_offset = lift_offsets.get(&board_key(parent)).copied().unwrap_or(0.0);
+            let new_grid_pos = GridPos::from_logical_cell(
+                logical_cell(transform, lift_offset, &board_config),
+                &board_config,
+            )?;
+            if grid_pos == Some(&new_grid_pos) {
+                None
+            } else {
+                Some((entity, new_grid_pos))
+            }
+        })
+        .collect::<Vec<_>>();
+    for (entity, grid_pos) in changed {
+        world.entity_mut(entity).insert(grid_pos);
+    }
+}
+
+// The one matching rule: the cells of the horizontal and vertical runs of 3 or more
+// same colors through `grid_pos`, counting `grid_pos` once. 0 when it is in neither.
+fn count_matches_at(grid: &BoardGrid, grid_pos: GridPos) -> usize {
+    let (row_idx, column_idx) = (grid_pos.row as usize, grid_pos.col as usize);
+    let block_color = match grid.get(row_idx).and_then(|row| row.get(column_idx)) {
+        Some(Some(block_color)) => *block_color,
+        _ => return 0,
+    };
+    let same = |row_idx: usize, column_idx: usize| {
+        grid.get(row_idx).and_then(|row| row.get(column_idx)) == Some(&Some(block_color))
+    };
+    let left = (1..=column_idx)
+        .take_while(|distance| same(row_idx, column_idx - distance))
+        .count();
+    let right = (1..)
+        .take_while(|distance| same(row_idx, column_idx + distance))
+        .count();
+    let below = (1..=row_idx)
+        .take_while(|distance| same(row_idx - distance, column_idx))
+        .count();
+    let above = (1..)
+        .take_while(|distance| same(row_idx + distance, column_idx))
+        .count();
+    let runs = [left + right + 1, below + above + 1]
+        .into_iter()
+        .filter(|run| *run >= 3)
+        .collect::<Vec<_>>();
+    match runs[..] {
+        [] => 0,
+        [run] => run,
+        [horizontal, vertical] => horizontal + vertical - 1,
+        _ => unreachable!(),
+    }
+}
+
+// Cells of the grid that are part of a match right now, in row then column order. Every
+// look ahead goes through here, so they all see the squares `SquareClear` adds.
+fn matched_cells(grid: &BoardGrid, square_clear: &SquareClear) -> Vec<GridPos> {
+    let mut cells = Vec::new();
+    for (row_idx, row) in grid.iter().enumerate() {
+        for column_idx in 0..row.len() {
+            let grid_pos = GridPos {
+                col: column_idx as u8,
+                row: row_idx as u8,
+            };
+            if count_matches_at(grid, grid_pos) > 0 {
+                cells.push(grid_pos);
+            }
+        }
+    }
+    if square_clear.0 {
+        for grid_pos in square_cells(grid) {
+            if !cells.contains(&grid_pos) {
+                cells.push(grid_pos);
+            }
+        }
+        cells.sort_by_key(|grid_pos| (grid_pos.row, grid_pos.col));
+    }
+    cells
+}
+
+// Cells of every 2x2 square of one color, for `SquareClear`. A cell in two squares is
+// listed once.
+fn square_cells(grid: &BoardGrid) -> Vec<GridPos> {
+    let mut cells = Vec::new();
+    for row_idx in 1..grid.len() {
+        for column_idx in 1..grid[row_idx].len() {
+            let block_color = grid[row_idx][column_idx];
+            if block_color.is_none()
+                || grid[row_idx][column_idx - 1] != block_color
+                || grid[row_idx - 1][column_idx] != block_color
+                || grid[row_idx - 1][column_idx - 1] != block_color
+            {
+                continue;
+            }
+            for (row, col) in [
+                (row_idx - 1, column_idx - 1),
+                (row_idx - 1, column_idx),
+                (row_idx, column_idx - 1),
+                (row_idx, column_idx),
+            ] {
+                let grid_pos = GridPos {
+                    col: col as u8,
+                    row: row as u8,
+                };
+                if !cells.contains(&grid_pos) {
+                    cells.push(grid_pos);
+                }
+            }
+        }
+    }
+    cells
+}
+
+fn update_board_snapshot(
+    mut board_snapshot: ResMut<BoardSnapshot>,
+    block: Query<(&GridPos, &BlockColor, Option<&Parent>), (With<Block>, With<Fixed>)>,
+    board_config: Res<BoardConfig>,
+) {
+    board_snapshot.0 = board_grids(
+        block
+            .iter()
+            .map(|(grid_pos, block_color, parent)| (board_key(parent), *grid_pos, *block_color)),
+        &board_config,
+    );
+}
+
+// The letters `parse_puzzle_layout` reads.
+fn color_letter(block_color: BlockColor) -> char {
+    match block_color {
+        BlockColor::RED => 'R',
+        BlockColor::GREEN => 'G',
+        BlockColor::BLUE => 'B',
+        BlockColor::YELLOW => 'Y',
+        BlockColor::PURPLE => 'P',
+        BlockColor::INDIGO => 'I',
+        // ids past the drawn colors show as hex digits, which puzzle files don't take
+        _ => std::char::from_digit(block_color.0 as u32, 16).unwrap_or('?'),
+    }
+}
+
+// Written the way puzzle layouts are, top row first, so a dump can be pasted into a
+// puzzle file.
+fn board_layout(grid: &BoardGrid) -> String {
+    grid.iter()
+        .rev()
+        .map(|row| {
+            row.iter()
+                .map(|cell| cell.map_or('.', color_letter))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Logs the settled blocks of every board; runs in step mode too, so a frozen board
+// can be dumped between steps.
+fn dump_board(
+    keyboard_input: Res<Input<KeyCode>>,
+    dev_mode: Res<DevMode>,
+    board_snapshot: Res<BoardSnapshot>,
+) {
+    if !dev_mode.0 || !keyboard_input.just_pressed(KeyCode::F8) {
+        return;
+    }
+    for (board, grid) in board_snapshot.0.iter() {
+        info!("board {:?}:\n{}", board, board_layout(grid));
+    }
+}
+
+struct CursorDebugText;
+
+// The cursor's left cell and the colors of both its cells, `.` for an empty one.
+// Blocks still moving count too, by the cell they are nearest.
+fn cursor_debug_line(
+    cursor_transform: &Transform,
+    blocks: &[(&Transform, &BlockColor)],
+    lift_offset: f32,
+    board_config: &BoardConfig,
+) -> String {
+    let half = board_config.block_size / 2.0;
+    let [left, right] = [-half, half].map(|dx| {
+        logical_cell(
+            &Transform::from_xyz(
+                cursor_transform.translation.x + dx,
+                cursor_transform.translation.y,
+                0.0,
+            ),
+            lift_offset,
+            board_config,
+        )
+    });
+    let letter_at = |cell| {
+        blocks
+            .iter()
+            .find(|(transform, _)| logical_cell(transform, lift_offset, board_config) == cell)
+            .map_or('.', |(_, block_color)| color_letter(**block_color))
+    };
+    format!(
+        "col {} row {}: {} {}",
+        left.0,
+        left.1,
+        letter_at(left),
+        letter_at(right)
+    )
+}
+
+// One line per cursor, in the corner above the FPS overlay.
+fn sync_cursor_debug_text(
+    mut commands: Commands,
+    cursor_debug: Res<CursorDebug>,
+    font_assets: Res<FontAssets>,
+    cursor: Query<(&Transform, Option<&Parent>), With<Cursor>>,
+    block: Query<(&Transform, &BlockColor, Option<&Parent>), With<Block>>,
+    lift: BoardLift,
+    mut debug_text: Query<(Entity, &mut Text), With<CursorDebugText>>,
+) {
+    if !cursor_debug.0 {
+        for (entity, _) in debug_text.iter_mut() {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
+    let lift_offsets = lift.offsets();
+    let board_config = &lift.board_config;
+    let label = cursor
+        .iter()
+        .map(|(cursor_transform, parent)| {
+            let board = board_key(parent);
+            let blocks = block
+                .iter()
+                .filter(|(_, _, block_parent)| board_key(*block_parent) == board)
+                .map(|(transform, block_color, _)| (transform, block_color))
+                .collect::<Vec<_>>();
+            cursor_debug_line(
+                cursor_transform,
+                &blocks,
+                lift_offsets.get(&board).copied().unwrap_or(0.0),
+                board_config,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if let Some((_, mut text)) = debug_text.iter_mut().next() {
+        text.sections[0].value = label;
+        return;
+    }
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(40.0),
+                    left: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                label,
+                TextStyle {
+                    font: font_assets.font.clone(),
+                    font_size: 20.0,
+                    color: Color::rgb(1.0, 1.0, 0.0),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(CursorDebugText);
+}
+
+// Lays cells out on one grid per board, rows from the bottom; cells off the board are dropped.
+fn board_grids<T: Copy>(
+    cells: impl Iterator<Item = (BoardKey, GridPos, T)>,
+    board_config: &BoardConfig,
+) -> HashMap<BoardKey, Vec<Vec<Option<T>>>> {
+    let mut grids = HashMap::new();
+    for (board, grid_pos, value) in cells {
+        let grid = grids
+            .entry(board)
+            .or_insert_with(|| vec![vec![None; board_config.width]; board_config.height]);
+        if let Some(cell) = grid
+            .get_mut(grid_pos.row as usize)
+            .and_then(|row: &mut Vec<Option<T>>| row.get_mut(grid_pos.col as usize))
+        {
+            *cell = Some(value);
+        }
+    }
+    grids
+}
+
+fn match_block(
+    mut commands: Commands,
+    block: Query<(Entity, &GridPos, &BlockColor, Option<&Parent>), (With<Block>, With<Fixed>)>,
+    board_config: Res<BoardConfig>,
+    square_clear: Res<SquareClear>,
+) {
+    let grids = board_grids(
+        block.iter().map(|(entity, grid_pos, block_color, parent)| {
+            (board_key(parent), *grid_pos, (entity, *block_color))
+        }),
+        &board_config,
+    );
+    for grid in grids.values() {
+        let colors = grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| cell.map(|(_, color)| color))
+                    .collect()
+            })
+            .collect::<BoardGrid>();
+        for grid_pos in matched_cells(&colors, &square_clear) {
+            if let Some((en, _)) = grid[grid_pos.row as usize][grid_pos.col as usize] {
+                commands.entity(en).insert(Matched).remove::<Fixed>();
+            }
+        }
+    }
+}
+
+fn settle_grid(grid: &mut BoardGrid) {
+    let width = grid.first().map_or(0, Vec::len);
+    for column_idx in 0..width {
+        let column = grid
+            .iter()
+            .filter_map(|row| row[column_idx])
+            .collect::<Vec<_>>();
+        for (row_idx, row) in grid.iter_mut().enumerate() {
+            row[column_idx] = column.get(row_idx).copied();
+        }
+    }
+}
+
+// Pure-logic forward simulation on a copy of the grid. Each step holds the cells
+// cleared at once, so the number of steps is the predicted chain depth.
+fn simulate_cascade(mut grid: BoardGrid, square_clear: &SquareClear) -> Vec<Vec<GridPos>> {
+    let mut steps = Vec::new();
+    loop {
+        settle_grid(&mut grid);
+        let step = matched_cells(&grid, square_clear);
+        if step.is_empty() {
+            return steps;
+        }
+        for grid_pos in step.iter() {
+            grid[grid_pos.row as usize][grid_pos.col as usize] = None;
+        }
+        steps.push(step);
+    }
+}
+
+// An overlay material made on first use and kept for the life of the system.
+#[derive(SystemParam)]
+pub struct CachedMaterial<'a> {
+    materials: ResMut<'a, Assets<ColorMaterial>>,
+    cached: Local<'a, Option<Handle<ColorMaterial>>>,
+}
+
+impl CachedMaterial<'_> {
+    fn get_or_add(&mut self, color: Color) -> Handle<ColorMaterial> {
+        let materials = &mut self.materials;
+        self.cached
+            .get_or_insert_with(|| materials.add(color.into()))
+            .clone()
+    }
+}
+
+fn preview_cascade(
+    mut commands: Commands,
+    match_preview: Res<MatchPreview>,
+    square_clear: Res<SquareClear>,
+    mut preview_material: CachedMaterial,
+    lift: BoardLift,
+    swapped_block: Query<
+        (&Transform, &BlockColor, &Move, Option<&Parent>),
+        (With<Block>, Added<Move>),
+    >,
+    fixed_block: Query<(&GridPos, &BlockColor, Option<&Parent>), (With<Block>, With<Fixed>)>,
+) {
+    if !match_preview.0 {
+        return;
+    }
+    let swapped_boards = swapped_block
+        .iter()
+        .map(|(_, _, _, parent)| board_key(parent))
+        .collect::<HashSet<_>>();
+    let lift_offsets = lift.offsets();
+    let board_config = &lift.board_config;
+    for board in swapped_boards {
+        let lift_offset = lift_offsets.get(&board).copied().unwrap_or(0.0);
+        let mut grid: BoardGrid = vec![vec![None; board_config.width]; board_config.height];
+        let swapped = swapped_block
+            .iter()
+            .filter(|(_, _, _, parent)| board_key(*parent) == board)
+            .filter_map(|(transform, block_color, move_target, _)| {
+                let target = Transform::from_xyz(move_target.0, transform.translation.y, 0.0);
+                let grid_pos = GridPos::from_logical_cell(
+                    logical_cell(&target, lift_offset, board_config),
+                    board_config,
+                )?;
+                Some((grid_pos, block_color))
+            });
+        let fixed = fixed_block
+            .iter()
+            .filter(|(_, _, parent)| board_key(*parent) == board)
+            .map(|(grid_pos, block_color, _)| (*grid_pos, block_color));
+        for (grid_pos, block_color) in fixed.chain(swapped) {
+            if let Some(cell) = grid
+                .get_mut(grid_pos.row as usize)
+                .and_then(|row| row.get_mut(grid_pos.col as usize))
+            {
+                *cell = Some(*block_color);
+            }
+        }
+        let material = preview_material.get_or_add(Color::rgba(1.0, 1.0, 1.0, 0.4));
+        for grid_pos in simulate_cascade(grid, &square_clear).into_iter().flatten() {
+            let highlight = commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(
+                        board_config.block_size,
+                        board_config.block_size,
+                    )),
+                    material: material.clone(),
+                    transform: Transform::from_translation(
+                        grid_pos.to_translation(2.0, board_config)
+                            + Vec3::new(0.0, lift_offset, 0.0),
+                    ),
+                    ..Default::default()
+                })
+                .insert(PreviewHighlight(Timer::from_seconds(0.5, false)))
+                .id();
+            if let Some(board_entity) = board {
+                commands.entity(board_entity).push_children(&[highlight]);
+            }
+        }
+    }
+}
+
+fn fade_preview_highlight(
+    mut commands: Commands,
+    fixed_step: Res<FixedStep>,
+    mut highlight: Query<(Entity, &mut PreviewHighlight)>,
+) {
+    for (entity, mut preview_highlight) in highlight.iter_mut() {
+        preview_highlight.0.tick(fixed_step.delta());
+        if preview_highlight.0.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// The matching swap closest to the cursor's left cell.
+fn nearest_swap(grid: &BoardGrid, cursor: GridPos, square_clear: &SquareClear) -> Option<GridPos> {
+    matching_swaps(grid, square_clear).min_by_key(|grid_pos| {
+        (grid_pos.col as i32 - cursor.col as i32).abs()
+            + (grid_pos.row as i32 - cursor.row as i32).abs()
+    })
+}
+
+// A hint is dropped when its board settles again, for `show_swap_hint` to work out anew,
+// and as soon as the player swaps on it, so it never points at a board that has changed.
+fn clear_swap_hint(
+    mut commands: Commands,
+    assist_settings: Res<AssistSettings>,
+    mut board_settled: EventReader<BoardSettled>,
+    block: Query<(Option<&Parent>, Option<&Move>), With<Block>>,
+    swap_hint: Query<(Entity, &Parent), With<SwapHint>>,
+) {
+    let settled_boards = board_settled
+        .iter()
+        .map(|board_settled| board_settled.0)
+        .collect::<HashSet<_>>();
+    let swapped_boards = block
+        .iter()
+        .filter(|(_, move_target)| move_target.is_some())
+        .map(|(parent, _)| board_key(parent))
+        .collect::<HashSet<_>>();
+    for (entity, block_entity) in swap_hint.iter() {
+        let board = block
+            .get(block_entity.0)
+            .map(|(parent, _)| board_key(parent))
+            .unwrap_or_default();
+        if !assist_settings.show_hint
+            || settled_boards.contains(&board)
+            || swapped_boards.contains(&board)
+        {
+            // recursive so the block's `Children` drops it too
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+// Whether the hint is on, and the rules it looks for a clearing swap under.
+#[derive(SystemParam)]
+pub struct HintRules<'a> {
+    assist_settings: Res<'a, AssistSettings>,
+    square_clear: Res<'a, SquareClear>,
+}
+
+// Only worked out when a player's board settles; `clear_swap_hint` drops the last one.
+fn show_swap_hint(
+    mut commands: Commands,
+    hint_rules: HintRules,
+    mut board_settled: EventReader<BoardSettled>,
+    mut hint_material: CachedMaterial,
+    cursor: Query<(&Transform, Option<&Parent>), (With<Cursor>, With<PlayerControls>)>,
+    lift: BoardLift,
+    fixed_block: Query<
+        (Entity, &Transform, &GridPos, &BlockColor, Option<&Parent>),
+        (With<Block>, With<Fixed>),
+    >,
+) {
+    if !hint_rules.assist_settings.show_hint {
+        return;
+    }
+    let settled_boards = board_settled
+        .iter()
+        .map(|board_settled| board_settled.0)
+        .collect::<HashSet<_>>();
+    let lift_offsets = lift.offsets();
+    let board_config = &lift.board_config;
+    let grids = board_grids(
+        fixed_block
+            .iter()
+            .map(|(entity, transform, grid_pos, block_color, parent)| {
+                (
+                    board_key(parent),
+                    *grid_pos,
+                    (entity, transform.rotation, *block_color),
+                )
+            }),
+        board_config,
+    );
+    for (cursor_transform, parent) in cursor.iter() {
+        let board = board_key(parent);
+        let grid = match grids.get(&board) {
+            Some(grid) if settled_boards.contains(&board) => grid,
+            _ => continue,
+        };
+        let lift_offset = lift_offsets.get(&board).copied().unwrap_or(0.0);
+        let cursor_left = Transform::from_xyz(
+            cursor_transform.translation.x - board_config.block_size / 2.0,
+            cursor_transform.translation.y,
+            0.0,
+        );
+        let cursor_cell = match GridPos::from_logical_cell(
+            logical_cell(&cursor_left, lift_offset, board_config),
+            board_config,
+        ) {
+            Some(cursor_cell) => cursor_cell,
+            None => continue,
+        };
+        let colors = grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| cell.map(|(_, _, color)| color))
+                    .collect()
+            })
+            .collect::<BoardGrid>();
+        let hint = match nearest_swap(&colors, cursor_cell, &hint_rules.square_clear) {
+            Some(hint) => hint,
+            None => continue,
+        };
+        let row = &grid[hint.row as usize];
+        let (block_entity, block_rotation, offset) =
+            match (row[hint.col as usize], row[hint.col as usize + 1]) {
+                (Some((left, rotation, _)), _) => (left, rotation, board_config.block_size / 2.0),
+                (None, Some((right, rotation, _))) => {
+                    (right, rotation, -board_config.block_size / 2.0)
+                }
+                (None, None) => continue,
+            };
+        // undoes the block's cosmetic rotation, so the hint lies across both cells
+        let unrotate = block_rotation.inverse();
+        let material = hint_material.get_or_add(Color::rgba(1.0, 1.0, 1.0, 0.25));
+        let hint_entity = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(
+                    board_config.block_size * 2.0,
+                    board_config.block_size,
+                )),
+                material,
+                transform: Transform {
+                    translation: unrotate * Vec3::new(offset, 0.0, 0.5),
+                    rotation: unrotate,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(SwapHint)
+            .id();
+        commands.entity(block_entity).push_children(&[hint_entity]);
+    }
+}
+
+// Counts the chain and reports each board's clear; the systems reading `ClearEvent`
+// do the rest.
+fn prepare_despawn_block(
+    match_block: Query<
+        (Entity, &Transform, Option<&Chain>, Option<&Parent>),
+        (With<Block>, With<Matched>),
+    >,
+    mut chain_counter: Query<(&mut ChainCounter, Option<&Parent>)>,
+    mut clear_event: EventWriter<ClearEvent>,
+) {
+    let mut matched_boards: HashMap<BoardKey, Vec<(Entity, bool, Vec3)>> = HashMap::new();
+    for (entity, transform, chain, parent) in match_block.iter() {
+        matched_boards.entry(board_key(parent)).or_default().push((
+            entity,
+            chain.is_some(),
+            transform.translation,
+        ));
+    }
+    for (mut cc, parent) in chain_counter.iter_mut() {
+        let board = board_key(parent);
+        let matched = match matched_boards.get(&board) {
+            Some(matched) => matched,
+            None => continue,
+        };
+        if matched.iter().any(|(_, chain, _)| *chain) {
+            cc.0 += 1;
+        }
+        let combo = matched.len();
+        clear_event.send(ClearEvent {
+            board,
+            entities: matched.iter().map(|(entity, _, _)| *entity).collect(),
+            combo,
+            chain: cc.0,
+            centroid: matched
+                .iter()
+                .fold(Vec3::ZERO, |sum, (_, _, translation)| sum + *translation)
+                / combo as f32,
+        });
+    }
+}
+
+// Extra points for clearing 4 or more blocks at once, on top of 10 per block.
+fn combo_bonus(combo: usize) -> u32 {
+    match combo {
+        0..=3 => 0,
+        4 => 20,
+        5 => 30,
+        6 => 50,
+        7 => 60,
+        8 => 70,
+        9 => 80,
+        10 => 100,
+        11 => 140,
+        12 => 170,
+        _ => 170 + 30 * (combo as u32 - 12),
+    }
+}
+
+// Extra points for each link of a chain after the first, topping out at 13.
+fn chain_bonus(chain: u32) -> u32 {
+    match chain {
+        0 | 1 => 0,
+        2 => 50,
+        3 => 80,
+        4 => 150,
+        5 => 300,
+        6 => 400,
+        7 => 500,
+        8 => 700,
+        9 => 900,
+        10 => 1100,
+        11 => 1300,
+        12 => 1500,
+        _ => 1800,
+    }
+}
+
+// the COM's clears don't count towards the player's score
+fn score_clear(
+    mut clear_event: EventReader<ClearEvent>,
+    ai_cursor: Query<Option<&Parent>, With<AiController>>,
+    mut score: ResMut<Score>,
+) {
+    let ai_boards = ai_cursor.iter().map(board_key).collect::<HashSet<_>>();
+    for clear in clear_event.iter() {
+        if !ai_boards.contains(&clear.board) {
+            score.0 +=
+                clear.combo as u32 * 10 + combo_bonus(clear.combo) + chain_bonus(clear.chain);
+        }
+    }
+}
+
+// Like the score, only counts the player's boards.
+fn track_clear_stats(
+    mut clear_event: EventReader<ClearEvent>,
+    ai_cursor: Query<Option<&Parent>, With<AiController>>,
+    block_color: Query<&BlockColor>,
+    mut max_chain: ResMut<MaxChain>,
+    mut clear_stats: ResMut<ClearStats>,
+) {
+    let ai_boards = ai_cursor.iter().map(board_key).collect::<HashSet<_>>();
+    for clear in clear_event.iter() {
+        if ai_boards.contains(&clear.board) {
+            continue;
+        }
+        max_chain.0 = max_chain.0.max(clear.chain);
+        clear_stats.total += clear.entities.len() as u32;
+        for entity in clear.entities.iter() {
+            // garbage has no color and only counts toward the total
+            if let Some(count) = block_color
+                .get(*entity)
+                .ok()
+                .and_then(|color| clear_stats.per_color.get_mut(color.0 as usize))
+            {
+                *count += 1;
+            }
+        }
+    }
+}
+
+fn play_clear_sound(
+    mut clear_event: EventReader<ClearEvent>,
+    audio: Res<Audio>,
+    sound_assets: Res<SoundAssets>,
+) {
+    for clear in clear_event.iter() {
+        let sound = if clear.chain > 1 {
+            &sound_assets.chain_sound
+        } else {
+            &sound_assets.match_sound
+        };
+        audio.play(sound.clone());
+    }
+}
+
+// Matched only lasts one update; the bigger the clear, the longer it flashes.
+fn start_despawning(mut commands: Commands, mut clear_event: EventReader<ClearEvent>) {
+    for clear in clear_event.iter() {
+        for entity in clear.entities.iter() {
+            commands
+                .entity(*entity)
+                .remove::<Matched>()
+                .insert(Despawining(Timer::from_seconds(
+                    clear.combo as f32 * 0.3,
+                    false,
+                )));
+        }
+    }
+}
+
+// Only versus games have another board to send garbage to. Each piece a board earns
+// first cancels a piece still on its way to that board.
+fn send_garbage(
+    mut clear_event: EventReader<ClearEvent>,
+    board_entity: Query<Entity, With<Board>>,
+    board_config: Res<BoardConfig>,
+    mut incoming: ResMut<IncomingGarbage>,
+    mut garbage_event: EventWriter<GarbageEvent>,
+) {
+    for clear in clear_event.iter() {
+        let mut garbage = Vec::new();
+        if clear.combo >= GARBAGE_COMBO_THRESHOLD {
+            garbage.push(((clear.combo - 1).min(board_config.width), 1));
+        }
+        if clear.chain > 1 {
+            garbage.push((board_config.width, 1));
+        }
+        if let Some(pending) = clear.board.and_then(|board| incoming.0.get_mut(&board)) {
+            let cancelled = garbage.len().min(pending.len());
+            pending.drain(..cancelled);
+            garbage.drain(..cancelled);
+        }
+        let opponents = board_entity
+            .iter()
+            .filter(|other| clear.board.is_some() && clear.board != Some(*other));
+        for target_board in opponents {
+            for (width, height) in garbage.iter() {
+                garbage_event.send(GarbageEvent {
+                    target_board,
+                    width: *width,
+                    height: *height,
+                });
+            }
+        }
+    }
+}
+
+fn spawn_combo_popup(
+    mut commands: Commands,
+    mut clear_event: EventReader<ClearEvent>,
+    font_assets: Res<FontAssets>,
+) {
+    for clear in clear_event.iter() {
+        if clear.combo < COMBO_POPUP_THRESHOLD {
+            continue;
+        }
+        let popup = commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(
+                    format!("{} Combo!", clear.combo),
+                    TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                    TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center,
+                    },
+                ),
+                transform: Transform::from_translation(clear.centroid + Vec3::new(0.0, 0.0, 4.0)),
+                ..Default::default()
+            })
+            .insert(ComboPopup(Timer::from_seconds(COMBO_POPUP_SECONDS, false)))
+            .insert(HudElement::default())
+            .id();
+        if let Some(board_entity) = clear.board {
+            commands.entity(board_entity).push_children(&[popup]);
+        }
+    }
+}
+
+fn fade_combo_popup(
+    mut commands: Commands,
+    fixed_step: Res<FixedStep>,
+    mut popup: Query<(Entity, &mut ComboPopup, &mut Text)>,
+) {
+    for (entity, mut combo_popup, mut text) in popup.iter_mut() {
+        combo_popup.0.tick(fixed_step.delta());
+        if combo_popup.0.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let alpha = 1.0 - combo_popup.0.percent();
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(alpha);
+        }
+    }
+}
+
+// The board counts as settled after staying stable for the whole `ChainWindow`.
+fn detect_board_settled(
+    mut stable_updates: Local<HashMap<BoardKey, u32>>,
+    chain_window: Res<ChainWindow>,
+    assist_mode: Res<AssistMode>,
+    board: Query<Entity, With<Board>>,
+    block: Query<(Option<&Parent>, Option<&Fixed>, Option<&Spawning>), With<Block>>,
+    mut board_settled: EventWriter<BoardSettled>,
+) {
+    let window = chain_window.effective(&assist_mode);
+    // a board without any blocks left is settled too
+    let mut in_motion = board
+        .iter()
+        .map(|board| (Some(board), false))
+        .collect::<HashMap<_, _>>();
+    for (parent, fixed, spawning) in block.iter() {
+        *in_motion.entry(board_key(parent)).or_default() |= fixed.is_none() && spawning.is_none();
+    }
+    stable_updates.retain(|board, _| in_motion.contains_key(board));
+    for (board, moving) in in_motion {
+        let stable_updates = stable_updates.entry(board).or_default();
+        if moving {
+            *stable_updates = 0;
+        } else if *stable_updates < window {
+            *stable_updates += 1;
+            if *stable_updates == window {
+                board_settled.send(BoardSettled(board));
+            }
+        }
+    }
+}
+
+// chain is over when the cascade settles without another match
+fn remove_chain(
+    mut commands: Commands,
+    mut board_settled: EventReader<BoardSettled>,
+    chain_block: Query<(Entity, Option<&Parent>), (With<Block>, With<Chain>)>,
+) {
+    let settled_boards = board_settled
+        .iter()
+        .map(|board_settled| board_settled.0)
+        .collect::<HashSet<_>>();
+    for (entity, parent) in chain_block.iter() {
+        if settled_boards.contains(&board_key(parent)) {
+            commands.entity(entity).remove::<Chain>();
+        }
+    }
+}
+
+fn reset_chain_counter(
+    mut board_settled: EventReader<BoardSettled>,
+    mut chain_counter: Query<(&mut ChainCounter, Option<&Parent>)>,
+) {
+    let settled_boards = board_settled
+        .iter()
+        .map(|board_settled| board_settled.0)
+        .collect::<HashSet<_>>();
+    for (mut cc, parent) in chain_counter.iter_mut() {
+        if settled_boards.contains(&board_key(parent)) {
+            cc.0 = 1;
+        }
+    }
+}
+
+// A settled board where no swap clears anything gets its colors dealt out again; every
+// block keeps its cell and the color counts stay the same. Boards with garbage or too
+// few blocks for any move are left alone.
+fn detect_no_moves(
+    mut commands: Commands,
+    mut board_settled: EventReader<BoardSettled>,
+    match_view: MatchView,
+    mut board_rng: ResMut<BoardRng>,
+    block_materials: Res<BlockMaterials>,
+    mut block: Query<
+        (
+            Entity,
+            &GridPos,
+            &mut BlockColor,
+            &mut Handle<ColorMaterial>,
+            Option<&Parent>,
+        ),
+        (With<Block>, With<Fixed>),
+    >,
+    mut symbol_text: Query<(&Parent, &mut Text), With<BlockSymbol>>,
+) {
+    for board_settled in board_settled.iter() {
+        let square_clear = &match_view.square_clear;
+        let grid = match match_view.board_snapshot.board(board_settled.0) {
+            Some(grid) => grid,
+            None => continue,
+        };
+        let mut settled = grid.clone();
+        settle_grid(&mut settled);
+        if settled != *grid
+            || has_matching_swap(grid, square_clear)
+            || !simulate_cascade(grid.clone(), square_clear).is_empty()
+        {
+            continue;
+        }
+        let shuffled = match shuffle_to_move(grid, &mut board_rng.0, square_clear) {
+            Some(shuffled) => shuffled,
+            None => continue,
+        };
+        let mut recolored = HashMap::new();
+        for (entity, grid_pos, mut block_color, mut material, parent) in block.iter_mut() {
+            if board_key(parent) != board_settled.0 {
+                continue;
+            }
+            let new_color = shuffled
+                .get(grid_pos.row as usize)
+                .and_then(|row| row.get(grid_pos.col as usize))
+                .copied()
+                .flatten();
+            match new_color {
+                Some(new_color) if new_color != *block_color => {
+                    *block_color = new_color;
+                    *material = block_material(&block_materials, new_color);
+                    recolored.insert(entity, new_color);
+                    commands.entity(entity).insert(LandingSquash(1.0)).insert(
+                        LandingSquash(1.0).ease_to(
+                            LandingSquash(0.0),
+                            EaseFunction::QuadraticOut,
+                            EasingType::Once {
+                                duration: Duration::from_secs_f32(LANDING_SQUASH_SECONDS),
+                            },
+                        ),
+                    );
+                }
+                _ => {}
+            }
+        }
+        for (parent, mut text) in symbol_text.iter_mut() {
+            if let Some(block_color) = recolored.get(&parent.0) {
+                text.sections[0].value = block_symbol(*block_color).to_string();
+            }
+        }
+    }
+}
+
+// Judged once the board settles, so a chain started by the last swap still counts. An
+// emptied board is left to `check_board_cleared`.
+fn check_puzzle_result(
+    mut commands: Commands,
+    mut board_settled: EventReader<BoardSettled>,
+    moves_left: Option<Res<MovesLeft>>,
+    block: Query<Entity, With<Block>>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let moves_left = match moves_left {
+        Some(moves_left) => moves_left,
+        None => return,
+    };
+    if board_settled.iter().count() == 0 {
+        return;
+    }
+    if block.iter().next().is_none() || moves_left.0 > 0 {
+        return;
+    }
+    if queue_state_change(state.set(AppState::GameOver)) {
+        commands.insert_resource(PuzzleOutcome::OutOfMoves);
+    }
+}
+
+// The boards that have cleared anything so far this game.
+#[derive(SystemParam)]
+pub struct ClearedBoards<'a> {
+    clear_events: EventReader<'a, ClearEvent>,
+    cleared: Local<'a, HashSet<BoardKey>>,
+    board: Query<'a, Entity, With<Board>>,
+}
+
+impl ClearedBoards<'_> {
+    fn update(&mut self) -> &HashSet<BoardKey> {
+        let board = &self.board;
+        self.cleared.extend(
+            self.clear_events
+                .iter()
+                .map(|clear_event| clear_event.board),
+        );
+        // boards of an earlier game are gone
+        self.cleared
+            .retain(|key| key.is_none_or(|entity| board.get(entity).is_ok()));
+        &self.cleared
+    }
+}
+
+// Puzzle and sandbox boards get no new rows, so clearing every block wins. Only a board
+// that cleared something counts: a sandbox board starts out empty and settled.
+fn check_board_cleared(
+    mut commands: Commands,
+    mut board_settled: EventReader<BoardSettled>,
+    mut cleared_boards: ClearedBoards,
+    game_mode: Res<GameMode>,
+    block: Query<Option<&Parent>, With<Block>>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let cleared_boards = cleared_boards.update();
+    if !matches!(*game_mode, GameMode::Puzzle | GameMode::Sandbox) {
+        return;
+    }
+    let emptied = board_settled.iter().any(|board_settled| {
+        cleared_boards.contains(&board_settled.0)
+            && block
+                .iter()
+                .all(|parent| board_key(parent) != board_settled.0)
+    });
+    if !emptied {
+        return;
+    }
+    if queue_state_change(state.set(AppState::GameOver)) {
+        commands.insert_resource(PuzzleOutcome::Cleared);
+    }
+}
+
+// Dev cheat: F7 clears every settled block at once, through the usual clear, score
+// and chain systems.
+fn clear_all_blocks(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    dev_mode: Res<DevMode>,
+    block: Query<Entity, (With<Block>, With<Fixed>)>,
+) {
+    if !dev_mode.0 || !keyboard_input.just_pressed(KeyCode::F7) {
+        return;
+    }
+    for entity in block.iter() {
+        commands.entity(entity).insert(Matched).remove::<Fixed>();
+    }
+}
+
+// Flashes a despawning block, then shrinks it away over the rest of its timer, so the
+// block is gone from view exactly when `despawn_block` removes it. With `ReduceFlashing`
+// the block stays shown and `outline_despawning_block` marks it instead.
+fn animate_despawning(
+    mut commands: Commands,
+    reduce_flashing: Res<ReduceFlashing>,
+    mut block: Query<
+        (
+            Entity,
+            &Despawining,
+            &mut Visible,
+            &mut Transform,
+            Option<&DespawnScale>,
+        ),
+        With<Block>,
+    >,
+) {
+    for (entity, despawning, mut visible, mut transform, despawn_scale) in block.iter_mut() {
+        // despawned once the commands run
+        if despawning.0.finished() {
+            continue;
+        }
+        let elapsed = despawning.0.elapsed_secs();
+        let duration = despawning.0.duration().as_secs_f32();
+        if elapsed < duration * DESPAWN_FLASH_PORTION {
+            visible.is_visible =
+                reduce_flashing.0 || ((elapsed / DESPAWN_FLASH_SECONDS) as u32).is_multiple_of(2);
+            continue;
+        }
+        visible.is_visible = true;
+        match despawn_scale {
+            Some(despawn_scale) => transform.scale = Vec3::splat(despawn_scale.0),
+            None => {
+                commands.entity(entity).insert(DespawnScale(1.0)).insert(
+                    DespawnScale(1.0).ease_to(
+                        DespawnScale(0.0),
+                        EaseFunction::QuadraticIn,
+                        EasingType::Once {
+                            duration: Duration::from_secs_f32(duration - elapsed),
+                        },
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn outline_despawning_block(
+    mut commands: Commands,
+    reduce_flashing: Res<ReduceFlashing>,
+    board_config: Res<BoardConfig>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut outline_material: Local<Option<Handle<ColorMaterial>>>,
+    block: Query<Entity, (With<Block>, Added<Despawining>)>,
+) {
+    if !reduce_flashing.0 {
+        return;
+    }
+    for entity in block.iter() {
+        let material = outline_material
+            .get_or_insert_with(|| materials.add(Color::rgba(1.0, 1.0, 1.0, 0.8).into()))
+            .clone();
+        let outline = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::splat(board_config.block_size + FRAME_THICKNESS * 2.0)),
+                material,
+                transform: Transform::from_xyz(0.0, 0.0, -0.1),
+                ..Default::default()
+            })
+            .insert(DespawnOutline)
+            .id();
+        commands.entity(entity).push_children(&[outline]);
+    }
+}
+
+fn despawn_block(
+    mut commands: Commands,
+    fixed_step: Res<FixedStep>,
+    time_scale: Res<TimeScale>,
+    mut despawning_block: Query<
+        (
+            Entity,
+            &mut Despawining,
+            Option<&Parent>,
+            Option<(&GridPos, &BlockColor)>,
+        ),
+        (With<Block>, With<Despawining>),
+    >,
+    settled_block: Query<(Entity, &GridPos, Option<&Parent>), (With<Block>, With<Fixed>)>,
+    mut block_cleared: EventWriter<BlockCleared>,
+) {
+    let settled = settled_block
+        .iter()
+        .map(|(entity, grid_pos, parent)| ((board_key(parent), *grid_pos), entity))
+        .collect::<HashMap<_, _>>();
+    for (despawning_entity, mut despawning, despawning_parent, colored) in
+        despawning_block.iter_mut()
+    {
+        despawning
+            .0
+            .tick(Duration::from_secs_f32(fixed_step.0 * time_scale.0));
+        if despawning.0.just_finished() {
+            commands.entity(despawning_entity).despawn_recursive();
+            let board = board_key(despawning_parent);
+            let (grid_pos, block_color) = match colored {
+                Some(colored) => colored,
+                None => continue,
+            };
+            block_cleared.send(BlockCleared {
+                board,
+                grid_pos: *grid_pos,
+                color: *block_color,
+            });
+            // the settled stack resting on the cleared cell falls into it
+            let mut above = GridPos {
+                col: grid_pos.col,
+                row: grid_pos.row + 1,
+            };
+            while let Some(entity) = settled.get(&(board, above)) {
+                commands.entity(*entity).insert(Chain);
+                above.row += 1;
+            }
+        }
+    }
+}
+
+// Garbage shows a dimmed version of the color that clears it.
+fn garbage_tint(block_color: BlockColor) -> Color {
+    match block_color.base() {
+        BlockColor::RED => Color::rgb(0.5, 0.15, 0.15),
+        BlockColor::GREEN => Color::rgb(0.15, 0.45, 0.15),
+        BlockColor::BLUE => Color::rgb(0.15, 0.2, 0.5),
+        BlockColor::YELLOW => Color::rgb(0.5, 0.45, 0.1),
+        BlockColor::PURPLE => Color::rgb(0.4, 0.15, 0.45),
+        _ => Color::rgb(0.2, 0.15, 0.4),
+    }
+}
+
+// Pops the pieces at the front of `queue` that are due by `now`.
+fn take_due_garbage(queue: &mut VecDeque<GarbageSpec>, now: f32) -> Vec<GarbageSpec> {
+    let mut due = Vec::new();
+    while queue.front().is_some_and(|piece| piece.due <= now) {
+        due.extend(queue.pop_front());
+    }
+    due
+}
+
+// Sent garbage waits in `IncomingGarbage` before it drops.
+fn queue_garbage(
+    mut garbage_event: EventReader<GarbageEvent>,
+    mut incoming: ResMut<IncomingGarbage>,
+    play_time: Res<PlayTime>,
+) {
+    for garbage_event in garbage_event.iter() {
+        incoming
+            .0
+            .entry(garbage_event.target_board)
+            .or_default()
+            .push_back(GarbageSpec {
+                width: garbage_event.width,
+                height: garbage_event.height,
+                due: play_time.0 + GARBAGE_DELAY_SECONDS,
+            });
+    }
+}
+
+// One `garbage_tint` material per color, made the first time a piece of it drops.
+#[derive(SystemParam)]
+pub struct GarbageMaterials<'a> {
+    materials: ResMut<'a, Assets<ColorMaterial>>,
+    cached: Local<'a, HashMap<BlockColor, Handle<ColorMaterial>>>,
+}
+
+impl GarbageMaterials<'_> {
+    fn get_or_add(&mut self, color: BlockColor) -> Handle<ColorMaterial> {
+        let materials = &mut self.materials;
+        self.cached
+            .entry(color)
+            .or_insert_with(|| materials.add(garbage_tint(color).into()))
+            .clone()
+    }
+}
+
+// Once due, the piece starts above the top of the board and falls onto the stack like
+// any block.
+fn spawn_garbage(
+    mut commands: Commands,
+    mut incoming: ResMut<IncomingGarbage>,
+    play_time: Res<PlayTime>,
+    mut garbage_materials: GarbageMaterials,
+    palette: Palette,
+    mut board_rng: ResMut<BoardRng>,
+    board_config: Res<BoardConfig>,
+) {
+    // checked first so the markers aren't rebuilt every update
+    let due_boards = incoming
+        .0
+        .iter()
+        .filter(|(_, queue)| queue.front().is_some_and(|piece| piece.due <= play_time.0))
+        .map(|(target_board, _)| *target_board)
+        .collect::<Vec<_>>();
+    let mut due_pieces = Vec::new();
+    for target_board in due_boards {
+        if let Some(queue) = incoming.0.get_mut(&target_board) {
+            for piece in take_due_garbage(queue, play_time.0) {
+                due_pieces.push((target_board, piece));
+            }
+        }
+    }
+    for (target_board, piece) in due_pieces {
+        let rng = &mut board_rng.0;
+        let color = match palette.block_colors().choose(rng) {
+            Some(color) => *color,
+            None => continue,
+        };
+        let material = garbage_materials.get_or_add(color);
+        let width = piece.width.min(board_config.width);
+        let first_col = rng.gen_range(0..=board_config.width - width);
+        let mut cells = Vec::with_capacity(width * piece.height);
+        for row in board_config.height..board_config.height + piece.height {
+            for col in first_col..first_col + width {
+                let grid_pos = GridPos {
+                    col: col as u8,
+                    row: row as u8,
+                };
+                let cell = commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: board_config.block_sprite(),
+                        material: material.clone(),
+                        transform: Transform::from_translation(
+                            grid_pos.to_translation(0.0, &board_config),
+                        ),
+                        ..Default::default()
+                    })
+                    .insert(Block)
+                    .insert(Fall)
+                    .id();
+                cells.push(cell);
+            }
+        }
+        for cell in cells.iter() {
+            commands.entity(*cell).insert(Garbage {
+                cells: cells.clone(),
+                color,
+            });
+        }
+        commands.entity(target_board).push_children(&cells);
+    }
+}
+
+// Stacks one bar per pending piece above each board, the next to drop lowest.
+fn update_incoming_garbage_markers(
+    mut commands: Commands,
+    incoming: Res<IncomingGarbage>,
+    board_config: Res<BoardConfig>,
+    marker: Query<Entity, With<IncomingGarbageMarker>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut marker_material: Local<Option<Handle<ColorMaterial>>>,
+) {
+    if !incoming.is_changed() {
+        return;
+    }
+    for entity in marker.iter() {
+        commands.entity(entity).despawn();
+    }
+    let block_size = board_config.block_size;
+    let material = marker_material
+        .get_or_insert_with(|| materials.add(Color::rgb(0.45, 0.45, 0.45).into()))
+        .clone();
+    for (board, queue) in incoming.0.iter() {
+        for (idx, piece) in queue.iter().enumerate() {
+            let width = piece.width.min(board_config.width) as f32 * block_size;
+            let marker = commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(width, block_size / 4.0)),
+                    material: material.clone(),
+                    transform: Transform::from_xyz(
+                        (width - board_config.size().x) / 2.0,
+                        board_config.size().y / 2.0 + block_size * (idx as f32 + 0.5) / 2.0,
+                        1.0,
+                    ),
+                    ..Default::default()
+                })
+                .insert(IncomingGarbageMarker)
+                .insert(HudElement::default())
+                .id();
+            commands.entity(*board).push_children(&[marker]);
+        }
+    }
+}
+
+// A cleared block next to a garbage piece of its own color turns the piece's bottom
+// row into normal blocks as it goes; the rows above stay garbage. The new blocks carry
+// `Chain`, so a match they make right away continues the chain.
+fn clear_adjacent_garbage(
+    mut commands: Commands,
+    mut block_cleared: EventReader<BlockCleared>,
+    garbage: Query<(&GridPos, &Garbage, Option<&Parent>), (With<Block>, With<Fixed>)>,
+    block_materials: Res<BlockMaterials>,
+    palette: Palette,
+    mut board_rng: ResMut<BoardRng>,
+) {
+    let matched_cells = block_cleared
+        .iter()
+        .map(|block_cleared| {
+            (
+                (
+                    block_cleared.board,
+                    (
+                        block_cleared.grid_pos.col as i32,
+                        block_cleared.grid_pos.row as i32,
+                    ),
+                ),
+                block_cleared.color,
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    if matched_cells.is_empty() {
+        return;
+    }
+    let mut hit_pieces = HashMap::new();
+    for (grid_pos, garbage_cell, parent) in garbage.iter() {
+        let board = board_key(parent);
+        let (col, row) = (grid_pos.col as i32, grid_pos.row as i32);
+        let touched = [
+            (col - 1, row),
+            (col + 1, row),
+            (col, row - 1),
+            (col, row + 1),
+        ]
+        .iter()
+        .any(|cell| matched_cells.get(&(board, *cell)) == Some(&garbage_cell.color));
+        if touched {
+            hit_pieces.insert(garbage_cell.cells[0], garbage_cell);
+        }
+    }
+    let block_colors = palette.block_colors();
+    for garbage_cell in hit_pieces.values() {
+        let rows = garbage_cell
+            .cells
+            .iter()
+            .filter_map(|cell| Some((*cell, garbage.get(*cell).ok()?.0.row)))
+            .collect::<Vec<_>>();
+        let bottom_row = rows.iter().map(|(_, row)| *row).min();
+        let (converted, rest): (Vec<_>, Vec<_>) = rows
+            .into_iter()
+            .partition(|(_, row)| Some(*row) == bottom_row);
+        for (cell, _) in converted {
+            if let Some(block_color) = block_colors.choose(&mut board_rng.0) {
+                commands
+                    .entity(cell)
+                    .remove::<Garbage>()
+                    .insert(*block_color)
+                    .insert(block_material(&block_materials, *block_color))
+                    .insert(Chain);
+            }
+        }
+        let rest = rest.into_iter().map(|(cell, _)| cell).collect::<Vec<_>>();
+        for cell in rest.iter() {
+            commands.entity(*cell).insert(Garbage {
+                cells: rest.clone(),
+                color: garbage_cell.color,
+            });
+        }
+    }
+}
+
+// A garbage piece only falls once none of its cells rests on something else.
+fn check_fall_block(
+    mut commands: Commands,
+    block: Query<
+        (Entity, &Transform, Option<&Parent>, Option<&Garbage>),
+        (With<Block>, With<Fixed>),
+    >,
+    other_block: Query<(Entity, &Transform, Option<&Parent>), With<Block>>,
+    bottom: Query<(&Transform, Option<&Parent>), With<Bottom>>,
+    board_config: Res<BoardConfig>,
+) {
+    let lift_offsets = lift_offsets(bottom.iter(), &board_config);
+    let lift_offset = |board| lift_offsets.get(&board).copied().unwrap_or(0.0);
+    let occupied_cells = other_block
+        .iter()
+        .map(|(entity, transform, parent)| {
+            let board = board_key(parent);
+            (
+                (
+                    board,
+                    logical_cell(transform, lift_offset(board), &board_config),
+                ),
+                entity,
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    // check is there block down next to?
+    let mut unsupported = Vec::new();
+    let mut supported_garbage = HashSet::new();
+    for (entity, transform, parent, garbage) in block.iter() {
+        let board = board_key(parent);
+        let (col, row) = logical_cell(transform, lift_offset(board), &board_config);
+        let below = occupied_cells.get(&(board, (col, row - 1)));
+        let supported = match (below, garbage) {
+            _ if row <= 0 => true,
+            (Some(below), Some(garbage)) => !garbage.cells.contains(below),
+            (below, _) => below.is_some(),
+        };
+        match garbage {
+            Some(garbage) if supported => {
+                supported_garbage.insert(garbage.cells[0]);
+            }
+            _ if supported => {}
+            _ => unsupported.push((entity, garbage)),
+        }
+    }
+    for (entity, garbage) in unsupported {
+        if garbage.is_some_and(|garbage| supported_garbage.contains(&garbage.cells[0])) {
+            continue;
+        }
+        commands
+            .entity(entity)
+            .remove::<Fixed>()
+            .insert(FallPrepare);
+    }
+}
+
+fn fall_upward(
+    mut commands: Commands,
+    settings: Res<GameSettings>,
+    board_config: Res<BoardConfig>,
+    fallprepare_block: Query<
+        (Entity, &GridPos, Option<&Parent>, Option<&Garbage>),
+        (With<Block>, With<FallPrepare>),
+    >,
+    fixed_block: Query<(Entity, &GridPos, Option<&Parent>), (With<Block>, With<Fixed>)>,
+    resting_block: Query<
+        (&GridPos, Option<&Parent>),
+        (With<Block>, Without<Floating>, Without<Fall>),
+    >,
+    mut block_transform: Query<&mut Transform, With<Block>>,
+) {
+    let fixed_cells = fixed_block
+        .iter()
+        .map(|(entity, grid_pos, parent)| ((board_key(parent), *grid_pos), entity))
+        .collect::<HashMap<_, _>>();
+    let mut occupied_cells = resting_block
+        .iter()
+        .map(|(grid_pos, parent)| (board_key(parent), *grid_pos))
+        .collect::<HashSet<_>>();
+    // lower stacks drop first, so the ones above land on them
+    let mut fallprepare_blocks = fallprepare_block.iter().collect::<Vec<_>>();
+    fallprepare_blocks.sort_unstable_by_key(|(_, grid_pos, _, _)| grid_pos.row);
+    for (fallprepare_entity, fallprepare_grid_pos, parent, garbage) in fallprepare_blocks {
+        let board = board_key(parent);
+        let mut fall_block_candidates = vec![fallprepare_entity];
+        // the stack directly above falls together until the first empty cell; cells rather
+        // than translations are compared, so a board caught mid-lift splits nowhere else
+        let mut above = *fallprepare_grid_pos;
+        loop {
+            above.row += 1;
+            match fixed_cells.get(&(board, above)) {
+                Some(fixed_entity) => fall_block_candidates.push(*fixed_entity),
+                None => break,
+            }
+        }
+        if settings.fall_style == FallStyle::Instant && garbage.is_none() {
+            let mut landing = *fallprepare_grid_pos;
+            while landing.row > 0
+                && !occupied_cells.contains(&(
+                    board,
+                    GridPos {
+                        row: landing.row - 1,
+                        ..landing
+                    },
+                ))
+            {
+                landing.row -= 1;
+            }
+            let drop = fallprepare_grid_pos.row - landing.row;
+            for (index, en) in fall_block_candidates.into_iter().enumerate() {
+                let from = GridPos {
+                    row: fallprepare_grid_pos.row + index as u8,
+                    ..*fallprepare_grid_pos
+                };
+                let to = GridPos {
+                    row: from.row - drop,
+                    ..from
+                };
+                occupied_cells.remove(&(board, from));
+                occupied_cells.insert((board, to));
+                if let Ok(mut transform) = block_transform.get_mut(en) {
+                    transform.translation.y -= drop as f32 * board_config.block_size;
+                }
+                // `match_block` sees the block once it is `Fixed`, on the next update
+                commands
+                    .entity(en)
+                    .remove::<FallPrepare>()
+                    .insert(Fixed)
+                    .insert(to);
+            }
+            continue;
+        }
+        for en in fall_block_candidates {
+            commands
+                .entity(en)
+                .remove::<FallPrepare>()
+                .remove::<Fixed>()
+                .insert(Floating(Timer::from_seconds(0.02, false)));
+        }
+    }
+}
+
+fn floating_to_fall(
+    mut commands: Commands,
+    fixed_step: Res<FixedStep>,
+    time_scale: Res<TimeScale>,
+    mut floating_block: Query<(Entity, &mut Floating), (With<Floating>, With<Block>)>,
+) {
+    for (entity, mut floating) in floating_block.iter_mut() {
+        floating
+            .0
+            .tick(Duration::from_secs_f32(fixed_step.0 * time_scale.0));
+        if floating.0.just_finished() {
+            commands.entity(entity).insert(Fall).remove::<Floating>();
+        }
+    }
+}
+
+// A long update slows the fall down rather than letting a block skip a cell.
+fn fall_step(fall_speed: &FallSpeed, delta_seconds: f32) -> f32 {
+    (fall_speed.0 * delta_seconds).min(MAX_FALL_STEP)
+}
+
+fn fall_block(
+    fixed_step: Res<FixedStep>,
+    time_scale: Res<TimeScale>,
+    fall_speed: Res<FallSpeed>,
+    mut block: Query<&mut Transform, (With<Block>, With<Fall>)>,
+) {
+    let step = fall_step(&fall_speed, fixed_step.0 * time_scale.0);
+    for mut transform in block.iter_mut() {
+        transform.translation.y -= step;
+    }
+}
+
+// When one cell of a garbage piece lands, the rest of the piece stops with it.
+// Blocks collide as squares of the board's block size, the size they land by, and not by
+// their sprites.
+fn stop_fall_block(
+    mut commands: Commands,
+    board_config: Res<BoardConfig>,
+    mut fall_block: Query<
+        (Entity, &mut Transform, Option<&Parent>, Option<&Garbage>),
+        (With<Block>, With<Fall>),
+    >,
+    other_block: Query<(&Transform, Option<&Parent>), (With<Block>, Without<Fall>)>,
+) {
+    let block_size = Vec2::splat(board_config.block_size);
+    let mut other_blocks: HashMap<_, Vec<_>> = HashMap::new();
+    for (other_block_transform, other_block_parent) in other_block.iter() {
+        other_blocks
+            .entry((
+                board_key(other_block_parent),
+                spatial_bucket(other_block_transform.translation, board_config.block_size),
+            ))
+            .or_default()
+            .push(other_block_transform);
+    }
+    let mut landed = HashSet::new();
+    let mut landed_garbage = HashMap::new();
+    for (fall_block_entity, mut fall_block_transform, fall_block_parent, garbage) in
+        fall_block.iter_mut()
+    {
+        let board = board_key(fall_block_parent);
+        let (col, row) = spatial_bucket(fall_block_transform.translation, board_config.block_size);
+        let nearby_blocks = (col - 1..=col + 1)
+            .flat_map(|col| (row - 1..=row + 1).map(move |row| (board, (col, row))))
+            .filter_map(|bucket| other_blocks.get(&bucket))
+            .flatten();
+        for other_block_transform in nearby_blocks {
+            if let Some(Collision::Top) = collide(
+                fall_block_transform.translation,
+                block_size,
+                other_block_transform.translation,
+                block_size,
+            ) {
+                commands
+                    .entity(fall_block_entity)
+                    .insert(FixedPrepare)
+                    .remove::<Fall>();
+                landed.insert(fall_block_entity);
+                let landed_y = other_block_transform.translation.y + board_config.block_size;
+                if let Some(garbage) = garbage {
+                    landed_garbage.insert(
+                        garbage.cells[0],
+                        (
+                            garbage.cells.clone(),
+                            landed_y - fall_block_transform.translation.y,
+                        ),
+                    );
+                }
+                fall_block_transform.translation.y = landed_y;
+            }
+        }
+    }
+    for (cells, shift) in landed_garbage.into_values() {
+        for cell in cells.into_iter().filter(|cell| !landed.contains(cell)) {
+            if let Ok((entity, mut transform, _, _)) = fall_block.get_mut(cell) {
+                transform.translation.y += shift;
+                commands
+                    .entity(entity)
+                    .insert(FixedPrepare)
+                    .remove::<Fall>();
+            }
+        }
+    }
+}
+
+// Settles each column from the bottom up. A `FixedPrepare` block starts a stack where it
+// landed, and any block within half a block of the next slot joins it flush, so several
+// stacks landing in one column on the same update neither overlap nor leave gaps.
+fn fixedprepare_to_fixed(
+    mut commands: Commands,
+    board_config: Res<BoardConfig>,
+    mut fixedprepare_block: Query<
+        (Entity, &mut Transform, Option<&Parent>),
+        (With<Block>, With<FixedPrepare>),
+    >,
+    mut fall_block: Query<
+        (Entity, &mut Transform, Option<&Parent>),
+        (With<Block>, With<Fall>, Without<FixedPrepare>),
+    >,
+) {
+    let mut columns: HashMap<(BoardKey, i32), Vec<(f32, Entity, bool)>> = HashMap::new();
+    for (entity, transform, parent) in fixedprepare_block.iter_mut() {
+        let column = spatial_bucket(transform.translation, board_config.block_size).0;
+        columns
+            .entry((board_key(parent), column))
+            .or_default()
+            .push((transform.translation.y, entity, true));
+    }
+    for (entity, transform, parent) in fall_block.iter_mut() {
+        let key = (
+            board_key(parent),
+            spatial_bucket(transform.translation, board_config.block_size).0,
+        );
+        // nothing lands in a column without a `FixedPrepare` block
+        if let Some(column) = columns.get_mut(&key) {
+            column.push((transform.translation.y, entity, false));
+        }
+    }
+    let block_size = board_config.block_size;
+    for mut column in columns.into_values() {
+        column.sort_unstable_by(|(y_a, _, _), (y_b, _, _)| y_a.partial_cmp(y_b).unwrap());
+        let mut stack_top: Option<f32> = None;
+        for (y, entity, fixed_prepare) in column {
+            let settled_y = match stack_top {
+                Some(top) if y - (top + block_size) <= block_size * 0.5 => top + block_size,
+                _ if fixed_prepare => y,
+                _ => {
+                    stack_top = None;
+                    continue;
+                }
+            };
+            stack_top = Some(settled_y);
+            commands
+                .entity(entity)
+                .remove::<FixedPrepare>()
+                .remove::<Fall>()
+                .insert(Fixed)
+                .insert(LandingSquash(1.0))
+                .insert(LandingSquash(1.0).ease_to(
+                    LandingSquash(0.0),
+                    EaseFunction::QuadraticOut,
+                    EasingType::Once {
+                        duration: Duration::from_secs_f32(LANDING_SQUASH_SECONDS),
+                    },
+                ));
+            let transform = if fixed_prepare {
+                fixedprepare_block
+                    .get_mut(entity)
+                    .map(|(_, transform, _)| transform)
+            } else {
+                fall_block
+                    .get_mut(entity)
+                    .map(|(_, transform, _)| transform)
+            };
+            if let Ok(mut transform) = transform {
+                transform.translation.y = settled_y;
+            }
+        }
+    }
+}
+
+// Scale only changes the sprite; falls and matches go by the translation and `GridPos`.
+fn squash_landed_block(
+    mut commands: Commands,
+    mut block: Query<
+        (
+            Entity,
+            &mut Transform,
+            &LandingSquash,
+            Option<&EasingComponent<LandingSquash>>,
+        ),
+        With<Block>,
+    >,
+) {
+    for (entity, mut transform, landing_squash, easing_component) in block.iter_mut() {
+        match easing_component {
+            Some(_) => {
+                let squash = LANDING_SQUASH_AMOUNT * landing_squash.0;
+                transform.scale = Vec3::new(1.0 + squash, 1.0 - squash, 1.0);
+            }
+            None => {
+                transform.scale = Vec3::ONE;
+                commands.entity(entity).remove::<LandingSquash>();
+            }
+        }
+    }
+}
+
+// Holding lift raises the board at `MANUAL_LIFT_SPEED` without waiting out the
+// `CountTimer`. It drops back to the ramped speed as soon as lift is let go, and when a
+// fast step would carry the stack past `game_over_y`.
+fn manual_liftup(
+    fixed_step: Res<FixedStep>,
+    time_scale: Res<TimeScale>,
+    keyboard_input: Res<Input<KeyCode>>,
+    board_config: Res<BoardConfig>,
+    cursor: Query<(&PlayerControls, Option<&Parent>), With<Cursor>>,
+    block: Query<(&Transform, Option<&Parent>), (With<Fixed>, With<Block>)>,
+    mut board_speed: Query<(&mut CountTimer, &mut GameSpeed, &RampClock, Option<&Parent>)>,
+) {
+    let lift_held = cursor
+        .iter()
+        .filter(|(controls, _)| pressed(&keyboard_input, &controls.lift))
+        .map(|(_, parent)| board_key(parent))
+        .collect::<HashSet<_>>();
+    let mut max_y = HashMap::new();
+    for (transform, parent) in block.iter() {
+        let y = max_y.entry(board_key(parent)).or_insert(f32::MIN);
+        *y = transform.translation.y.max(*y);
+    }
+    let manual_step = fixed_step.0 * time_scale.0 * MANUAL_LIFT_SPEED;
+    for (mut count_timer, mut game_speed, ramp_clock, parent) in board_speed.iter_mut() {
+        let board = board_key(parent);
+        let room_to_lift = max_y
+            .get(&board)
+            .is_none_or(|y| y + manual_step <= board_config.game_over_y());
+        if lift_held.contains(&board) && room_to_lift {
+            let duration = count_timer.0.duration();
+            count_timer.0.set_elapsed(duration);
+            game_speed.current = MANUAL_LIFT_SPEED;
+        } else {
+            game_speed.current = game_speed.ramp.speed_at(game_speed.origin, ramp_clock.0);
+        }
+    }
+}
+
+// A board rises as one piece, so anything in motion anywhere on it (a fall, a clear, a
+// landing) pauses the whole board however far it is from the top. Other boards keep rising.
+// A chain also holds the board, and `chain_freeze` keeps it still a little after the chain.
+fn auto_liftup(
+    step: ScaledStep,
+    board_config: Res<BoardConfig>,
+    mut chain_freeze: Local<HashMap<BoardKey, f32>>,
+    chain_block: Query<Option<&Parent>, (With<Chain>, With<Block>)>,
+    chain_counter: Query<(&ChainCounter, Option<&Parent>)>,
+    mut count_timer: Query<(&mut CountTimer, &GameSpeed, Option<&Parent>)>,
+    mut query_set: QuerySet<(
+        Query<
+            Option<&Parent>,
+            (
+                Without<Fixed>,
+                Without<Spawning>,
+                Without<Moving>,
+                Without<Move>,
+                With<Block>,
+            ),
+        >,
+        Query<(&Transform, Option<&Parent>), (With<Fixed>, With<Block>)>,
+        Query<(&mut Transform, Option<&Parent>), Or<(With<Cursor>, With<Block>, With<Bottom>)>>,
+    )>,
+) {
+    let busy_boards = query_set.q0().iter().map(board_key).collect::<HashSet<_>>();
+    let chaining_boards = chain_block
+        .iter()
+        .map(board_key)
+        .chain(
+            chain_counter
+                .iter()
+                .filter(|(chain_counter, _)| chain_counter.0 > 1)
+                .map(|(_, parent)| board_key(parent)),
+        )
+        .collect::<HashSet<_>>();
+    let delta = step.seconds();
+    for (board, freeze) in chain_freeze.iter_mut() {
+        if !chaining_boards.contains(board) {
+            *freeze -= delta;
+        }
+    }
+    chain_freeze.retain(|_, freeze| *freeze > 0.0);
+    for board in chaining_boards {
+        chain_freeze.insert(board, CHAIN_FREEZE_SECONDS);
+    }
+    let mut max_y = HashMap::new();
+    for (transform, parent) in query_set.q1().iter() {
+        let y = max_y.entry(board_key(parent)).or_insert(f32::MIN);
+        *y = transform.translation.y.max(*y);
+    }
+    let mut lifting_boards = HashMap::new();
+    for (mut count_timer, game_speed, parent) in count_timer.iter_mut() {
+        count_timer.0.tick(Duration::from_secs_f32(step.seconds()));
+        let board = board_key(parent);
+        if let Some(max_y) = max_y.get(&board) {
+            if count_timer.0.finished()
+                && *max_y < board_config.game_over_y()
+                && !busy_boards.contains(&board)
+                && !chain_freeze.contains_key(&board)
+            {
+                lifting_boards.insert(board, game_speed.current);
+            }
+        }
+    }
+    // lift up
+    for (mut transform, parent) in query_set.q2_mut().iter_mut() {
+        if let Some(speed) = lifting_boards.get(&board_key(parent)) {
+            transform.translation.y += step.seconds() * speed;
+        }
+    }
+}
+
+// The clock stops while the board has blocks clearing, so time spent watching a chain
+// doesn't speed the board up.
+fn speed_ramp(
+    fixed_step: Res<FixedStep>,
+    clearing_block: Query<Option<&Parent>, (Or<(With<Matched>, With<Despawining>)>, With<Block>)>,
+    mut board_speed: Query<(&mut RampClock, &mut GameSpeed, Option<&Parent>)>,
+) {
+    let clearing_boards = clearing_block.iter().map(board_key).collect::<HashSet<_>>();
+    for (mut ramp_clock, mut game_speed, parent) in board_speed.iter_mut() {
+        if clearing_boards.contains(&board_key(parent)) {
+            continue;
+        }
+        ramp_clock.0 += fixed_step.0;
+        game_speed.current = game_speed.ramp.speed_at(game_speed.origin, ramp_clock.0);
+    }
+}
+
+// Score attack ends like a top out once `PlayTime` reaches the limit.
+fn end_score_attack(
+    mut commands: Commands,
+    play_time: Res<PlayTime>,
+    finish: Option<Res<GameOverFinish>>,
+) {
+    if finish.is_none() && play_time.0 >= SCORE_ATTACK_SECONDS {
+        commands.insert_resource(GameOverFinish(Timer::from_seconds(FINISH_SECONDS, false)));
+    }
+}
+
+fn tick_play_time(fixed_step: Res<FixedStep>, mut play_time: ResMut<PlayTime>) {
+    play_time.0 += fixed_step.0;
+}
+
+// Columns whose settled blocks reach above `danger_y`.
+fn danger_columns(
+    block_heights: impl IntoIterator<Item = (u8, f32)>,
+    danger_y: f32,
+) -> HashSet<u8> {
+    block_heights
+        .into_iter()
+        .filter(|(_, y)| *y > danger_y)
+        .map(|(col, _)| col)
+        .collect()
+}
+
+// Keeps one overlay on each column in danger, pulsing until the column drops again; with
+// `ReduceFlashing` the overlay stays on.
+fn danger_flash(
+    mut commands: Commands,
+    time: Res<Time>,
+    reduce_flashing: Res<ReduceFlashing>,
+    board_config: Res<BoardConfig>,
+    mut danger_material: CachedMaterial,
+    block: Query<(&GridPos, &Transform, Option<&Parent>), (With<Fixed>, With<Block>)>,
+    mut flash: Query<(Entity, &DangerFlash, &mut HudElement, Option<&Parent>)>,
+) {
+    let mut block_heights = HashMap::<BoardKey, Vec<(u8, f32)>>::new();
+    for (grid_pos, transform, parent) in block.iter() {
+        block_heights
+            .entry(board_key(parent))
+            .or_default()
+            .push((grid_pos.col, transform.translation.y));
+    }
+    let mut danger = block_heights
+        .into_iter()
+        .map(|(board, heights)| (board, danger_columns(heights, board_config.danger_y())))
+        .collect::<HashMap<_, _>>();
+    let is_visible = reduce_flashing.0
+        || ((time.seconds_since_startup() / DANGER_FLASH_SECONDS as f64) as u64).is_multiple_of(2);
+    for (entity, danger_flash, mut hud_element, parent) in flash.iter_mut() {
+        // what is left in `danger` afterwards still needs an overlay
+        let still_in_danger = danger
+            .get_mut(&board_key(parent))
+            .is_some_and(|columns| columns.remove(&danger_flash.0));
+        if still_in_danger {
+            hud_element.shown = is_visible;
+        } else {
+            commands.entity(entity).despawn();
+        }
+    }
+    let material = danger_material.get_or_add(Color::rgba(1.0, 0.0, 0.0, 0.3));
+    for (board, columns) in danger {
+        for col in columns {
+            let x = GridPos { col, row: 0 }.to_translation(0.8, &board_config).x;
+            let overlay = commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(board_config.block_size, board_config.size().y)),
+                    material: material.clone(),
+                    transform: Transform::from_xyz(x, 0.0, 0.8),
+                    ..Default::default()
+                })
+                .insert(DangerFlash(col))
+                .insert(HudElement::default())
+                .id();
+            if let Some(board_entity) = board {
+                commands.entity(board_entity).push_children(&[overlay]);
+            }
+        }
+    }
+}
+
+// Any board topping out ends the game. Only settled blocks count, and a board with a
+// `TopOutTimer` has to stay topped out until it finishes. In a versus game the other
+// board's player wins, unless both topped out on the same update.
+fn check_game_over(
+    mut commands: Commands,
+    fixed_step: Res<FixedStep>,
+    finish: Option<Res<GameOverFinish>>,
+    block: Query<(&Transform, Option<&Parent>), (With<Fixed>, With<Block>)>,
+    mut top_out_timer: Query<(&mut TopOutTimer, Option<&Parent>)>,
+    player_board: Query<(Entity, &Player), With<Board>>,
+    board_config: Res<BoardConfig>,
+) {
+    if finish.is_some() {
+        return;
+    }
+    let mut topped_out = block
+        .iter()
+        .filter(|(transform, _)| transform.translation.y > board_config.game_over_y())
+        .map(|(_, parent)| board_key(parent))
+        .collect::<HashSet<_>>();
+    for (mut top_out_timer, parent) in top_out_timer.iter_mut() {
+        let board = board_key(parent);
+        if !topped_out.contains(&board) {
+            top_out_timer.0.reset();
+            continue;
+        }
+        top_out_timer.0.tick(fixed_step.delta());
+        if !top_out_timer.0.finished() {
+            topped_out.remove(&board);
+        }
+    }
+    if topped_out.is_empty() {
+        return;
+    }
+    let standing = player_board
+        .iter()
+        .filter(|(board, _)| !topped_out.contains(&Some(*board)))
+        .map(|(_, player)| *player)
+        .collect::<Vec<_>>();
+    if let [player] = standing[..] {
+        commands.insert_resource(Winner(player));
+    }
+    commands.insert_resource(GameOverFinish(Timer::from_seconds(FINISH_SECONDS, false)));
+}
+
+// Eases from full speed down to `FINISH_TIME_SCALE` over `FINISH_SECONDS`.
+fn finish_time_scale(elapsed: f32) -> f32 {
+    1.0 + (FINISH_TIME_SCALE - 1.0) * (elapsed / FINISH_SECONDS).min(1.0)
+}
+
+// Ticks by the fixed step, not the scaled one, so the slow down itself isn't slowed.
+fn finish_game_over(
+    fixed_step: Res<FixedStep>,
+    finish: Option<ResMut<GameOverFinish>>,
+    mut time_scale: ResMut<TimeScale>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let mut finish = match finish {
+        Some(finish) => finish,
+        None => return,
+    };
+    finish.0.tick(fixed_step.delta());
+    time_scale.0 = finish_time_scale(finish.0.elapsed_secs());
+    if finish.0.just_finished() {
+        queue_state_change(state.set(AppState::GameOver));
+    }
+}
+
+// Largest scale, at most 1, at which every board's area fits in the window when the
+// layout is scaled around the middle of the screen.
+fn layout_scale(window: Vec2, origins: impl Iterator<Item = Vec3>, half_extent: Vec2) -> f32 {
+    let needed = origins.fold(Vec2::ZERO, |needed, origin| {
+        needed.max(origin.truncate().abs() + half_extent)
+    });
+    if needed.x <= 0.0 || needed.y <= 0.0 {
+        return 1.0;
+    }
+    (window.x / 2.0 / needed.x)
+        .min(window.y / 2.0 / needed.y)
+        .min(1.0)
+}
+
+// Shrinks the boards and the gaps between them together. Everything on a board is one
+// of its children in board units, so the cursor limits and cell math stay as they are.
+fn fit_board_to_window(
+    mut window_resized: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    board_config: Res<BoardConfig>,
+    new_board: Query<(), Added<Board>>,
+    mut board: Query<(&BoardOrigin, &mut Transform), With<Board>>,
+) {
+    if window_resized.iter().count() == 0 && new_board.iter().next().is_none() {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => Vec2::new(window.width(), window.height()),
+        None => return,
+    };
+    let origins = board
+        .iter_mut()
+        .map(|(origin, _)| origin.0)
+        .collect::<Vec<_>>();
+    let scale = layout_scale(window, origins.into_iter(), board_config.half_extent());
+    for (origin, mut transform) in board.iter_mut() {
+        transform.translation = (origin.0.truncate() * scale).extend(origin.0.z);
+        transform.scale = Vec3::new(scale, scale, 1.0);
+    }
+}
+
+// Blocks, cursor and bottom are children of the board, so they go with it.
+fn cleanup_ingame(
+    mut commands: Commands,
+    ingame_entity: Query<
+        Entity,
+        Or<(
+            With<Board>,
+            With<BoardBottomCover>,
+            With<ChainCounter>,
+            With<CountTimer>,
+            With<IngameCamera>,
+            With<CountdownUi>,
+            With<CursorDebugText>,
+        )>,
+    >,
+) {
+    for entity in ingame_entity.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<MovesLeft>();
+    commands.remove_resource::<GameOverFinish>();
+}
+
+fn spawning_to_fixed(
+    mut commands: Commands,
+    spawning_block: Query<(Entity, &Transform), (With<Spawning>, With<Block>)>,
+    board_config: Res<BoardConfig>,
+) {
+    for (entity, transform) in spawning_block.iter() {
+        if transform.translation.y - board_config.block_size / 2.0 > board_config.bottom_edge_y() {
+            commands.entity(entity).remove::<Spawning>().insert(Fixed);
+        }
+    }
+}
+
+// A block shows from when its top edge rises past the cover, so it fades in over the
+// last row it rises and is fully opaque once it becomes `Fixed`.
+fn spawn_fade_step(y: f32, board_config: &BoardConfig) -> u8 {
+    let revealed = (y + board_config.block_size / 2.0 - board_config.bottom_edge_y())
+        / board_config.block_size;
+    (revealed.clamp(0.0, 1.0) * SPAWN_FADE_STEPS as f32).floor() as u8
+}
+
+// Sprites share one material per color, so the fade goes through a few translucent
+// copies of it, made on first use.
+fn fade_in_spawning_block(
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    block_materials: Res<BlockMaterials>,
+    mut faded_materials: Local<HashMap<(BlockColor, u8), (Color, Handle<ColorMaterial>)>>,
+    board_config: Res<BoardConfig>,
+    mut spawning_block: Query<
+        (&Transform, &BlockColor, &mut Handle<ColorMaterial>),
+        (With<Spawning>, With<Block>),
+    >,
+    mut settled_block: Query<
+        (&BlockColor, &mut Handle<ColorMaterial>),
+        (Added<Fixed>, Without<Spawning>, With<Block>),
+    >,
+) {
+    for (transform, block_color, mut material) in spawning_block.iter_mut() {
+        let full_material = block_material(&block_materials, *block_color);
+        let step = spawn_fade_step(transform.translation.y, &board_config);
+        let faded_material = if step >= SPAWN_FADE_STEPS {
+            full_material
+        } else {
+            shaded_material(
+                &mut materials,
+                &mut faded_materials,
+                (*block_color, step),
+                &full_material,
+                |mut color| {
+                    color.set_a(color.a() * step as f32 / SPAWN_FADE_STEPS as f32);
+                    color
+                },
+            )
+        };
+        if *material != faded_material {
+            *material = faded_material;
+        }
+    }
+    // in case the lift carried a block past the last step and it settled on the same update
+    for (block_color, mut material) in settled_block.iter_mut() {
+        let full_material = block_material(&block_materials, *block_color);
+        if *material != full_material {
+            *material = full_material;
+        }
+    }
+}
+
+fn bottom_down(mut bottom: Query<&mut Transform, With<Bottom>>, board_config: Res<BoardConfig>) {
+    for mut transform in bottom.iter_mut() {
+        if transform.translation.y >= board_config.first_row_y() {
+            transform.translation.y = board_config.bottom_y();
+        }
+    }
+}
+
+fn generate_spawning_block(
+    mut commands: Commands,
+    mut looks: BlockLooks,
+    board: Query<Entity, With<Board>>,
+    bottom: Query<(&Transform, Option<&Parent>), With<Bottom>>,
+    palette: Palette,
+    board_config: Res<BoardConfig>,
+    mut next_row: ResMut<NextRow>,
+) {
+    let block_size = board_config.block_size;
+    let relative_x = block_size / 2.0 - board_config.size().x / 2.0;
+    for (transform, parent) in bottom.iter() {
+        let board_entity = match parent.and_then(|parent| board.get(parent.0).ok()) {
+            Some(board_entity) => board_entity,
+            None => continue,
+        };
+        if transform.translation.y >= board_config.first_row_y() {
+            let rng = &mut looks.board_rng.0;
+            let mut block_colors = palette.block_colors();
+            block_colors.shuffle(rng);
+            let mut previous_block_queue = VecDeque::with_capacity(2);
+            let mut row_colors = Vec::with_capacity(board_config.width);
+            for column_idx in 0..board_config.width {
+                let number = rng.gen_range(0..block_colors.len());
+                row_colors.push(block_colors[number]);
+                let block = commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: board_config.block_sprite(),
+                        material: block_material(&looks.block_materials, block_colors[number]),
+                        transform: Transform {
+                            translation: Vec3::new(
+                                relative_x + block_size * column_idx as f32,
+                                board_config.bottom_y() - block_size,
+                                0.0,
+                            ),
+                            rotation: block_rotation(&looks.settings, rng),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(Block)
+                    .insert(block_colors[number])
+                    .insert(Spawning)
+                    .id();
+                commands.entity(board_entity).push_children(&[block]);
+                let tmp_remove_block = Some(block_colors.remove(number));
+                previous_block_queue.push_back(tmp_remove_block);
+                if previous_block_queue.len() > 1 {
+                    if let Some(Some(back_color_block)) = previous_block_queue.pop_front() {
+                        block_colors.push(back_color_block);
+                    }
+                }
+            }
+            next_row.0.insert(Some(board_entity), row_colors);
+        }
+    }
+}
+
+// The loaded sounds and block art, as default handles.
+#[cfg(test)]
+fn insert_asset_fixtures(world: &mut World) {
+    world.insert_resource(SoundAssets {
+        swap_sound: Handle::default(),
+        match_sound: Handle::default(),
+        chain_sound: Handle::default(),
+    });
+    world.insert_resource(BlockMaterials {
+        red_material: Handle::<ColorMaterial>::default(),
+        green_material: Handle::<ColorMaterial>::default(),
+        blue_material: Handle::<ColorMaterial>::default(),
+        yellow_material: Handle::<ColorMaterial>::default(),
+        purple_material: Handle::<ColorMaterial>::default(),
+        indigo_material: Handle::<ColorMaterial>::default(),
+        extra_materials: Vec::new(),
+    });
+}
+
+#[test]
+fn test_setup_board() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(GameMode::default());
+    world.insert_resource(GameSpeed::default());
+    world.insert_resource(KeyBindings::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(setup_board.system());
+    world.insert_resource(NextRow::default());
+    world.insert_resource(ColorCount::default());
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(BoardRng::new(None));
+
+    world.insert_resource(BoardMaterials {
+        board_material: Handle::<ColorMaterial>::default(),
+    });
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BottomMaterials {
+        bottom_material: Handle::<ColorMaterial>::default(),
+    });
+    world.insert_resource(CursorMaterials {
+        cursor_material: Handle::<ColorMaterial>::default(),
+    });
+
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<&Board>().iter(&world).len(), 1);
+    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
+    assert!(world.query::<&Block>().iter(&world).len() > 5);
+    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 12);
+    assert_eq!(world.query::<&Bottom>().iter(&world).len(), 1);
+    let board = world
+        .query_filtered::<Entity, With<Board>>()
+        .iter(&world)
+        .next();
+    assert_eq!(
+        world.get_resource::<NextRow>().unwrap().0[&board].len(),
+        BOARD_WIDTH
+    );
+}
+
+#[test]
+fn test_game_save_round_trip() {
+    fn setup_world() -> World {
+        let mut world = World::default();
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(GameMode::Endless);
+        world.insert_resource(GameSpeed::default());
+        world.insert_resource(KeyBindings::default());
+        world.insert_resource(NextRow::default());
+        world.insert_resource(Difficulty::default());
+        world.insert_resource(ColorCount::default());
+        world.insert_resource(ColorTable::default());
+        world.insert_resource(BoardRng::new(None));
+        world.insert_resource(Score(0));
+        world.insert_resource(MaxChain(0));
+        world.insert_resource(PlayTime(0.0));
+        world.insert_resource(BoardMaterials {
+            board_material: Handle::<ColorMaterial>::default(),
+        });
+        insert_asset_fixtures(&mut world);
+        world.insert_resource(BottomMaterials {
+            bottom_material: Handle::<ColorMaterial>::default(),
+        });
+        world.insert_resource(CursorMaterials {
+            cursor_material: Handle::<ColorMaterial>::default(),
+        });
+        world
+    }
+
+    fn run_setup(world: &mut World) {
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(setup_board.system());
+        update_stage.add_system(resume_saved_game.exclusive_system().at_end());
+        update_stage.run(world);
+    }
+
+    let mut world = setup_world();
+    run_setup(&mut world);
+    world.insert_resource(Score(1230));
+    world.insert_resource(MaxChain(4));
+    world.insert_resource(PlayTime(61.5));
+    for mut transform in world
+        .query_filtered::<&mut Transform, With<Cursor>>()
+        .iter_mut(&mut world)
+    {
+        transform.translation.x -= BLOCK_SIZE;
+        transform.translation.y += BLOCK_SIZE * 2.0;
+    }
+    for mut ramp_clock in world.query::<&mut RampClock>().iter_mut(&mut world) {
+        ramp_clock.0 = 30.0;
+    }
+    let save = serialize_game(&mut world).unwrap();
+    assert_eq!(save.score, 1230);
+    assert_eq!(save.rows.len(), SPAWNING_ROWS + BOARD_HEIGHT);
+    assert!(save.rows[SPAWNING_ROWS].iter().all(Option::is_some));
+
+    let ron = ron::ser::to_string(&save).unwrap();
+    let loaded: GameSave = ron::de::from_str(&ron).unwrap();
+    assert_eq!(loaded, save);
+
+    let mut world = setup_world();
+    world.insert_resource(PendingGameSave(loaded));
+    run_setup(&mut world);
+    assert!(world.get_resource::<PendingGameSave>().is_none());
+    assert_eq!(world.query::<&Board>().iter(&world).len(), 1);
+    assert_eq!(serialize_game(&mut world).unwrap(), save);
+}
+
+#[test]
+fn test_replay_reproduces_swaps() {
+    use crate::replay::{feed_replay_input, record_replay_input, ReplayLog, ReplayPlayer};
+
+    // returns the recorded log, every block's color, position and pending swap, and the score
+    fn play(
+        pressed: &[&[KeyCode]],
+        replay_player: Option<ReplayPlayer>,
+    ) -> (ReplayLog, Vec<(BlockColor, Vec3, bool)>, u32) {
+        let mut world = World::default();
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(GameMode::Endless);
+        world.insert_resource(GameSpeed::default());
+        world.insert_resource(KeyBindings::default());
+        world.insert_resource(NextRow::default());
+        world.insert_resource(ColorCount::default());
+        world.insert_resource(ColorTable::default());
+        world.insert_resource(BoardRng::new(Some(&GameSeed(7))));
+        world.insert_resource(Score(0));
+        world.insert_resource(FixedStep::default());
+        world.insert_resource(InputTuning::default());
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+        world.insert_resource(Audio::default());
+        insert_asset_fixtures(&mut world);
+        world.insert_resource(BoardMaterials {
+            board_material: Handle::<ColorMaterial>::default(),
+        });
+        world.insert_resource(BottomMaterials {
+            bottom_material: Handle::<ColorMaterial>::default(),
+        });
+        world.insert_resource(CursorMaterials {
+            cursor_material: Handle::<ColorMaterial>::default(),
+        });
+        world.insert_resource(ReplayLog {
+            recording: true,
+            ..Default::default()
+        });
+        if let Some(replay_player) = replay_player {
+            world.insert_resource(replay_player);
+        }
+        let mut setup_stage = SystemStage::parallel();
+        setup_stage.add_system(setup_board.system());
+        setup_stage.run(&mut world);
+
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(feed_replay_input.system().label("feed_replay_input"));
+        update_stage.add_system(
+            record_replay_input
+                .system()
+                .label("record_replay_input")
+                .after("feed_replay_input"),
+        );
+        update_stage.add_system(move_cursor.system().after("record_replay_input"));
+        update_stage.add_system(move_tag_block.system().after("record_replay_input"));
+        // like keyboard events, only changes from the previous frame reach the input
+        let mut previous: &[KeyCode] = &[];
+        for keys in pressed {
+            let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+            input.update();
+            for key in [KeyCode::Left, KeyCode::Down, KeyCode::Space] {
+                if keys.contains(&key) && !previous.contains(&key) {
+                    input.press(key);
+                } else if !keys.contains(&key) && previous.contains(&key) {
+                    input.release(key);
+                }
+            }
+            previous = keys;
+            update_stage.run(&mut world);
+        }
+        let blocks = world
+            .query_filtered::<(&BlockColor, &Transform, Option<&Move>), With<Block>>()
+            .iter(&world)
+            .map(|(block_color, transform, swap)| {
+                (*block_color, transform.translation, swap.is_some())
+            })
+            .collect();
+        (
+            world.get_resource::<ReplayLog>().unwrap().clone(),
+            blocks,
+            world.get_resource::<Score>().unwrap().0,
+        )
+    }
+
+    // two swaps a few cells apart, each pressed while a move key is still held
+    let pressed: [&[KeyCode]; 9] = [
+        &[KeyCode::Down],
+        &[],
+        &[KeyCode::Down],
+        &[KeyCode::Down, KeyCode::Space],
+        &[],
+        &[KeyCode::Left],
+        &[],
+        &[KeyCode::Left],
+        &[KeyCode::Left, KeyCode::Space],
+    ];
+    let (log, blocks, score) = play(&pressed, None);
+    assert_eq!(log.frames.len(), pressed.len());
+    assert!(blocks.iter().filter(|(_, _, swap)| *swap).count() > 2);
+
+    // nothing is pressed during playback, the log drives the cursor alone
+    let (replayed_log, replayed_blocks, replayed_score) = play(
+        &[&[] as &[KeyCode]; 9],
+        Some(ReplayPlayer {
+            log: log.clone(),
+            frame: 0,
+        }),
+    );
+    assert_eq!(replayed_log.frames, log.frames);
+    assert_eq!(replayed_blocks, blocks);
+    assert_eq!(replayed_score, score);
+}
+
+#[test]
+fn test_place_sandbox_block() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(place_sandbox_block.system());
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(GameMode::Sandbox);
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(BoardRng::new(None));
+    insert_asset_fixtures(&mut world);
+    let board = world.spawn().insert(Board).id();
+    let cursor = world
+        .spawn()
+        .insert(Cursor)
+        .insert(Transform::from_translation(
+            BoardConfig::default().cursor_start().extend(1.0),
+        ))
+        .id();
+    world.entity_mut(board).push_children(&[cursor]);
+    let mut press = |world: &mut World, key: KeyCode| {
+        let mut input = Input::<KeyCode>::default();
+        input.press(key);
+        world.insert_resource(input);
+        update_stage.run(world);
+    };
+    let blocks = |world: &mut World| {
+        world
+            .query::<(&BlockColor, &GridPos, &Parent, &Fixed)>()
+            .iter(world)
+            .map(|(block_color, grid_pos, parent, _)| (*block_color, *grid_pos, parent.0))
+            .collect::<Vec<_>>()
+    };
+    // the cursor starts with its left cell in the third column, halfway up
+    let cell = GridPos { col: 2, row: 6 };
+
+    press(&mut world, KeyCode::Key1);
+    assert_eq!(blocks(&mut world), vec![(BlockColor::RED, cell, board)]);
+    press(&mut world, KeyCode::Key3);
+    assert_eq!(blocks(&mut world), vec![(BlockColor::BLUE, cell, board)]);
+    press(&mut world, KeyCode::Key3);
+    assert!(blocks(&mut world).is_empty());
+
+    // nothing is placed outside the sandbox
+    world.insert_resource(GameMode::Endless);
+    press(&mut world, KeyCode::Key1);
+    assert!(blocks(&mut world).is_empty());
+}
+
+#[test]
+fn test_layout_scale() {
+    let half_extent = BoardConfig::default().half_extent();
+    // a single board fits the default window as it is
+    assert_eq!(
+        layout_scale(
+            Vec2::new(1920.0, 1080.0),
+            [Vec3::ZERO].into_iter(),
+            half_extent
+        ),
+        1.0
+    );
+    // a short window scales by height, a narrow one by width
+    let short = layout_scale(
+        Vec2::new(1920.0, 475.0),
+        [Vec3::ZERO].into_iter(),
+        half_extent,
+    );
+    assert_eq!(short, 0.5);
+    let narrow = layout_scale(
+        Vec2::new(200.0, 1080.0),
+        [Vec3::ZERO].into_iter(),
+        half_extent,
+    );
+    assert_eq!(narrow, 0.5);
+
+    // side by side boards keep the whole pair inside and centered
+    let origins = [Vec3::new(-200.0, 0.0, 0.0), Vec3::new(200.0, 0.0, 0.0)];
+    let scale = layout_scale(Vec2::new(600.0, 1080.0), origins.into_iter(), half_extent);
+    assert_eq!(scale, 0.75);
+    for origin in origins {
+        let right = (origin.x.abs() + half_extent.x) * scale;
+        assert!(right <= 300.0);
+    }
+    assert_eq!((origins[0].x + origins[1].x) * scale, 0.0);
+}
+
+#[test]
+fn test_countdown_label() {
+    let labels =
+        [0.0, 1.5, 2.5, 3.5, 4.5].map(|steps| countdown_label(steps * COUNTDOWN_STEP_SECONDS));
+    assert_eq!(labels, [Some("3"), Some("2"), Some("1"), Some("Go!"), None]);
+}
+
+#[test]
+fn test_countdown_criteria() {
+    #[derive(Default)]
+    struct UpdateCounter(u32);
+    fn count_update(mut counter: ResMut<UpdateCounter>) {
+        counter.0 += 1;
+    }
+
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_run_criteria((|| ShouldRun::Yes).system().label("always"));
+    update_stage.add_system_set(
+        SystemSet::new()
+            .with_run_criteria(RunCriteria::pipe("always", countdown_criteria.system()))
+            .with_system(count_update.system()),
+    );
+    world.insert_resource(GameStarted(false));
+    world.insert_resource(UpdateCounter::default());
+
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<UpdateCounter>().unwrap().0, 0);
+    world.get_resource_mut::<GameStarted>().unwrap().0 = true;
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<UpdateCounter>().unwrap().0, 1);
+}
+
+#[test]
+fn test_start_colors_fewer_colors_than_pattern() {
+    let settings = GameSettings {
+        start_layout: StartLayout::Pattern,
+        ..Default::default()
+    };
+    let block_colors = block_colors(&ColorCount(4), &ColorTable::default());
+    let mut rng = StdRng::seed_from_u64(0);
+    let colors = start_colors(&settings, &BoardConfig::default(), block_colors, &mut rng);
+    assert!(colors
+        .iter()
+        .flatten()
+        .flatten()
+        .all(|block_color| block_color.0 < 4));
+    assert_eq!(
+        colors[SPAWNING_ROWS..].iter().flatten().flatten().count(),
+        30
+    );
+    assert!(!has_initial_match(&colors));
+}
+
+#[test]
+fn test_setup_board_start_layout() {
+    let blocks = |start_layout: StartLayout| {
+        let mut world = World::default();
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(GameSettings {
+            start_layout,
+            ..Default::default()
+        });
+        world.insert_resource(GameMode::default());
+        world.insert_resource(GameSpeed::default());
+        world.insert_resource(KeyBindings::default());
+        world.insert_resource(ColorCount::default());
+        world.insert_resource(ColorTable::default());
+        world.insert_resource(BoardRng::new(None));
+        world.insert_resource(NextRow::default());
+        world.insert_resource(BoardMaterials {
+            board_material: Handle::<ColorMaterial>::default(),
+        });
+        insert_asset_fixtures(&mut world);
+        world.insert_resource(BottomMaterials {
+            bottom_material: Handle::<ColorMaterial>::default(),
+        });
+        world.insert_resource(CursorMaterials {
+            cursor_material: Handle::<ColorMaterial>::default(),
+        });
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(setup_board.system());
+        update_stage.run(&mut world);
+        let spawning = world.query::<(&Block, &Spawning)>().iter(&world).len();
+        let fixed = world.query::<(&Block, &Fixed)>().iter(&world).len();
+        assert_eq!(fixed, world.query::<&GridPos>().iter(&world).len());
+        let mut grid: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+        for (grid_pos, block_color) in world.query::<(&GridPos, &BlockColor)>().iter(&world) {
+            grid[grid_pos.row as usize][grid_pos.col as usize] = Some(*block_color);
+        }
+        (spawning, grid)
+    };
+
+    let (spawning, grid) = blocks(StartLayout::Empty);
+    assert_eq!(spawning, 12);
+    assert!(grid.iter().flatten().all(Option::is_none));
+
+    let (spawning, grid) = blocks(StartLayout::Flat(3));
+    assert_eq!(spawning, 12);
+    assert!(grid[..3].iter().flatten().all(Option::is_some));
+    assert!(grid[3..].iter().flatten().all(Option::is_none));
+    assert!(!has_initial_match(&grid));
+
+    // a flat fill has no holes, so every cell of its rows holds a settled block
+    let max_rows = BoardConfig::default().max_start_rows();
+    for (rows, filled) in [(9, 9), (u8::MAX, max_rows)] {
+        let (spawning, grid) = blocks(StartLayout::Flat(rows));
+        assert_eq!(spawning, 12);
+        assert_eq!(
+            grid.iter().flatten().filter(|cell| cell.is_some()).count(),
+            filled * BOARD_WIDTH
+        );
+        assert!(grid[filled..].iter().flatten().all(Option::is_none));
+        assert!(!has_initial_match(&grid));
+    }
+}
+
+#[test]
+fn test_setup_gamespeed() {
+    let speeds = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard].map(|difficulty| {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(setup_gamespeed.system());
+        world.insert_resource(GameSpeed::default());
+        world.insert_resource(difficulty);
+        update_stage.run(&mut world);
+        *world.get_resource::<GameSpeed>().unwrap()
+    });
+    let [easy, normal, hard] = speeds;
+    assert_eq!(normal.current, GameSpeed::default().origin);
+    assert!(easy.current < normal.current);
+    assert!(normal.current < hard.current);
+    assert!(easy.lift_delay > normal.lift_delay);
+    assert!(normal.lift_delay > hard.lift_delay);
+    // the chosen speed is kept for the next run
+    assert_eq!(hard.origin, GameSpeed::default().origin);
+}
+
+#[test]
+fn test_setup_board_with_game_seed() {
+    fn run_setup_board(seed: u64) -> Vec<(BlockColor, Vec3)> {
+        let mut world = World::default();
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(GameMode::default());
+        world.insert_resource(GameSpeed::default());
+        world.insert_resource(KeyBindings::default());
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(setup_board.system());
+        world.insert_resource(NextRow::default());
+        world.insert_resource(ColorCount::default());
+        world.insert_resource(ColorTable::default());
+        world.insert_resource(BoardRng::new(Some(&GameSeed(seed))));
+        world.insert_resource(BoardMaterials {
+            board_material: Handle::<ColorMaterial>::default(),
+        });
+        insert_asset_fixtures(&mut world);
+        world.insert_resource(BottomMaterials {
+            bottom_material: Handle::<ColorMaterial>::default(),
+        });
+        world.insert_resource(CursorMaterials {
+            cursor_material: Handle::<ColorMaterial>::default(),
+        });
+
+        update_stage.run(&mut world);
+        world
+            .query_filtered::<(&BlockColor, &Transform), With<Block>>()
+            .iter(&world)
+            .map(|(block_color, transform)| (*block_color, transform.translation))
+            .collect()
+    }
+
+    let first = run_setup_board(42);
+    assert!(!first.is_empty());
+    assert_eq!(first, run_setup_board(42));
+}
+
+#[test]
+fn test_setup_board_custom_config() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig {
+        width: 8,
+        initial_rows: 0,
+        ..Default::default()
+    });
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(GameMode::default());
+    world.insert_resource(GameSpeed::default());
+    world.insert_resource(KeyBindings::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(setup_board.system());
+    world.insert_resource(NextRow::default());
+    world.insert_resource(ColorCount(4));
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(BoardRng::new(None));
+    world.insert_resource(BoardMaterials {
+        board_material: Handle::<ColorMaterial>::default(),
+    });
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BottomMaterials {
+        bottom_material: Handle::<ColorMaterial>::default(),
+    });
+    world.insert_resource(CursorMaterials {
+        cursor_material: Handle::<ColorMaterial>::default(),
+    });
+
+    update_stage.run(&mut world);
+    // only the spawning rows, each as wide as the board
+    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 16);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    assert!(world
+        .query::<&BlockColor>()
+        .iter(&world)
+        .all(
+            |block_color| block_colors(&ColorCount(4), &ColorTable::default())
+                .contains(block_color)
+        ));
+}
+
+#[test]
+fn test_left_move_cursor() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_cursor.system());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(InputTuning::default());
+    world.spawn().insert(Board);
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::ZERO
+    );
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Left);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(-1.0 * BLOCK_SIZE, 0.0, 0.0)
+    );
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Left);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(-2.0 * BLOCK_SIZE, 0.0, 0.0)
+    );
+    // can't move left more
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Left);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(-2.0 * BLOCK_SIZE, 0.0, 0.0)
+    );
+}
+
+#[test]
+fn test_diagonal_move_cursor() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_cursor.system());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(InputTuning::default());
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert(Transform::default());
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Up);
+    input.press(KeyCode::Right);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world
+            .query_filtered::<&Transform, With<Cursor>>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .translation,
+        Vec3::new(BLOCK_SIZE, BLOCK_SIZE, 0.0)
+    );
+}
+
+#[test]
+fn test_right_move_cursor() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_cursor.system());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(InputTuning::default());
+    world.spawn().insert(Board);
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::ZERO
+    );
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Right);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(BLOCK_SIZE, 0.0, 0.0)
+    );
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Right);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(2.0 * BLOCK_SIZE, 0.0, 0.0)
+    );
+    // can't move right more
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Right);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(2.0 * BLOCK_SIZE, 0.0, 0.0)
+    );
+}
+
+#[test]
+fn test_down_move_cursor() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_cursor.system());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(InputTuning::default());
+
+    world.spawn().insert(Board);
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::ZERO
+    );
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Down);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(0.0, -1.0 * BLOCK_SIZE, 0.0)
+    );
+
+    for _ in 0..7 {
+        let mut input = Input::<KeyCode>::default();
+        input.press(KeyCode::Down);
+        world.insert_resource(input);
+        update_stage.run(&mut world);
+    }
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(0.0, -6.0 * BLOCK_SIZE, 0.0)
+    );
+}
+
+#[test]
+fn test_up_move_cursor() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_cursor.system());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(InputTuning::default());
+
+    world.spawn().insert(Board);
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::ZERO
+    );
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Up);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(0.0, BLOCK_SIZE, 0.0)
+    );
+
+    for _ in 0..7 {
+        let mut input = Input::<KeyCode>::default();
+        input.press(KeyCode::Up);
+        world.insert_resource(input);
+        update_stage.run(&mut world);
+    }
+
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(
+        world
+            .query::<(&Cursor, &Transform)>()
+            .iter(&world)
+            .next()
+            .unwrap()
+            .1
+            .translation,
+        Vec3::new(0.0, 6.0 * BLOCK_SIZE, 0.0)
+    );
+}
+
+#[test]
+fn test_move_tag_block_both_fix() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        transform: Transform {
+            translation: Vec3::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::BLUE)
+        .insert(Fixed);
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
+
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 2);
+}
+
+#[test]
+fn test_move_tag_block_rebound_swap() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    let mut key_bindings = KeyBindings::default();
+    key_bindings.solo.swap = vec![KeyCode::J];
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(key_bindings.solo.clone())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            ..Default::default()
+        });
+    for (x, color) in [(0.5, BlockColor::RED), (-0.5, BlockColor::BLUE)] {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform::from_translation(Vec3::new(x * BLOCK_SIZE, 0.0, 0.0)),
+                ..Default::default()
+            })
+            .insert(color)
+            .insert(Fixed);
+    }
+
+    // the old key no longer swaps
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Space);
+    input.update();
+    input.press(KeyCode::J);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 2);
+}
+
+#[test]
+fn test_move_tag_block_left_one_fix() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        transform: Transform {
+            translation: Vec3::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 1);
+}
+
+#[test]
+fn test_move_tag_block_target_cell_falling() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            ..Default::default()
+        });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform::from_translation(Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0)),
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            // partly inside the cursor's left cell, on its way down
+            transform: Transform::from_translation(Vec3::new(
+                -BLOCK_SIZE / 2.0,
+                BLOCK_SIZE * 0.6,
+                0.0,
+            )),
+            ..Default::default()
+        })
+        .insert(BlockColor::BLUE)
+        .insert(Fall);
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    let swap_rejected = world
+        .get_resource::<bevy::app::Events<SwapRejected>>()
+        .unwrap();
+    assert_eq!(swap_rejected.get_reader().iter(swap_rejected).count(), 1);
+}
+
+#[test]
+fn test_move_tag_block_empty_cell_beside_fall() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            ..Default::default()
+        });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform::from_translation(Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0)),
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            // falling one column further left, clear of both cells
+            transform: Transform::from_translation(Vec3::new(
+                -BLOCK_SIZE * 1.5,
+                BLOCK_SIZE * 0.6,
+                0.0,
+            )),
+            ..Default::default()
+        })
+        .insert(BlockColor::BLUE)
+        .insert(Fall);
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 1);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_move_tag_block_right_one_fix() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        transform: Transform {
+            translation: Vec3::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 1);
+}
+
+#[test]
+fn test_move_tag_block_into_empty_cell() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            ..Default::default()
+        });
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform::from_translation(Vec3::new(-BLOCK_SIZE / 2.0, 0.0, 0.0)),
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed)
+        .id();
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    // slides into the empty right cell, and nothing takes its place
+    assert_eq!(world.get::<Move>(block).unwrap().0, BLOCK_SIZE / 2.0);
+    assert!(world.get::<Fixed>(block).is_none());
+    assert_eq!(world.query::<&Block>().iter(&world).len(), 1);
+}
+
+#[test]
+fn test_move_tag_block_board_edges() {
+    let board_config = BoardConfig::default();
+    let y = board_config.first_row_y();
+    for (cursor_x, block_x, target_x) in [
+        // leftmost and rightmost cursor positions swap inside the board
+        (
+            -2.0 * BLOCK_SIZE,
+            -2.5 * BLOCK_SIZE,
+            Some(-1.5 * BLOCK_SIZE),
+        ),
+        (2.0 * BLOCK_SIZE, 2.5 * BLOCK_SIZE, Some(1.5 * BLOCK_SIZE)),
+        // one column further out, half the cursor is off the board
+        (-3.0 * BLOCK_SIZE, -2.5 * BLOCK_SIZE, None),
+        (3.0 * BLOCK_SIZE, 2.5 * BLOCK_SIZE, None),
+    ] {
+        let mut world = World::default();
+        world.insert_resource(Audio::default());
+        insert_asset_fixtures(&mut world);
+        world.insert_resource(BoardConfig::default());
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(move_tag_block.system());
+        world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+        world
+            .spawn()
+            .insert(Cursor)
+            .insert(PlayerControls::solo())
+            .insert(Transform::from_xyz(cursor_x, y, 0.0));
+        let block = world
+            .spawn()
+            .insert(Block)
+            .insert(Transform::from_xyz(block_x, y, 0.0))
+            .insert(BlockColor::RED)
+            .insert(Fixed)
+            .id();
+
+        let mut input = Input::<KeyCode>::default();
+        input.press(KeyCode::Space);
+        world.insert_resource(input);
+        update_stage.run(&mut world);
+        assert_eq!(
+            world.get::<Move>(block).map(|target| target.0),
+            target_x,
+            "cursor at {}",
+            cursor_x
+        );
+    }
+}
+
+#[test]
+fn test_move_tag_block_there_is_collide() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        transform: Transform {
+            translation: Vec3::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 1.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_move_tag_block_not_fixed_block() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+
+    world.spawn().insert(Board).insert_bundle(SpriteBundle {
+        sprite: Sprite::new(Vec2::new(
+            BOARD_WIDTH as f32 * BLOCK_SIZE,
+            BOARD_HEIGHT as f32 * BLOCK_SIZE,
+        )),
+        transform: Transform {
+            translation: Vec3::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::BLUE);
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_move_block() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_block.system());
+    world.insert_resource(TuningConfig::default());
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Move(-1.0 * BLOCK_SIZE / 2.0));
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::BLUE)
+        .insert(Move(BLOCK_SIZE / 2.0));
+
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 2);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Moving)>().iter(&world).len(), 2);
+}
+
+#[test]
+fn test_sync_grid_pos() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, -300.0 + 0.4 * BLOCK_SIZE, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed)
+        .id();
+    let falling_block = world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fall)
+        .id();
+
+    sync_grid_pos(&mut world);
+    assert_eq!(
+        world.get::<GridPos>(block),
+        Some(&GridPos { col: 3, row: 0 })
+    );
+    assert!(world.get::<GridPos>(falling_block).is_none());
+
+    // lifted more than half a block -> next row
+    world.get_mut::<Transform>(block).unwrap().translation.y += 0.2 * BLOCK_SIZE;
+    sync_grid_pos(&mut world);
+    assert_eq!(
+        world.get::<GridPos>(block),
+        Some(&GridPos { col: 3, row: 1 })
+    );
+    assert_eq!(
+        GridPos { col: 3, row: 1 }.to_translation(0.0, &BoardConfig::default()),
+        Vec3::new(BLOCK_SIZE / 2.0, -250.0, 0.0)
+    );
+}
+
+#[test]
+fn test_match_row_block_three_matched() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    for i in 0..3 {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(
+                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                        -300.0,
+                        0.0,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BlockColor::RED)
+            .insert(Fixed);
+    }
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 3);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 3);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_block_board_scoped() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+    let player_board = world.spawn().insert(Board).id();
+    let com_board = world.spawn().insert(Board).id();
+
+    // a red run split across two boards is not a match
+    for i in 0..3 {
+        let block = world
+            .spawn()
+            .insert(Block)
+            .insert(Transform::from_xyz(
+                BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                -300.0,
+                0.0,
+            ))
+            .insert(BlockColor::RED)
+            .insert(Fixed)
+            .id();
+        let board = if i == 2 { com_board } else { player_board };
+        world.entity_mut(board).push_children(&[block]);
+    }
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_on_one_board_leaves_the_other() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(TimeScale::default());
+    world.insert_resource(FixedStep::default());
+    let mut match_stage = SystemStage::parallel();
+    match_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+    let mut lift_stage = SystemStage::parallel();
+    lift_stage.add_system(auto_liftup.system());
+
+    // the same row on both boards, only the first one lines up three reds
+    let mut boards = Vec::new();
+    for last_color in [BlockColor::RED, BlockColor::BLUE] {
+        let board = world.spawn().insert(Board).id();
+        let count_timer = world
+            .spawn()
+            .insert(CountTimer(Timer::from_seconds(0.0, false)))
+            .insert(GameSpeed::default())
+            .id();
+        world.entity_mut(board).push_children(&[count_timer]);
+        let mut blocks = Vec::new();
+        for (i, block_color) in [BlockColor::RED, BlockColor::RED, last_color]
+            .into_iter()
+            .enumerate()
+        {
+            let block = world
+                .spawn()
+                .insert(Block)
+                .insert(Transform::from_xyz(
+                    BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i as f32 - 3.0),
+                    -300.0,
+                    0.0,
+                ))
+                .insert(block_color)
+                .insert(Fixed)
+                .id();
+            blocks.push(block);
+        }
+        world.entity_mut(board).push_children(&blocks);
+        boards.push(blocks);
+    }
+    sync_grid_pos(&mut world);
+    match_stage.run(&mut world);
+    for block in &boards[0] {
+        assert!(world.get::<Matched>(*block).is_some());
+    }
+    for block in &boards[1] {
+        assert!(world.get::<Matched>(*block).is_none());
+        assert!(world.get::<Fixed>(*block).is_some());
+    }
+
+    // the clear holds the first board's lift only
+    lift_stage.run(&mut world);
+    assert_eq!(
+        world.get::<Transform>(boards[0][0]).unwrap().translation.y,
+        -300.0
+    );
+    assert!(world.get::<Transform>(boards[1][0]).unwrap().translation.y > -300.0);
+}
+
+#[test]
+fn test_match_block_eight_colors() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    // ids 6 and 7 line up; id 6 shares its art with id 0 but doesn't match it
+    let rows = [
+        [6, 6, 6, 7, 0, 1],
+        [0, 1, 2, 7, 3, 4],
+        [5, 2, 3, 7, 4, 5],
+        [0, 6, 6, 1, 2, 3],
+    ];
+    let mut blocks = HashMap::new();
+    for (row, ids) in rows.iter().enumerate() {
+        for (col, id) in ids.iter().enumerate() {
+            let grid_pos = GridPos {
+                col: col as u8,
+                row: row as u8,
+            };
+            let block = world
+                .spawn()
+                .insert(Block)
+                .insert(BlockColor(*id))
+                .insert(grid_pos)
+                .insert(Fixed)
+                .id();
+            blocks.insert((col, row), block);
+        }
+    }
+    update_stage.run(&mut world);
+    let matched = blocks
+        .iter()
+        .filter(|(_, block)| world.get::<Matched>(**block).is_some())
+        .map(|(cell, _)| *cell)
+        .collect::<HashSet<_>>();
+    assert_eq!(
+        matched,
+        [(0, 0), (1, 0), (2, 0), (3, 0), (3, 1), (3, 2)]
+            .iter()
+            .copied()
+            .collect()
+    );
+
+    let color_table = ColorTable::extended(8);
+    assert_eq!(color_table.0[..6], ColorTable::default().0[..]);
+    assert_eq!(
+        block_colors(&ColorCount(8), &color_table),
+        (0..8).map(BlockColor).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        block_colors(&ColorCount(8), &ColorTable::default()).len(),
+        6
+    );
+}
+
+#[test]
+fn test_match_block_square() {
+    // a lone 2x2 square of reds, with no line of three
+    let rows = [[0, 0, 1], [0, 0, 2]];
+    for (enabled, expected) in [(false, 0), (true, 4)] {
+        let mut world = World::default();
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(SquareClear(enabled));
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(match_block.system());
+        for (row, ids) in rows.iter().enumerate() {
+            for (col, id) in ids.iter().enumerate() {
+                world
+                    .spawn()
+                    .insert(Block)
+                    .insert(BlockColor(*id))
+                    .insert(GridPos {
+                        col: col as u8,
+                        row: row as u8,
+                    })
+                    .insert(Fixed);
+            }
+        }
+        update_stage.run(&mut world);
+        assert_eq!(
+            world.query::<(&Block, &Matched)>().iter(&world).len(),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_match_block_square_overlapping_line() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(SquareClear(true));
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+
+    // the bottom row of reds is a line; the square shares two of its cells
+    let rows = [[0, 0, 0, 1], [0, 0, 1, 2]];
+    let mut blocks = HashMap::new();
+    for (row, ids) in rows.iter().enumerate() {
+        for (col, id) in ids.iter().enumerate() {
+            let block = world
+                .spawn()
+                .insert(Block)
+                .insert(BlockColor(*id))
+                .insert(GridPos {
+                    col: col as u8,
+                    row: row as u8,
+                })
+                .insert(Fixed)
+                .id();
+            blocks.insert((col, row), block);
+        }
+    }
+    update_stage.run(&mut world);
+    let matched = blocks
+        .iter()
+        .filter(|(_, block)| world.get::<Matched>(**block).is_some())
+        .map(|(cell, _)| *cell)
+        .collect::<HashSet<_>>();
+    assert_eq!(
+        matched,
+        [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1)]
+            .iter()
+            .copied()
+            .collect()
+    );
+    assert_eq!(
+        square_cells(&vec![vec![Some(BlockColor::RED); 3]; 2]).len(),
+        6
+    );
+}
+
+#[test]
+fn test_match_row_block_four_matched() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    for i in 0..4 {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(
+                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                        -300.0,
+                        0.0,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BlockColor::RED)
+            .insert(Fixed);
+    }
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 4);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 4);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_row_block_three_matched_only() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    for i in 0..5 {
+        match i {
+            0 | 1 | 2 | 4 => {
+                world
+                    .spawn()
+                    .insert(Block)
+                    .insert_bundle(SpriteBundle {
+                        sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                        transform: Transform {
+                            translation: Vec3::new(
+                                BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                                -300.0,
+                                0.0,
+                            ),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(BlockColor::RED)
+                    .insert(Fixed);
+            }
+            3 => {
+                world
+                    .spawn()
+                    .insert(Block)
+                    .insert_bundle(SpriteBundle {
+                        sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                        transform: Transform {
+                            translation: Vec3::new(
+                                BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                                -300.0,
+                                0.0,
+                            ),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(BlockColor::BLUE)
+                    .insert(Fixed);
+            }
+            _ => {
+                unreachable!()
+            }
+        }
+    }
+
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 3);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
+}
+
+#[test]
+fn test_match_row_block_five_matched() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    for i in 0..5 {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(
+                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                        -300.0,
+                        0.0,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BlockColor::RED)
+            .insert(Fixed);
+    }
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 5);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_row_block_six_matched() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    for i in 0..6 {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(
+                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                        -300.0,
+                        0.0,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BlockColor::RED)
+            .insert(Fixed);
+    }
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 6);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 6);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_row_block_six_matched_two_colors() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    for i in 0..6 {
+        if i < 3 {
+            world
+                .spawn()
+                .insert(Block)
+                .insert_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                    transform: Transform {
+                        translation: Vec3::new(
+                            BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                            -300.0,
+                            0.0,
+                        ),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(BlockColor::RED)
+                .insert(Fixed);
+        } else {
+            world
+                .spawn()
+                .insert(Block)
+                .insert_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                    transform: Transform {
+                        translation: Vec3::new(
+                            BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                            -300.0,
+                            0.0,
+                        ),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(BlockColor::BLUE)
+                .insert(Fixed);
+        }
+    }
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 6);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 6);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_no_match_block() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    world
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE * 2.0, -300.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Block)
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, -300.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Block)
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, -300.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Block)
+        .insert(BlockColor::BLUE)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE * 2.0, -300.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Block)
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE - 300.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Block)
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_column_block_three_matched() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    for i in 0..3 {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0 - BLOCK_SIZE * i as f32, 0.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BlockColor::RED)
+            .insert(Fixed);
+    }
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 3);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 3);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_row_and_column_block_five_matched() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    // row
+    for i in 0..3 {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(
+                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
+                        0.0,
+                        0.0,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(BlockColor::RED)
+            .insert(Fixed);
+    }
+    // column
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE * 2.0, BLOCK_SIZE, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    // column
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE * 2.0, -1.0 * BLOCK_SIZE, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 5);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_match_row_and_column_block_l_shape() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
+
+    // a column of 3 standing on the left end of a row of 3, and a blue beside the column
+    let left_x = BLOCK_SIZE / 2.0 - BLOCK_SIZE * 3.0;
+    for (x, y, block_color) in [
+        (left_x, 0.0, BlockColor::RED),
+        (left_x + BLOCK_SIZE, 0.0, BlockColor::RED),
+        (left_x + BLOCK_SIZE * 2.0, 0.0, BlockColor::RED),
+        (left_x, BLOCK_SIZE, BlockColor::RED),
+        (left_x, BLOCK_SIZE * 2.0, BlockColor::RED),
+        (left_x + BLOCK_SIZE, BLOCK_SIZE, BlockColor::BLUE),
+    ] {
+        world
+            .spawn()
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform::from_xyz(x, y, 0.0),
+                ..Default::default()
+            })
+            .insert(block_color)
+            .insert(Fixed);
+    }
+    sync_grid_pos(&mut world);
+    update_stage.run(&mut world);
+    // the corner belongs to both runs but is matched once
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 5);
+    assert_eq!(
+        world
+            .query::<(&BlockColor, &Fixed)>()
+            .iter(&world)
+            .map(|(block_color, _)| *block_color)
+            .collect::<Vec<_>>(),
+        vec![BlockColor::BLUE]
+    );
+}
+
+#[test]
+fn test_prepare_despawn_block() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(
+        prepare_despawn_block
+            .system()
+            .label("prepare_despawn_block"),
+    );
+    update_stage.add_system(score_clear.system().after("prepare_despawn_block"));
+    update_stage.add_system(play_clear_sound.system().after("prepare_despawn_block"));
+    update_stage.add_system(start_despawning.system().after("prepare_despawn_block"));
+    world.insert_resource(Score::default());
+    world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::default())
+        .insert(Matched);
+    let chain_counter = world.spawn().insert(ChainCounter(1)).id();
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 0);
+    assert_eq!(
+        world.query::<(&Block, &Despawining)>().iter(&world).len(),
+        1
+    );
+    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 1);
+    assert_eq!(world.get_resource::<Score>().unwrap().0, 10);
+}
+
+#[test]
+fn test_prepare_despawn_block_chain() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(
+        prepare_despawn_block
+            .system()
+            .label("prepare_despawn_block"),
+    );
+    update_stage.add_system(score_clear.system().after("prepare_despawn_block"));
+    update_stage.add_system(play_clear_sound.system().after("prepare_despawn_block"));
+    update_stage.add_system(start_despawning.system().after("prepare_despawn_block"));
+    world.insert_resource(Score::default());
+    world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::default())
+        .insert(Matched)
+        .insert(Chain);
+    let chain_counter = world.spawn().insert(ChainCounter(1)).id();
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 0);
+    assert_eq!(
+        world.query::<(&Block, &Despawining)>().iter(&world).len(),
+        1
+    );
+    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 2);
+    assert_eq!(world.get_resource::<Score>().unwrap().0, 60);
+}
+
+#[test]
+fn test_combo_bonus() {
+    for (combo, bonus) in [
+        (3, 0),
+        (4, 20),
+        (5, 30),
+        (6, 50),
+        (7, 60),
+        (8, 70),
+        (9, 80),
+        (10, 100),
+        (11, 140),
+        (12, 170),
+        (13, 200),
+        (14, 230),
+    ] {
+        assert_eq!(combo_bonus(combo), bonus, "combo {}", combo);
+    }
+}
+
+#[test]
+fn test_chain_bonus() {
+    for (chain, bonus) in [
+        (1, 0),
+        (2, 50),
+        (3, 80),
+        (4, 150),
+        (5, 300),
+        (6, 400),
+        (7, 500),
+        (8, 700),
+        (9, 900),
+        (10, 1100),
+        (11, 1300),
+        (12, 1500),
+        (13, 1800),
+        (20, 1800),
+    ] {
+        assert_eq!(chain_bonus(chain), bonus, "chain {}", chain);
+    }
+}
+
+#[test]
+fn test_track_clear_stats() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(track_clear_stats.system());
+    world.insert_resource(MaxChain::default());
+    world.insert_resource(ClearStats::new(&ColorTable::default()));
+    world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+    let board = world.spawn().insert(Board).id();
+    let com_board = world.spawn().insert(Board).id();
+    let com_cursor = world.spawn().insert(AiController::default()).id();
+    world.entity_mut(com_board).push_children(&[com_cursor]);
+    let mut clear = |world: &mut World, board: Entity, blocks: usize, chain: u32| {
+        let entities = (0..blocks).map(|_| world.spawn().id()).collect();
+        world
+            .get_resource_mut::<bevy::app::Events<ClearEvent>>()
+            .unwrap()
+            .send(ClearEvent {
+                board: Some(board),
+                entities,
+                combo: blocks,
+                chain,
+                centroid: Vec3::ZERO,
+            });
+        update_stage.run(world);
+    };
+
+    clear(&mut world, board, 3, 1);
+    clear(&mut world, board, 4, 2);
+    assert_eq!(world.get_resource::<MaxChain>().unwrap().0, 2);
+    assert_eq!(world.get_resource::<ClearStats>().unwrap().total, 7);
+    // a shorter chain later doesn't lower the best
+    clear(&mut world, board, 3, 1);
+    assert_eq!(world.get_resource::<MaxChain>().unwrap().0, 2);
+    assert_eq!(world.get_resource::<ClearStats>().unwrap().total, 10);
+    // the COM's clears are left out
+    clear(&mut world, com_board, 5, 4);
+    assert_eq!(world.get_resource::<MaxChain>().unwrap().0, 2);
+    assert_eq!(world.get_resource::<ClearStats>().unwrap().total, 10);
+}
+
+#[test]
+fn test_track_clear_stats_per_color() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(track_clear_stats.system());
+    world.insert_resource(MaxChain::default());
+    world.insert_resource(ClearStats::new(&ColorTable::extended(8)));
+    world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+    let board = world.spawn().insert(Board).id();
+    let mut entities = Vec::new();
+    for block_color in [
+        BlockColor::RED,
+        BlockColor::RED,
+        BlockColor::RED,
+        BlockColor::BLUE,
+        BlockColor::BLUE,
+        BlockColor::BLUE,
+        BlockColor::INDIGO,
+        BlockColor(7),
+    ] {
+        entities.push(world.spawn().insert(Block).insert(block_color).id());
+    }
+    // a garbage block has no color
+    entities.push(world.spawn().insert(Block).id());
+    world
+        .get_resource_mut::<bevy::app::Events<ClearEvent>>()
+        .unwrap()
+        .send(ClearEvent {
+            board: Some(board),
+            entities,
+            combo: 8,
+            chain: 1,
+            centroid: Vec3::ZERO,
+        });
+    update_stage.run(&mut world);
+
+    let clear_stats = world.get_resource::<ClearStats>().unwrap();
+    assert_eq!(clear_stats.per_color, [3, 0, 3, 0, 0, 1, 0, 1]);
+    assert_eq!(clear_stats.total, 9);
+    assert_eq!(
+        clear_stats.breakdown(),
+        "R 3  G 0  B 3  Y 0  P 0  I 1  6 0  7 1"
+    );
+}
+
+#[test]
+fn test_moving_to_fixed_removes_swap_highlight() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(moving_to_fixed.system());
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::default())
+        .insert(Moving(BLOCK_SIZE / 2.0))
+        .id();
+    let highlight = world.spawn().insert(SwapHighlight).id();
+    let symbol = world.spawn().insert(BlockSymbol).id();
+    world.entity_mut(block).push_children(&[highlight, symbol]);
+
+    update_stage.run(&mut world);
+    assert!(world.get::<Fixed>(block).is_some());
+    assert!(world.get_entity(highlight).is_none());
+    assert_eq!(world.get::<Children>(block).unwrap().len(), 1);
+    // other children of the block stay
+    assert!(world.get_entity(symbol).is_some());
+}
+
+#[test]
+fn test_swap_extends_chain() {
+    for (chain_open, expected_chain) in [(false, 1), (true, 2)] {
+        let mut world = World::default();
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+        let mut settle_stage = SystemStage::parallel();
+        settle_stage.add_system(moving_to_fixed.system());
+        let mut match_stage = SystemStage::parallel();
+        match_stage.add_system(match_block.system());
+        world.insert_resource(SquareClear::default());
+        let mut despawn_stage = SystemStage::parallel();
+        despawn_stage.add_system(
+            prepare_despawn_block
+                .system()
+                .label("prepare_despawn_block"),
+        );
+        despawn_stage.add_system(start_despawning.system().after("prepare_despawn_block"));
+
+        for col in 0..2 {
+            world
+                .spawn()
+                .insert(Block)
+                .insert(BlockColor::RED)
+                .insert(GridPos { col, row: 0 })
+                .insert(Transform::default())
+                .insert(Fixed);
+        }
+        // the swapped block finishing its slide into the third cell
+        world
+            .spawn()
+            .insert(Block)
+            .insert(BlockColor::RED)
+            .insert(GridPos { col: 2, row: 0 })
+            .insert(Transform::default())
+            .insert(Moving(0.0));
+        if chain_open {
+            // still falling after the previous clear
+            world
+                .spawn()
+                .insert(Block)
+                .insert(BlockColor::BLUE)
+                .insert(Fall)
+                .insert(Chain);
+        }
+        let chain_counter = world.spawn().insert(ChainCounter(1)).id();
+
+        settle_stage.run(&mut world);
+        match_stage.run(&mut world);
+        despawn_stage.run(&mut world);
+        assert_eq!(
+            world.query::<(&Block, &Despawining)>().iter(&world).len(),
+            3
+        );
+        assert_eq!(
+            world.get::<ChainCounter>(chain_counter).unwrap().0,
+            expected_chain
+        );
+    }
+}
+
+#[test]
+fn test_count_matches_at() {
+    let [red, green, blue, _, _, _] = BLOCK_COLORS;
+    let at = |col, row| GridPos { col, row };
+    let mut grid: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+    // isolated cells and pairs don't count, and neither do empty cells
+    grid[0] = [red, green, green, blue, red, red].map(Some).to_vec();
+    assert_eq!(count_matches_at(&grid, at(0, 0)), 0);
+    assert_eq!(count_matches_at(&grid, at(1, 0)), 0);
+    assert_eq!(count_matches_at(&grid, at(0, 1)), 0);
+
+    // a run against the right wall counts from any of its cells
+    grid[0][3] = Some(red);
+    assert_eq!(count_matches_at(&grid, at(5, 0)), 3);
+    assert_eq!(count_matches_at(&grid, at(3, 0)), 3);
+    assert_eq!(count_matches_at(&grid, at(2, 0)), 0);
+
+    // a column of three up from the red at the corner of an L
+    grid[1][3] = Some(red);
+    grid[2][3] = Some(red);
+    assert_eq!(count_matches_at(&grid, at(3, 2)), 3);
+    // the corner belongs to both runs and is counted once
+    assert_eq!(count_matches_at(&grid, at(3, 0)), 5);
+    assert_eq!(count_matches_at(&grid, at(4, 0)), 3);
+}
+
+#[test]
+fn test_theme_tint() {
+    assert!(BLOCK_COLORS
+        .iter()
+        .all(|block_color| theme_tint(Theme::Classic, *block_color) == Color::WHITE));
+    // every color gets its own tint, and none of them lightens the art
+    let vivid = BLOCK_COLORS.map(|block_color| theme_tint(Theme::Vivid, block_color));
+    for (index, tint) in vivid.iter().enumerate() {
+        assert!(!vivid[index + 1..].contains(tint));
+        assert!(tint.r() <= 1.0 && tint.g() <= 1.0 && tint.b() <= 1.0);
+    }
+    assert_ne!(
+        theme_tint(Theme::Night, BlockColor::RED),
+        theme_tint(Theme::Classic, BlockColor::RED)
+    );
+}
+
+#[test]
+fn test_nearest_swap() {
+    let [red, green, blue, yellow, purple, _] = BLOCK_COLORS;
+    let mut grid: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+    assert_eq!(
+        nearest_swap(&grid, GridPos { col: 0, row: 0 }, &SquareClear::default()),
+        None
+    );
+    // R R G R clears at (2, 0), Y B P B B at (1, 1)
+    grid[0] = [red, red, green, red, blue, yellow].map(Some).to_vec();
+    grid[1] = [yellow, blue, purple, blue, blue, green].map(Some).to_vec();
+    assert_eq!(
+        nearest_swap(&grid, GridPos { col: 0, row: 1 }, &SquareClear::default()),
+        Some(GridPos { col: 1, row: 1 })
+    );
+    assert_eq!(
+        nearest_swap(&grid, GridPos { col: 3, row: 0 }, &SquareClear::default()),
+        Some(GridPos { col: 2, row: 0 })
+    );
+}
+
+#[test]
+fn test_matching_swaps_square_clear() {
+    let [red, green, blue, yellow, purple, _] = BLOCK_COLORS;
+    let mut grid: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+    grid[0] = [red, red, green, blue, yellow, purple].map(Some).to_vec();
+    grid[1] = [red, green, red, yellow, purple, blue].map(Some).to_vec();
+    // swapping G R in the second row closes a red square, but lines up nothing
+    let square_swap = GridPos { col: 1, row: 1 };
+    assert!(!matching_swaps(&grid, &SquareClear(false)).any(|swap| swap == square_swap));
+    assert!(matching_swaps(&grid, &SquareClear(true)).any(|swap| swap == square_swap));
+
+    grid[1][1] = Some(red);
+    grid[1][2] = Some(green);
+    assert!(simulate_cascade(grid.clone(), &SquareClear(false)).is_empty());
+    assert_eq!(
+        simulate_cascade(grid, &SquareClear(true)),
+        vec![vec![
+            GridPos { col: 0, row: 0 },
+            GridPos { col: 1, row: 0 },
+            GridPos { col: 0, row: 1 },
+            GridPos { col: 1, row: 1 },
+        ]]
+    );
+}
+
+#[test]
+fn test_shuffle_to_move() {
+    let [red, green, blue, _, _, _] = BLOCK_COLORS;
+    let mut grid: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+    grid[0] = [red, green, blue, red, green, blue].map(Some).to_vec();
+    grid[1] = [green, blue, red, green, blue, red].map(Some).to_vec();
+    assert!(!has_matching_swap(&grid, &SquareClear::default()));
+    grid[0][1] = Some(red);
+    grid[0][2] = Some(green);
+    // R R G R: swapping the middle pair lines up three reds
+    assert!(has_matching_swap(&grid, &SquareClear::default()));
+    grid[0][1] = Some(green);
+    grid[0][2] = Some(blue);
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let shuffled = shuffle_to_move(&grid, &mut rng, &SquareClear::default()).unwrap();
+    assert!(has_matching_swap(&shuffled, &SquareClear::default()));
+    assert!(simulate_cascade(shuffled.clone(), &SquareClear::default()).is_empty());
+    let count = |grid: &BoardGrid, color: BlockColor| {
+        grid.iter()
+            .flatten()
+            .filter(|cell| **cell == Some(color))
+            .count()
+    };
+    for color in [red, green, blue] {
+        assert_eq!(count(&shuffled, color), count(&grid, color));
+    }
+    for (row, shuffled_row) in grid.iter().zip(shuffled.iter()) {
+        for (cell, shuffled_cell) in row.iter().zip(shuffled_row.iter()) {
+            assert_eq!(cell.is_some(), shuffled_cell.is_some());
+        }
+    }
+
+    // two blocks can never make a match
+    let mut sparse: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+    sparse[0][0] = Some(red);
+    sparse[0][1] = Some(green);
+    assert!(shuffle_to_move(&sparse, &mut rng, &SquareClear::default()).is_none());
+}
+
+#[test]
+fn test_board_layout() {
+    let mut grid: BoardGrid = vec![vec![None; 4]; 3];
+    grid[0] = vec![Some(BlockColor::GREEN), Some(BlockColor::RED), None, None];
+    grid[1][1] = Some(BlockColor::INDIGO);
+    assert_eq!(board_layout(&grid), "....\n.I..\nGR..");
+    assert_eq!(parse_puzzle_layout(&board_layout(&grid), 4).unwrap(), grid);
+}
+
+#[test]
+fn test_cursor_debug_line() {
+    let board_config = BoardConfig::default();
+    let lift_offset = 20.0;
+    let at = |col, row| {
+        let mut translation = GridPos { col, row }.to_translation(0.0, &board_config);
+        translation.y += lift_offset;
+        Transform::from_translation(translation)
+    };
+    let mut cursor_transform = at(2, 3);
+    cursor_transform.translation.x += board_config.block_size / 2.0;
+    let red = at(2, 3);
+    let green = at(4, 3);
+    let blue = at(3, 4);
+    let blocks = [
+        (&red, &BlockColor::RED),
+        (&green, &BlockColor::GREEN),
+        (&blue, &BlockColor::BLUE),
+    ];
+    assert_eq!(
+        cursor_debug_line(&cursor_transform, &blocks, lift_offset, &board_config),
+        "col 2 row 3: R ."
+    );
+
+    cursor_transform.translation.x += board_config.block_size;
+    assert_eq!(
+        cursor_debug_line(&cursor_transform, &blocks, lift_offset, &board_config),
+        "col 3 row 3: . G"
+    );
+}
+
+#[test]
+fn test_update_board_snapshot() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(update_board_snapshot.system());
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(BoardSnapshot::default());
+
+    let board = world.spawn().insert(Board).id();
+    let red = world
+        .spawn()
+        .insert(Block)
+        .insert(BlockColor::RED)
+        .insert(GridPos { col: 0, row: 0 })
+        .insert(Fixed)
+        .id();
+    let blue = world
+        .spawn()
+        .insert(Block)
+        .insert(BlockColor::BLUE)
+        .insert(GridPos { col: 5, row: 1 })
+        .insert(Fixed)
+        .id();
+    // still falling, so not part of the snapshot
+    let green = world
+        .spawn()
+        .insert(Block)
+        .insert(BlockColor::GREEN)
+        .insert(GridPos { col: 1, row: 0 })
+        .insert(Fall)
+        .id();
+    world.entity_mut(board).push_children(&[red, blue, green]);
+
+    update_stage.run(&mut world);
+    let board_snapshot = world.get_resource::<BoardSnapshot>().unwrap();
+    assert!(board_snapshot.board(None).is_none());
+    let grid = board_snapshot.board(Some(board)).unwrap();
+    assert_eq!(grid.len(), BOARD_HEIGHT);
+    assert!(grid.iter().all(|row| row.len() == BOARD_WIDTH));
+    assert_eq!(
+        grid[0],
+        vec![Some(BlockColor::RED), None, None, None, None, None]
+    );
+    assert_eq!(
+        grid[1],
+        vec![None, None, None, None, None, Some(BlockColor::BLUE)]
+    );
+    assert!(grid[2..].iter().flatten().all(Option::is_none));
+}
+
+#[test]
+fn test_detect_board_settled() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(detect_board_settled.system());
+    world.insert_resource(ChainWindow::default());
+    world.insert_resource(AssistMode::default());
+    world.insert_resource(bevy::app::Events::<BoardSettled>::default());
+    let block = world.spawn().insert(Block).insert(Fall).id();
+    world.spawn().insert(Block).insert(Fixed);
+
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    let board_settled = world
+        .get_resource::<bevy::app::Events<BoardSettled>>()
+        .unwrap();
+    assert_eq!(board_settled.get_reader().iter(board_settled).count(), 0);
+
+    world.entity_mut(block).remove::<Fall>();
+    world.entity_mut(block).insert(Fixed);
+    for _ in 0..4 {
+        update_stage.run(&mut world);
+    }
+    let board_settled = world
+        .get_resource::<bevy::app::Events<BoardSettled>>()
+        .unwrap();
+    assert_eq!(board_settled.get_reader().iter(board_settled).count(), 1);
+}
+
+#[test]
+fn test_detect_board_settled_assist_mode() {
+    let chain_window = ChainWindow::default();
+    assert_eq!(chain_window.effective(&AssistMode(false)), chain_window.0);
+    assert!(chain_window.effective(&AssistMode(true)) > chain_window.0);
+
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(detect_board_settled.system());
+    world.insert_resource(chain_window);
+    world.insert_resource(AssistMode(true));
+    world.insert_resource(bevy::app::Events::<BoardSettled>::default());
+    world.spawn().insert(Block).insert(Fixed);
+
+    for _ in 0..chain_window.0 {
+        update_stage.run(&mut world);
+    }
+    let board_settled = world
+        .get_resource::<bevy::app::Events<BoardSettled>>()
+        .unwrap();
+    assert_eq!(board_settled.get_reader().iter(board_settled).count(), 0);
+
+    for _ in chain_window.0..chain_window.effective(&AssistMode(true)) {
+        update_stage.run(&mut world);
+    }
+    let board_settled = world
+        .get_resource::<bevy::app::Events<BoardSettled>>()
+        .unwrap();
+    assert_eq!(board_settled.get_reader().iter(board_settled).count(), 1);
+}
+
+#[test]
+fn test_detect_board_settled_match_after_landing() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(detect_board_settled.system());
+    world.insert_resource(ChainWindow::default());
+    world.insert_resource(AssistMode::default());
+    world.insert_resource(bevy::app::Events::<BoardSettled>::default());
+    let block = world.spawn().insert(Block).insert(Fixed).id();
+
+    update_stage.run(&mut world);
+    // matched on the update right after landing
+    world.entity_mut(block).remove::<Fixed>();
+    world.entity_mut(block).insert(Matched);
+    update_stage.run(&mut world);
+    let board_settled = world
+        .get_resource::<bevy::app::Events<BoardSettled>>()
+        .unwrap();
+    assert_eq!(board_settled.get_reader().iter(board_settled).count(), 0);
+}
+
+#[test]
+fn test_remove_chain() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(remove_chain.system());
+    let mut board_settled = bevy::app::Events::<BoardSettled>::default();
+    board_settled.send(BoardSettled(None));
+    world.insert_resource(board_settled);
+    world.spawn().insert(Block).insert(Fixed).insert(Chain);
+    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 1);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_remove_chain_not_settled() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(remove_chain.system());
+    world.insert_resource(bevy::app::Events::<BoardSettled>::default());
+    world.spawn().insert(Block).insert(Matched).insert(Chain);
+    world.spawn().insert(Block).insert(Fixed).insert(Chain);
+
+    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 2);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 2);
+}
+
+#[test]
+fn test_reset_chain_counter() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(reset_chain_counter.system());
+    let mut board_settled = bevy::app::Events::<BoardSettled>::default();
+    board_settled.send(BoardSettled(None));
+    world.insert_resource(board_settled);
+    let chain_counter = world.spawn().insert(ChainCounter(2)).id();
+    update_stage.run(&mut world);
+    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 1);
+}
+
+#[test]
+fn test_reset_chain_counter_not_reset() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(reset_chain_counter.system());
+    world.insert_resource(bevy::app::Events::<BoardSettled>::default());
+    let chain_counter = world.spawn().insert(ChainCounter(2)).id();
+    world.spawn().insert(Block).insert(Chain);
+    update_stage.run(&mut world);
+    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 2);
+}
+
+#[test]
+fn test_ai_swap_blocks() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(BoardRng::new(Some(&GameSeed(0))));
+    world.insert_resource(AiRng(StdRng::seed_from_u64(0)));
+    world.insert_resource(SquareClear::default());
+    world.insert_resource(BoardSnapshot::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(ai_swap_blocks.system());
+    let player_board = world.spawn().insert(Board).id();
+    let com_board = world.spawn().insert(Board).id();
+
+    let mut ai_controller = AiController::default();
+    ai_controller
+        .timer
+        .set_elapsed(Duration::from_secs_f32(1.0));
+    let com_cursor = world
+        .spawn()
+        .insert(Cursor)
+        .insert(Transform::default())
+        .insert(ai_controller)
+        .id();
+    world.entity_mut(com_board).push_children(&[com_cursor]);
+    let spawn_block = |world: &mut World, board: Entity, col: f32| {
+        let block = world
+            .spawn()
+            .insert(Block)
+            .insert(Transform::from_xyz((col - 2.5) * BLOCK_SIZE, -300.0, 0.0))
+            .insert(Fixed)
+            .id();
+        world.entity_mut(board).push_children(&[block]);
+        block
+    };
+    let com_blocks = [
+        spawn_block(&mut world, com_board, 2.0),
+        spawn_block(&mut world, com_board, 3.0),
+    ];
+    let player_block = spawn_block(&mut world, player_board, 2.0);
+
+    update_stage.run(&mut world);
+    assert!(com_blocks
+        .iter()
+        .any(|block| world.get::<Move>(*block).is_some()));
+    assert!(world.get::<Move>(player_block).is_none());
+    assert!(world.get::<Fixed>(player_block).is_some());
+    // the boards' draws are left as they were
+    assert_eq!(
+        world.get_resource_mut::<BoardRng>().unwrap().0.gen::<u64>(),
+        BoardRng::new(Some(&GameSeed(0))).0.gen::<u64>()
+    );
+}
+
+#[test]
+fn test_ai_swap_blocks_takes_match() {
+    let mut world = World::default();
+    let board_config = BoardConfig::default();
+    world.insert_resource(board_config);
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(AiRng(StdRng::seed_from_u64(0)));
+    world.insert_resource(SquareClear::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(
+        update_board_snapshot
+            .system()
+            .label("update_board_snapshot"),
+    );
+    update_stage.add_system(ai_swap_blocks.system().after("update_board_snapshot"));
+    world.insert_resource(BoardSnapshot::default());
+    let com_board = world.spawn().insert(Board).id();
+
+    let mut ai_controller = AiController::default();
+    ai_controller
+        .timer
+        .set_elapsed(Duration::from_secs_f32(1.0));
+    let com_cursor = world
+        .spawn()
+        .insert(Cursor)
+        .insert(Transform::default())
+        .insert(ai_controller)
+        .id();
+    world.entity_mut(com_board).push_children(&[com_cursor]);
+    // only swapping the last two lines up three reds
+    let blocks = [
+        BlockColor::RED,
+        BlockColor::RED,
+        BlockColor::BLUE,
+        BlockColor::RED,
+    ]
+    .iter()
+    .enumerate()
+    .map(|(col, block_color)| {
+        let grid_pos = GridPos {
+            col: col as u8,
+            row: 0,
+        };
+        let block = world
+            .spawn()
+            .insert(Block)
+            .insert(*block_color)
+            .insert(Transform::from_translation(
+                grid_pos.to_translation(0.0, &board_config),
+            ))
+            .insert(grid_pos)
+            .insert(Fixed)
+            .id();
+        world.entity_mut(com_board).push_children(&[block]);
+        block
+    })
+    .collect::<Vec<_>>();
+
+    update_stage.run(&mut world);
+    let column_x = |col| GridPos { col, row: 0 }.to_translation(0.0, &board_config).x;
+    assert_eq!(world.get::<Move>(blocks[2]).unwrap().0, column_x(3));
+    assert_eq!(world.get::<Move>(blocks[3]).unwrap().0, column_x(2));
+    assert!(world.get::<Move>(blocks[0]).is_none());
+    assert!(world.get::<Move>(blocks[1]).is_none());
+}
+
+#[test]
+fn test_check_puzzle_result() {
+    fn run_check(moves_left: u32, blocks: usize) -> Option<PuzzleOutcome> {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(check_puzzle_result.system());
+        update_stage.add_system(check_board_cleared.system());
+        let mut board_settled = bevy::app::Events::<BoardSettled>::default();
+        board_settled.send(BoardSettled(None));
+        world.insert_resource(board_settled);
+        let mut clear_events = bevy::app::Events::<ClearEvent>::default();
+        clear_events.send(ClearEvent {
+            board: None,
+            entities: Vec::new(),
+            combo: 3,
+            chain: 1,
+            centroid: Vec3::ZERO,
+        });
+        world.insert_resource(clear_events);
+        world.insert_resource(GameMode::Puzzle);
+        world.insert_resource(MovesLeft(moves_left));
+        world.insert_resource(State::new(AppState::InGame));
+        for _ in 0..blocks {
+            world.spawn().insert(Block).insert(Fixed);
+        }
+        update_stage.run(&mut world);
+        world.get_resource::<PuzzleOutcome>().copied()
+    }
+
+    assert_eq!(run_check(1, 0), Some(PuzzleOutcome::Cleared));
+    assert_eq!(run_check(0, 0), Some(PuzzleOutcome::Cleared));
+    assert_eq!(run_check(0, 3), Some(PuzzleOutcome::OutOfMoves));
+    assert_eq!(run_check(1, 3), None);
+}
+
+#[test]
+fn test_check_board_cleared() {
+    fn run_check(game_mode: GameMode, blocks: usize, cleared: bool) -> Option<PuzzleOutcome> {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(check_board_cleared.system());
+        let mut board_settled = bevy::app::Events::<BoardSettled>::default();
+        board_settled.send(BoardSettled(None));
+        world.insert_resource(board_settled);
+        let mut clear_events = bevy::app::Events::<ClearEvent>::default();
+        if cleared {
+            clear_events.send(ClearEvent {
+                board: None,
+                entities: Vec::new(),
+                combo: 3,
+                chain: 1,
+                centroid: Vec3::ZERO,
+            });
+        }
+        world.insert_resource(clear_events);
+        world.insert_resource(game_mode);
+        world.insert_resource(State::new(AppState::InGame));
+        for _ in 0..blocks {
+            world.spawn().insert(Block).insert(Fixed);
+        }
+        update_stage.run(&mut world);
+        world.get_resource::<PuzzleOutcome>().copied()
+    }
+
+    // a new sandbox board is empty and settled without having cleared anything
+    assert_eq!(run_check(GameMode::Sandbox, 0, false), None);
+    assert_eq!(
+        run_check(GameMode::Sandbox, 0, true),
+        Some(PuzzleOutcome::Cleared)
+    );
+    assert_eq!(run_check(GameMode::Sandbox, 2, true), None);
+    assert_eq!(run_check(GameMode::Endless, 0, true), None);
+}
+
+#[test]
+fn test_clear_all_blocks() {
+    for dev_mode in [true, false] {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(clear_all_blocks.system());
+        world.insert_resource(DevMode(dev_mode));
+        let mut input = Input::<KeyCode>::default();
+        input.press(KeyCode::F7);
+        world.insert_resource(input);
+        let fixed = (0..3)
+            .map(|_| world.spawn().insert(Block).insert(Fixed).id())
+            .collect::<Vec<_>>();
+        let falling = world.spawn().insert(Block).id();
+
+        update_stage.run(&mut world);
+        for entity in fixed {
+            assert_eq!(world.get::<Matched>(entity).is_some(), dev_mode);
+            assert_eq!(world.get::<Fixed>(entity).is_none(), dev_mode);
+        }
+        assert!(world.get::<Matched>(falling).is_none());
+    }
+}
+
+#[test]
+fn test_swap_decision() {
+    let left = Entity::new(0);
+    let right = Entity::new(1);
+    assert_eq!(
+        swap_decision(Some(CellState::Fixed(left)), Some(CellState::Fixed(right))),
+        SwapDecision::BothSwap { left, right }
+    );
+    assert_eq!(
+        swap_decision(None, Some(CellState::Fixed(right))),
+        SwapDecision::MoveRightToLeft(right)
+    );
+    assert_eq!(
+        swap_decision(Some(CellState::Fixed(left)), None),
+        SwapDecision::MoveLeftToRight(left)
+    );
+    for other in [None, Some(CellState::Occupied), Some(CellState::Landing)] {
+        assert_eq!(swap_decision(other, None), SwapDecision::None);
+        assert_eq!(swap_decision(None, other), SwapDecision::None);
+        if other.is_some() {
+            assert_eq!(
+                swap_decision(other, Some(CellState::Fixed(right))),
+                SwapDecision::None
+            );
+            assert_eq!(
+                swap_decision(Some(CellState::Fixed(left)), other),
+                SwapDecision::None
+            );
+        }
+    }
+}
+
+#[test]
+fn test_move_tag_block_moves_left() {
+    let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(MovesLeft(1));
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert(Transform::from_xyz(0.0, 0.0, 0.0));
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, 0.0, 0.0))
+        .insert(BlockColor::RED)
+        .insert(Fixed)
+        .id();
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<MovesLeft>().unwrap().0, 0);
+    assert!(world.get::<Move>(block).is_some());
+
+    // out of moves: the block stays put
+    world.entity_mut(block).remove::<Move>();
+    world.entity_mut(block).insert(Fixed);
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Space);
+    input.update();
+    input.press(KeyCode::Space);
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<MovesLeft>().unwrap().0, 0);
+    assert!(world.get::<Move>(block).is_none());
+}
+
+#[test]
+fn test_despawn_block() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(despawn_block.system());
+    world.insert_resource(TimeScale::default());
+    world.insert_resource(bevy::app::Events::<BlockCleared>::default());
+    world.insert_resource(FixedStep::default());
+
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Despawining(Timer::from_seconds(0.0, false)))
+        .id();
+
+    update_stage.run(&mut world);
+    assert!(world.get::<Block>(block).is_none());
+}
+
+#[test]
+fn test_despawn_block_add_chain() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(despawn_block.system());
+    world.insert_resource(TimeScale::default());
+    world.insert_resource(bevy::app::Events::<BlockCleared>::default());
+    world.insert_resource(FixedStep::default());
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert(BlockColor::RED)
+        .insert(GridPos { col: 0, row: 1 })
+        .insert(Despawining(Timer::from_seconds(0.0, false)));
+    // directly above the cleared cell
+    let above = world
+        .spawn()
+        .insert(Block)
+        .insert(GridPos { col: 0, row: 2 })
+        .insert(Fixed)
+        .id();
+    // above a gap, it only falls as far as the gap
+    let over_gap = world
+        .spawn()
+        .insert(Block)
+        .insert(GridPos { col: 0, row: 4 })
+        .insert(Fixed)
+        .id();
+    let below = world
+        .spawn()
+        .insert(Block)
+        .insert(GridPos { col: 0, row: 0 })
+        .insert(Fixed)
+        .id();
+
+    update_stage.run(&mut world);
+    assert!(world.get::<Chain>(above).is_some());
+    assert!(world.get::<Chain>(over_gap).is_none());
+    assert!(world.get::<Chain>(below).is_none());
+}
+
+#[test]
+fn test_despawn_block_chain_skips_adjacent_column() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(despawn_block.system());
+    world.insert_resource(TimeScale::default());
+    world.insert_resource(bevy::app::Events::<BlockCleared>::default());
+    world.insert_resource(FixedStep::default());
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert(BlockColor::RED)
+        .insert(GridPos { col: 2, row: 0 })
+        .insert(Despawining(Timer::from_seconds(0.0, false)));
+    let beside = world
+        .spawn()
+        .insert(Block)
+        .insert(GridPos { col: 3, row: 1 })
+        .insert(Fixed)
+        .id();
+    // a block sliding over the cell has no settled place to fall from
+    let sliding = world
+        .spawn()
+        .insert(Block)
+        .insert(GridPos { col: 2, row: 1 })
+        .insert(Moving(0.0))
+        .id();
+
+    update_stage.run(&mut world);
+    assert!(world.get::<Chain>(beside).is_none());
+    assert!(world.get::<Chain>(sliding).is_none());
+}
+
+#[test]
+fn test_check_fall_block() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(check_fall_block.system());
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.query::<(&Block, &FallPrepare)>().iter(&world).len(),
+        1
+    );
+}
+
+#[test]
+fn test_check_fall_block_there_isnot_between_block() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(check_fall_block.system());
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 3);
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
     assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(-1.0 * BLOCK_SIZE, 0.0, 0.0)
+        world.query::<(&Block, &FallPrepare)>().iter(&world).len(),
+        1
     );
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Left);
-    world.insert_resource(input);
+}
+
+#[test]
+fn test_check_fall_block_there_is_between_block() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(check_fall_block.system());
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 4);
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(-2.0 * BLOCK_SIZE, 0.0, 0.0)
-    );
-    // can't move left more
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Left);
-    world.insert_resource(input);
+    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_check_fall_block_there_is_start_block_move() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(check_fall_block.system());
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Move(BLOCK_SIZE / 2.0));
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Move(BLOCK_SIZE / 2.0));
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_check_fall_block_there_is_between_block_move() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(check_fall_block.system());
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE + 1.0, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 - 1.0, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 4);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_check_fall_block_bottom_block_not_fall() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(check_fall_block.system());
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -6.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+}
+
+#[test]
+fn test_fall_upward() {
+    let mut world = World::default();
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(fall_upward.system());
+
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(FallPrepare)
+        .insert(GridPos { col: 3, row: 6 });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed)
+        .insert(GridPos { col: 3, row: 7 });
+
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(-2.0 * BLOCK_SIZE, 0.0, 0.0)
-    );
+    assert_eq!(world.query::<(&Block, &Floating)>().iter(&world).len(), 2);
 }
 
 #[test]
-fn test_right_move_cursor() {
+fn test_fall_upward_divide() {
     let mut world = World::default();
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_cursor.system());
-    world.spawn().insert(Board);
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-
-    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::ZERO
-    );
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Right);
-    world.insert_resource(input);
-
-    update_stage.run(&mut world);
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(BLOCK_SIZE, 0.0, 0.0)
-    );
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Right);
-    world.insert_resource(input);
+    update_stage.add_system(fall_upward.system());
 
-    update_stage.run(&mut world);
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(2.0 * BLOCK_SIZE, 0.0, 0.0)
-    );
-    // can't move right more
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Right);
-    world.insert_resource(input);
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(FallPrepare)
+        .insert(GridPos { col: 3, row: 6 });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed)
+        .insert(GridPos { col: 3, row: 7 });
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * 3.0, 0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Fixed)
+        .insert(GridPos { col: 3, row: 9 });
 
     update_stage.run(&mut world);
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(2.0 * BLOCK_SIZE, 0.0, 0.0)
-    );
+    assert_eq!(world.query::<(&Block, &Floating)>().iter(&world).len(), 2);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
 }
 
 #[test]
-fn test_down_move_cursor() {
+fn test_fall_upward_whole_stack() {
     let mut world = World::default();
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_cursor.system());
-
-    world.spawn().insert(Board);
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
+    update_stage.add_system(fall_upward.system());
 
-    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::ZERO
-    );
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Down);
-    world.insert_resource(input);
+    // a 4-tall stack over a hole at row 0, its translations a little off as if mid-lift
+    for row in 1..5 {
+        let mut block = world.spawn();
+        block
+            .insert(Block)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(
+                        BLOCK_SIZE / 2.0,
+                        BLOCK_SIZE * row as f32 + row as f32 * 7.0,
+                        0.0,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(GridPos { col: 2, row });
+        if row == 1 {
+            block.insert(FallPrepare);
+        } else {
+            block.insert(Fixed);
+        }
+    }
+    world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(GridPos { col: 3, row: 1 });
 
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(0.0, -1.0 * BLOCK_SIZE, 0.0)
-    );
-
-    for _ in 0..7 {
-        let mut input = Input::<KeyCode>::default();
-        input.press(KeyCode::Down);
-        world.insert_resource(input);
-        update_stage.run(&mut world);
-    }
+    let floating_cells = world
+        .query_filtered::<&GridPos, (With<Block>, With<Floating>)>()
+        .iter(&world)
+        .map(|grid_pos| (grid_pos.col, grid_pos.row))
+        .collect::<HashSet<_>>();
     assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(0.0, -6.0 * BLOCK_SIZE, 0.0)
+        floating_cells,
+        [(2, 1), (2, 2), (2, 3), (2, 4)].iter().copied().collect()
     );
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
 }
 
 #[test]
-fn test_up_move_cursor() {
+fn test_fall_upward_instant() {
     let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_cursor.system());
-
-    world.spawn().insert(Board);
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
+    world.insert_resource(GameSettings {
+        fall_style: FallStyle::Instant,
         ..Default::default()
     });
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(fall_upward.system());
 
-    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 1);
-    assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::ZERO
-    );
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Up);
-    world.insert_resource(input);
+    // a stack of two over a 2-cell hole, on a block at row 0
+    world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, 0.0, 0.0))
+        .insert(Fixed)
+        .insert(GridPos { col: 3, row: 0 });
+    let falling = world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, BLOCK_SIZE * 3.0, 0.0))
+        .insert(FallPrepare)
+        .insert(GridPos { col: 3, row: 3 })
+        .id();
+    let above = world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, BLOCK_SIZE * 4.0, 0.0))
+        .insert(Fixed)
+        .insert(GridPos { col: 3, row: 4 })
+        .id();
 
     update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Floating)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 3);
     assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(0.0, BLOCK_SIZE, 0.0)
+        *world.get::<GridPos>(falling).unwrap(),
+        GridPos { col: 3, row: 1 }
     );
-
-    for _ in 0..7 {
-        let mut input = Input::<KeyCode>::default();
-        input.press(KeyCode::Up);
-        world.insert_resource(input);
-        update_stage.run(&mut world);
-    }
-
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    assert_eq!(world.get::<GridPos>(above).unwrap().row, 2);
     assert_eq!(
-        world
-            .query::<(&Cursor, &Transform)>()
-            .iter(&world)
-            .next()
-            .unwrap()
-            .1
-            .translation,
-        Vec3::new(0.0, 6.0 * BLOCK_SIZE, 0.0)
+        world.get::<Transform>(falling).unwrap().translation.y,
+        BLOCK_SIZE
+    );
+    assert_eq!(
+        world.get::<Transform>(above).unwrap().translation.y,
+        BLOCK_SIZE * 2.0
     );
 }
 
 #[test]
-fn test_move_tag_block_both_fix() {
+fn test_floating_to_fall() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_tag_block.system());
+    update_stage.add_system(floating_to_fall.system());
+    world.insert_resource(TimeScale::default());
 
-    world.spawn().insert(Board).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(
-            BOARD_WIDTH as f32 * BLOCK_SIZE,
-            BOARD_HEIGHT as f32 * BLOCK_SIZE,
-        )),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
+    world.insert_resource(FixedStep::default());
+    world
+        .spawn()
+        .insert(Block)
+        .insert(Floating(Timer::from_seconds(0.0, false)));
+
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 1);
+}
+
+#[test]
+fn test_stop_fall_block() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(stop_fall_block.system());
+    world.insert_resource(BoardConfig::default());
     world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 99.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Red)
-        .insert(Fixed);
+        .insert(Fall);
     world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 50.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Blue)
         .insert(Fixed);
 
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Space);
-    world.insert_resource(input);
-
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
-
+    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 1);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
-    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 2);
+    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    assert_eq!(
+        world.query::<(&Block, &FixedPrepare)>().iter(&world).len(),
+        1
+    );
 }
 
 #[test]
-fn test_move_tag_block_left_one_fix() {
+fn test_stop_fall_block_ignores_sprite_size() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_tag_block.system());
-
-    world.spawn().insert(Board).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(
-            BOARD_WIDTH as f32 * BLOCK_SIZE,
-            BOARD_HEIGHT as f32 * BLOCK_SIZE,
-        )),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
+    update_stage.add_system(stop_fall_block.system());
+    world.insert_resource(BoardConfig::default());
+    // sized like a wide texture that would reach the neighbouring columns
+    let fall_block = world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 3.0, BLOCK_SIZE / 2.0)),
+            transform: Transform::from_xyz(BLOCK_SIZE / 2.0, 99.0, 0.0),
             ..Default::default()
-        },
-        ..Default::default()
-    });
-    world
+        })
+        .insert(Fall)
+        .id();
+    let beside = world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
+            transform: Transform::from_xyz(BLOCK_SIZE * 1.5, 50.0, 0.0),
             ..Default::default()
         })
-        .insert(BlockColor::Red)
-        .insert(Fixed);
-
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Space);
-    world.insert_resource(input);
+        .insert(Fixed)
+        .id();
 
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    update_stage.run(&mut world);
+    assert!(world.get::<Fall>(fall_block).is_some());
 
+    // over its own column it lands a whole block above, not half of one
+    world
+        .entity_mut(beside)
+        .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, 50.0, 0.0));
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 1);
+    assert!(world.get::<Fall>(fall_block).is_none());
+    assert_eq!(
+        world.get::<Transform>(fall_block).unwrap().translation.y,
+        50.0 + BLOCK_SIZE
+    );
 }
 
 #[test]
-fn test_move_tag_block_right_one_fix() {
+fn test_board_config_block_sprite() {
+    let board_config = BoardConfig {
+        block_size: 40.0,
+        ..Default::default()
+    };
+    let sprite = board_config.block_sprite();
+    assert_eq!(sprite.size, Vec2::splat(40.0));
+    // `Automatic` would take the texture's size once it loads
+    assert_eq!(sprite.resize_mode, SpriteResizeMode::Manual);
+}
+
+#[test]
+fn test_fall_step_long_update() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_tag_block.system());
-
-    world.spawn().insert(Board).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(
-            BOARD_WIDTH as f32 * BLOCK_SIZE,
-            BOARD_HEIGHT as f32 * BLOCK_SIZE,
-        )),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
+    update_stage.add_system(stop_fall_block.system());
+    world.insert_resource(BoardConfig::default());
+    let fall_block = world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform::from_xyz(BLOCK_SIZE / 2.0, 200.0, 0.0),
             ..Default::default()
-        },
-        ..Default::default()
-    });
+        })
+        .insert(Fall)
+        .id();
     world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
+            transform: Transform::from_xyz(BLOCK_SIZE / 2.0, 50.0, 0.0),
             ..Default::default()
         })
-        .insert(BlockColor::Red)
         .insert(Fixed);
 
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Space);
-    world.insert_resource(input);
-
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
-
-    update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 1);
+    // a whole second per update would move 600 pixels
+    assert_eq!(fall_step(&FallSpeed::default(), 1.0), MAX_FALL_STEP);
+    while world.get::<Fall>(fall_block).is_some() {
+        world
+            .get_mut::<Transform>(fall_block)
+            .unwrap()
+            .translation
+            .y -= fall_step(&FallSpeed::default(), 1.0);
+        update_stage.run(&mut world);
+    }
+    assert_eq!(
+        world.get::<Transform>(fall_block).unwrap().translation.y,
+        50.0 + BLOCK_SIZE
+    );
 }
 
 #[test]
-fn test_move_tag_block_there_is_collide() {
+fn test_fixedprepare_to_fixed() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_tag_block.system());
-
-    world.spawn().insert(Board).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(
-            BOARD_WIDTH as f32 * BLOCK_SIZE,
-            BOARD_HEIGHT as f32 * BLOCK_SIZE,
-        )),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
+    update_stage.add_system(fixedprepare_to_fixed.system());
+    world.insert_resource(BoardConfig::default());
+    world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
+                ..Default::default()
+            },
             ..Default::default()
-        },
-        ..Default::default()
-    });
+        })
+        .insert(FixedPrepare);
     world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 1.0, 0.0),
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Red);
+        .insert(Fall);
     world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * 3.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Red)
-        .insert(Fixed);
-
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Space);
-    world.insert_resource(input);
+        .insert(Fall);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.query::<(&Block, &FixedPrepare)>().iter(&world).len(),
+        0
+    );
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
+    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 1);
+}
 
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+#[test]
+fn test_fixedprepare_to_fixed_two_stacks_in_column() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(fixedprepare_to_fixed.system());
+    world.insert_resource(BoardConfig::default());
+    let mut spawn_block = |y: f32, fixed_prepare: bool| {
+        let mut block = world.spawn();
+        block
+            .insert(Block)
+            .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, y, 0.0));
+        if fixed_prepare {
+            block.insert(FixedPrepare);
+        } else {
+            block.insert(Fall);
+        }
+        block.id()
+    };
+    // two groups landing in the same column, neither quite flush
+    let lower = spawn_block(BLOCK_SIZE * 2.0, true);
+    let lower_top = spawn_block(BLOCK_SIZE * 3.1, false);
+    let upper = spawn_block(BLOCK_SIZE * 4.06, true);
+    let upper_top = spawn_block(BLOCK_SIZE * 5.0, false);
+    // still in the air
+    let falling = spawn_block(BLOCK_SIZE * 8.0, false);
 
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+    for (block, y) in [
+        (lower, BLOCK_SIZE * 2.0),
+        (lower_top, BLOCK_SIZE * 3.0),
+        (upper, BLOCK_SIZE * 4.0),
+        (upper_top, BLOCK_SIZE * 5.0),
+    ] {
+        assert!(world.get::<Fixed>(block).is_some());
+        assert_eq!(world.get::<Transform>(block).unwrap().translation.y, y);
+    }
+    assert!(world.get::<Fall>(falling).is_some());
+    assert_eq!(
+        world.get::<Transform>(falling).unwrap().translation.y,
+        BLOCK_SIZE * 8.0
+    );
 }
 
 #[test]
-fn test_move_tag_block_not_fixed_block() {
+fn test_fixed_step_criteria() {
+    #[derive(Default)]
+    struct StepCounter(u32);
+    fn count_step(mut counter: ResMut<StepCounter>) {
+        counter.0 += 1;
+    }
+
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_tag_block.system());
+    update_stage.add_system_run_criteria((|| ShouldRun::Yes).system().label("always"));
+    update_stage.add_system_set(
+        SystemSet::new()
+            .with_run_criteria(RunCriteria::pipe("always", fixed_step_criteria.system()))
+            .with_system(count_step.system()),
+    );
+    world.insert_resource(FixedStep(0.0625));
+    world.insert_resource(StepCounter::default());
+    world.insert_resource(StepClock::default());
+    // never updated, so a frame adds nothing and only the time given to the clock counts
+    world.insert_resource(Time::default());
+
+    // one run per whole step of frame time, the rest carries over
+    for (delta, steps) in [(0.15625, 2), (0.15625, 5)] {
+        world
+            .get_resource_mut::<StepClock>()
+            .unwrap()
+            .advance(delta);
+        update_stage.run(&mut world);
+        assert_eq!(world.get_resource::<StepCounter>().unwrap().0, steps);
+    }
 
-    world.spawn().insert(Board).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(
-            BOARD_WIDTH as f32 * BLOCK_SIZE,
-            BOARD_HEIGHT as f32 * BLOCK_SIZE,
-        )),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    world.spawn().insert(Cursor).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::ZERO,
-            ..Default::default()
-        },
-        ..Default::default()
+    // a long hitch only catches up the backlog limit
+    world.get_resource_mut::<StepClock>().unwrap().advance(10.0);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<StepCounter>().unwrap().0,
+        5 + (MAX_STEP_BACKLOG_SECONDS / 0.0625) as u32
+    );
+}
+
+#[test]
+fn test_interpolate_step_translation() {
+    let mut world = World::default();
+    let mut step_stage = SystemStage::single_threaded();
+    step_stage.add_system(record_step_translation.exclusive_system());
+    let mut frame_stage = SystemStage::single_threaded();
+    frame_stage.add_system(interpolate_step_translation.system());
+    let mut restore_stage = SystemStage::single_threaded();
+    restore_stage.add_system(restore_step_translation.system());
+    world.insert_resource(FixedStep(0.01));
+    world.insert_resource(StepClock {
+        accumulator: 0.0025,
+        stepping: false,
     });
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::from_xyz(0.0, 0.0, 0.0))
+        .id();
+
+    // a step moves the block, the frame draws it a quarter of the way there
+    step_stage.run(&mut world);
+    world.get_mut::<Transform>(block).unwrap().translation.y = 8.0;
+    step_stage.run(&mut world);
+    frame_stage.run(&mut world);
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 2.0);
+    restore_stage.run(&mut world);
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 8.0);
+
+    // moved between the steps, it snaps instead
+    world.get_mut::<Transform>(block).unwrap().translation.y = 20.0;
+    frame_stage.run(&mut world);
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 20.0);
+}
+
+#[test]
+fn test_step_keyboard_input() {
+    let mut world = World::default();
+    let mut frame_stage = SystemStage::parallel();
+    frame_stage.add_system(collect_step_input.system());
+    let mut step_stage = SystemStage::parallel();
+    step_stage.add_system(step_keyboard_input.system());
+    world.insert_resource(StepInput::default());
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    input.press(KeyCode::Left);
+    input.release(KeyCode::Left);
+    world.insert_resource(input);
+
+    // a frame with two steps, the press only reaches the first
+    frame_stage.run(&mut world);
+    step_stage.run(&mut world);
+    let input = world.get_resource::<Input<KeyCode>>().unwrap();
+    assert!(input.just_pressed(KeyCode::Space));
+    assert!(input.just_pressed(KeyCode::Left));
+    assert!(!input.pressed(KeyCode::Left));
+    step_stage.run(&mut world);
+    let input = world.get_resource::<Input<KeyCode>>().unwrap();
+    assert!(!input.just_pressed(KeyCode::Space));
+    assert!(input.pressed(KeyCode::Space));
+    assert!(!input.just_pressed(KeyCode::Left));
+}
+
+#[test]
+fn test_auto_liftup() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(auto_liftup.system());
+    world.insert_resource(TimeScale::default());
+    let app_state = State::new(AppState::InGame);
+    world.insert_resource(app_state);
+    world.insert_resource(FixedStep::default());
     world
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(0.0, false)))
+        .insert(GameSpeed::default());
+
+    let block = world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
@@ -1509,40 +9537,64 @@ fn test_move_tag_block_not_fixed_block() {
             },
             ..Default::default()
         })
-        .insert(BlockColor::Red)
-        .insert(Fixed);
+        .insert(Fixed)
+        .id();
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+
+    update_stage.run(&mut world);
+    assert_ne!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+}
+
+#[test]
+fn test_auto_liftup_stop_with_timer() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(auto_liftup.system());
+    world.insert_resource(TimeScale::default());
+    let app_state = State::new(AppState::InGame);
+    world.insert_resource(app_state);
+    world.insert_resource(FixedStep::default());
     world
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(1.0, false)))
+        .insert(GameSpeed::default());
+
+    let block = world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Blue);
-
-    let mut input = Input::<KeyCode>::default();
-    input.press(KeyCode::Space);
-    world.insert_resource(input);
-
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+        .insert(Fixed)
+        .id();
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
 
     update_stage.run(&mut world);
-    world.get_resource_mut::<Input<KeyCode>>().unwrap();
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
-    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
 }
 
 #[test]
-fn test_move_block() {
+fn test_auto_liftup_stop_with_fall_block() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(move_block.system());
-
+    update_stage.add_system(auto_liftup.system());
+    world.insert_resource(TimeScale::default());
+    let app_state = State::new(AppState::InGame);
+    world.insert_resource(app_state);
+    world.insert_resource(FixedStep::default());
     world
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(0.0, false)))
+        .insert(GameSpeed::default());
+
+    let block = world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
@@ -1553,1293 +9605,2059 @@ fn test_move_block() {
             },
             ..Default::default()
         })
-        .insert(BlockColor::Red)
-        .insert(Move(-1.0 * BLOCK_SIZE / 2.0));
+        .insert(Fall)
+        .id();
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+    update_stage.run(&mut world);
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+}
+
+#[test]
+fn test_auto_liftup_stop_with_matched_block() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(auto_liftup.system());
+    world.insert_resource(TimeScale::default());
+    let app_state = State::new(AppState::InGame);
+    world.insert_resource(app_state);
+    world.insert_resource(FixedStep::default());
     world
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(0.0, false)))
+        .insert(GameSpeed::default());
+
+    let block = world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(-1.0 * BLOCK_SIZE / 2.0, 0.0, 0.0),
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Blue)
-        .insert(Move(BLOCK_SIZE / 2.0));
-
-    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 2);
+        .insert(Matched)
+        .id();
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
-    assert_eq!(world.query::<(&Block, &Moving)>().iter(&world).len(), 2);
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
 }
 
 #[test]
-fn test_match_row_block_three_matched() {
+fn test_auto_liftup_stop_with_chain() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
+    update_stage.add_system(auto_liftup.system());
+    world.insert_resource(TimeScale::default());
+    let app_state = State::new(AppState::InGame);
+    world.insert_resource(app_state);
+    world.insert_resource(FixedStep::default());
+    world
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(0.0, false)))
+        .insert(GameSpeed::default());
 
-    for i in 0..3 {
-        world
-            .spawn()
-            .insert(Block)
-            .insert_bundle(SpriteBundle {
-                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                transform: Transform {
-                    translation: Vec3::new(
-                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                        -300.0,
-                        0.0,
-                    ),
-                    ..Default::default()
-                },
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
                 ..Default::default()
-            })
-            .insert(BlockColor::Red)
-            .insert(Fixed);
-    }
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 3);
+            },
+            ..Default::default()
+        })
+        .insert(Fixed)
+        .insert(Chain)
+        .id();
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 3);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
-}
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
 
-#[test]
-fn test_match_row_block_four_matched() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
+    // the board stays still for a moment after the chain ends
+    world.entity_mut(block).remove::<Chain>();
+    update_stage.run(&mut world);
+    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
 
-    for i in 0..4 {
-        world
-            .spawn()
-            .insert(Block)
-            .insert_bundle(SpriteBundle {
-                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                transform: Transform {
-                    translation: Vec3::new(
-                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                        -300.0,
-                        0.0,
-                    ),
-                    ..Default::default()
-                },
-                ..Default::default()
-            })
-            .insert(BlockColor::Red)
-            .insert(Fixed);
-    }
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 4);
+    world.insert_resource(FixedStep(CHAIN_FREEZE_SECONDS));
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 4);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    update_stage.run(&mut world);
+    assert_ne!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
 }
 
 #[test]
-fn test_match_row_block_three_matched_only() {
+fn test_pulse_cursor() {
+    assert_eq!(cursor_pulse_scale(0.0), 1.0);
+    assert!((cursor_pulse_scale(CURSOR_PULSE_SECONDS / 4.0) - 1.05).abs() < 1e-6);
+
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
-
-    for i in 0..5 {
-        match i {
-            0 | 1 | 2 | 4 => {
-                world
-                    .spawn()
-                    .insert(Block)
-                    .insert_bundle(SpriteBundle {
-                        sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                        transform: Transform {
-                            translation: Vec3::new(
-                                BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                                -300.0,
-                                0.0,
-                            ),
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    })
-                    .insert(BlockColor::Red)
-                    .insert(Fixed);
-            }
-            3 => {
-                world
-                    .spawn()
-                    .insert(Block)
-                    .insert_bundle(SpriteBundle {
-                        sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                        transform: Transform {
-                            translation: Vec3::new(
-                                BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                                -300.0,
-                                0.0,
-                            ),
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    })
-                    .insert(BlockColor::Blue)
-                    .insert(Fixed);
-            }
-            _ => {
-                unreachable!()
-            }
-        }
-    }
+    update_stage.add_system(pulse_cursor.system());
+    world.insert_resource(Time::default());
+    world.insert_resource(AccessibilitySettings {
+        reduce_motion: true,
+        ..Default::default()
+    });
+    let cursor = world
+        .spawn()
+        .insert(Cursor)
+        .insert(Transform::from_scale(Vec3::new(1.2, 1.2, 1.0)))
+        .id();
 
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 3);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
+    assert_eq!(world.get::<Transform>(cursor).unwrap().scale, Vec3::ONE);
 }
 
 #[test]
-fn test_match_row_block_five_matched() {
+fn test_manual_liftup() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
+    update_stage.add_system(manual_liftup.system());
+    world.insert_resource(TimeScale::default());
+    world.insert_resource(FixedStep::default());
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::LShift);
+    world.insert_resource(input);
 
-    for i in 0..5 {
-        world
-            .spawn()
-            .insert(Block)
-            .insert_bundle(SpriteBundle {
-                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                transform: Transform {
-                    translation: Vec3::new(
-                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                        -300.0,
-                        0.0,
-                    ),
-                    ..Default::default()
-                },
-                ..Default::default()
-            })
-            .insert(BlockColor::Red)
-            .insert(Fixed);
-    }
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+    let board = world.spawn().insert(Board).id();
+    let cursor = world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .id();
+    let count_timer = world
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(1.0, false)))
+        .insert(GameSpeed::default())
+        .insert(RampClock::default())
+        .id();
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(0.0, 0.0, 0.0))
+        .id();
+    world
+        .entity_mut(board)
+        .push_children(&[cursor, count_timer, block]);
+
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get::<GameSpeed>(count_timer).unwrap().current,
+        MANUAL_LIFT_SPEED
+    );
+    assert_eq!(
+        world
+            .get::<CountTimer>(count_timer)
+            .unwrap()
+            .0
+            .elapsed_secs(),
+        1.0
+    );
+
+    // letting go goes back to the board's own speed
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::LShift);
+    input.update();
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 5);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    assert_eq!(
+        world.get::<GameSpeed>(count_timer).unwrap().current,
+        GameSpeed::default().origin
+    );
+
+    // no fast step past the game over line
+    world
+        .get_resource_mut::<Input<KeyCode>>()
+        .unwrap()
+        .press(KeyCode::LShift);
+    world.get_mut::<Transform>(block).unwrap().translation.y = BoardConfig::default().game_over_y();
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get::<GameSpeed>(count_timer).unwrap().current,
+        GameSpeed::default().origin
+    );
+
+    // an empty board has all the room it needs
+    world.despawn(block);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get::<GameSpeed>(count_timer).unwrap().current,
+        MANUAL_LIFT_SPEED
+    );
 }
 
 #[test]
-fn test_match_row_block_six_matched() {
+fn test_speed_ramp_pauses_while_clearing() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
+    update_stage.add_system(speed_ramp.system());
+    world.insert_resource(FixedStep::default());
 
-    for i in 0..6 {
-        world
+    let mut count_timers = Vec::new();
+    for clearing in [true, false] {
+        let board = world.spawn().insert(Board).id();
+        let count_timer = world
             .spawn()
-            .insert(Block)
-            .insert_bundle(SpriteBundle {
-                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                transform: Transform {
-                    translation: Vec3::new(
-                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                        -300.0,
-                        0.0,
-                    ),
-                    ..Default::default()
-                },
-                ..Default::default()
-            })
-            .insert(BlockColor::Red)
-            .insert(Fixed);
+            .insert(GameSpeed::default())
+            .insert(RampClock(40.0))
+            .id();
+        world.entity_mut(board).push_children(&[count_timer]);
+        if clearing {
+            let clearing_block = world
+                .spawn()
+                .insert(Block)
+                .insert(Despawining(Timer::from_seconds(1.0, false)))
+                .id();
+            world.entity_mut(board).push_children(&[clearing_block]);
+        }
+        count_timers.push(count_timer);
     }
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 6);
+
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 6);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    assert_eq!(world.get::<RampClock>(count_timers[0]).unwrap().0, 40.0);
+    assert_eq!(
+        world.get::<GameSpeed>(count_timers[0]).unwrap().current,
+        10.0
+    );
+    assert!(world.get::<RampClock>(count_timers[1]).unwrap().0 > 40.0);
+    // two 20 second steps in
+    assert_eq!(
+        world.get::<GameSpeed>(count_timers[1]).unwrap().current,
+        12.0
+    );
 }
 
 #[test]
-fn test_match_row_block_six_matched_two_colors() {
+fn test_auto_liftup_stop_with_distant_settling_block() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
+    update_stage.add_system(auto_liftup.system());
+    world.insert_resource(TimeScale::default());
+    world.insert_resource(FixedStep::default());
 
-    for i in 0..6 {
-        if i < 3 {
-            world
-                .spawn()
-                .insert(Block)
-                .insert_bundle(SpriteBundle {
-                    sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                    transform: Transform {
-                        translation: Vec3::new(
-                            BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                            -300.0,
-                            0.0,
-                        ),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(BlockColor::Red)
-                .insert(Fixed);
-        } else {
-            world
+    let mut bottom_blocks = Vec::new();
+    for settling in [true, false] {
+        let board = world.spawn().insert(Board).id();
+        let count_timer = world
+            .spawn()
+            .insert(CountTimer(Timer::from_seconds(0.0, false)))
+            .insert(GameSpeed::default())
+            .id();
+        let bottom_block = world
+            .spawn()
+            .insert(Block)
+            .insert(Transform::from_translation(Vec3::new(
+                -2.5 * BLOCK_SIZE,
+                -5.0 * BLOCK_SIZE,
+                0.0,
+            )))
+            .insert(Fixed)
+            .id();
+        world
+            .entity_mut(board)
+            .push_children(&[count_timer, bottom_block]);
+        if settling {
+            // landing on the far side of the board
+            let settling_block = world
                 .spawn()
                 .insert(Block)
-                .insert_bundle(SpriteBundle {
-                    sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                    transform: Transform {
-                        translation: Vec3::new(
-                            BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                            -300.0,
-                            0.0,
-                        ),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(BlockColor::Blue)
-                .insert(Fixed);
+                .insert(Transform::from_translation(Vec3::new(
+                    2.5 * BLOCK_SIZE,
+                    3.0 * BLOCK_SIZE,
+                    0.0,
+                )))
+                .insert(FixedPrepare)
+                .id();
+            world.entity_mut(board).push_children(&[settling_block]);
         }
+        bottom_blocks.push(bottom_block);
     }
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 6);
+
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 6);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    assert_eq!(
+        world
+            .get::<Transform>(bottom_blocks[0])
+            .unwrap()
+            .translation
+            .y,
+        -5.0 * BLOCK_SIZE
+    );
+    assert!(
+        world
+            .get::<Transform>(bottom_blocks[1])
+            .unwrap()
+            .translation
+            .y
+            > -5.0 * BLOCK_SIZE
+    );
 }
 
 #[test]
-fn test_no_match_block() {
+fn test_check_game_over() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(State::new(AppState::InGame));
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(TimeScale::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(check_game_over.system());
+    update_stage.add_system(finish_game_over.system());
 
+    // a board without a top out timer has no grace
     world
         .spawn()
+        .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE * 2.0, -300.0, 0.0),
+                translation: Vec3::new(
+                    BLOCK_SIZE / 2.0,
+                    BoardConfig::default().game_over_y() + 0.1,
+                    0.0,
+                ),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(Block)
-        .insert(BlockColor::Red)
         .insert(Fixed);
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<GameOverFinish>().is_some());
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+
+    world
+        .get_resource_mut::<GameOverFinish>()
+        .unwrap()
+        .0
+        .set_duration(Duration::ZERO);
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::GameOver
+    );
+}
+
+#[test]
+fn test_check_game_over_ignores_falling_block() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(State::new(AppState::InGame));
+    world.insert_resource(FixedStep::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(check_game_over.system());
+    let game_over_y = BoardConfig::default().game_over_y();
+    world
+        .spawn()
+        .insert(Block)
+        .insert(Fall)
+        .insert(Transform::from_xyz(0.0, game_over_y + 1.0, 0.0));
+
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+}
+
+#[test]
+fn test_check_game_over_cleared_within_grace() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(State::new(AppState::InGame));
+    world.insert_resource(FixedStep::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(check_game_over.system());
+    let game_over_y = BoardConfig::default().game_over_y();
+    let board = world.spawn().insert(Board).id();
+    let mut timer = Timer::from_seconds(1.0, false);
+    timer.set_elapsed(Duration::from_secs_f32(0.9));
+    let top_out_timer = world.spawn().insert(TopOutTimer(timer)).id();
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(0.0, game_over_y + 1.0, 0.0))
+        .id();
+    world
+        .entity_mut(board)
+        .push_children(&[top_out_timer, block]);
+
+    // the top block clears just before the grace runs out
+    world.despawn(block);
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+    assert_eq!(
+        world.get::<TopOutTimer>(top_out_timer).unwrap().0.elapsed(),
+        Duration::ZERO
+    );
+    assert!(world.get_resource::<GameOverFinish>().is_none());
+}
+
+#[test]
+fn test_check_game_over_after_top_out_timer() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(State::new(AppState::InGame));
+    world.insert_resource(FixedStep::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(check_game_over.system());
+    update_stage.add_system(finish_game_over.system());
+    world.insert_resource(TimeScale::default());
+    let game_over_y = BoardConfig::default().game_over_y();
+    let board = world.spawn().insert(Board).id();
+    let top_out_timer = world
+        .spawn()
+        .insert(TopOutTimer(Timer::from_seconds(10.0, false)))
+        .id();
+    let block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(0.0, game_over_y + 1.0, 0.0))
+        .id();
+    world
+        .entity_mut(board)
+        .push_children(&[top_out_timer, block]);
+
+    // still within the grace time
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+
+    world
+        .get_mut::<TopOutTimer>(top_out_timer)
+        .unwrap()
+        .0
+        .set_duration(Duration::ZERO);
+    update_stage.run(&mut world);
+    // nothing left to top out once the finish has started
+    world.despawn(block);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+
+    world
+        .get_resource_mut::<GameOverFinish>()
+        .unwrap()
+        .0
+        .set_duration(Duration::ZERO);
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::GameOver
+    );
+}
+
+#[test]
+fn test_column_guide_xs() {
+    let board_config = BoardConfig::default();
+    let xs = column_guide_xs(&board_config);
+    assert_eq!(xs.len(), BOARD_WIDTH - 1);
+    // evenly spaced, with the middle line in the board's center
+    assert_eq!(xs[BOARD_WIDTH / 2 - 1], 0.0);
+    for pair in xs.windows(2) {
+        assert_eq!(pair[1] - pair[0], BLOCK_SIZE);
+    }
+}
+
+#[test]
+fn test_game_over_line_y() {
+    let board_config = BoardConfig::default();
+    // one row below the top of the board
+    assert_eq!(
+        board_config.game_over_line_y(),
+        board_config.size().y / 2.0 - BLOCK_SIZE
+    );
+    // a block resting just under the line doesn't top out, one nudged past it does
+    let resting_y = board_config.game_over_line_y() - BLOCK_SIZE / 2.0;
+    assert!(resting_y <= board_config.game_over_y());
+    assert!(resting_y + 1.0 > board_config.game_over_y());
+}
+
+#[test]
+fn test_finish_time_scale() {
+    assert_eq!(finish_time_scale(0.0), 1.0);
+    assert!((finish_time_scale(FINISH_SECONDS / 2.0) - 0.6).abs() < 1e-6);
+    assert!((finish_time_scale(FINISH_SECONDS) - FINISH_TIME_SCALE).abs() < 1e-6);
+    // holds at the lowest speed
+    assert_eq!(
+        finish_time_scale(FINISH_SECONDS * 2.0),
+        finish_time_scale(FINISH_SECONDS)
+    );
+}
+
+#[test]
+fn test_spawn_fade_step() {
+    let board_config = BoardConfig::default();
+    // still behind the cover
+    assert_eq!(spawn_fade_step(board_config.bottom_y(), &board_config), 0);
+    assert_eq!(
+        spawn_fade_step(board_config.bottom_y() - BLOCK_SIZE, &board_config),
+        0
+    );
+    assert_eq!(
+        spawn_fade_step(board_config.bottom_y() + BLOCK_SIZE / 2.0, &board_config),
+        SPAWN_FADE_STEPS / 2
+    );
+    assert_eq!(
+        spawn_fade_step(board_config.first_row_y(), &board_config),
+        SPAWN_FADE_STEPS
+    );
+}
+
+#[test]
+fn test_danger_columns() {
+    let board_config = BoardConfig::default();
+    assert_eq!(board_config.danger_y(), BLOCK_SIZE * 4.0);
+    let block_heights = [
+        (0, BLOCK_SIZE * 4.5),
+        (0, BLOCK_SIZE * 3.5),
+        (2, BLOCK_SIZE * 4.0),
+        (3, BLOCK_SIZE * 5.0),
+    ];
+    assert_eq!(
+        danger_columns(block_heights, board_config.danger_y()),
+        [0, 3].into_iter().collect()
+    );
+    assert!(danger_columns([(1, 0.0)], board_config.danger_y()).is_empty());
+}
+
+#[test]
+fn test_cleanup_ingame() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(cleanup_ingame.system());
+    let board = world.spawn().insert(Board).id();
+    let block = world.spawn().insert(Block).id();
+    let cursor = world.spawn().insert(Cursor).id();
+    world.entity_mut(board).push_children(&[block, cursor]);
+    world.spawn().insert(BoardBottomCover);
+    world
+        .spawn()
+        .insert(CountTimer(Timer::from_seconds(1.0, false)));
+    world.spawn().insert(ChainCounter(1));
+    world.spawn().insert(IngameCamera);
+    let other = world.spawn().id();
+
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<&Board>().iter(&world).len(), 0);
+    assert_eq!(world.query::<&Block>().iter(&world).len(), 0);
+    assert_eq!(world.query::<&Cursor>().iter(&world).len(), 0);
+    assert_eq!(world.query::<&BoardBottomCover>().iter(&world).len(), 0);
+    assert_eq!(world.query::<&CountTimer>().iter(&world).len(), 0);
+    assert_eq!(world.query::<&ChainCounter>().iter(&world).len(), 0);
+    assert_eq!(world.query::<&IngameCamera>().iter(&world).len(), 0);
+    assert!(world.get_entity(other).is_some());
+}
+
+#[test]
+fn test_spawning_to_fixed() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(spawning_to_fixed.system());
     world
         .spawn()
+        .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, -300.0, 0.0),
+                translation: Vec3::new(0.0, BLOCK_SIZE * -5.9, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(Block)
-        .insert(BlockColor::Red)
-        .insert(Fixed);
-    world
+        .insert(Spawning);
+    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 1);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 0);
+}
+
+#[test]
+fn test_bottom_cover_matches_reveal_line() {
+    let board_config = BoardConfig {
+        height: 20,
+        ..Default::default()
+    };
+    let mut world = World::default();
+    world.insert_resource(board_config);
+    world.insert_resource(BoardBottomCoverMaterials {
+        board_bottom_cover_material: Handle::default(),
+    });
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(setup_board_bottom_cover.system());
+    update_stage.add_system(spawning_to_fixed.system());
+    world.spawn().insert(Board);
+
+    // blocks whose bottom edge is just under and just over the cover's top edge
+    let cover_top = board_config.bottom_edge_y();
+    let spawn_block = |world: &mut World, bottom_edge: f32| {
+        world
+            .spawn()
+            .insert(Block)
+            .insert(Transform::from_xyz(
+                0.0,
+                bottom_edge + board_config.block_size / 2.0,
+                0.0,
+            ))
+            .insert(Spawning)
+            .id()
+    };
+    let hidden = spawn_block(&mut world, cover_top - 1.0);
+    let revealed = spawn_block(&mut world, cover_top + 1.0);
+    update_stage.run(&mut world);
+
+    let (cover_transform, cover_sprite) = world
+        .query_filtered::<(&Transform, &Sprite), With<BoardBottomCover>>()
+        .iter(&world)
+        .next()
+        .unwrap();
+    assert_eq!(
+        cover_transform.translation.y + cover_sprite.size.y / 2.0,
+        cover_top
+    );
+    assert_eq!(cover_sprite.size.x, board_config.size().x);
+    assert!(cover_transform.translation.z > 0.0);
+    assert!(world.get::<Spawning>(hidden).is_some());
+    assert!(world.get::<Fixed>(revealed).is_some());
+    assert_eq!(
+        spawn_fade_step(cover_top - board_config.block_size / 2.0, &board_config),
+        0
+    );
+}
+
+#[test]
+fn test_bottom_down() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(bottom_down.system());
+    let bottom = world
         .spawn()
+        .insert(Bottom)
         .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 6.0, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, -300.0, 0.0),
+                translation: Vec3::new(0.0, -300.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(Block)
-        .insert(BlockColor::Blue)
-        .insert(Fixed);
-    world
+        .id();
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get::<Transform>(bottom).unwrap().translation.y,
+        -350.0
+    );
+}
+
+#[test]
+fn test_generate_spawning_block() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(GameSettings::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(generate_spawning_block.system());
+    world.insert_resource(NextRow::default());
+    world.insert_resource(ColorCount::default());
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(BoardRng::new(None));
+    insert_asset_fixtures(&mut world);
+    let board = world
         .spawn()
+        .insert(Board)
         .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE * 2.0, -300.0, 0.0),
-                ..Default::default()
-            },
             ..Default::default()
         })
-        .insert(Block)
-        .insert(BlockColor::Red)
-        .insert(Fixed);
-    world
+        .id();
+    let bottom = world
         .spawn()
+        .insert(Bottom)
         .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 6.0, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE - 300.0, 0.0),
+                translation: Vec3::new(0.0, -300.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(Block)
-        .insert(BlockColor::Red)
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+        .id();
+    world.entity_mut(board).push_children(&[bottom]);
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 6);
+    // the preview shows the row just made, left to right
+    let mut spawned = world
+        .query::<(&Transform, &BlockColor, &Spawning)>()
+        .iter(&world)
+        .map(|(transform, block_color, _)| (transform.translation.x, *block_color))
+        .collect::<Vec<_>>();
+    spawned.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    assert_eq!(
+        world.get_resource::<NextRow>().unwrap().0[&Some(board)],
+        spawned
+            .into_iter()
+            .map(|(_, block_color)| block_color)
+            .collect::<Vec<_>>()
+    );
 }
 
 #[test]
-fn test_match_column_block_three_matched() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
-
-    for i in 0..3 {
-        world
+fn test_generate_spawning_block_rotation() {
+    let spawn_rotations = |random_block_rotation: bool| {
+        let mut world = World::default();
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(GameSettings {
+            random_block_rotation,
+            ..Default::default()
+        });
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system(generate_spawning_block.system());
+        world.insert_resource(NextRow::default());
+        world.insert_resource(ColorCount::default());
+        world.insert_resource(ColorTable::default());
+        world.insert_resource(BoardRng::new(Some(&GameSeed(7))));
+        insert_asset_fixtures(&mut world);
+        let board = world
             .spawn()
-            .insert(Block)
+            .insert(Board)
             .insert_bundle(SpriteBundle {
-                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                transform: Transform {
-                    translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0 - BLOCK_SIZE * i as f32, 0.0),
-                    ..Default::default()
-                },
                 ..Default::default()
             })
-            .insert(BlockColor::Red)
-            .insert(Fixed);
+            .id();
+        let bottom = world
+            .spawn()
+            .insert(Bottom)
+            .insert_bundle(SpriteBundle {
+                transform: Transform::from_xyz(0.0, -300.0, 0.0),
+                ..Default::default()
+            })
+            .id();
+        world.entity_mut(board).push_children(&[bottom]);
+        for _ in 0..3 {
+            update_stage.run(&mut world);
+        }
+        let mut rotations = world
+            .query_filtered::<&Transform, With<Spawning>>()
+            .iter(&world)
+            .map(|transform| {
+                (
+                    (transform.translation.x, transform.translation.y),
+                    transform.rotation,
+                )
+            })
+            .collect::<Vec<_>>();
+        rotations.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        rotations
+            .into_iter()
+            .map(|(_, rotation)| rotation)
+            .collect::<Vec<_>>()
+    };
+
+    let rotations = spawn_rotations(true);
+    assert_eq!(rotations.len(), 18);
+    assert_eq!(rotations, spawn_rotations(true));
+    assert!(rotations.iter().any(|rotation| *rotation != Quat::IDENTITY));
+    assert!(spawn_rotations(false)
+        .iter()
+        .all(|rotation| *rotation == Quat::IDENTITY));
+}
+
+#[test]
+fn test_has_initial_match() {
+    let mut colors = [[None; BOARD_WIDTH]; 3];
+    assert!(!has_initial_match(&colors));
+
+    // horizontal
+    colors[1][2] = Some(BlockColor::RED);
+    colors[1][3] = Some(BlockColor::RED);
+    assert!(!has_initial_match(&colors));
+    colors[1][4] = Some(BlockColor::RED);
+    assert!(has_initial_match(&colors));
+
+    // vertical
+    let mut colors = [[None; BOARD_WIDTH]; 3];
+    colors[0][5] = Some(BlockColor::BLUE);
+    colors[1][5] = Some(BlockColor::BLUE);
+    colors[2][5] = Some(BlockColor::GREEN);
+    assert!(!has_initial_match(&colors));
+    colors[2][5] = Some(BlockColor::BLUE);
+    assert!(has_initial_match(&colors));
+}
+
+#[test]
+fn test_remove_initial_matches() {
+    let block_colors = [
+        BlockColor::RED,
+        BlockColor::GREEN,
+        BlockColor::BLUE,
+        BlockColor::YELLOW,
+        BlockColor::PURPLE,
+    ];
+    let mut rng = rand::thread_rng();
+    for _ in 0..100 {
+        let mut colors = [[Some(BlockColor::RED); BOARD_WIDTH]; 4];
+        colors[3][0] = None;
+        remove_initial_matches(&mut colors, &block_colors, &mut rng);
+        assert!(!has_initial_match(&colors));
+        assert_eq!(colors[3][0], None);
+        assert!(colors.iter().enumerate().all(|(row_idx, row)| row
+            .iter()
+            .enumerate()
+            .all(|(column_idx, block_color)| (row_idx, column_idx) == (3, 0)
+                || block_color.is_some())));
     }
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 3);
-    update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 3);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
 }
 
 #[test]
-fn test_match_row_and_column_block_five_matched() {
+fn test_move_tag_block_reject_moving_block() {
     let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(match_block.system());
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
 
-    // row
-    for i in 0..3 {
-        world
-            .spawn()
-            .insert(Block)
-            .insert_bundle(SpriteBundle {
-                sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-                transform: Transform {
-                    translation: Vec3::new(
-                        BLOCK_SIZE / 2.0 + BLOCK_SIZE * (i - 3) as f32,
-                        0.0,
-                        0.0,
-                    ),
-                    ..Default::default()
-                },
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 2.0, BLOCK_SIZE)),
+            transform: Transform {
+                translation: Vec3::ZERO,
                 ..Default::default()
-            })
-            .insert(BlockColor::Red)
-            .insert(Fixed);
-    }
-    // column
+            },
+            ..Default::default()
+        });
     world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE * 2.0, BLOCK_SIZE, 0.0),
+                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Red)
+        .insert(BlockColor::RED)
         .insert(Fixed);
-    // column
+    // left target is still animating a previous swap, so it has no Fixed
     world
         .spawn()
         .insert(Block)
         .insert_bundle(SpriteBundle {
             sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE * 2.0, -1.0 * BLOCK_SIZE, 0.0),
+                translation: Vec3::new(-BLOCK_SIZE / 2.0, 0.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(BlockColor::Red)
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 5);
+        .insert(BlockColor::BLUE)
+        .insert(Moving(-BLOCK_SIZE / 2.0));
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 5);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+    let swap_rejected = world
+        .get_resource::<bevy::app::Events<SwapRejected>>()
+        .unwrap();
+    assert_eq!(swap_rejected.get_reader().iter(swap_rejected).count(), 1);
 }
 
 #[test]
-fn test_prepare_despawn_block() {
+fn test_move_tag_block_buffers_early_press() {
     let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(prepare_despawn_block.system());
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
 
-    world.spawn().insert(Block).insert(Matched);
-    let chain_counter = world.spawn().insert(ChainCounter(1)).id();
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert(Transform::default());
+    world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, 0.0, 0.0))
+        .insert(BlockColor::RED)
+        .insert(Fixed);
+    // left target is finishing a previous swap when the press comes in
+    let left = world
+        .spawn()
+        .insert(Block)
+        .insert(Transform::from_xyz(-BLOCK_SIZE / 2.0, 0.0, 0.0))
+        .insert(BlockColor::BLUE)
+        .insert(Moving(-BLOCK_SIZE / 2.0))
+        .id();
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 0);
+
+    // the swap settles and the key is still held, not freshly pressed
+    let mut left = world.entity_mut(left);
+    left.remove::<Moving>();
+    left.insert(Fixed);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<(&Block, &Move)>().iter(&world).len(), 2);
+    let swap_rejected = world
+        .get_resource::<bevy::app::Events<SwapRejected>>()
+        .unwrap();
+    assert_eq!(swap_rejected.get_reader().iter(swap_rejected).count(), 1);
+}
+
+#[test]
+fn test_simulate_cascade_chain() {
+    let mut grid: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+    grid[0] = vec![
+        Some(BlockColor::RED),
+        Some(BlockColor::BLUE),
+        Some(BlockColor::BLUE),
+        Some(BlockColor::BLUE),
+        None,
+        None,
+    ];
+    grid[1][0] = Some(BlockColor::GREEN);
+    grid[1][1] = Some(BlockColor::RED);
+    grid[1][2] = Some(BlockColor::RED);
+
+    let steps = simulate_cascade(grid, &SquareClear::default());
+    assert_eq!(steps.len(), 2);
     assert_eq!(
-        world.query::<(&Block, &Despawining)>().iter(&world).len(),
-        1
+        steps[0],
+        vec![
+            GridPos { col: 1, row: 0 },
+            GridPos { col: 2, row: 0 },
+            GridPos { col: 3, row: 0 },
+        ]
+    );
+    assert_eq!(
+        steps[1],
+        vec![
+            GridPos { col: 0, row: 0 },
+            GridPos { col: 1, row: 0 },
+            GridPos { col: 2, row: 0 },
+        ]
     );
-    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 1);
 }
 
 #[test]
-fn test_prepare_despawn_block_chain() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(prepare_despawn_block.system());
+fn test_simulate_cascade_no_match() {
+    let mut grid: BoardGrid = vec![vec![None; BOARD_WIDTH]; BOARD_HEIGHT];
+    grid[0][0] = Some(BlockColor::RED);
+    grid[1][0] = Some(BlockColor::RED);
+    grid[3][1] = Some(BlockColor::RED);
+    assert!(simulate_cascade(grid, &SquareClear::default()).is_empty());
+}
 
-    world
-        .spawn()
-        .insert(Block)
-        .insert(Matched)
-        .insert(Chain(Timer::from_seconds(0.04, false)));
-    let chain_counter = world.spawn().insert(ChainCounter(1)).id();
-    update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 0);
+#[test]
+fn test_logical_cell_mid_lift() {
+    let board_config = BoardConfig::default();
+    let lift_offset = bottom_lift_offset(&Transform::from_xyz(0.0, -320.0, 0.0), &board_config);
+    assert_eq!(lift_offset, 30.0);
     assert_eq!(
-        world.query::<(&Block, &Despawining)>().iter(&world).len(),
-        1
+        bottom_lift_offset(&Transform::from_xyz(0.0, -350.0, 0.0), &board_config),
+        0.0
+    );
+    assert_eq!(
+        logical_cell(
+            &Transform::from_xyz(-125.0, -270.0, 0.0),
+            lift_offset,
+            &board_config
+        ),
+        (0, 0)
+    );
+    assert_eq!(
+        logical_cell(
+            &Transform::from_xyz(125.0, 80.0, 0.0),
+            lift_offset,
+            &board_config
+        ),
+        (5, 7)
+    );
+    // spawning row under the board
+    assert_eq!(
+        logical_cell(
+            &Transform::from_xyz(-75.0, -320.0, 0.0),
+            lift_offset,
+            &board_config
+        ),
+        (1, -1)
     );
-    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 2);
 }
 
 #[test]
-fn test_remove_chain() {
+fn test_board_config() {
+    let board_config = BoardConfig::default();
+    assert_eq!(board_config.first_row_y(), -300.0);
+    assert_eq!(board_config.bottom_y(), -350.0);
+    assert_eq!(board_config.cursor_start(), Vec2::new(0.0, 0.0));
+    assert_eq!(board_config.cursor_min(), Vec2::new(-100.0, -300.0));
+    assert_eq!(board_config.cursor_max(), Vec2::new(100.0, 300.0));
+    assert_eq!(board_config.game_over_y(), 250.0);
+
+    let board_config = BoardConfig {
+        width: 8,
+        height: 10,
+        block_size: 40.0,
+        ..Default::default()
+    };
+    assert_eq!(board_config.size(), Vec2::new(320.0, 400.0));
+    assert_eq!(board_config.first_row_y(), -180.0);
+    assert_eq!(board_config.cursor_start(), Vec2::new(0.0, 20.0));
+    assert_eq!(board_config.cursor_min(), Vec2::new(-120.0, -180.0));
+    assert_eq!(
+        GridPos { col: 7, row: 0 }.to_translation(0.0, &board_config),
+        Vec3::new(140.0, -180.0, 0.0)
+    );
+    assert_eq!(GridPos::from_logical_cell((8, 0), &board_config), None);
+}
+
+#[test]
+fn test_match_block_mid_lift() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(remove_chain.system());
-    let mut time = Time::default();
-    time.update();
-    world.insert_resource(time);
+    update_stage.add_system(match_block.system());
+    world.insert_resource(SquareClear::default());
     world
+        .spawn()
+        .insert(Bottom)
+        .insert(Transform::from_xyz(0.0, -320.0, 1.0));
+    for x in [-125.0, -75.0, -25.0] {
+        world
+            .spawn()
+            .insert(Block)
+            .insert(BlockColor::RED)
+            .insert(Fixed)
+            .insert(Transform::from_xyz(x, -270.0, 0.0));
+    }
+    let other = world
         .spawn()
         .insert(Block)
+        .insert(BlockColor::RED)
         .insert(Fixed)
-        .insert(Chain(Timer::from_seconds(0.0, false)));
-    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 1);
+        .insert(Transform::from_xyz(25.0, -220.0, 0.0))
+        .id();
+
+    sync_grid_pos(&mut world);
+    assert_eq!(
+        world.get::<GridPos>(other).unwrap(),
+        &GridPos { col: 3, row: 1 }
+    );
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 0);
+    assert_eq!(world.query::<(&Block, &Matched)>().iter(&world).len(), 3);
+    assert!(world.get::<Fixed>(other).is_some());
 }
 
 #[test]
-fn test_remove_chain_not_fixed() {
+fn test_check_fall_block_mid_lift() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(remove_chain.system());
-    let mut time = Time::default();
-    time.update();
-    world.insert_resource(time);
+    update_stage.add_system(check_fall_block.system());
     world
         .spawn()
-        .insert(Block)
-        .insert(Matched)
-        .insert(Chain(Timer::from_seconds(0.0, false)));
+        .insert(Bottom)
+        .insert(Transform::from_xyz(0.0, -320.0, 1.0));
     world
         .spawn()
         .insert(Block)
-        .insert(Despawining)
-        .insert(Chain(Timer::from_seconds(0.0, false)));
-
-    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 2);
-    update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 2);
-}
+        .insert(Spawning)
+        .insert(Transform::from_xyz(-125.0, -320.0, 0.0));
+    let bottom_block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(-125.0, -270.0, 0.0))
+        .id();
+    let supported_block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(-125.0, -220.0, 0.0))
+        .id();
+    let floating_block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(-75.0, -170.0, 0.0))
+        .id();
 
-#[test]
-fn test_reset_chain_counter() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(reset_chain_counter.system());
-    let chain_counter = world.spawn().insert(ChainCounter(2)).id();
     update_stage.run(&mut world);
-    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 1);
+    assert!(world.get::<Fixed>(bottom_block).is_some());
+    assert!(world.get::<Fixed>(supported_block).is_some());
+    assert!(world.get::<FallPrepare>(floating_block).is_some());
 }
 
 #[test]
-fn test_reset_chain_counter_not_reset() {
+fn test_move_tag_block_mid_lift() {
     let mut world = World::default();
+    world.insert_resource(Audio::default());
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(reset_chain_counter.system());
-    let chain_counter = world.spawn().insert(ChainCounter(2)).id();
+    update_stage.add_system(move_tag_block.system());
+    world.insert_resource(bevy::app::Events::<SwapRejected>::default());
+    world
+        .spawn()
+        .insert(Bottom)
+        .insert(Transform::from_xyz(0.0, -320.0, 1.0));
+    world
+        .spawn()
+        .insert(Cursor)
+        .insert(PlayerControls::solo())
+        .insert(Transform::from_xyz(0.0, 30.0, 1.0));
+    let left_block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(-25.0, 30.0, 0.0))
+        .id();
+    let right_block = world
+        .spawn()
+        .insert(Block)
+        .insert(Fixed)
+        .insert(Transform::from_xyz(25.0, 30.0, 0.0))
+        .id();
+    // resting on the right block, so it is not a falling collision
     world
         .spawn()
         .insert(Block)
-        .insert(Chain(Timer::from_seconds(0.04, false)));
+        .insert(Fixed)
+        .insert(Transform::from_xyz(25.0, 80.0, 0.0));
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+
     update_stage.run(&mut world);
-    assert_eq!(world.get::<ChainCounter>(chain_counter).unwrap().0, 2);
+    assert_eq!(world.get::<Move>(left_block).unwrap().0, 25.0);
+    assert_eq!(world.get::<Move>(right_block).unwrap().0, -25.0);
 }
 
 #[test]
-fn test_despawn_block() {
+fn test_generate_spawning_block_indigo() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(GameSettings::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(despawn_block.system());
-    let time = Time::default();
-    world.insert_resource(time);
-
-    let block = world
+    update_stage.add_system(generate_spawning_block.system());
+    world.insert_resource(NextRow::default());
+    world.insert_resource(ColorCount(6));
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(BoardRng::new(Some(&GameSeed(0))));
+    insert_asset_fixtures(&mut world);
+    let board = world
         .spawn()
-        .insert(Block)
+        .insert(Board)
+        .insert_bundle(SpriteBundle {
+            ..Default::default()
+        })
+        .id();
+    let bottom = world
+        .spawn()
+        .insert(Bottom)
         .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
             transform: Transform {
-                translation: Vec3::ZERO,
+                translation: Vec3::new(0.0, -300.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(Despawining(Timer::from_seconds(0.0, false)))
         .id();
+    world.entity_mut(board).push_children(&[bottom]);
+    // every run adds a row while the bottom stays up
+    for _ in 0..10 {
+        update_stage.run(&mut world);
+    }
+    assert!(world
+        .query::<&BlockColor>()
+        .iter(&world)
+        .any(|block_color| *block_color == BlockColor::INDIGO));
 
-    update_stage.run(&mut world);
-    assert!(world.get::<Block>(block).is_none());
+    assert_eq!(
+        block_colors(&ColorCount(5), &ColorTable::default()).len(),
+        5
+    );
+    assert!(!block_colors(&ColorCount(5), &ColorTable::default()).contains(&BlockColor::INDIGO));
 }
 
 #[test]
-fn test_despawn_block_add_chain() {
+fn test_swap_animation_reaches_target() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(despawn_block.system());
-    let time = Time::default();
-    world.insert_resource(time);
+    update_stage.add_system(slide_swapping_block.system());
+    update_stage.add_system(move_block.system().label("move_block"));
+    update_stage.add_system(moving_to_fixed.system().after("move_block"));
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(TuningConfig::default());
 
-    world
+    let left_block = world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::ZERO,
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Despawining(Timer::from_seconds(0.0, false)));
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(0.0, BLOCK_SIZE, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
+        .insert(Transform::from_xyz(-BLOCK_SIZE / 2.0, 0.0, 0.0))
+        .insert(Move(BLOCK_SIZE / 2.0))
+        .id();
+    let right_block = world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(0.0, BLOCK_SIZE * 3.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-
-    world
+        .insert(Transform::from_xyz(BLOCK_SIZE / 2.0, 0.0, 0.0))
+        .insert(Move(-BLOCK_SIZE / 2.0))
+        .id();
+    // nothing to swap with, it still slides into the empty cell
+    let lone_block = world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(0.0, BLOCK_SIZE * -1.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
+        .insert(Transform::from_xyz(-BLOCK_SIZE / 2.0, BLOCK_SIZE, 0.0))
+        .insert(Move(BLOCK_SIZE / 2.0))
+        .id();
 
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Chain)>().iter(&world).len(), 1);
+    assert!(world.get::<Moving>(lone_block).is_some());
+    for _ in 0..20 {
+        update_stage.run(&mut world);
+    }
+    for (entity, target_x) in [
+        (left_block, BLOCK_SIZE / 2.0),
+        (right_block, -BLOCK_SIZE / 2.0),
+        (lone_block, BLOCK_SIZE / 2.0),
+    ] {
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation.x,
+            target_x
+        );
+        assert!(world.get::<Fixed>(entity).is_some());
+        assert!(world.get::<Moving>(entity).is_none());
+    }
 }
 
 #[test]
-fn test_check_fall_block() {
+fn test_swap_animation_duration() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(check_fall_block.system());
-    world
+    update_stage.add_system(slide_swapping_block.system());
+    update_stage.add_system(move_block.system().label("move_block"));
+    update_stage.add_system(moving_to_fixed.system().after("move_block"));
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(TuningConfig {
+        swap_seconds: SWAP_SECONDS * 4.0,
+    });
+    let block = world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
-    update_stage.run(&mut world);
+        .insert(Transform::from_xyz(-BLOCK_SIZE / 2.0, 0.0, 0.0))
+        .insert(Move(BLOCK_SIZE / 2.0))
+        .id();
+
+    // the default swap would be done by now
+    let default_steps = (SWAP_SECONDS / FIXED_STEP_SECONDS).ceil() as usize;
+    for _ in 0..=default_steps {
+        update_stage.run(&mut world);
+    }
+    assert!(world.get::<Moving>(block).is_some());
+    assert!(world.get::<Transform>(block).unwrap().translation.x < BLOCK_SIZE / 2.0);
+
+    for _ in 0..default_steps * 4 {
+        update_stage.run(&mut world);
+    }
+    assert!(world.get::<Moving>(block).is_none());
     assert_eq!(
-        world.query::<(&Block, &FallPrepare)>().iter(&world).len(),
-        1
+        world.get::<Transform>(block).unwrap().translation.x,
+        BLOCK_SIZE / 2.0
     );
 }
 
 #[test]
-fn test_check_fall_block_there_isnot_between_block() {
+fn test_move_cursor_two_player() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(check_fall_block.system());
-    world
+    update_stage.add_system(move_cursor.system());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(InputTuning::default());
+    let player_one = world
         .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
+        .insert(Cursor)
+        .insert(PlayerControls::player_one())
+        .insert(Transform::default())
+        .id();
+    let player_two = world
         .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
+        .insert(Cursor)
+        .insert(PlayerControls::player_two())
+        .insert(Transform::default())
+        .id();
+    let com = world
         .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 3);
+        .insert(Cursor)
+        .insert(AiController::default())
+        .insert(Transform::default())
+        .id();
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::A);
+    input.press(KeyCode::Up);
+    world.insert_resource(input);
     update_stage.run(&mut world);
     assert_eq!(
-        world.query::<(&Block, &FallPrepare)>().iter(&world).len(),
-        1
+        world.get::<Transform>(player_one).unwrap().translation,
+        Vec3::new(-BLOCK_SIZE, 0.0, 0.0)
     );
+    assert_eq!(
+        world.get::<Transform>(player_two).unwrap().translation,
+        Vec3::new(0.0, BLOCK_SIZE, 0.0)
+    );
+    assert_eq!(world.get::<Transform>(com).unwrap().translation, Vec3::ZERO);
 }
 
 #[test]
-fn test_check_fall_block_there_is_between_block() {
+fn test_check_game_over_two_player() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(State::new(AppState::InGame));
+    world.insert_resource(FixedStep::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(check_fall_block.system());
-    world
+    update_stage.add_system(check_game_over.system());
+    let board_one = world.spawn().insert(Board).insert(Player::One).id();
+    let board_two = world.spawn().insert(Board).insert(Player::Two).id();
+    let game_over_y = BoardConfig::default().game_over_y();
+    for (board, y) in [(board_one, game_over_y + 1.0), (board_two, 0.0)] {
+        let block = world
+            .spawn()
+            .insert(Block)
+            .insert(Fixed)
+            .insert(Transform::from_xyz(0.0, y, 0.0))
+            .id();
+        world.entity_mut(board).push_children(&[block]);
+    }
+
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<Winner>(), Some(&Winner(Player::Two)));
+}
+
+#[test]
+fn test_check_game_over_vs_com() {
+    let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(FixedStep::default());
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(check_game_over.system());
+    let player_board = world.spawn().insert(Board).insert(Player::One).id();
+    world.spawn().insert(Board).insert(Player::Com);
+    let block = world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
+        .insert(Fixed)
+        .insert(Transform::from_xyz(
+            0.0,
+            BoardConfig::default().game_over_y() + 1.0,
+            0.0,
+        ))
+        .id();
+    world.entity_mut(player_board).push_children(&[block]);
+
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<Winner>(), Some(&Winner(Player::Com)));
+}
+
+#[test]
+fn test_send_garbage() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(send_garbage.system());
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+    world.insert_resource(bevy::app::Events::<GarbageEvent>::default());
+    world.insert_resource(IncomingGarbage::default());
+
+    let board = world.spawn().insert(Board).id();
+    let opponent = world.spawn().insert(Board).id();
     world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
+        .get_resource_mut::<bevy::app::Events<ClearEvent>>()
+        .unwrap()
+        .send(ClearEvent {
+            board: Some(board),
+            entities: Vec::new(),
+            combo: 4,
+            chain: 2,
+            centroid: Vec3::ZERO,
+        });
+    update_stage.run(&mut world);
+
+    let garbage_events = world
+        .get_resource::<bevy::app::Events<GarbageEvent>>()
+        .unwrap();
+    let mut reader = garbage_events.get_reader();
+    assert_eq!(
+        reader.iter(garbage_events).copied().collect::<Vec<_>>(),
+        vec![
+            GarbageEvent {
+                target_board: opponent,
+                width: 3,
+                height: 1,
             },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
+            GarbageEvent {
+                target_board: opponent,
+                width: BOARD_WIDTH,
+                height: 1,
             },
-            ..Default::default()
-        })
-        .insert(Fixed);
+        ]
+    );
+}
+
+#[test]
+fn test_garbage_only_in_versus_modes() {
+    for (game_mode, sends) in [
+        (GameMode::Endless, false),
+        (GameMode::ScoreAttack, false),
+        (GameMode::VsCom, true),
+        (GameMode::TwoPlayer, true),
+    ] {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system_run_criteria((|| ShouldRun::Yes).system().label("always"));
+        update_stage.add_system_set(
+            SystemSet::new()
+                .with_run_criteria(RunCriteria::pipe("always", versus_mode_criteria.system()))
+                .with_system(send_garbage.system()),
+        );
+        world.insert_resource(game_mode);
+        world.insert_resource(BoardConfig::default());
+        world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+        world.insert_resource(bevy::app::Events::<GarbageEvent>::default());
+        world.insert_resource(IncomingGarbage::default());
+
+        // two boards even outside versus, so only the mode keeps the garbage back
+        let board = world.spawn().insert(Board).id();
+        world.spawn().insert(Board);
+        world
+            .get_resource_mut::<bevy::app::Events<ClearEvent>>()
+            .unwrap()
+            .send(ClearEvent {
+                board: Some(board),
+                entities: Vec::new(),
+                combo: 4,
+                chain: 2,
+                centroid: Vec3::ZERO,
+            });
+        update_stage.run(&mut world);
+
+        let garbage_events = world
+            .get_resource::<bevy::app::Events<GarbageEvent>>()
+            .unwrap();
+        let mut reader = garbage_events.get_reader();
+        assert_eq!(reader.iter(garbage_events).count() > 0, sends);
+    }
+}
+
+#[test]
+fn test_score_attack_clock_only_in_score_attack() {
+    for (game_mode, ends) in [
+        (GameMode::Endless, false),
+        (GameMode::VsCom, false),
+        (GameMode::TwoPlayer, false),
+        (GameMode::Puzzle, false),
+        (GameMode::Sandbox, false),
+        (GameMode::ScoreAttack, true),
+    ] {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system_run_criteria((|| ShouldRun::Yes).system().label("always"));
+        update_stage.add_system_set(
+            SystemSet::new()
+                .with_run_criteria(RunCriteria::pipe("always", score_attack_criteria.system()))
+                .with_system(end_score_attack.system()),
+        );
+        world.insert_resource(game_mode);
+        world.insert_resource(PlayTime(SCORE_ATTACK_SECONDS - 1.0));
 
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 4);
-    update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
+        update_stage.run(&mut world);
+        assert!(world.get_resource::<GameOverFinish>().is_none());
+
+        world.insert_resource(PlayTime(SCORE_ATTACK_SECONDS));
+        update_stage.run(&mut world);
+        assert_eq!(world.get_resource::<GameOverFinish>().is_some(), ends);
+    }
 }
 
 #[test]
-fn test_check_fall_block_there_is_start_block_move() {
+fn test_send_garbage_cancels_incoming() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(check_fall_block.system());
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Move(BLOCK_SIZE / 2.0));
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Move(BLOCK_SIZE / 2.0));
+    update_stage.add_system(send_garbage.system());
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(bevy::app::Events::<ClearEvent>::default());
+    world.insert_resource(bevy::app::Events::<GarbageEvent>::default());
 
+    let board = world.spawn().insert(Board).id();
+    world.spawn().insert(Board);
+    let pending = GarbageSpec {
+        width: 3,
+        height: 1,
+        due: GARBAGE_DELAY_SECONDS,
+    };
+    let mut incoming = IncomingGarbage::default();
+    incoming.0.insert(board, VecDeque::from(vec![pending]));
+    world.insert_resource(incoming);
     world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
+        .get_resource_mut::<bevy::app::Events<ClearEvent>>()
+        .unwrap()
+        .send(ClearEvent {
+            board: Some(board),
+            entities: Vec::new(),
+            combo: 4,
+            chain: 2,
+            centroid: Vec3::ZERO,
+        });
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
+
+    // the combo piece cancels the pending one and only the chain piece is sent
+    assert!(world.get_resource::<IncomingGarbage>().unwrap().0[&board].is_empty());
+    let garbage_events = world
+        .get_resource::<bevy::app::Events<GarbageEvent>>()
+        .unwrap();
+    let mut reader = garbage_events.get_reader();
+    assert_eq!(
+        reader
+            .iter(garbage_events)
+            .map(|garbage_event| garbage_event.width)
+            .collect::<Vec<_>>(),
+        vec![BOARD_WIDTH]
+    );
 }
 
 #[test]
-fn test_check_fall_block_there_is_between_block_move() {
+fn test_match_preview_in_puzzle_mode() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(check_fall_block.system());
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -5.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - BLOCK_SIZE + 1.0, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 - 1.0, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
+    update_stage.add_system(setup_run_resources.system());
+    world.insert_resource(ColorTable::default());
+    for (game_mode, match_preview) in [(GameMode::Puzzle, true), (GameMode::Endless, false)] {
+        world.insert_resource(game_mode);
+        update_stage.run(&mut world);
+        assert_eq!(
+            world.get_resource::<MatchPreview>().unwrap().0,
+            match_preview
+        );
+    }
+}
+
+#[test]
+fn test_queued_garbage_drops_after_delay() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(queue_garbage.system().label("queue_garbage"));
+    update_stage.add_system(spawn_garbage.system().after("queue_garbage"));
+    world.insert_resource(bevy::app::Events::<GarbageEvent>::default());
+    world.insert_resource(IncomingGarbage::default());
+    world.insert_resource(PlayTime(10.0));
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(ColorCount::default());
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(BoardRng::new(None));
+    // the materials store only comes with the asset plugin
+    let mut app = App::build();
+    app.add_plugin(bevy::core::CorePlugin)
+        .add_plugin(bevy::asset::AssetPlugin)
+        .add_asset::<ColorMaterial>();
+    world.insert_resource(
+        app.world_mut()
+            .remove_resource::<Assets<ColorMaterial>>()
+            .unwrap(),
+    );
 
+    let board = world.spawn().insert(Board).id();
     world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0 + BLOCK_SIZE, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 4);
+        .get_resource_mut::<bevy::app::Events<GarbageEvent>>()
+        .unwrap()
+        .send(GarbageEvent {
+            target_board: board,
+            width: 3,
+            height: 1,
+        });
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
+    assert_eq!(
+        world.get_resource::<IncomingGarbage>().unwrap().0[&board],
+        [GarbageSpec {
+            width: 3,
+            height: 1,
+            due: 10.0 + GARBAGE_DELAY_SECONDS,
+        }]
+    );
+
+    // still waiting just before it's due
+    world.get_resource_mut::<PlayTime>().unwrap().0 = 10.0 + GARBAGE_DELAY_SECONDS - 0.5;
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<&Garbage>().iter(&world).len(), 0);
+
+    world.get_resource_mut::<PlayTime>().unwrap().0 = 10.0 + GARBAGE_DELAY_SECONDS;
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<IncomingGarbage>().unwrap().0[&board].is_empty());
+    let cells = world
+        .query_filtered::<&Parent, (With<Garbage>, With<Fall>)>()
+        .iter(&world)
+        .map(|parent| parent.0)
+        .collect::<Vec<_>>();
+    assert_eq!(cells, [board; 3]);
 }
 
 #[test]
-fn test_check_fall_block_bottom_block_not_fall() {
+fn test_garbage_falls_as_one_piece() {
     let mut world = World::default();
+    world.insert_resource(BoardConfig::default());
     let mut update_stage = SystemStage::parallel();
     update_stage.add_system(check_fall_block.system());
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * -6.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    let board_config = BoardConfig::default();
+    let spawn_cell = |world: &mut World, col: u8, row: u8| {
+        world
+            .spawn()
+            .insert(Block)
+            .insert(Fixed)
+            .insert(Transform::from_translation(
+                GridPos { col, row }.to_translation(0.0, &board_config),
+            ))
+            .id()
+    };
+    // a 2-wide piece on row 1 with a block only under its left cell
+    spawn_cell(&mut world, 0, 0);
+    let cells = vec![spawn_cell(&mut world, 0, 1), spawn_cell(&mut world, 1, 1)];
+    for cell in cells.iter() {
+        world.entity_mut(*cell).insert(Garbage {
+            cells: cells.clone(),
+            color: BlockColor::RED,
+        });
+    }
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    assert_eq!(world.query::<&FallPrepare>().iter(&world).len(), 0);
+
+    // without that block the whole piece falls
+    let support = world
+        .query_filtered::<Entity, Without<Garbage>>()
+        .iter(&world)
+        .next()
+        .unwrap();
+    world.despawn(support);
+    update_stage.run(&mut world);
+    for cell in cells {
+        assert!(world.get::<FallPrepare>(cell).is_some());
+    }
 }
 
 #[test]
-fn test_fall_upward() {
+fn test_clear_adjacent_garbage() {
     let mut world = World::default();
+    world.insert_resource(ColorCount::default());
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(BoardRng::new(Some(&GameSeed(0))));
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(bevy::app::Events::<BlockCleared>::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(fall_upward.system());
+    update_stage.add_system(clear_adjacent_garbage.system());
+    let spawn_cell = |world: &mut World, col: u8, row: u8| {
+        world
+            .spawn()
+            .insert(Block)
+            .insert(Fixed)
+            .insert(GridPos { col, row })
+            .id()
+    };
+    // a 2x2 red piece on the left with blocks cleared on its right
+    let cells = vec![
+        spawn_cell(&mut world, 0, 0),
+        spawn_cell(&mut world, 1, 0),
+        spawn_cell(&mut world, 0, 1),
+        spawn_cell(&mut world, 1, 1),
+    ];
+    for cell in cells.iter() {
+        world.entity_mut(*cell).insert(Garbage {
+            cells: cells.clone(),
+            color: BlockColor::RED,
+        });
+    }
+    let clear = |world: &mut World, color: BlockColor, row: u8| {
+        world
+            .get_resource_mut::<bevy::app::Events<BlockCleared>>()
+            .unwrap()
+            .send(BlockCleared {
+                board: None,
+                grid_pos: GridPos { col: 2, row },
+                color,
+            });
+    };
 
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(FallPrepare);
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
+    // a different color leaves the garbage alone
+    clear(&mut world, BlockColor::GREEN, 0);
+    update_stage.run(&mut world);
+    assert_eq!(world.query::<&Garbage>().iter(&world).len(), 4);
 
+    world
+        .get_resource_mut::<bevy::app::Events<BlockCleared>>()
+        .unwrap()
+        .update();
+    clear(&mut world, BlockColor::RED, 1);
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Floating)>().iter(&world).len(), 2);
+    for cell in &cells[..2] {
+        assert!(world.get::<Garbage>(*cell).is_none());
+        assert!(world.get::<BlockColor>(*cell).is_some());
+        assert!(world.get::<Chain>(*cell).is_some());
+    }
+    for cell in &cells[2..] {
+        assert_eq!(world.get::<Garbage>(*cell).unwrap().cells, cells[2..]);
+    }
 }
 
 #[test]
-fn test_fall_upward_divide() {
+fn test_clear_beside_garbage_peels_a_layer() {
     let mut world = World::default();
+    world.insert_resource(ColorCount::default());
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(BoardRng::new(Some(&GameSeed(0))));
+    insert_asset_fixtures(&mut world);
+    world.insert_resource(TimeScale::default());
+    world.insert_resource(FixedStep::default());
+    world.insert_resource(bevy::app::Events::<BlockCleared>::default());
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(fall_upward.system());
-
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(FallPrepare);
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * 3.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
+    update_stage.add_system(despawn_block.system().label("despawn_block"));
+    update_stage.add_system(clear_adjacent_garbage.system().after("despawn_block"));
+
+    // a 3-wide, 2-tall blue piece on rows 1 and 2, a blue match clearing below it
+    let mut pieces = Vec::new();
+    for row in 1..3 {
+        for col in 0..3 {
+            let cell = world
+                .spawn()
+                .insert(Block)
+                .insert(Transform::from_xyz(
+                    BLOCK_SIZE * col as f32,
+                    BLOCK_SIZE * row as f32,
+                    0.0,
+                ))
+                .insert(Fixed)
+                .insert(GridPos { col, row })
+                .id();
+            pieces.push(cell);
+        }
+    }
+    for cell in pieces.iter() {
+        world.entity_mut(*cell).insert(Garbage {
+            cells: pieces.clone(),
+            color: BlockColor::BLUE,
+        });
+    }
+    for col in 0..3 {
+        world
+            .spawn()
+            .insert(Block)
+            .insert(Transform::from_xyz(BLOCK_SIZE * col as f32, 0.0, 0.0))
+            .insert(BlockColor::BLUE)
+            .insert(GridPos { col, row: 0 })
+            .insert(Despawining(Timer::from_seconds(0.0, false)));
+    }
 
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Floating)>().iter(&world).len(), 2);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    assert_eq!(world.query::<&Despawining>().iter(&world).len(), 0);
+    for cell in &pieces[..3] {
+        assert!(world.get::<Garbage>(*cell).is_none());
+        assert!(world.get::<BlockColor>(*cell).is_some());
+        assert!(world.get::<Fixed>(*cell).is_some());
+        assert!(world.get::<Chain>(*cell).is_some());
+    }
+    for cell in &pieces[3..] {
+        assert_eq!(world.get::<Garbage>(*cell).unwrap().cells, pieces[3..]);
+    }
 }
 
 #[test]
-fn test_floating_to_fall() {
+fn test_animate_despawning() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(floating_to_fall.system());
-
-    let time = Time::default();
-    world.insert_resource(time);
-    world
+    update_stage.add_system(animate_despawning.system());
+    world.insert_resource(ReduceFlashing::default());
+    let mut timer = Timer::from_seconds(1.0, false);
+    timer.set_elapsed(Duration::from_secs_f32(0.1));
+    let block = world
         .spawn()
         .insert(Block)
-        .insert(Floating(Timer::from_seconds(0.0, false)));
+        .insert(Despawining(timer))
+        .insert(Visible::default())
+        .insert(Transform::default())
+        .id();
 
+    // flashing first
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 1);
-}
+    assert!(!world.get::<Visible>(block).unwrap().is_visible);
+    assert!(world.get::<DespawnScale>(block).is_none());
 
-#[test]
-fn test_stop_fall_block() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(stop_fall_block.system());
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 99.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fall);
+    // then shrinking over the rest of the timer
     world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 50.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
+        .get_mut::<Despawining>(block)
+        .unwrap()
+        .0
+        .set_elapsed(Duration::from_secs_f32(0.8));
+    update_stage.run(&mut world);
+    assert!(world.get::<Visible>(block).unwrap().is_visible);
+    assert!(world.get::<EasingComponent<DespawnScale>>(block).is_some());
 
-    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 1);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
+    world.get_mut::<DespawnScale>(block).unwrap().0 = 0.5;
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 0);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
     assert_eq!(
-        world.query::<(&Block, &FixedPrepare)>().iter(&world).len(),
-        1
+        world.get::<Transform>(block).unwrap().scale,
+        Vec3::splat(0.5)
     );
 }
 
 #[test]
-fn test_fixedprepare_to_fixed() {
+fn test_animate_despawning_reduce_flashing() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(fixedprepare_to_fixed.system());
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(FixedPrepare);
-    world
+    update_stage.add_system(animate_despawning.system());
+    world.insert_resource(ReduceFlashing(true));
+    let mut timer = Timer::from_seconds(1.0, false);
+    timer.set_elapsed(Duration::from_secs_f32(0.1));
+    let block = world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fall);
+        .insert(Despawining(timer))
+        .insert(Visible::default())
+        .insert(Transform::default())
+        .id();
+
+    // steady through the whole flash portion
+    update_stage.run(&mut world);
+    assert!(world.get::<Visible>(block).unwrap().is_visible);
     world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * 3.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fall);
+        .get_mut::<Despawining>(block)
+        .unwrap()
+        .0
+        .set_elapsed(Duration::from_secs_f32(0.2));
     update_stage.run(&mut world);
-    assert_eq!(
-        world.query::<(&Block, &FixedPrepare)>().iter(&world).len(),
-        0
-    );
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 2);
-    assert_eq!(world.query::<(&Block, &Fall)>().iter(&world).len(), 1);
+    assert!(world.get::<Visible>(block).unwrap().is_visible);
+    assert!(world.get::<DespawnScale>(block).is_none());
 }
 
 #[test]
-fn test_auto_liftup() {
+fn test_squash_landed_block() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(auto_liftup.system());
-    let app_state = State::new(AppState::InGame);
-    world.insert_resource(app_state);
-    let mut time = Time::default();
-    time.update();
-    world.insert_resource(time);
-    world
-        .spawn()
-        .insert(CountTimer(Timer::from_seconds(0.0, false)));
-
+    update_stage.add_system(squash_landed_block.system());
     let block = world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
+        .insert(Transform::from_xyz(0.0, 10.0, 0.0))
+        .insert(LandingSquash(0.5))
+        .insert(LandingSquash(0.5).ease_to(
+            LandingSquash(0.0),
+            EaseFunction::QuadraticOut,
+            EasingType::Once {
+                duration: Duration::from_secs_f32(LANDING_SQUASH_SECONDS),
             },
-            ..Default::default()
-        })
-        .insert(Fixed)
+        ))
         .id();
-    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
 
-    world.get_resource_mut::<Time>().unwrap().update();
     update_stage.run(&mut world);
-    assert_ne!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+    let transform = *world.get::<Transform>(block).unwrap();
+    assert!(transform.scale.x > 1.0 && transform.scale.y < 1.0);
+    assert_eq!(transform.translation, Vec3::new(0.0, 10.0, 0.0));
+
+    world
+        .entity_mut(block)
+        .remove::<EasingComponent<LandingSquash>>();
+    update_stage.run(&mut world);
+    assert_eq!(world.get::<Transform>(block).unwrap().scale, Vec3::ONE);
+    assert!(world.get::<LandingSquash>(block).is_none());
 }
 
 #[test]
-fn test_auto_liftup_stop_with_timer() {
+fn test_update_chain_text() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(auto_liftup.system());
-    let app_state = State::new(AppState::InGame);
-    world.insert_resource(app_state);
-    let mut time = Time::default();
-    time.update();
-    world.insert_resource(time);
-    world
-        .spawn()
-        .insert(CountTimer(Timer::from_seconds(1.0, false)));
-
-    let block = world
+    update_stage.add_system(update_chain_text.system());
+    let board = world.spawn().insert(Board).id();
+    let chain_counter = world.spawn().insert(ChainCounter(3)).id();
+    let chain_text = world
         .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed)
+        .insert(ChainText(1))
+        .insert(Text::with_section(
+            "",
+            TextStyle::default(),
+            TextAlignment::default(),
+        ))
+        .insert(HudElement { shown: false })
+        .insert(Transform::default())
         .id();
-    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+    world
+        .entity_mut(board)
+        .push_children(&[chain_counter, chain_text]);
 
-    world.get_resource_mut::<Time>().unwrap().update();
     update_stage.run(&mut world);
-    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+    assert!(world.get::<HudElement>(chain_text).unwrap().shown);
+    assert_eq!(
+        world.get::<Text>(chain_text).unwrap().sections[0].value,
+        "x3"
+    );
+    assert!(world
+        .get::<EasingComponent<Transform>>(chain_text)
+        .is_some());
+
+    world.get_mut::<ChainCounter>(chain_counter).unwrap().0 = 1;
+    update_stage.run(&mut world);
+    assert!(!world.get::<HudElement>(chain_text).unwrap().shown);
 }
 
 #[test]
-fn test_auto_liftup_stop_with_fall_block() {
+fn test_prepare_despawn_block_clear_event() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(auto_liftup.system());
-    let app_state = State::new(AppState::InGame);
-    world.insert_resource(app_state);
-    let mut time = Time::default();
-    time.update();
-    world.insert_resource(time);
-    world
-        .spawn()
-        .insert(CountTimer(Timer::from_seconds(0.0, false)));
+    update_stage.add_system(prepare_despawn_block.system());
+    world.insert_resource(bevy::app::Events::<ClearEvent>::default());
 
-    let block = world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, 0.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fall)
-        .id();
-    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
-    world.get_resource_mut::<Time>().unwrap().update();
+    world.spawn().insert(ChainCounter(1));
+    let mut entities = Vec::new();
+    for x in 0..4 {
+        let block = world
+            .spawn()
+            .insert(Block)
+            .insert(Transform::from_xyz(x as f32 * BLOCK_SIZE, BLOCK_SIZE, 0.0))
+            .insert(Matched)
+            .id();
+        entities.push(block);
+    }
     update_stage.run(&mut world);
-    assert_eq!(world.get::<Transform>(block).unwrap().translation.y, 0.0);
+
+    let clear_events = world
+        .get_resource::<bevy::app::Events<ClearEvent>>()
+        .unwrap();
+    let mut reader = clear_events.get_reader();
+    let mut clears = reader.iter(clear_events).cloned().collect::<Vec<_>>();
+    for clear in clears.iter_mut() {
+        clear.entities.sort();
+    }
+    entities.sort();
+    assert_eq!(
+        clears,
+        vec![ClearEvent {
+            board: None,
+            entities,
+            combo: 4,
+            chain: 1,
+            centroid: Vec3::new(1.5 * BLOCK_SIZE, BLOCK_SIZE, 0.0),
+        }]
+    );
 }
 
-#[ignore = "how to change state?"]
 #[test]
-fn test_auto_liftup_gameover() {
+fn test_fade_combo_popup() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(auto_liftup.system());
-    let app_state = State::new(AppState::InGame);
-    world.insert_resource(app_state);
-    let mut time = Time::default();
-    time.update();
-    world.insert_resource(time);
-    world
+    update_stage.add_system(fade_combo_popup.system());
+    world.insert_resource(FixedStep::default());
+    let popup = world
         .spawn()
-        .insert(CountTimer(Timer::from_seconds(0.0, false)));
+        .insert(ComboPopup(Timer::from_seconds(0.05, false)))
+        .insert(Text::with_section(
+            "4 Combo!",
+            TextStyle::default(),
+            TextAlignment::default(),
+        ))
+        .id();
 
-    world
-        .spawn()
-        .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(BLOCK_SIZE / 2.0, BLOCK_SIZE * 5.0 + 0.1, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Fixed);
-    assert_eq!(
-        world.get_resource::<State<AppState>>().unwrap().current(),
-        &AppState::InGame
-    );
-    world.get_resource_mut::<Time>().unwrap().update();
+    world.insert_resource(FixedStep(0.02));
     update_stage.run(&mut world);
+    let alpha = world.get::<Text>(popup).unwrap().sections[0]
+        .style
+        .color
+        .a();
+    assert!(alpha < 1.0 && alpha > 0.0);
 
-    assert_eq!(
-        world.get_resource::<State<AppState>>().unwrap().current(),
-        &AppState::GameOver
-    );
+    world.insert_resource(FixedStep(0.05));
+    update_stage.run(&mut world);
+    assert!(world.get_entity(popup).is_none());
 }
 
 #[test]
-fn test_spawning_to_fixed() {
+fn test_spawn_block_symbols() {
     let mut world = World::default();
     let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(spawning_to_fixed.system());
+    update_stage.add_system(spawn_block_symbols.system());
+    world.insert_resource(AccessibilitySettings::default());
+    world.insert_resource(FontAssets {
+        font: Handle::default(),
+    });
+    world.insert_resource(BoardConfig::default());
     world
         .spawn()
         .insert(Block)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(0.0, BLOCK_SIZE * -5.9, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Spawning);
-    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 1);
+        .insert(BlockColor::RED)
+        .insert(Transform::default());
+
     update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Fixed)>().iter(&world).len(), 1);
-    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 0);
-}
+    assert_eq!(world.query::<&BlockSymbol>().iter(&world).count(), 0);
 
-#[test]
-fn test_bottom_down() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(bottom_down.system());
-    let bottom = world
+    world
+        .get_resource_mut::<AccessibilitySettings>()
+        .unwrap()
+        .symbols = true;
+    let block = world
         .spawn()
-        .insert(Bottom)
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 6.0, BLOCK_SIZE)),
-            transform: Transform {
-                translation: Vec3::new(0.0, -300.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
+        .insert(Block)
+        .insert(BlockColor::BLUE)
+        .insert(Transform::default())
         .id();
     update_stage.run(&mut world);
+    let symbols = world
+        .query::<(&Text, &Parent)>()
+        .iter(&world)
+        .map(|(text, parent)| (text.sections[0].value.clone(), parent.0))
+        .collect::<Vec<_>>();
     assert_eq!(
-        world.get::<Transform>(bottom).unwrap().translation.y,
-        -350.0
+        symbols,
+        vec![(block_symbol(BlockColor::BLUE).to_string(), block)]
     );
-}
 
-#[test]
-fn test_generate_spawning_block() {
-    let mut world = World::default();
-    let mut update_stage = SystemStage::parallel();
-    update_stage.add_system(generate_spawning_block.system());
-    world.insert_resource(BlockMaterials {
-        red_material: Handle::<ColorMaterial>::default(),
-        green_material: Handle::<ColorMaterial>::default(),
-        blue_material: Handle::<ColorMaterial>::default(),
-        yellow_material: Handle::<ColorMaterial>::default(),
-        purple_material: Handle::<ColorMaterial>::default(),
-        indigo_material: Handle::<ColorMaterial>::default(),
-    });
-    world.spawn().insert(Board).insert_bundle(SpriteBundle {
-        ..Default::default()
-    });
-    world.spawn().insert(Bottom).insert_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(BLOCK_SIZE * 6.0, BLOCK_SIZE)),
-        transform: Transform {
-            translation: Vec3::new(0.0, -300.0, 0.0),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    update_stage.run(&mut world);
-    assert_eq!(world.query::<(&Block, &Spawning)>().iter(&world).len(), 6);
+    // every color gets its own symbol
+    let mut all_symbols = BLOCK_COLORS
+        .iter()
+        .map(|block_color| block_symbol(*block_color))
+        .collect::<Vec<_>>();
+    all_symbols.sort_unstable();
+    all_symbols.dedup();
+    assert_eq!(all_symbols.len(), BLOCK_COLORS.len());
 }
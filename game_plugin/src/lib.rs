@@ -1,28 +1,86 @@
+mod controls;
+mod custom;
+mod debug;
+mod gameover;
+mod hud;
 mod ingame;
 mod loading;
 mod menu;
+mod music;
+mod pause;
+mod persistence;
+mod puzzle;
+mod replay;
+mod settings;
+mod stats;
+use bevy::ecs::schedule::StateError;
 use bevy::prelude::AppBuilder;
 use bevy::prelude::*;
+use bevy_kira_audio::AudioPlugin;
 
+use crate::controls::ControlsPlugin;
+use crate::custom::CustomGamePlugin;
+use crate::debug::DebugPlugin;
+use crate::gameover::GameOverPlugin;
+use crate::hud::HudPlugin;
 use crate::ingame::IngamePlugin;
 use crate::loading::LoadingPlugin;
 use crate::menu::MenuPlugin;
+use crate::music::MusicPlugin;
+use crate::pause::PausePlugin;
+use crate::replay::ReplayPlugin;
+use crate::settings::{
+    AssistMode, AssistSettings, ColorCount, Difficulty, GameMode, GameSettings, SquareClear, Theme,
+};
+use crate::stats::StatsPlugin;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum AppState {
     Menu,
     Loading,
+    CustomGame,
+    Controls,
     InGame,
+    Paused,
     GameOver,
 }
 
+// Bevy queues one state change per frame and refuses a second. When two systems ask on
+// the same frame, e.g. a puzzle clearing as its stack tops out, the first one wins;
+// false tells the others theirs was dropped.
+fn queue_state_change(result: Result<(), StateError>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(StateError::StateAlreadyQueued) => false,
+        Err(err) => panic!("state change failed: {}", err),
+    }
+}
+
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_state(AppState::Loading)
+            .init_resource::<GameSettings>()
+            .init_resource::<ColorCount>()
+            .init_resource::<Difficulty>()
+            .init_resource::<AssistMode>()
+            .init_resource::<AssistSettings>()
+            .init_resource::<SquareClear>()
+            .init_resource::<Theme>()
+            .init_resource::<GameMode>()
+            .add_plugin(AudioPlugin)
+            .add_plugin(DebugPlugin)
+            .add_plugin(HudPlugin)
+            .add_plugin(StatsPlugin)
             .add_plugin(LoadingPlugin)
             .add_plugin(MenuPlugin)
-            .add_plugin(IngamePlugin);
+            .add_plugin(MusicPlugin)
+            .add_plugin(CustomGamePlugin)
+            .add_plugin(ControlsPlugin)
+            .add_plugin(IngamePlugin)
+            .add_plugin(PausePlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(GameOverPlugin);
     }
 }
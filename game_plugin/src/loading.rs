@@ -1,12 +1,51 @@
 use bevy::prelude::*;
 use bevy_asset_loader::{AssetCollection, AssetLoader};
+use bevy_kira_audio::AudioSource;
 
-use crate::AppState;
+use crate::{
+    persistence,
+    puzzle::{Puzzle, PuzzleLoader},
+    settings::{
+        AccessibilitySettings, AudioSettings, AutoPause, InputTuning, KeyBindings, ReduceFlashing,
+    },
+    stats::HighScores,
+    AppState,
+};
+
+pub const AUDIO_SETTINGS_FILE: &str = "audio.ron";
+pub const GAME_SAVE_FILE: &str = "save.ron";
+pub const REPLAY_FILE: &str = "replay.ron";
+pub const HIGH_SCORES_FILE: &str = "high_scores.ron";
+pub const KEY_BINDINGS_FILE: &str = "keys.ron";
+const INPUT_TUNING_FILE: &str = "input.ron";
+const REDUCE_FLASHING_FILE: &str = "flashing.ron";
+const AUTO_PAUSE_FILE: &str = "auto_pause.ron";
+const ACCESSIBILITY_FILE: &str = "accessibility.ron";
 
 pub struct LoadingPlugin;
 
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut AppBuilder) {
+        // written back so there is a file to edit, with any missing actions filled in
+        let key_bindings = persistence::load::<KeyBindings>(KEY_BINDINGS_FILE).with_default_lift();
+        persistence::save(KEY_BINDINGS_FILE, &key_bindings);
+        let input_tuning = persistence::load::<InputTuning>(INPUT_TUNING_FILE);
+        persistence::save(INPUT_TUNING_FILE, &input_tuning);
+        let reduce_flashing = persistence::load::<ReduceFlashing>(REDUCE_FLASHING_FILE);
+        persistence::save(REDUCE_FLASHING_FILE, &reduce_flashing);
+        let auto_pause = persistence::load::<AutoPause>(AUTO_PAUSE_FILE);
+        persistence::save(AUTO_PAUSE_FILE, &auto_pause);
+        let accessibility = persistence::load::<AccessibilitySettings>(ACCESSIBILITY_FILE);
+        persistence::save(ACCESSIBILITY_FILE, &accessibility);
+        app.insert_resource(key_bindings)
+            .insert_resource(input_tuning)
+            .insert_resource(reduce_flashing)
+            .insert_resource(auto_pause)
+            .insert_resource(accessibility)
+            .insert_resource(persistence::load::<AudioSettings>(AUDIO_SETTINGS_FILE).clamped())
+            .insert_resource(persistence::load::<HighScores>(HIGH_SCORES_FILE))
+            .add_asset::<Puzzle>()
+            .init_asset_loader::<PuzzleLoader>();
         AssetLoader::new(AppState::Loading, AppState::Menu)
             .with_collection::<BlockMaterials>()
             .with_collection::<CursorMaterials>()
@@ -14,6 +53,9 @@ impl Plugin for LoadingPlugin {
             .with_collection::<BoardMaterials>()
             .with_collection::<BottomMaterials>()
             .with_collection::<FontAssets>()
+            .with_collection::<SoundAssets>()
+            .with_collection::<MusicAssets>()
+            .with_collection::<PuzzleAssets>()
             .build(app);
     }
 }
@@ -24,6 +66,28 @@ pub struct FontAssets {
     pub font: Handle<Font>,
 }
 
+#[derive(AssetCollection)]
+pub struct SoundAssets {
+    #[asset(path = "audio/swap.ogg")]
+    pub swap_sound: Handle<AudioSource>,
+    #[asset(path = "audio/match.ogg")]
+    pub match_sound: Handle<AudioSource>,
+    #[asset(path = "audio/chain.ogg")]
+    pub chain_sound: Handle<AudioSource>,
+}
+
+#[derive(AssetCollection)]
+pub struct MusicAssets {
+    #[asset(path = "audio/bgm.ogg")]
+    pub bgm: Handle<AudioSource>,
+}
+
+#[derive(AssetCollection)]
+pub struct PuzzleAssets {
+    #[asset(path = "puzzles/first.puzzle")]
+    pub puzzle: Handle<Puzzle>,
+}
+
 #[derive(AssetCollection)]
 pub struct BlockMaterials {
     #[asset(color_material)]
@@ -44,6 +108,8 @@ pub struct BlockMaterials {
     #[asset(color_material)]
     #[asset(path = "images/indigo_block.png")]
     pub indigo_material: Handle<ColorMaterial>,
+    // copies of the art for `ColorTable` ids past the six above, made in game
+    pub extra_materials: Vec<Handle<ColorMaterial>>,
 }
 
 #[derive(AssetCollection)]
@@ -1,43 +1,1127 @@
-use crate::{loading::FontAssets, AppState};
-use bevy::prelude::*;
+use crate::{
+    ingame::{BoardConfig, ColorTable, GameSave, GameSeed, GameSpeed, PendingGameSave},
+    loading::{BlockMaterials, FontAssets, GAME_SAVE_FILE, REPLAY_FILE},
+    persistence, queue_state_change,
+    replay::{ReplayLog, ReplayPlayer},
+    settings::{
+        on_off, AssistMode, AssistSettings, ColorCount, Difficulty, GameMode, GameSettings, Theme,
+    },
+    AppState,
+};
+use bevy::{app::AppExit, ecs::system::SystemParam, prelude::*};
 
 pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system_set(SystemSet::on_enter(AppState::Menu).with_system(setup_menu.system()))
-            .add_system_set(SystemSet::on_update(AppState::Menu).with_system(go_to_game.system()));
+        app.add_event::<StartAttractDemo>()
+            .init_resource::<IdleTimer>()
+            .add_system(quit_game.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Menu)
+                    .with_system(setup_menu.system())
+                    .with_system(reset_idle_timer.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Menu)
+                    .with_system(go_to_game.system())
+                    .with_system(go_to_score_attack.system())
+                    .with_system(continue_game.system())
+                    .with_system(watch_replay.system())
+                    .with_system(start_attract_demo.system())
+                    .with_system(open_custom_game.system())
+                    .with_system(open_controls.system())
+                    .with_system(go_to_puzzle.system())
+                    .with_system(go_to_vs_com.system())
+                    .with_system(go_to_two_player.system())
+                    .with_system(go_to_sandbox.system())
+                    .with_system(select_difficulty.system())
+                    .with_system(select_theme.system())
+                    .with_system(toggle_swap_hints.system())
+                    .with_system(toggle_assist_mode.system())
+                    .with_system(track_idle_time.system()),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Menu).with_system(cleanup_menu.system()));
     }
 }
 
-fn setup_menu(mut commands: Commands, font_assets: Res<FontAssets>) {
+struct MenuUi;
+
+struct DifficultyText;
+
+struct ThemeText;
+
+struct SwapHintText;
+
+struct AssistModeText;
+
+// The beginner assists the menu switches.
+#[derive(SystemParam)]
+pub struct Assists<'a> {
+    assist_settings: Res<'a, AssistSettings>,
+    assist_mode: Res<'a, AssistMode>,
+}
+
+fn setup_menu(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    difficulty: Res<Difficulty>,
+    color_count: Res<ColorCount>,
+    theme: Res<Theme>,
+    block_materials: Res<BlockMaterials>,
+    assists: Assists,
+) {
     commands.spawn_bundle(UiCameraBundle::default());
-    commands.spawn_bundle(TextBundle {
-        text: Text {
-            sections: vec![TextSection {
-                value: "Press Space KEY!".to_string(),
-                style: TextStyle {
-                    font: font_assets.font.clone(),
-                    font_size: 40.0,
-                    color: Color::rgb(1.0, 1.0, 1.0),
-                },
-            }],
-            alignment: Default::default(),
-        },
-        ..Default::default()
-    });
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text {
+                sections: vec![TextSection {
+                    value: "Press Space KEY!".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 40.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(60.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: difficulty_label(&difficulty, &color_count),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(DifficultyText)
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(100.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "C: Custom game".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(140.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "P: Puzzle".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(180.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "V: VS COM".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(220.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "2: 2 Players (WASD+F / Arrows+Return)".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(260.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "K: Controls".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(300.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "S: Sandbox (1-6: Place block)".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(340.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "A: Score Attack (2 min)".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(380.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "L: Load saved game".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(420.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "R: Watch last replay".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(460.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "Escape: Quit".to_string(),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(500.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: theme_label(&theme),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(ThemeText)
+        .insert(MenuUi);
+    // one block of each color, drawn with the shared block materials so it follows the theme
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(540.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            // only the blocks inside are drawn
+            visible: Visible {
+                is_visible: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(MenuUi)
+        .with_children(|parent| {
+            for material in [
+                &block_materials.red_material,
+                &block_materials.green_material,
+                &block_materials.blue_material,
+                &block_materials.yellow_material,
+                &block_materials.purple_material,
+                &block_materials.indigo_material,
+            ] {
+                parent.spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(30.0), Val::Px(30.0)),
+                        margin: Rect::all(Val::Px(2.0)),
+                        ..Default::default()
+                    },
+                    material: material.clone(),
+                    ..Default::default()
+                });
+            }
+        });
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(580.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: swap_hints_label(&assists.assist_settings),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(SwapHintText)
+        .insert(MenuUi);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(620.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: assist_mode_label(&assists.assist_mode),
+                    style: TextStyle {
+                        font: font_assets.font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..Default::default()
+        })
+        .insert(AssistModeText)
+        .insert(MenuUi);
+}
+
+fn theme_label(theme: &Theme) -> String {
+    format!("T: Theme < {:?} >", theme)
+}
+
+fn select_theme(
+    input: Res<Input<KeyCode>>,
+    mut theme: ResMut<Theme>,
+    mut theme_text: Query<&mut Text, With<ThemeText>>,
+) {
+    if !input.just_pressed(KeyCode::T) {
+        return;
+    }
+    *theme = theme.next();
+    for mut text in theme_text.iter_mut() {
+        text.sections[0].value = theme_label(&theme);
+    }
+}
+
+fn swap_hints_label(assist_settings: &AssistSettings) -> String {
+    format!("H: Swap hints < {} >", on_off(assist_settings.show_hint))
+}
+
+fn toggle_swap_hints(
+    input: Res<Input<KeyCode>>,
+    mut assist_settings: ResMut<AssistSettings>,
+    mut hint_text: Query<&mut Text, With<SwapHintText>>,
+) {
+    if !input.just_pressed(KeyCode::H) {
+        return;
+    }
+    assist_settings.show_hint = !assist_settings.show_hint;
+    for mut text in hint_text.iter_mut() {
+        text.sections[0].value = swap_hints_label(&assist_settings);
+    }
+}
+
+fn assist_mode_label(assist_mode: &AssistMode) -> String {
+    format!("B: Longer chain window < {} >", on_off(assist_mode.0))
+}
+
+fn toggle_assist_mode(
+    input: Res<Input<KeyCode>>,
+    mut assist_mode: ResMut<AssistMode>,
+    mut assist_text: Query<&mut Text, With<AssistModeText>>,
+) {
+    if !input.just_pressed(KeyCode::B) {
+        return;
+    }
+    assist_mode.0 = !assist_mode.0;
+    for mut text in assist_text.iter_mut() {
+        text.sections[0].value = assist_mode_label(&assist_mode);
+    }
+}
+
+fn difficulty_label(difficulty: &Difficulty, color_count: &ColorCount) -> String {
+    format!("< {:?} ({} colors) >", difficulty, color_count.0)
+}
+
+// Set before any of the modes below start, so they all play at the chosen difficulty.
+fn select_difficulty(
+    input: Res<Input<KeyCode>>,
+    mut difficulty: ResMut<Difficulty>,
+    mut color_count: ResMut<ColorCount>,
+    mut difficulty_text: Query<&mut Text, With<DifficultyText>>,
+) {
+    if input.just_pressed(KeyCode::Left) {
+        *difficulty = difficulty.easier();
+    } else if input.just_pressed(KeyCode::Right) {
+        *difficulty = difficulty.harder();
+    } else {
+        return;
+    }
+    *color_count = difficulty.color_count();
+    for mut text in difficulty_text.iter_mut() {
+        text.sections[0].value = difficulty_label(&difficulty, &color_count);
+    }
+}
+
+// The resources a custom game may have changed, put back for a standard game.
+#[derive(SystemParam)]
+pub struct StandardGame<'a> {
+    board_config: ResMut<'a, BoardConfig>,
+    game_speed: ResMut<'a, GameSpeed>,
+    game_mode: ResMut<'a, GameMode>,
+    settings: ResMut<'a, GameSettings>,
+    difficulty: ResMut<'a, Difficulty>,
+    color_count: ResMut<'a, ColorCount>,
+    color_table: ResMut<'a, ColorTable>,
 }
 
+impl StandardGame<'_> {
+    fn set_up(&mut self, game_mode: GameMode) {
+        *self.board_config = BoardConfig::default();
+        // a custom game may have dealt more colors than the difficulty does
+        *self.color_count = self.difficulty.color_count();
+        *self.color_table = ColorTable::extended(self.color_count.0);
+        let defaults = GameSettings::default();
+        self.settings.start_layout = defaults.start_layout;
+        self.settings.column_guides = defaults.column_guides;
+        self.settings.game_over_line = defaults.game_over_line;
+        self.settings.fall_style = defaults.fall_style;
+        self.settings.top_out_grace = defaults.top_out_grace;
+        self.game_speed.origin = GameSpeed::default().origin;
+        *self.game_mode = game_mode;
+    }
+}
+
+// The standard game always goes back to the defaults for whatever a custom game set.
+// Every screen reached from here goes back on Escape, and only Escape on this menu
+// itself leaves the app, see `quit_game`.
 fn go_to_game(
     mut commands: Commands,
     input: Res<Input<KeyCode>>,
-    text: Query<Entity, With<Text>>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if input.just_pressed(KeyCode::Space) && queue_state_change(state.set(AppState::InGame)) {
+        standard_game.set_up(GameMode::Endless);
+        commands.remove_resource::<GameSeed>();
+    }
+}
+
+// A standard game against the clock.
+fn go_to_score_attack(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut standard_game: StandardGame,
     mut state: ResMut<State<AppState>>,
 ) {
-    if input.just_pressed(KeyCode::Space) {
-        for entity in text.iter() {
-            commands.entity(entity).despawn();
+    if input.just_pressed(KeyCode::A) && queue_state_change(state.set(AppState::InGame)) {
+        standard_game.set_up(GameMode::ScoreAttack);
+        commands.remove_resource::<GameSeed>();
+    }
+}
+
+// A save is good for one resume, so the file is emptied as soon as it is read.
+fn continue_game(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if !input.just_pressed(KeyCode::L) {
+        return;
+    }
+    let save = match persistence::load::<Option<GameSave>>(GAME_SAVE_FILE) {
+        Some(save) => save,
+        None => return,
+    };
+    if !queue_state_change(state.set(AppState::InGame)) {
+        return;
+    }
+    persistence::save(GAME_SAVE_FILE, &None::<GameSave>);
+    resume_game(&mut commands, &mut standard_game, save);
+}
+
+// The board and colors come from the save, the rest is a standard game.
+fn resume_game(commands: &mut Commands, standard_game: &mut StandardGame, save: GameSave) {
+    *standard_game.difficulty = save.difficulty;
+    standard_game.set_up(GameMode::Endless);
+    standard_game.board_config.width = save.width;
+    standard_game.board_config.height = save.height;
+    standard_game.color_count.0 = save.color_count;
+    *standard_game.color_table = ColorTable::extended(save.color_count);
+    commands.remove_resource::<GameSeed>();
+    commands.insert_resource(PendingGameSave(save));
+}
+
+// Sets up the standard game the replay was recorded in, with its seed and difficulty.
+fn watch_replay(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if input.just_pressed(KeyCode::R) {
+        play_last_replay(&mut commands, &mut standard_game, &mut state);
+    }
+}
+
+// The attract demo plays the last saved replay; with none saved the menu stays up.
+fn start_attract_demo(
+    mut commands: Commands,
+    mut start_attract_demo: EventReader<StartAttractDemo>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if start_attract_demo.iter().next().is_some() {
+        play_last_replay(&mut commands, &mut standard_game, &mut state);
+    }
+}
+
+fn play_last_replay(
+    commands: &mut Commands,
+    standard_game: &mut StandardGame,
+    state: &mut State<AppState>,
+) {
+    let log = persistence::load::<ReplayLog>(REPLAY_FILE);
+    if log.frames.is_empty() || !queue_state_change(state.set(AppState::InGame)) {
+        return;
+    }
+    // the colors follow the replay's difficulty
+    *standard_game.difficulty = log.difficulty;
+    standard_game.set_up(GameMode::Endless);
+    commands.insert_resource(GameSeed(log.seed));
+    commands.insert_resource(ReplayPlayer { log, frame: 0 });
+}
+
+// Puzzles are laid out for the default board.
+fn go_to_puzzle(
+    input: Res<Input<KeyCode>>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if input.just_pressed(KeyCode::P) && queue_state_change(state.set(AppState::InGame)) {
+        standard_game.set_up(GameMode::Puzzle);
+    }
+}
+
+fn open_custom_game(input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if input.just_pressed(KeyCode::C) && queue_state_change(state.set(AppState::CustomGame)) {}
+}
+
+fn open_controls(input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if input.just_pressed(KeyCode::K) && queue_state_change(state.set(AppState::Controls)) {}
+}
+
+// Versus play uses the standard board and the same speed for both sides.
+fn go_to_vs_com(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if input.just_pressed(KeyCode::V) && queue_state_change(state.set(AppState::InGame)) {
+        standard_game.set_up(GameMode::VsCom);
+        commands.remove_resource::<GameSeed>();
+    }
+}
+
+fn go_to_two_player(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if input.just_pressed(KeyCode::Key2) && queue_state_change(state.set(AppState::InGame)) {
+        standard_game.set_up(GameMode::TwoPlayer);
+        commands.remove_resource::<GameSeed>();
+    }
+}
+
+fn go_to_sandbox(
+    input: Res<Input<KeyCode>>,
+    mut standard_game: StandardGame,
+    mut state: ResMut<State<AppState>>,
+) {
+    if input.just_pressed(KeyCode::S) && queue_state_change(state.set(AppState::InGame)) {
+        standard_game.set_up(GameMode::Sandbox);
+    }
+}
+
+// Runs outside the state sets like `toggle_pause`, so the Escape that brings a submenu
+// back here is seen while the submenu is still current and doesn't quit as well.
+fn quit_game(
+    input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    if *state.current() == AppState::Menu && input.just_pressed(KeyCode::Escape) {
+        app_exit.send(AppExit);
+    }
+}
+
+fn cleanup_menu(mut commands: Commands, menu_ui: Query<Entity, With<MenuUi>>) {
+    for entity in menu_ui.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Sent once the menu has been idle for `GameSettings::idle_demo_timeout`.
+pub struct StartAttractDemo;
+
+#[derive(Default)]
+struct IdleTimer(f32);
+
+impl IdleTimer {
+    // returns true only on the update the timeout is reached
+    fn tick(&mut self, delta: f32, input_received: bool, timeout: f32) -> bool {
+        if input_received {
+            self.0 = 0.0;
+            return false;
         }
-        state.set(AppState::InGame).unwrap();
+        let was_due = self.is_due(timeout);
+        self.0 += delta;
+        !was_due && self.is_due(timeout)
+    }
+
+    fn is_due(&self, timeout: f32) -> bool {
+        timeout > 0.0 && self.0 >= timeout
     }
 }
+
+fn reset_idle_timer(mut idle_timer: ResMut<IdleTimer>) {
+    idle_timer.0 = 0.0;
+}
+
+fn track_idle_time(
+    time: Res<Time>,
+    input: Res<Input<KeyCode>>,
+    settings: Res<GameSettings>,
+    mut idle_timer: ResMut<IdleTimer>,
+    mut start_attract_demo: EventWriter<StartAttractDemo>,
+) {
+    let input_received = input.get_just_pressed().next().is_some();
+    if idle_timer.tick(
+        time.delta_seconds(),
+        input_received,
+        settings.idle_demo_timeout,
+    ) {
+        start_attract_demo.send(StartAttractDemo);
+    }
+}
+
+#[test]
+fn test_idle_timer() {
+    let mut idle_timer = IdleTimer::default();
+    assert!(!idle_timer.tick(0.3, false, 0.5));
+    assert!(idle_timer.tick(0.3, false, 0.5));
+    assert!(idle_timer.is_due(0.5));
+    // only triggers once
+    assert!(!idle_timer.tick(0.3, false, 0.5));
+
+    // any input resets it
+    assert!(!idle_timer.tick(0.3, true, 0.5));
+    assert!(!idle_timer.is_due(0.5));
+
+    // 0.0 disables the demo
+    let mut idle_timer = IdleTimer::default();
+    assert!(!idle_timer.tick(100.0, false, 0.0));
+    assert!(!idle_timer.is_due(0.0));
+}
+
+#[test]
+fn test_select_theme() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(select_theme.system());
+    world.insert_resource(Theme::default());
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::T);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(*world.get_resource::<Theme>().unwrap(), Theme::Vivid);
+
+    // holding the key doesn't keep cycling
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(*world.get_resource::<Theme>().unwrap(), Theme::Vivid);
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::T);
+    input.update();
+    input.press(KeyCode::T);
+    update_stage.run(&mut world);
+    assert_eq!(*world.get_resource::<Theme>().unwrap(), Theme::Night);
+}
+
+#[test]
+fn test_toggle_swap_hints() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(toggle_swap_hints.system());
+    world.insert_resource(AssistSettings::default());
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::H);
+    world.insert_resource(input);
+    let hint_text = world
+        .spawn()
+        .insert(Text::with_section(
+            "",
+            TextStyle::default(),
+            Default::default(),
+        ))
+        .insert(SwapHintText)
+        .id();
+
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<AssistSettings>().unwrap().show_hint);
+    assert_eq!(
+        world.get::<Text>(hint_text).unwrap().sections[0].value,
+        "H: Swap hints < On >"
+    );
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::H);
+    input.update();
+    input.press(KeyCode::H);
+    update_stage.run(&mut world);
+    assert!(!world.get_resource::<AssistSettings>().unwrap().show_hint);
+}
+
+#[test]
+fn test_toggle_assist_mode() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(toggle_assist_mode.system());
+    world.insert_resource(AssistMode::default());
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::B);
+    world.insert_resource(input);
+    let assist_text = world
+        .spawn()
+        .insert(Text::with_section(
+            "",
+            TextStyle::default(),
+            Default::default(),
+        ))
+        .insert(AssistModeText)
+        .id();
+
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<AssistMode>().unwrap().0);
+    assert_eq!(
+        world.get::<Text>(assist_text).unwrap().sections[0].value,
+        "B: Longer chain window < On >"
+    );
+}
+
+#[test]
+fn test_select_difficulty() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(select_difficulty.system());
+    world.insert_resource(Difficulty::default());
+    world.insert_resource(ColorCount::default());
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Right);
+    world.insert_resource(input);
+
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<ColorCount>().unwrap().0, 6);
+    assert_eq!(
+        *world.get_resource::<Difficulty>().unwrap(),
+        Difficulty::Hard
+    );
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.update();
+    input.press(KeyCode::Left);
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<ColorCount>().unwrap().0, 5);
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Left);
+    input.update();
+    input.press(KeyCode::Left);
+    update_stage.run(&mut world);
+    assert_eq!(
+        *world.get_resource::<Difficulty>().unwrap(),
+        Difficulty::Easy
+    );
+    assert_eq!(world.get_resource::<ColorCount>().unwrap().0, 5);
+}
+
+#[test]
+fn test_quit_game() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(quit_game.system());
+    update_stage.add_system_set(
+        SystemSet::on_update(AppState::CustomGame).with_system(
+            (|input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>| {
+                if input.just_pressed(KeyCode::Escape) {
+                    state.set(AppState::Menu).unwrap();
+                }
+            })
+            .system(),
+        ),
+    );
+    world.insert_resource(State::new(AppState::CustomGame));
+    world.insert_resource(bevy::app::Events::<AppExit>::default());
+    let exits = |world: &World| {
+        world
+            .get_resource::<bevy::app::Events<AppExit>>()
+            .unwrap()
+            .get_reader()
+            .iter(world.get_resource::<bevy::app::Events<AppExit>>().unwrap())
+            .count()
+    };
+
+    // leaving a submenu lands on the menu without quitting
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Escape);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::Menu
+    );
+    assert_eq!(exits(&world), 0);
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Escape);
+    input.update();
+    input.press(KeyCode::Escape);
+    update_stage.run(&mut world);
+    assert_eq!(exits(&world), 1);
+}
+
+#[test]
+fn test_menu_keys_on_the_same_frame() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(open_custom_game.system());
+    update_stage.add_system(open_controls.system());
+    world.insert_resource(State::new(AppState::Menu));
+
+    // both items ask for a change; the one that asks second is dropped, not a panic
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::C);
+    input.press(KeyCode::K);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert!(matches!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        AppState::CustomGame | AppState::Controls
+    ));
+}
+
+#[test]
+fn test_standard_game_resets_colors() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(go_to_game.system());
+    world.insert_resource(State::new(AppState::Menu));
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(GameSpeed::default());
+    world.insert_resource(GameMode::Endless);
+    world.insert_resource(GameSettings::default());
+    // what an 8-color custom game leaves behind
+    world.insert_resource(Difficulty::Normal);
+    world.insert_resource(ColorCount(8));
+    world.insert_resource(ColorTable::extended(8));
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Space);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(world.get_resource::<ColorCount>().unwrap().0, 5);
+    assert_eq!(
+        *world.get_resource::<ColorTable>().unwrap(),
+        ColorTable::default()
+    );
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+}
+
+#[test]
+fn test_resume_game_colors() {
+    use crate::ingame::{apply_theme, block_material, BlockColor};
+
+    let mut app = App::build();
+    app.add_plugin(bevy::core::CorePlugin)
+        .add_plugin(bevy::asset::AssetPlugin)
+        .add_asset::<ColorMaterial>();
+    let mut world = World::default();
+    world.insert_resource(
+        app.world_mut()
+            .remove_resource::<Assets<ColorMaterial>>()
+            .unwrap(),
+    );
+    world.insert_resource(BoardConfig::default());
+    world.insert_resource(GameSpeed::default());
+    world.insert_resource(GameMode::Endless);
+    world.insert_resource(GameSettings::default());
+    world.insert_resource(Difficulty::Normal);
+    world.insert_resource(ColorCount::default());
+    world.insert_resource(ColorTable::default());
+    world.insert_resource(Theme::default());
+    world.insert_resource(BlockMaterials {
+        red_material: Handle::<ColorMaterial>::default(),
+        green_material: Handle::<ColorMaterial>::default(),
+        blue_material: Handle::<ColorMaterial>::default(),
+        yellow_material: Handle::<ColorMaterial>::default(),
+        purple_material: Handle::<ColorMaterial>::default(),
+        indigo_material: Handle::<ColorMaterial>::default(),
+        extra_materials: Vec::new(),
+    });
+    let save = GameSave {
+        width: 6,
+        height: 12,
+        difficulty: Difficulty::Normal,
+        color_count: 8,
+        rows: Vec::new(),
+        cursor: (2, 5),
+        score: 0,
+        chain: 0,
+        max_chain: 0,
+        play_time: 0.0,
+        speed_origin: GameSpeed::default().origin,
+        ramp_clock: 0.0,
+        lift_delay_left: 0.0,
+    };
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(
+        (move |mut commands: Commands, mut standard_game: StandardGame| {
+            resume_game(&mut commands, &mut standard_game, save.clone());
+        })
+        .system()
+        .label("resume_game"),
+    );
+    update_stage.add_system(apply_theme.system().after("resume_game"));
+    update_stage.run(&mut world);
+
+    assert_eq!(world.get_resource::<ColorCount>().unwrap().0, 8);
+    assert!(world.get_resource::<PendingGameSave>().is_some());
+    // the extra ids get their own tinted material instead of their base art
+    let block_materials = world.get_resource::<BlockMaterials>().unwrap();
+    let material = block_material(block_materials, BlockColor(7));
+    assert_ne!(material, block_materials.green_material);
+    assert_eq!(
+        world
+            .get_resource::<Assets<ColorMaterial>>()
+            .unwrap()
+            .get(material)
+            .unwrap()
+            .color
+            .as_rgba(),
+        world.get_resource::<ColorTable>().unwrap().0[7].as_rgba()
+    );
+}
+
+#[test]
+fn test_cleanup_menu() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(cleanup_menu.system());
+    let menu_text = world.spawn().insert(Text::default()).insert(MenuUi).id();
+    // e.g. the FPS counter, which outlives the menu
+    let other_text = world.spawn().insert(Text::default()).id();
+
+    update_stage.run(&mut world);
+    assert!(world.get_entity(menu_text).is_none());
+    assert!(world.get_entity(other_text).is_some());
+}
@@ -0,0 +1,205 @@
+use bevy::{prelude::*, window::WindowFocused};
+use bevy_kira_audio::{Audio, AudioChannel};
+
+use crate::{
+    loading::{MusicAssets, AUDIO_SETTINGS_FILE},
+    persistence,
+    settings::AudioSettings,
+    AppState,
+};
+
+pub struct MusicPlugin;
+
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(MusicController {
+            channel: AudioChannel::new("music".to_string()),
+            playing: false,
+            muted: false,
+        })
+        .add_system(sync_music.system())
+        .add_system(mute_on_focus_loss.system().before("apply_audio_settings"))
+        .add_system(apply_audio_settings.system().label("apply_audio_settings"));
+    }
+}
+
+// The background track plays on its own channel so it can be stopped without
+// cutting off sound effects.
+pub struct MusicController {
+    pub channel: AudioChannel,
+    pub playing: bool,
+    // set while the window is unfocused
+    pub muted: bool,
+}
+
+// The track keeps going across menus, play and pause, and stops on game over.
+fn music_should_play(state: &AppState) -> bool {
+    match state {
+        AppState::Menu
+        | AppState::CustomGame
+        | AppState::Controls
+        | AppState::InGame
+        | AppState::Paused => true,
+        AppState::Loading | AppState::GameOver => false,
+    }
+}
+
+// Only reacts to changes of the decision, so moving from Menu to InGame does not
+// restart the track.
+fn sync_music(
+    state: Res<State<AppState>>,
+    audio: Res<Audio>,
+    music_assets: Option<Res<MusicAssets>>,
+    mut music_controller: ResMut<MusicController>,
+) {
+    let should_play = music_should_play(state.current());
+    if should_play == music_controller.playing {
+        return;
+    }
+    if should_play {
+        if let Some(music_assets) = music_assets {
+            audio.play_looped_in_channel(music_assets.bgm.clone(), &music_controller.channel);
+            music_controller.playing = true;
+        }
+    } else {
+        audio.stop_channel(&music_controller.channel);
+        music_controller.playing = false;
+    }
+}
+
+// Muting doesn't depend on `AutoPause`: nothing should play from a window in the
+// background, paused or not.
+fn mute_on_focus_loss(
+    mut focused_events: EventReader<WindowFocused>,
+    mut music_controller: ResMut<MusicController>,
+) {
+    if let Some(event) = focused_events.iter().last() {
+        if music_controller.muted == event.focused {
+            music_controller.muted = !event.focused;
+        }
+    }
+}
+
+// Sound effects play on the default channel and the track on the music channel;
+// kira keeps a channel's volume for sounds started later.
+fn apply_audio_settings(
+    audio: Res<Audio>,
+    mut audio_settings: ResMut<AudioSettings>,
+    music_controller: Res<MusicController>,
+) {
+    if !audio_settings.is_changed() && !music_controller.is_changed() {
+        return;
+    }
+    let clamped = audio_settings.clamped();
+    if *audio_settings != clamped {
+        *audio_settings = clamped;
+    }
+    if music_controller.muted {
+        audio.set_volume(0.0);
+        audio.set_volume_in_channel(0.0, &music_controller.channel);
+    } else {
+        audio.set_volume(clamped.sfx_volume());
+        audio.set_volume_in_channel(clamped.bgm_volume(), &music_controller.channel);
+    }
+    // the loaded value needs no saving
+    if audio_settings.is_changed() && !audio_settings.is_added() {
+        persistence::save(AUDIO_SETTINGS_FILE, &clamped);
+    }
+}
+
+#[test]
+fn test_music_should_play() {
+    assert!(!music_should_play(&AppState::Loading));
+    assert!(music_should_play(&AppState::Menu));
+    assert!(music_should_play(&AppState::InGame));
+    assert!(music_should_play(&AppState::Paused));
+    assert!(!music_should_play(&AppState::GameOver));
+}
+
+#[test]
+fn test_sync_music() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(sync_music.system());
+    world.insert_resource(State::new(AppState::Menu));
+    world.insert_resource(Audio::default());
+    world.insert_resource(MusicAssets {
+        bgm: Handle::default(),
+    });
+    world.insert_resource(MusicController {
+        channel: AudioChannel::new("music".to_string()),
+        playing: false,
+        muted: false,
+    });
+
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<MusicController>().unwrap().playing);
+
+    world
+        .get_resource_mut::<State<AppState>>()
+        .unwrap()
+        .set(AppState::GameOver)
+        .unwrap();
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert!(!world.get_resource::<MusicController>().unwrap().playing);
+}
+
+#[test]
+fn test_apply_audio_settings_clamps() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(apply_audio_settings.system());
+    world.insert_resource(Audio::default());
+    world.insert_resource(AudioSettings {
+        master: 2.0,
+        sfx: 0.5,
+        bgm: -1.0,
+    });
+    world.insert_resource(MusicController {
+        channel: AudioChannel::new("music".to_string()),
+        playing: false,
+        muted: false,
+    });
+
+    update_stage.run(&mut world);
+    assert_eq!(
+        *world.get_resource::<AudioSettings>().unwrap(),
+        AudioSettings {
+            master: 1.0,
+            sfx: 0.5,
+            bgm: 0.0,
+        }
+    );
+}
+
+#[test]
+fn test_mute_on_focus_loss() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system(mute_on_focus_loss.system());
+    world.insert_resource(bevy::app::Events::<WindowFocused>::default());
+    world.insert_resource(MusicController {
+        channel: AudioChannel::new("music".to_string()),
+        playing: true,
+        muted: false,
+    });
+
+    let focus = |world: &mut World, focused| {
+        world
+            .get_resource_mut::<bevy::app::Events<WindowFocused>>()
+            .unwrap()
+            .send(WindowFocused {
+                id: Default::default(),
+                focused,
+            });
+    };
+    focus(&mut world, false);
+    update_stage.run(&mut world);
+    assert!(world.get_resource::<MusicController>().unwrap().muted);
+
+    focus(&mut world, true);
+    update_stage.run(&mut world);
+    assert!(!world.get_resource::<MusicController>().unwrap().muted);
+}
@@ -0,0 +1,263 @@
+use crate::{
+    ingame::serialize_game,
+    loading::{FontAssets, GAME_SAVE_FILE},
+    persistence, queue_state_change,
+    settings::{AutoPause, GameMode},
+    AppState,
+};
+use bevy::{prelude::*, window::WindowFocused};
+
+pub struct PausePlugin;
+
+// Paused is pushed on top of InGame, so every `on_update(AppState::InGame)` system,
+// including the block timers, stops advancing until it is popped again.
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(toggle_pause.system())
+            .add_system(pause_on_focus_loss.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Paused).with_system(setup_pause_overlay.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Paused)
+                    .with_system(save_and_quit.exclusive_system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Paused).with_system(cleanup_pause_overlay.system()),
+            );
+    }
+}
+
+struct PauseOverlay;
+
+// Runs outside the state sets so the same Escape press can't both push and pop.
+fn toggle_pause(keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        match state.current() {
+            AppState::InGame => {
+                queue_state_change(state.push(AppState::Paused));
+            }
+            AppState::Paused => {
+                queue_state_change(state.pop());
+            }
+            _ => {}
+        }
+    }
+}
+
+// On the web winit reports the canvas losing focus, which also covers switching tabs.
+// Refocusing only resumes a pause this system made, never one the player asked for.
+fn pause_on_focus_loss(
+    mut focused_events: EventReader<WindowFocused>,
+    auto_pause: Res<AutoPause>,
+    mut auto_paused: Local<bool>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let focused = match focused_events.iter().last() {
+        Some(event) => event.focused,
+        None => return,
+    };
+    if !focused {
+        // a pause already queued this frame by Escape is left alone
+        if auto_pause.0 && *state.current() == AppState::InGame {
+            *auto_paused = queue_state_change(state.push(AppState::Paused));
+        }
+    } else if *auto_paused {
+        *auto_paused = false;
+        if *state.current() == AppState::Paused {
+            queue_state_change(state.pop());
+        }
+    }
+}
+
+// Only endless runs can be saved; the menu offers them again with L.
+fn save_and_quit(world: &mut World) {
+    let pressed = world
+        .get_resource::<Input<KeyCode>>()
+        .is_some_and(|input| input.just_pressed(KeyCode::Q));
+    if !pressed || *world.get_resource::<GameMode>().unwrap() != GameMode::Endless {
+        return;
+    }
+    let save = match serialize_game(world) {
+        Some(save) => save,
+        None => return,
+    };
+    persistence::save(GAME_SAVE_FILE, &Some(save));
+    queue_state_change(
+        world
+            .get_resource_mut::<State<AppState>>()
+            .unwrap()
+            .replace(AppState::Menu),
+    );
+}
+
+fn setup_pause_overlay(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    game_mode: Res<GameMode>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let text_style = |font_size| TextStyle {
+        font: font_assets.font.clone(),
+        font_size,
+        color: Color::rgb(1.0, 1.0, 1.0),
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.5).into()),
+            ..Default::default()
+        })
+        .insert(PauseOverlay)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section("Paused", text_style(60.0), Default::default()),
+                ..Default::default()
+            });
+            if *game_mode == GameMode::Endless {
+                parent.spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "Q: Save & Quit",
+                        text_style(30.0),
+                        Default::default(),
+                    ),
+                    ..Default::default()
+                });
+            }
+        });
+}
+
+fn cleanup_pause_overlay(mut commands: Commands, pause_overlay: Query<Entity, With<PauseOverlay>>) {
+    for entity in pause_overlay.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[test]
+fn test_toggle_pause() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(toggle_pause.system());
+    world.insert_resource(State::new(AppState::InGame));
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Escape);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    // the input system clears just_pressed between frames
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    let state = world.get_resource::<State<AppState>>().unwrap();
+    assert_eq!(state.current(), &AppState::Paused);
+
+    let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+    input.release(KeyCode::Escape);
+    input.update();
+    input.press(KeyCode::Escape);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::InGame
+    );
+}
+
+#[test]
+fn test_pause_on_focus_loss() {
+    for (enabled, pauses) in [(true, true), (false, false)] {
+        let mut world = World::default();
+        let mut update_stage = SystemStage::parallel();
+        update_stage.add_system_set(State::<AppState>::get_driver());
+        update_stage.add_system(pause_on_focus_loss.system());
+        world.insert_resource(State::new(AppState::InGame));
+        world.insert_resource(AutoPause(enabled));
+        world.insert_resource(bevy::app::Events::<WindowFocused>::default());
+
+        let focus = |world: &mut World, focused| {
+            world
+                .get_resource_mut::<bevy::app::Events<WindowFocused>>()
+                .unwrap()
+                .send(WindowFocused {
+                    id: Default::default(),
+                    focused,
+                });
+        };
+        focus(&mut world, false);
+        update_stage.run(&mut world);
+        update_stage.run(&mut world);
+        let paused =
+            world.get_resource::<State<AppState>>().unwrap().current() == &AppState::Paused;
+        assert_eq!(paused, pauses);
+
+        focus(&mut world, true);
+        update_stage.run(&mut world);
+        update_stage.run(&mut world);
+        assert_eq!(
+            world.get_resource::<State<AppState>>().unwrap().current(),
+            &AppState::InGame
+        );
+    }
+}
+
+#[test]
+fn test_refocus_keeps_player_pause() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(pause_on_focus_loss.system());
+    world.insert_resource(State::new(AppState::Paused));
+    world.insert_resource(AutoPause(true));
+    world.insert_resource(bevy::app::Events::<WindowFocused>::default());
+
+    world
+        .get_resource_mut::<bevy::app::Events<WindowFocused>>()
+        .unwrap()
+        .send(WindowFocused {
+            id: Default::default(),
+            focused: true,
+        });
+    update_stage.run(&mut world);
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::Paused
+    );
+}
+
+#[test]
+fn test_toggle_pause_with_game_over_on_the_same_frame() {
+    let mut world = World::default();
+    let mut update_stage = SystemStage::parallel();
+    update_stage.add_system_set(State::<AppState>::get_driver());
+    update_stage.add_system(
+        (|mut state: ResMut<State<AppState>>| {
+            if *state.current() == AppState::InGame {
+                queue_state_change(state.set(AppState::GameOver));
+            }
+        })
+        .system()
+        .label("game_over"),
+    );
+    // the game over asks first, so the pause is the request that gets dropped
+    update_stage.add_system(toggle_pause.system().after("game_over"));
+    world.insert_resource(State::new(AppState::InGame));
+
+    let mut input = Input::<KeyCode>::default();
+    input.press(KeyCode::Escape);
+    world.insert_resource(input);
+    update_stage.run(&mut world);
+    world.get_resource_mut::<Input<KeyCode>>().unwrap().update();
+    update_stage.run(&mut world);
+    assert_eq!(
+        world.get_resource::<State<AppState>>().unwrap().current(),
+        &AppState::GameOver
+    );
+}
@@ -0,0 +1,47 @@
+use std::{fs, path::PathBuf};
+
+use bevy::log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+
+// Small RON files under the user's data dir. Where there is no data dir (e.g. WASM)
+// values simply live in memory for the session.
+fn save_path(file_name: &str) -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("panepow").join(file_name))
+}
+
+pub fn load<T: DeserializeOwned + Default>(file_name: &str) -> T {
+    let path = match save_path(file_name) {
+        Some(path) => path,
+        None => return T::default(),
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => ron::de::from_str(&contents).unwrap_or_else(|err| {
+            warn!("failed to parse {}: {}", path.display(), err);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+pub fn save<T: Serialize>(file_name: &str, value: &T) {
+    let path = match save_path(file_name) {
+        Some(path) => path,
+        None => return,
+    };
+    let contents = match ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!("failed to serialize {}: {}", file_name, err);
+            return;
+        }
+    };
+    if let Some(dir) = path.parent() {
+        if let Err(err) = fs::create_dir_all(dir) {
+            warn!("failed to create {}: {}", dir.display(), err);
+            return;
+        }
+    }
+    if let Err(err) = fs::write(&path, contents) {
+        warn!("failed to write {}: {}", path.display(), err);
+    }
+}
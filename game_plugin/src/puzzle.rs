@@ -0,0 +1,106 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::ingame::BlockColor;
+
+// A fixed start layout and the number of swaps allowed to clear it.
+// The layout is drawn top row first, one character per cell:
+// R G B Y P I for the block colors and `.` for an empty cell.
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "e272ec0f-2062-4f2a-b106-f5eea183f0fa"]
+pub struct Puzzle {
+    pub move_limit: u32,
+    pub layout: String,
+}
+
+#[derive(Default)]
+pub struct PuzzleLoader;
+
+impl AssetLoader for PuzzleLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let puzzle = ron::de::from_bytes::<Puzzle>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(puzzle));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["puzzle"]
+    }
+}
+
+// Returns rows from the bottom up, each `width` cells long. Blank lines are ignored so
+// the layout can be indented freely in the RON file.
+pub fn parse_puzzle_layout(
+    layout: &str,
+    width: usize,
+) -> Result<Vec<Vec<Option<BlockColor>>>, String> {
+    let mut rows = Vec::new();
+    for line in layout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let row = line
+            .chars()
+            .map(|cell| match cell {
+                'R' => Ok(Some(BlockColor::RED)),
+                'G' => Ok(Some(BlockColor::GREEN)),
+                'B' => Ok(Some(BlockColor::BLUE)),
+                'Y' => Ok(Some(BlockColor::YELLOW)),
+                'P' => Ok(Some(BlockColor::PURPLE)),
+                'I' => Ok(Some(BlockColor::INDIGO)),
+                '.' => Ok(None),
+                other => Err(format!("unknown cell {:?}", other)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if row.len() != width {
+            return Err(format!(
+                "row {:?} has {} cells, expected {}",
+                line,
+                row.len(),
+                width
+            ));
+        }
+        rows.push(row);
+    }
+    rows.reverse();
+    Ok(rows)
+}
+
+#[test]
+fn test_parse_puzzle_layout() {
+    let rows = parse_puzzle_layout(
+        "
+        .R..
+        GRG.
+        ",
+        4,
+    )
+    .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            vec![
+                Some(BlockColor::GREEN),
+                Some(BlockColor::RED),
+                Some(BlockColor::GREEN),
+                None
+            ],
+            vec![None, Some(BlockColor::RED), None, None],
+        ]
+    );
+
+    assert!(parse_puzzle_layout("RG.", 4).is_err());
+    assert!(parse_puzzle_layout("RGX.", 4).is_err());
+    assert_eq!(parse_puzzle_layout("", 4).unwrap().len(), 0);
+}
@@ -0,0 +1,180 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ingame::{BoardConfig, GameSpeed, PendingGameSave, RunSeed},
+    loading::REPLAY_FILE,
+    persistence,
+    settings::{Difficulty, GameMode, GameSettings, KeyBindings, PlayerControls, StartLayout},
+    AppState,
+};
+
+pub struct ReplayPlugin;
+
+// Both systems run at the start of every gameplay step, once the step's keys are set, so
+// every gameplay system sees the fed keys. Recording also runs during playback, which
+// saves the replay again unchanged.
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ReplayLog>()
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria("gameplay")
+                    .with_system(
+                        feed_replay_input
+                            .exclusive_system()
+                            .at_start()
+                            .label("feed_replay_input")
+                            .after("step_keyboard_input"),
+                    )
+                    .with_system(
+                        record_replay_input
+                            .exclusive_system()
+                            .at_start()
+                            .after("feed_replay_input"),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame).with_system(start_replay_log.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::InGame).with_system(save_replay_log.system()),
+            );
+    }
+}
+
+// Bits of a `ReplayLog` frame, one per solo action.
+const LEFT: u8 = 1;
+const RIGHT: u8 = 1 << 1;
+const UP: u8 = 1 << 2;
+const DOWN: u8 = 1 << 3;
+const LIFT: u8 = 1 << 4;
+const SWAP: u8 = 1 << 5;
+
+fn actions(controls: &PlayerControls) -> [(&Vec<KeyCode>, u8); 6] {
+    [
+        (&controls.left, LEFT),
+        (&controls.right, RIGHT),
+        (&controls.up, UP),
+        (&controls.down, DOWN),
+        (&controls.lift, LIFT),
+        (&controls.swap, SWAP),
+    ]
+}
+
+// The solo actions held on every gameplay step of a standard endless run. Actions
+// rather than keys are kept, so a replay survives rebinding. Steps have a fixed length,
+// so playback matches the run at any frame rate.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayLog {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub frames: Vec<u8>,
+    // false for runs a replay can't start again, which are neither recorded nor saved
+    #[serde(skip)]
+    pub recording: bool,
+}
+
+// Present while a replay drives the solo cursor; `frame` is the next step to feed.
+pub struct ReplayPlayer {
+    pub log: ReplayLog,
+    pub frame: usize,
+}
+
+// The menu starts replays on the default board with the default speed, like a
+// standard game.
+fn is_standard_run(
+    game_mode: &GameMode,
+    board_config: &BoardConfig,
+    game_speed: &GameSpeed,
+    settings: &GameSettings,
+) -> bool {
+    let default_board = BoardConfig::default();
+    *game_mode == GameMode::Endless
+        && board_config.width == default_board.width
+        && board_config.height == default_board.height
+        && board_config.initial_rows == default_board.initial_rows
+        && game_speed.origin == GameSpeed::default().origin
+        && settings.start_layout == StartLayout::Pattern
+}
+
+fn start_replay_log(
+    game_mode: Res<GameMode>,
+    board_config: Res<BoardConfig>,
+    game_speed: Res<GameSpeed>,
+    settings: Res<GameSettings>,
+    difficulty: Res<Difficulty>,
+    pending_save: Option<Res<PendingGameSave>>,
+    mut replay_log: ResMut<ReplayLog>,
+) {
+    *replay_log = ReplayLog {
+        seed: 0,
+        difficulty: *difficulty,
+        frames: Vec::new(),
+        recording: pending_save.is_none()
+            && is_standard_run(&game_mode, &board_config, &game_speed, &settings),
+    };
+}
+
+pub fn record_replay_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut replay_log: ResMut<ReplayLog>,
+) {
+    if !replay_log.recording {
+        return;
+    }
+    let frame = actions(&key_bindings.solo)
+        .iter()
+        .filter(|(keys, _)| keys.iter().any(|key| keyboard_input.pressed(*key)))
+        .fold(0, |frame, (_, bit)| frame | bit);
+    replay_log.frames.push(frame);
+}
+
+// Holds the first key bound to each recorded action and lets go of every other solo
+// key; frames past the end of the log hold nothing.
+pub fn feed_replay_input(
+    key_bindings: Res<KeyBindings>,
+    replay_player: Option<ResMut<ReplayPlayer>>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+) {
+    let mut replay_player = match replay_player {
+        Some(replay_player) => replay_player,
+        None => return,
+    };
+    let frame = replay_player
+        .log
+        .frames
+        .get(replay_player.frame)
+        .copied()
+        .unwrap_or(0);
+    replay_player.frame += 1;
+    for (keys, bit) in actions(&key_bindings.solo) {
+        for (index, key) in keys.iter().enumerate() {
+            let held = index == 0 && frame & bit != 0;
+            if held && !keyboard_input.pressed(*key) {
+                keyboard_input.press(*key);
+            } else if !held && keyboard_input.pressed(*key) {
+                keyboard_input.release(*key);
+            } else if !held {
+                keyboard_input.reset(*key);
+            }
+        }
+    }
+}
+
+// The seed is only known once the run has set up its rng.
+fn save_replay_log(
+    mut commands: Commands,
+    run_seed: Option<Res<RunSeed>>,
+    mut replay_log: ResMut<ReplayLog>,
+) {
+    commands.remove_resource::<ReplayPlayer>();
+    let run_seed = match run_seed {
+        Some(run_seed) if replay_log.recording => run_seed,
+        _ => return,
+    };
+    replay_log.seed = run_seed.0;
+    replay_log.recording = false;
+    persistence::save(REPLAY_FILE, &*replay_log);
+}
@@ -0,0 +1,468 @@
+use bevy::input::{keyboard::KeyCode, Input};
+use serde::{Deserialize, Serialize};
+
+// Player-adjustable knobs shared across states.
+pub struct GameSettings {
+    // seconds without input on the menu before the attract demo starts, 0.0 disables it
+    pub idle_demo_timeout: f32,
+    // cosmetic quarter-turns on block sprites, drawn from the board rng
+    pub random_block_rotation: bool,
+    pub start_layout: StartLayout,
+    // seconds a stack may stay above the game over line, None waits one lift delay
+    pub top_out_grace: Option<f32>,
+    // faint lines between the board's columns
+    pub column_guides: bool,
+    // a red line across the board where a settled stack tops out
+    pub game_over_line: bool,
+    // frame rate in a corner of the screen, F3 toggles it
+    pub fps_overlay: bool,
+    pub fall_style: FallStyle,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            idle_demo_timeout: 20.0,
+            random_block_rotation: false,
+            start_layout: StartLayout::Pattern,
+            top_out_grace: None,
+            column_guides: true,
+            game_over_line: true,
+            fps_overlay: false,
+            fall_style: FallStyle::Animated,
+        }
+    }
+}
+
+// What a fresh endless board holds above its spawning rows. `Pattern` takes
+// `BoardConfig::initial_rows` rows of the built-in layout; `Flat` fills that many bottom
+// rows, up to `BoardConfig::max_start_rows`, with random colors that don't match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartLayout {
+    Pattern,
+    Empty,
+    Flat(u8),
+}
+
+// How blocks left without support come down. `Animated` floats them briefly and lets
+// them fall; `Instant` drops them straight onto whatever is below in the same update.
+// Garbage pieces always fall animated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallStyle {
+    Animated,
+    Instant,
+}
+
+// Number of block colors in play, taken from the front of the canonical list.
+pub struct ColorCount(pub usize);
+
+impl Default for ColorCount {
+    fn default() -> Self {
+        Self(5)
+    }
+}
+
+// Picked on the menu. Scales the lift speed and sets how long a fresh board waits
+// before it starts rising; Hard also brings in the sixth color.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn speed_scale(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.5,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 2.0,
+        }
+    }
+
+    // seconds before the first lift
+    pub fn lift_delay(self) -> f32 {
+        match self {
+            Difficulty::Easy => 3.0,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 0.5,
+        }
+    }
+
+    pub fn speed_ramp(self) -> SpeedRamp {
+        match self {
+            Difficulty::Easy => SpeedRamp {
+                interval: 30.0,
+                step: 0.5,
+                max: 10.0,
+            },
+            Difficulty::Normal => SpeedRamp {
+                interval: 20.0,
+                step: 1.0,
+                max: 20.0,
+            },
+            Difficulty::Hard => SpeedRamp {
+                interval: 15.0,
+                step: 2.0,
+                max: 40.0,
+            },
+        }
+    }
+
+    pub fn color_count(self) -> ColorCount {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => ColorCount(5),
+            Difficulty::Hard => ColorCount(6),
+        }
+    }
+
+    pub fn easier(self) -> Self {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => Difficulty::Easy,
+            Difficulty::Hard => Difficulty::Normal,
+        }
+    }
+
+    pub fn harder(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal | Difficulty::Hard => Difficulty::Hard,
+        }
+    }
+}
+
+// Cursor auto-repeat: a held direction moves again after `first_repeat` seconds, then
+// every `repeat` seconds for as long as it is held.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputTuning {
+    pub first_repeat: f32,
+    pub repeat: f32,
+}
+
+impl Default for InputTuning {
+    fn default() -> Self {
+        Self {
+            first_repeat: 0.4,
+            repeat: 0.04,
+        }
+    }
+}
+
+impl InputTuning {
+    // Repeats due while a key held for `held_before` seconds is held until `held_after`.
+    pub fn repeats(&self, held_before: f32, held_after: f32) -> u32 {
+        let due = |held: f32| {
+            if held < self.first_repeat {
+                0
+            } else {
+                ((held - self.first_repeat) / self.repeat.max(f32::EPSILON)).floor() as u32 + 1
+            }
+        };
+        due(held_after) - due(held_before)
+    }
+}
+
+// Endless boards lift `step` pixels per second faster every `interval` seconds of play,
+// up to `max`. A board that already starts faster than `max` keeps its speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedRamp {
+    pub interval: f32,
+    pub step: f32,
+    pub max: f32,
+}
+
+impl SpeedRamp {
+    pub fn speed_at(&self, start: f32, seconds: f32) -> f32 {
+        let steps = (seconds / self.interval).floor();
+        (start + steps * self.step).min(self.max.max(start))
+    }
+}
+
+// Endless stacks up from below forever; ScoreAttack is Endless against a clock;
+// Puzzle clears a fixed layout in limited swaps; VsCom plays Endless next to a
+// computer-controlled board; TwoPlayer puts two local players on Endless boards side by
+// side; Sandbox starts empty and never lifts, and blocks are placed by hand.
+//
+// The run criteria of `IngamePlugin` pick the gameplay systems each mode runs:
+//
+//   mode          lift, spawn   garbage   score attack clock
+//   Endless       yes           -         -
+//   ScoreAttack   yes           -         yes
+//   VsCom         yes           yes       -
+//   TwoPlayer     yes           yes       -
+//   Puzzle        -             -         -
+//   Sandbox       -             -         -
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameMode {
+    #[default]
+    Endless,
+    Puzzle,
+    VsCom,
+    TwoPlayer,
+    Sandbox,
+    ScoreAttack,
+}
+
+// Keys for one human cursor; any of the keys listed for an action triggers it.
+// A cursor without controls isn't moved by the keyboard.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerControls {
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub up: Vec<KeyCode>,
+    pub down: Vec<KeyCode>,
+    // held to raise the stack quickly; unbound in key files saved before it existed
+    #[serde(default)]
+    pub lift: Vec<KeyCode>,
+    pub swap: Vec<KeyCode>,
+}
+
+impl PlayerControls {
+    pub fn solo() -> Self {
+        Self {
+            left: vec![KeyCode::Left],
+            right: vec![KeyCode::Right],
+            up: vec![KeyCode::Up],
+            down: vec![KeyCode::Down],
+            lift: vec![KeyCode::LShift],
+            swap: vec![KeyCode::Space],
+        }
+    }
+
+    pub fn player_one() -> Self {
+        Self {
+            left: vec![KeyCode::A],
+            right: vec![KeyCode::D],
+            up: vec![KeyCode::W],
+            down: vec![KeyCode::S],
+            lift: vec![KeyCode::G],
+            swap: vec![KeyCode::F],
+        }
+    }
+
+    pub fn player_two() -> Self {
+        Self {
+            left: vec![KeyCode::Left],
+            right: vec![KeyCode::Right],
+            up: vec![KeyCode::Up],
+            down: vec![KeyCode::Down],
+            lift: vec![KeyCode::RShift],
+            swap: vec![KeyCode::Return],
+        }
+    }
+}
+
+pub fn just_pressed(input: &Input<KeyCode>, keys: &[KeyCode]) -> bool {
+    keys.iter().any(|key| input.just_pressed(*key))
+}
+
+pub fn pressed(input: &Input<KeyCode>, keys: &[KeyCode]) -> bool {
+    keys.iter().any(|key| input.pressed(*key))
+}
+
+// How the menus show a switch.
+pub fn on_off(on: bool) -> &'static str {
+    if on {
+        "On"
+    } else {
+        "Off"
+    }
+}
+
+// Loaded from a file by `LoadingPlugin`, so keys can be rebound without recompiling.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub solo: PlayerControls,
+    pub player_one: PlayerControls,
+    pub player_two: PlayerControls,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            solo: PlayerControls::solo(),
+            player_one: PlayerControls::player_one(),
+            player_two: PlayerControls::player_two(),
+        }
+    }
+}
+
+impl KeyBindings {
+    // Key files saved before lift existed load it unbound; gives those the default key.
+    pub fn with_default_lift(mut self) -> Self {
+        let defaults = KeyBindings::default();
+        for (controls, default) in [
+            (&mut self.solo, defaults.solo),
+            (&mut self.player_one, defaults.player_one),
+            (&mut self.player_two, defaults.player_two),
+        ] {
+            if controls.lift.is_empty() {
+                controls.lift = default.lift;
+            }
+        }
+        self
+    }
+}
+
+// Beginner assist: lengthens the chain window so slower follow-ups still count.
+#[derive(Debug, Default)]
+pub struct AssistMode(pub bool);
+
+// Variant rule: a 2x2 square of one color clears as well as the lines. Off for the
+// classic rules.
+#[derive(Debug, Default)]
+pub struct SquareClear(pub bool);
+
+// Beginner assist: a faint mark over a swap near the cursor that would clear something.
+// Only a suggestion; the player still has to make the swap.
+#[derive(Debug, Default)]
+pub struct AssistSettings {
+    pub show_hint: bool,
+}
+
+// For players who can't tell the colors apart or are sensitive to motion. Loaded from a
+// file by `LoadingPlugin`, like `ReduceFlashing`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilitySettings {
+    // draws a symbol per color on every block
+    pub symbols: bool,
+    // keeps the cursor still instead of pulsing
+    pub reduce_motion: bool,
+}
+
+// For players sensitive to flashing: clearing blocks and columns in danger get a steady
+// mark instead of blinking.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReduceFlashing(pub bool);
+
+// Pauses play when the window loses focus; competitive players can turn it off so an
+// accidental click elsewhere doesn't break their run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoPause(pub bool);
+
+impl Default for AutoPause {
+    fn default() -> Self {
+        AutoPause(true)
+    }
+}
+
+// Palette laid over the block art; `Classic` shows it as drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    Classic,
+    Vivid,
+    Night,
+}
+
+impl Theme {
+    pub fn next(self) -> Self {
+        match self {
+            Theme::Classic => Theme::Vivid,
+            Theme::Vivid => Theme::Night,
+            Theme::Night => Theme::Classic,
+        }
+    }
+}
+
+// Volumes in 0.0..=1.0; master scales both the effects and the music.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master: f32,
+    pub sfx: f32,
+    pub bgm: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            sfx: 1.0,
+            bgm: 0.8,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn clamped(&self) -> Self {
+        Self {
+            master: self.master.clamp(0.0, 1.0),
+            sfx: self.sfx.clamp(0.0, 1.0),
+            bgm: self.bgm.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn sfx_volume(&self) -> f32 {
+        let clamped = self.clamped();
+        clamped.master * clamped.sfx
+    }
+
+    pub fn bgm_volume(&self) -> f32 {
+        let clamped = self.clamped();
+        clamped.master * clamped.bgm
+    }
+}
+
+#[test]
+fn test_audio_settings_clamped() {
+    let audio_settings = AudioSettings {
+        master: 0.5,
+        sfx: 1.5,
+        bgm: -0.2,
+    };
+    assert_eq!(
+        audio_settings.clamped(),
+        AudioSettings {
+            master: 0.5,
+            sfx: 1.0,
+            bgm: 0.0,
+        }
+    );
+    assert_eq!(audio_settings.sfx_volume(), 0.5);
+    assert_eq!(audio_settings.bgm_volume(), 0.0);
+}
+
+#[test]
+fn test_key_bindings_default_lift() {
+    let old = KeyBindings::default();
+    let mut old = ron::ser::to_string(&old).unwrap();
+    old = old.replace("lift:[LShift],", "").replace("lift:[G],", "");
+    let key_bindings = ron::de::from_str::<KeyBindings>(&old).unwrap();
+    assert!(key_bindings.solo.lift.is_empty());
+    let key_bindings = key_bindings.with_default_lift();
+    assert_eq!(key_bindings, KeyBindings::default());
+}
+
+#[test]
+fn test_input_tuning_repeats() {
+    let tuning = InputTuning {
+        first_repeat: 0.5,
+        repeat: 0.25,
+    };
+    // held in 0.125 second updates: nothing until 0.5, then one every 0.25
+    let mut moves = Vec::new();
+    for update in 0..12 {
+        let held = update as f32 * 0.125;
+        moves.push(tuning.repeats(held, held + 0.125));
+    }
+    assert_eq!(moves, [0, 0, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1]);
+    // a long update catches up on every repeat it covered
+    assert_eq!(tuning.repeats(0.0, 1.1), 3);
+    assert_eq!(InputTuning::default().repeats(0.0, 0.39), 0);
+}
+
+#[test]
+fn test_speed_ramp() {
+    let ramp = Difficulty::Normal.speed_ramp();
+    assert_eq!(ramp.speed_at(10.0, 0.0), 10.0);
+    assert_eq!(ramp.speed_at(10.0, 19.0), 10.0);
+    assert_eq!(ramp.speed_at(10.0, 60.0), 13.0);
+    // capped
+    assert_eq!(ramp.speed_at(10.0, 1000.0), 20.0);
+    assert_eq!(ramp.speed_at(25.0, 1000.0), 25.0);
+}
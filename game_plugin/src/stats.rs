@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ingame::{PlayTime, Score},
+    persistence,
+    settings::GameMode,
+    AppState,
+};
+
+const STATS_FILE: &str = "stats.ron";
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(persistence::load::<PlayerStats>(STATS_FILE))
+            .add_system_set(
+                SystemSet::on_exit(AppState::InGame).with_system(record_best_efficiency.system()),
+            );
+    }
+}
+
+// Bests carried across runs and saved to disk.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerStats {
+    pub best_efficiency: f32,
+}
+
+// Best score of each mode, loaded by `LoadingPlugin` and saved on game over.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighScores {
+    pub scores: HashMap<GameMode, u32>,
+}
+
+impl HighScores {
+    pub fn best(&self, game_mode: GameMode) -> u32 {
+        self.scores.get(&game_mode).copied().unwrap_or(0)
+    }
+}
+
+// A run has to beat the stored best; a first run only counts once it scored at all.
+pub fn is_new_high_score(score: u32, best: u32) -> bool {
+    score > best
+}
+
+// Score per second of play.
+pub fn efficiency(score: u32, elapsed: f32) -> f32 {
+    if elapsed > 0.0 {
+        score as f32 / elapsed
+    } else {
+        0.0
+    }
+}
+
+fn record_best_efficiency(
+    score: Res<Score>,
+    play_time: Res<PlayTime>,
+    mut player_stats: ResMut<PlayerStats>,
+) {
+    let run_efficiency = efficiency(score.0, play_time.0);
+    if run_efficiency > player_stats.best_efficiency {
+        player_stats.best_efficiency = run_efficiency;
+        persistence::save(STATS_FILE, &*player_stats);
+    }
+}
+
+#[test]
+fn test_efficiency() {
+    assert_eq!(efficiency(300, 60.0), 5.0);
+    assert_eq!(efficiency(45, 1.5), 30.0);
+    assert_eq!(efficiency(100, 0.0), 0.0);
+}
+
+#[test]
+fn test_is_new_high_score() {
+    assert!(is_new_high_score(100, 0));
+    assert!(is_new_high_score(101, 100));
+    assert!(!is_new_high_score(100, 100));
+    assert!(!is_new_high_score(0, 0));
+
+    let mut high_scores = HighScores::default();
+    assert_eq!(high_scores.best(GameMode::Endless), 0);
+    high_scores.scores.insert(GameMode::Endless, 300);
+    assert_eq!(high_scores.best(GameMode::Endless), 300);
+    assert_eq!(high_scores.best(GameMode::Puzzle), 0);
+}